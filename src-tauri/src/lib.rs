@@ -4,6 +4,8 @@ use tauri::Manager;
 use tauri_plugin_dialog::DialogExt;
 use tauri_plugin_store::StoreExt;
 
+mod scripting;
+
 #[tauri::command]
 async fn ask(app: tauri::AppHandle, msg: &str) -> Result<bool, ()> {
     let confirm = app
@@ -17,6 +19,24 @@ async fn ask(app: tauri::AppHandle, msg: &str) -> Result<bool, ()> {
     Ok(confirm)
 }
 
+/// Asks where to save a test report and writes it there, returning why it
+/// couldn't be saved, if it couldn't. Cancelling isn't an error.
+#[tauri::command]
+async fn save_report(app: tauri::AppHandle, contents: String) -> Option<String> {
+    let path = app
+        .dialog()
+        .file()
+        .set_title("Export JUnit report")
+        .set_file_name("report.xml")
+        .add_filter("JUnit XML", &["xml"])
+        .blocking_save_file()?;
+    let path = match path.into_path() {
+        Ok(path) => path,
+        Err(e) => return Some(e.to_string()),
+    };
+    std::fs::write(path, contents).err().map(|e| e.to_string())
+}
+
 #[tauri::command]
 fn initialize(app: tauri::AppHandle) -> AppData {
     println!("Initialize command called");
@@ -78,6 +98,7 @@ pub fn run() {
                 last_address,
                 last_port,
             });
+            app.manage(scripting::Scripts::default());
 
             Ok(())
         })
@@ -91,7 +112,11 @@ pub fn run() {
             initialize,
             store_set,
             store_delete,
-            store_get
+            store_get,
+            save_report,
+            scripting::script_run,
+            scripting::script_stop,
+            scripting::script_status
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,295 @@
+//! Rhai scripts for test procedures that need loops, arithmetic and branching
+//! on replies. Scripts only get the functions registered here: no files, no
+//! modules, no `eval`, and limits on how deep and how big they can get.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rhai::{module_resolvers::DummyModuleResolver, Dynamic, Engine, EvalAltResult};
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_store::StoreExt;
+use tauri_plugin_tcp_client::TcpClientExt;
+use tcp_commander_models::*;
+
+/// Where `get_var` and `set_var` keep their values, so they outlive a single run.
+const VARIABLES_STORE: &str = "variables.json";
+
+/// How long `query` waits for a reply unless it's told otherwise.
+const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often waiting functions check for a reply, or for the script being stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Console lines kept per run; a script printing in a loop shouldn't eat all the memory.
+const MAX_OUTPUT_LINES: usize = 10_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+#[derive(Debug, Default)]
+struct Console {
+    running: bool,
+    lines: Vec<String>,
+    error: Option<String>,
+}
+
+impl Console {
+    fn log(&mut self, line: String) {
+        match self.lines.len() {
+            n if n < MAX_OUTPUT_LINES => self.lines.push(line),
+            n if n == MAX_OUTPUT_LINES => self.lines.push("Output truncated".into()),
+            _ => {}
+        }
+    }
+}
+
+/// The script run, if any, shared with the thread running it.
+#[derive(Default)]
+pub struct Scripts {
+    console: Arc<Mutex<Console>>,
+    stop: Arc<AtomicBool>,
+}
+
+// A panic in a script function shouldn't take the console down with it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptStatus {
+    running: bool,
+    /// Console lines from the requested one on.
+    lines: Vec<String>,
+    error: Option<String>,
+}
+
+impl Scripts {
+    fn status(&self, after: usize) -> ScriptStatus {
+        let console = lock(&self.console);
+        ScriptStatus {
+            running: console.running,
+            lines: console.lines.get(after..).unwrap_or_default().to_vec(),
+            error: console.error.clone(),
+        }
+    }
+}
+
+/// Sleeps for `duration`, giving up early if the script is stopped.
+fn pause(stop: &AtomicBool, duration: Duration) -> ScriptResult<()> {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        if stop.load(Ordering::SeqCst) {
+            return Err("Script stopped".into());
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+    }
+    Ok(())
+}
+
+fn millis(ms: i64) -> ScriptResult<Duration> {
+    u64::try_from(ms).map(Duration::from_millis).map_err(|_| format!("{ms} isn't a valid duration").into())
+}
+
+fn transmit(app: &AppHandle, message: &str) -> ScriptResult<()> {
+    let response = app
+        .tcp_client()
+        .transmit(TransmitRequest { message: Some(message.to_string()), suffix: None })
+        .map_err(|e| e.to_string())?;
+    response.error.map_or(Ok(()), |e| Err(e.into()))
+}
+
+/// Sends `message` and returns the next thing received, as it shows in the transcript.
+fn query(app: &AppHandle, stop: &AtomicBool, message: &str, timeout: Duration) -> ScriptResult<String> {
+    let transcript = |after| app.tcp_client().transcript(TranscriptRequest { after }).map_err(|e| e.to_string());
+    let last = transcript(None)?.entries.last().map(|entry| entry.id);
+    transmit(app, message)?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let reply = transcript(last)?.entries.into_iter().find(|entry| entry.direction == Direction::Received);
+        if let Some(reply) = reply {
+            return Ok(reply.data);
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("No reply to {message:?} within {} ms", timeout.as_millis()).into());
+        }
+        pause(stop, POLL_INTERVAL)?;
+    }
+}
+
+/// Goes through the plugin's `connect`, so scripts are held to its global
+/// destination scope. Allow and deny entries given to the `connect` command's
+/// own permission only reach commands invoked from the frontend, so they
+/// don't apply here.
+fn connect(app: &AppHandle, address: &str, port: i64, protocol: Option<&str>) -> ScriptResult<()> {
+    let protocol = protocol
+        .map(|protocol| serde_json::from_value(protocol.into()).map_err(|_| format!("Unknown protocol '{protocol}'")))
+        .transpose()?;
+    let request = ConnectRequest {
+        address: Some(address.to_string()),
+        port: Some(i32::try_from(port).map_err(|_| format!("{port} isn't a valid port"))?),
+        transport: None,
+        framing: None,
+        protocol,
+        mqtt: None,
+        redis: None,
+    };
+    let response = app.tcp_client().connect(request).map_err(|e| e.to_string())?;
+    response.error.map_or(Ok(()), |e| Err(e.into()))
+}
+
+fn engine(app: AppHandle, console: Arc<Mutex<Console>>, stop: Arc<AtomicBool>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.set_max_call_levels(64);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(1 << 20);
+    engine.set_max_array_size(100_000);
+    engine.set_max_map_size(100_000);
+
+    // Checked between operations, so even a tight loop can be stopped
+    let stopped = stop.clone();
+    engine.on_progress(move |_| stopped.load(Ordering::SeqCst).then(|| Dynamic::from("Script stopped")));
+
+    let output = console.clone();
+    engine.on_print(move |text| lock(&output).log(text.to_string()));
+    let output = console.clone();
+    engine.on_debug(move |text, _, position| lock(&output).log(format!("{position:?}: {text}")));
+    let output = console;
+    engine.register_fn("log", move |value: Dynamic| lock(&output).log(value.to_string()));
+
+    let handle = app.clone();
+    engine.register_fn("connect", move |address: &str, port: i64| connect(&handle, address, port, None));
+    let handle = app.clone();
+    engine.register_fn("connect", move |address: &str, port: i64, protocol: &str| {
+        connect(&handle, address, port, Some(protocol))
+    });
+    let handle = app.clone();
+    engine.register_fn("disconnect", move || -> ScriptResult<()> {
+        handle.tcp_client().disconnect(DisconnectRequest::default()).map_err(|e| e.to_string().into())
+    });
+    let handle = app.clone();
+    engine.register_fn("send", move |message: &str| transmit(&handle, message));
+    let (handle, stopped) = (app.clone(), stop.clone());
+    engine.register_fn("query", move |message: &str| query(&handle, &stopped, message, DEFAULT_QUERY_TIMEOUT));
+    let (handle, stopped) = (app.clone(), stop.clone());
+    engine.register_fn("query", move |message: &str, timeout_ms: i64| {
+        query(&handle, &stopped, message, millis(timeout_ms)?)
+    });
+    engine.register_fn("sleep", move |ms: i64| pause(&stop, millis(ms)?));
+
+    let handle = app.clone();
+    engine.register_fn("get_var", move |name: &str| -> ScriptResult<Dynamic> {
+        let store = handle.store(VARIABLES_STORE).map_err(|e| e.to_string())?;
+        store.get(name).map_or(Ok(Dynamic::UNIT), |value| rhai::serde::to_dynamic(value))
+    });
+    engine.register_fn("set_var", move |name: &str, value: Dynamic| -> ScriptResult<()> {
+        let value: serde_json::Value = rhai::serde::from_dynamic(&value)?;
+        app.store(VARIABLES_STORE).map_err(|e| e.to_string())?.set(name, value);
+        Ok(())
+    });
+
+    engine.register_fn("assert", |condition: bool| -> ScriptResult<()> {
+        if condition { Ok(()) } else { Err("Assertion failed".into()) }
+    });
+    engine.register_fn("assert", |condition: bool, message: &str| -> ScriptResult<()> {
+        if condition { Ok(()) } else { Err(format!("Assertion failed: {message}").into()) }
+    });
+    engine
+}
+
+/// Starts running `source` in the background. Syntax errors are reported
+/// straight away.
+#[tauri::command]
+pub fn script_run(app: AppHandle, source: String) -> ScriptStatus {
+    let scripts = app.state::<Scripts>();
+    let (console, stop) = (scripts.console.clone(), scripts.stop.clone());
+    let failed = |error: String| ScriptStatus { running: false, lines: Vec::new(), error: Some(error) };
+    let engine = engine(app.clone(), console.clone(), stop.clone());
+    let compiled = engine.compile(&source);
+
+    // Checked and claimed under one lock, so two runs started at once can't both get in
+    let mut current = lock(&console);
+    if current.running {
+        return failed("A script is already running".into());
+    }
+    let ast = match compiled {
+        Ok(ast) => ast,
+        Err(e) => {
+            let error = e.to_string();
+            *current = Console { running: false, lines: vec![error.clone()], error: Some(error.clone()) };
+            return failed(error);
+        }
+    };
+    stop.store(false, Ordering::SeqCst);
+    *current = Console { running: true, ..Default::default() };
+    drop(current);
+
+    thread::spawn(move || {
+        let result = engine.run_ast(&ast);
+        let mut console = lock(&console);
+        console.running = false;
+        match result {
+            Ok(()) => console.log("Script finished".into()),
+            Err(e) => {
+                // Whatever was interrupted, stopping isn't the script's fault
+                let error = if stop.load(Ordering::SeqCst) { "Script stopped".to_string() } else { e.to_string() };
+                console.log(error.clone());
+                console.error = Some(error);
+            }
+        }
+    });
+    scripts.status(0)
+}
+
+#[tauri::command]
+pub fn script_stop(app: AppHandle) {
+    app.state::<Scripts>().stop.store(true, Ordering::SeqCst);
+}
+
+/// What the script has printed from line `after` on, and whether it's still running.
+#[tauri::command]
+pub fn script_status(app: AppHandle, after: usize) -> ScriptStatus {
+    app.state::<Scripts>().status(after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_must_not_be_negative() {
+        assert_eq!(millis(250).unwrap(), Duration::from_millis(250));
+        assert!(millis(-1).unwrap_err().to_string().contains("-1 isn't a valid duration"));
+    }
+
+    #[test]
+    fn pauses_end_early_when_stopped() {
+        let start = Instant::now();
+        assert!(pause(&AtomicBool::new(false), Duration::from_millis(50)).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let start = Instant::now();
+        let stopped = pause(&AtomicBool::new(true), Duration::from_secs(10));
+        assert!(stopped.unwrap_err().to_string().contains("Script stopped"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn output_is_truncated() {
+        let mut console = Console::default();
+        for line in 0..MAX_OUTPUT_LINES + 5 {
+            console.log(line.to_string());
+        }
+        assert_eq!(console.lines.len(), MAX_OUTPUT_LINES + 1);
+        assert_eq!(console.lines[MAX_OUTPUT_LINES - 1], (MAX_OUTPUT_LINES - 1).to_string());
+        assert_eq!(console.lines.last().unwrap(), "Output truncated");
+    }
+}
@@ -1,10 +1,11 @@
-use std::{collections::BTreeMap, net::IpAddr};
+use std::{collections::{BTreeMap, BTreeSet}, net::IpAddr};
 
 use ev::MouseEvent;
 use leptos::leptos_dom::ev::SubmitEvent;
 use leptos::*;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
+use tcp_commander_models::{checksum::*, client, library::MessageDefaults, template::Placeholder, *};
 use wasm_bindgen::prelude::*;
 use web_sys::HtmlDialogElement;
 
@@ -63,13 +64,69 @@ async fn store_delete(rid: f64, key: &str) -> Result<bool, serde_wasm_bindgen::E
     serde_wasm_bindgen::from_value(invoke("plugin:store|delete", args).await)
 }
 
-fn map_append(option: &str) -> &str {
-    match option {
-        "LF" => "\n",
-        "CR" => "\r",
-        "CRLF" => "\r\n",
-        _ => "",
+/// Builds a Modbus operation from the Modbus form. `data` is a count for
+/// reads and a comma-separated list of values for writes.
+fn modbus_operation(function: &str, address: &str, data: &str) -> Result<ModbusOperation, String> {
+    let address: u16 = address.trim().parse().map_err(|_| format!("'{address}' isn't a valid address"))?;
+    let count = || data.trim().parse::<u16>().map_err(|_| format!("'{data}' isn't a valid count"));
+    let registers = || data.split(',')
+        .map(|value| value.trim().parse::<u16>().map_err(|_| format!("'{value}' isn't a valid register value")))
+        .collect::<Result<Vec<_>, _>>();
+    let coils = || data.split(',')
+        .map(|value| match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "on" | "true" => Ok(true),
+            "0" | "off" | "false" => Ok(false),
+            other => Err(format!("'{other}' isn't a valid coil value, use 1 or 0")),
+        })
+        .collect::<Result<Vec<_>, _>>();
+    Ok(match function {
+        "readCoils" => ModbusOperation::ReadCoils { address, count: count()? },
+        "readDiscreteInputs" => ModbusOperation::ReadDiscreteInputs { address, count: count()? },
+        "readHoldingRegisters" => ModbusOperation::ReadHoldingRegisters { address, count: count()? },
+        "readInputRegisters" => ModbusOperation::ReadInputRegisters { address, count: count()? },
+        "writeSingleCoil" => ModbusOperation::WriteSingleCoil { address, value: coils()?[0] },
+        "writeSingleRegister" => ModbusOperation::WriteSingleRegister { address, value: registers()?[0] },
+        "writeMultipleCoils" => ModbusOperation::WriteMultipleCoils { address, values: coils()? },
+        "writeMultipleRegisters" => ModbusOperation::WriteMultipleRegisters { address, values: registers()? },
+        other => return Err(format!("Unknown Modbus function '{other}'")),
+    })
+}
+
+/// Builds an OSC argument from a row of the OSC form. Blobs are written as
+/// hex bytes, e.g. `01 ff 7f`.
+fn osc_argument(kind: &str, value: &str) -> Result<OscArgument, String> {
+    let value = value.trim();
+    Ok(match kind {
+        "int" => OscArgument::Int(value.parse().map_err(|_| format!("'{value}' isn't a valid int"))?),
+        "float" => OscArgument::Float(value.parse().map_err(|_| format!("'{value}' isn't a valid float"))?),
+        "string" => OscArgument::String(value.to_string()),
+        "blob" => OscArgument::Blob(
+            value.split_whitespace()
+                .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("'{byte}' isn't a valid hex byte")))
+                .collect::<Result<_, _>>()?,
+        ),
+        "true" => OscArgument::True,
+        "false" => OscArgument::False,
+        other => return Err(format!("Unknown OSC type '{other}'")),
+    })
+}
+
+/// Bytes for `{{uuid}}`. Math.random isn't a CSPRNG, but these only need to be unique.
+fn random_bytes() -> [u8; 16] {
+    let mut bytes = [0; 16];
+    for byte in &mut bytes {
+        *byte = (js_sys::Math::random() * 256.0) as u8;
     }
+    bytes
+}
+
+/// Reads the file picked in a file input as text.
+async fn read_picked_file(input: JsValue) -> Option<String> {
+    let files = js_sys::Reflect::get(&input, &"files".into()).ok()?;
+    let file = js_sys::Reflect::get(&files, &0.into()).ok().filter(|file| !file.is_undefined())?;
+    let text = js_sys::Reflect::get(&file, &"text".into()).ok()?.dyn_into::<js_sys::Function>().ok()?;
+    let promise = text.call0(&file).ok()?.dyn_into::<js_sys::Promise>().ok()?;
+    wasm_bindgen_futures::JsFuture::from(promise).await.ok()?.as_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,6 +164,80 @@ pub fn Sidebar(
     }
 }
 
+/// Algorithm, format and range inputs for a checksum. `on_change` gets the
+/// checksum after every edit.
+#[component]
+fn ChecksumFields(checksum: RwSignal<Checksum>, #[prop(into)] on_change: Callback<Checksum>) -> impl IntoView {
+    let update = move |change: &dyn Fn(&mut Checksum)| {
+        checksum.update(|checksum| change(checksum));
+        on_change.call(checksum.get_untracked());
+    };
+    let skip = move |ev: ev::Event| event_target_value(&ev).trim().parse::<usize>().unwrap_or_default();
+    view! {
+        <select on:input=move |ev| {
+            let algorithm = serde_json::from_value(event_target_value(&ev).into()).unwrap_or_default();
+            update(&|checksum| checksum.algorithm = algorithm);
+        }>
+            <For
+                each=move || ChecksumAlgorithm::ALL.map(|algorithm| (algorithm, algorithm == checksum.get().algorithm))
+                key=|(algorithm, selected)| (algorithm.label(), *selected)
+                children=|(algorithm, selected)| {
+                    let value = serde_json::to_value(algorithm).unwrap().as_str().unwrap_or_default().to_string();
+                    if selected {
+                        view! { <option value={value} selected="selected">{algorithm.label()}</option> }
+                    } else {
+                        view! { <option value={value}>{algorithm.label()}</option> }
+                    }
+                }
+            />
+        </select>
+        <select
+            on:input=move |ev| {
+                let format = if event_target_value(&ev) == "hex" { ChecksumFormat::Hex } else { ChecksumFormat::Raw };
+                update(&|checksum| checksum.format = format);
+            }
+            disabled=move || checksum.get().algorithm == ChecksumAlgorithm::None
+        >
+            <For
+                each=move || [(ChecksumFormat::Raw, "raw", "Raw bytes"), (ChecksumFormat::Hex, "hex", "ASCII hex")]
+                    .map(|(format, value, label)| (value, label, format == checksum.get().format))
+                key=|(value, _, selected)| (value.to_string(), *selected)
+                children=|(value, label, selected)| {
+                    if selected {
+                        view! { <option value={value} selected="selected">{label}</option> }
+                    } else {
+                        view! { <option value={value}>{label}</option> }
+                    }
+                }
+            />
+        </select>
+        <input
+            type="number"
+            min="0"
+            placeholder="Skip bytes at start"
+            title="Bytes at the start left out of the checksum"
+            on:change=move |ev| {
+                let bytes = skip(ev);
+                update(&|checksum| checksum.skip_start = bytes);
+            }
+            prop:value=move || checksum.get().skip_start.to_string()
+            disabled=move || checksum.get().algorithm == ChecksumAlgorithm::None
+        />
+        <input
+            type="number"
+            min="0"
+            placeholder="Skip bytes at end"
+            title="Bytes at the end left out of the checksum"
+            on:change=move |ev| {
+                let bytes = skip(ev);
+                update(&|checksum| checksum.skip_end = bytes);
+            }
+            prop:value=move || checksum.get().skip_end.to_string()
+            disabled=move || checksum.get().algorithm == ChecksumAlgorithm::None
+        />
+    }
+}
+
 #[component]
 pub fn App() -> impl IntoView {
     let address = create_rw_signal(String::new());
@@ -115,10 +246,96 @@ pub fn App() -> impl IntoView {
     let append = create_rw_signal(String::new());
     let prepend = create_rw_signal(String::new());
     let prepend_enabled = create_rw_signal(false);
+    let checksum = create_rw_signal(Checksum::default());
+    // Named prefixes and suffixes saved commands can use instead of prepend and append
+    let presets = create_rw_signal(BTreeMap::<String, Preset>::new());
+    let preset_name = create_rw_signal(String::new());
+    let preset_prefix = create_rw_signal(String::new());
+    let preset_suffix = create_rw_signal(String::new());
+    let scpi_check_errors = create_rw_signal(false);
+    // Latest reply to each saved SCPI query, shown on its card
+    let scpi_replies = create_rw_signal(BTreeMap::<String, String>::new());
+    let disconnect_mode = create_rw_signal(DisconnectMode::default());
+    let transport = create_rw_signal(String::from("tcp"));
+    let protocol = create_rw_signal(Protocol::Raw);
+    // `None` leaves it up to the plugin's configured default
+    let framing = create_rw_signal(None::<Framing>);
+    let transcript = create_rw_signal(Vec::<TranscriptEntry>::new());
+
+    let modbus_function = create_rw_signal(String::from("readHoldingRegisters"));
+    let modbus_unit = create_rw_signal(String::new());
+    let modbus_address = create_rw_signal(String::new());
+    let modbus_data = create_rw_signal(String::new());
+
+    // Everything but the password is remembered between runs
+    let mqtt_options = create_rw_signal(MqttOptions::default());
+    let mqtt_password = create_rw_signal(String::new());
+    let mqtt_topic = create_rw_signal(String::new());
+    let mqtt_payload = create_rw_signal(String::new());
+    let mqtt_qos = create_rw_signal(0u8);
+    let mqtt_retain = create_rw_signal(false);
+    let mqtt_filter = create_rw_signal(String::new());
+    let mqtt_subscriptions = create_rw_signal(BTreeMap::<String, u8>::new());
+
+    let redis_options = create_rw_signal(RedisOptions::default());
+    // Names of saved commands to send together, in order
+    let pipeline = create_rw_signal(Vec::<String>::new());
+
+    let gcode_program = create_rw_signal(String::new());
+    let gcode_status = create_rw_signal(GcodeStatus::default());
+    let gcode_poll = store_value(None::<IntervalHandle>);
+
+    let http_method = create_rw_signal(String::from("GET"));
+    let http_path = create_rw_signal(String::from("/"));
+    // One `Name: value` per line
+    let http_headers = create_rw_signal(String::new());
+    let http_body = create_rw_signal(String::new());
+    let http_response = create_rw_signal(None::<HttpResponse>);
+
+    let osc_address = create_rw_signal(String::new());
+    // (type, value) for each argument row
+    let osc_arguments = create_rw_signal(Vec::<(String, String)>::new());
+    let osc_bundle = create_rw_signal(false);
+    let osc_delay = create_rw_signal(String::new());
+
+    let saved = create_rw_signal(BTreeMap::<String, CommandRecord>::new());
+    // Only commands whose name, description or tags contain this are shown
+    let command_filter = create_rw_signal(String::new());
+
+    let sequences = create_rw_signal(BTreeMap::<String, Vec<SequenceItem>>::new());
+    let sequence_name = create_rw_signal(String::new());
+    let sequence_text = create_rw_signal(String::new());
+    let sequence_status = create_rw_signal(SequenceStatus::default());
+    let sequence_poll = store_value(None::<IntervalHandle>);
+
+    let expectations = create_rw_signal(BTreeMap::<String, Expectation>::new());
+    let test_commands = create_rw_signal(BTreeSet::<String>::new());
+    let test_sequences = create_rw_signal(BTreeSet::<String>::new());
+    let test_report = create_rw_signal(None::<TestReport>);
+    let test_running = create_rw_signal(false);
+
+    let schedules = create_rw_signal(Vec::<ScheduleStatus>::new());
+    let schedule_interval = create_rw_signal(String::from("1000"));
+    let schedule_count = create_rw_signal(String::new());
+    let schedule_duration = create_rw_signal(String::new());
+    let schedule_poll = store_value(None::<IntervalHandle>);
 
-    let saved = create_rw_signal(BTreeMap::<String, String>::new());
+    let scripts = create_rw_signal(BTreeMap::<String, String>::new());
+    let script_name = create_rw_signal(String::new());
+    let script_source = create_rw_signal(String::new());
+    let script_console = create_rw_signal(Vec::<String>::new());
+    let script_running = create_rw_signal(false);
+    let script_poll = store_value(None::<IntervalHandle>);
     let command_name = create_rw_signal(String::new());
 
+    // Shared with scripts' get_var/set_var through variables.json
+    let variables = create_rw_signal(BTreeMap::<String, serde_json::Value>::new());
+    let variable_name = create_rw_signal(String::new());
+    let variable_value = create_rw_signal(String::new());
+    let template_counter = create_rw_signal(0u64);
+    // What was typed for each asked-for placeholder, offered again next time
+    let template_answers = create_rw_signal(BTreeMap::<String, String>::new());
+
     let update_address = move |ev| address.set(event_target_value(&ev));
     let update_port = move |ev| port.set(event_target_value(&ev));
     let update_message = move |ev| message.set(event_target_value(&ev));
@@ -137,65 +354,369 @@ pub fn App() -> impl IntoView {
         });
     };
 
-    let connect = move |ev: SubmitEvent| {
-        ev.prevent_default();
-        
-        if address.get().is_empty() {
-            window().alert_with_message("Missing address").unwrap();
-            return;
+    // Connects to what's in the connection fields, saving them for next time
+    let open_connection = move || async move {
+        if address.get_untracked().is_empty() {
+            return Err("Missing address".to_string());
         }
-        if let Err(e) =  address.get().parse::<IpAddr>() {
-            window().alert_with_message(&format!("Invalid IP address; error: {e}")).unwrap();
-            return;
+        // Hostnames are fine too now, which TLS needs for certificate checks
+        if let Err(e) =  address.get_untracked().parse::<IpAddr>() {
+            if address.get_untracked().contains(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')) {
+                return Err(format!("Invalid address; error: {e}"));
+            }
         }
-        if port.get().is_empty() {
-            window().alert_with_message("Missing port number").unwrap();
-            return;
+        if port.get_untracked().is_empty() {
+            return Err("Missing port number".to_string());
         }
-        let port: i32 = port.get().parse().expect("failed to get number from form input");
-        let address = address.get();
-        
+        let port: i32 = port.get_untracked().parse().map_err(|_| "Invalid port number".to_string())?;
+        let address = address.get_untracked();
+        let transport = transport.get_untracked();
+        let protocol = protocol.get_untracked();
 
         spawn_local( {
             let address = address.clone();
+            let transport = transport.clone();
             async move {
                 let store = store_load("store.json").await;
                 store_set(store, "last_address", &address).await.expect("failed to save address");
                 logging::log!("Saved address: {address}");
                 store_set(store, "last_port", port.to_string()).await.expect("failed to save port");
                 logging::log!("Saved port: {port}");
+                store_set(store, "last_transport", &transport).await.expect("failed to save transport");
+                store_set(store, "last_protocol", protocol).await.expect("failed to save protocol");
             }
         });
 
+        let request = ConnectRequest {
+            address: Some(address),
+            port: Some(port),
+            transport: Some(transport),
+            framing: framing.get_untracked(),
+            protocol: Some(protocol),
+            mqtt: (protocol == Protocol::Mqtt).then(|| MqttOptions {
+                password: Some(mqtt_password.get_untracked()).filter(|p| !p.is_empty()),
+                ..mqtt_options.get_untracked()
+            }),
+            redis: (protocol == Protocol::Redis).then(|| redis_options.get_untracked()),
+        };
+        mqtt_subscriptions.set(BTreeMap::new());
+        client::connect(&request).await
+            .map_err(|e| e.to_string())
+            .and_then(|result| result.error.map_or(Ok(()), Err))
+            .map_err(|err| format!("Error connecting to server: {err}"))
+    };
+
+    let connect = move |ev: SubmitEvent| {
+        ev.prevent_default();
         spawn_local(async move {
-            let args = serde_wasm_bindgen::to_value(&ConnectArgs { address: &address, port }).unwrap();
-            let resp = invoke("plugin:tcp-client|connect", args).await;
-            let result: ConnectResponse = serde_wasm_bindgen::from_value(resp).unwrap();
             // too lazy for proper popup
-            if let Some(err) = result.error {
-                window().alert_with_message(&format!("Error connecting to server: {err}")).unwrap();
-            } else {
-                window().alert_with_message("Connected to server!").unwrap();
+            match open_connection().await {
+                Ok(()) => window().alert_with_message("Connected to server!").unwrap(),
+                Err(err) => window().alert_with_message(&err).unwrap(),
             }
         });
     };
 
+    let update_disconnect_mode = move |ev| {
+        let updated: DisconnectMode = serde_json::from_value(event_target_value(&ev).into()).unwrap_or_default();
+        disconnect_mode.set(updated);
+        spawn_local(async move {
+            let store = store_load("store.json").await;
+            store_set(store, "last_disconnect_mode", &updated).await.expect("failed to save");
+        });
+    };
+
     let disconnect = move |ev: ev::MouseEvent| {
         ev.prevent_default();
+        let mode = disconnect_mode.get();
+        spawn_local(async move {
+            if let Err(e) = client::disconnect(&DisconnectRequest { mode: Some(mode) }).await {
+                window().alert_with_message(&format!("Error disconnecting: {e}")).unwrap();
+            }
+        });
+    };
+
+    let diagnose_popup = create_node_ref::<html::Dialog>();
+    let diagnostics = create_rw_signal(None::<Vec<DiagnosticStep>>);
+    let diagnose = move |ev: ev::MouseEvent| {
+        ev.prevent_default();
+        let address = address.get();
+        let port = match port.get().trim() {
+            "" => None,
+            text => match text.parse() {
+                Ok(port) => Some(port),
+                Err(_) => {
+                    window().alert_with_message(&format!("'{text}' isn't a valid port")).unwrap();
+                    return;
+                }
+            },
+        };
+        diagnostics.set(None);
+        diagnose_popup.get().unwrap().show_modal().unwrap();
+        spawn_local(async move {
+            match client::diagnose(&DiagnoseRequest { address: Some(address), port, addresses: None }).await {
+                Ok(result) => diagnostics.set(Some(result.steps)),
+                Err(e) => {
+                    diagnose_popup.get().unwrap().close();
+                    window().alert_with_message(&format!("Failed to run diagnostics: {e}")).unwrap();
+                }
+            }
+        });
+    };
+
+    let discover_popup = create_node_ref::<html::Dialog>();
+    let service_types = create_rw_signal(String::from("_tcp-commander._tcp, _telnet._tcp"));
+    let discovered = create_rw_signal(Vec::<DiscoveredService>::new());
+    let discovering = create_rw_signal(false);
+    let discover = create_action(move |_: &()| {
+        let types = service_types.get();
+        discovering.set(true);
+        async move {
+            let store = store_load("store.json").await;
+            store_set(store, "discovery_service_types", &types).await.expect("failed to save");
+
+            let service_types = types
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .collect();
+            let request = DiscoverRequest { service_types: Some(service_types), timeout_ms: None };
+            match client::discover(&request).await {
+                Ok(result) => discovered.set(result.services),
+                Err(e) => {
+                    window().alert_with_message(&format!("Failed to search for devices: {e}")).unwrap();
+                }
+            }
+            discovering.set(false);
+        }
+    });
+    let pick_service = move |service: DiscoveredService| {
+        // Not every OS resolves .local names, so prefer an address (IPv4 sorts first)
+        let picked = service.addresses.first().cloned().unwrap_or(service.hostname);
+        address.set(picked);
+        port.set(service.port.to_string());
+        discover_popup.get().unwrap().close();
+    };
+
+    let run_scpi = move |command: String, name: Option<String>| {
+        let request = ScpiRequest { command, check_errors: Some(scpi_check_errors.get()) };
+        spawn_local(async move {
+            let response = match client::scpi(&request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    window().alert_with_message(&err.to_string()).unwrap();
+                    return;
+                }
+            };
+            let reply = response.reply
+                .or_else(|| response.block.map(|block| format!("<binary block, {} bytes>", block.len())));
+            if let (Some(name), Some(reply)) = (name, reply) {
+                scpi_replies.update(|replies| { replies.insert(name, reply); });
+            }
+            if let Some(err) = response.error {
+                window().alert_with_message(&err).unwrap();
+            } else if !response.errors.is_empty() {
+                window().alert_with_message(&format!("Instrument reported:\n{}", response.errors.join("\n"))).unwrap();
+            }
+        });
+    };
+
+    let run_redis = move |commands: Vec<String>| {
+        spawn_local(async move {
+            // Replies are shown in the transcript
+            let result = client::redis(&RedisRequest { commands }).await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.error.map_or(Ok(()), Err));
+            if let Err(err) = result {
+                window().alert_with_message(&err).unwrap();
+            }
+        });
+    };
+
+    // Follows a stream's progress until it's over
+    let poll_gcode = move || {
+        if gcode_poll.get_value().is_some() {
+            return;
+        }
+        let handle = set_interval_with_handle(move || spawn_local(async move {
+            let Ok(status) = client::gcode_status().await else {
+                return;
+            };
+            if !matches!(status.state, GcodeState::Running | GcodeState::Paused) {
+                if let Some(handle) = gcode_poll.get_value() {
+                    handle.clear();
+                    gcode_poll.set_value(None);
+                }
+                if let Some(err) = &status.error {
+                    window().alert_with_message(&format!("G-code stream failed: {err}")).unwrap();
+                }
+            }
+            gcode_status.set(status);
+        }), std::time::Duration::from_millis(250));
+        gcode_poll.set_value(handle.ok());
+    };
+
+    let stream_gcode = move |program: String| {
         spawn_local(async move {
-            invoke("plugin:tcp-client|disconnect", JsValue::null()).await;
+            let request = GcodeStreamRequest { program: Some(program), ..Default::default() };
+            match client::gcode_stream(&request).await {
+                Ok(GcodeStatus { error: Some(err), .. }) => window().alert_with_message(&err).unwrap(),
+                Ok(status) => {
+                    gcode_status.set(status);
+                    poll_gcode();
+                }
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
         });
     };
 
-    let transmit_message = move |message: String| {
+    let control_gcode = move |action: GcodeAction| {
+        spawn_local(async move {
+            match client::gcode_control(&GcodeControlRequest { action }).await {
+                Ok(GcodeStatus { error: Some(err), .. }) => window().alert_with_message(&err).unwrap(),
+                Ok(status) => gcode_status.set(status),
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
+        });
+    };
+
+    // The value of a placeholder, or `None` for a variable nobody has given a value yet
+    let placeholder_value = move |placeholder: &Placeholder| match placeholder {
+        Placeholder::Counter => Some((template_counter.get() + 1).to_string()),
+        Placeholder::Timestamp => Some((js_sys::Date::now() as u64).to_string()),
+        Placeholder::Uuid => Some(template::uuid_v4(random_bytes())),
+        Placeholder::Random { min, max } => Some(template::random_in(*min, *max, js_sys::Math::random()).to_string()),
+        Placeholder::Variable(name) => variables.get().get(name).map(variable_text),
+    };
+
+    // Fills in a message's placeholders, asking for variables that aren't saved.
+    // `None` if that was cancelled or a placeholder is invalid.
+    let fill_template = move |message: String| -> Option<String> {
+        let mut counted = false;
+        let filled = template::expand(&message, |placeholder| {
+            counted |= *placeholder == Placeholder::Counter;
+            if let Some(value) = placeholder_value(placeholder) {
+                return Ok(value);
+            }
+            let Placeholder::Variable(name) = placeholder else { unreachable!() };
+            let last = template_answers.get_untracked().get(name).cloned().unwrap_or_default();
+            // An empty error means it was cancelled
+            let answer = window()
+                .prompt_with_message_and_default(&format!("Value for {{{{{name}}}}}"), &last)
+                .ok()
+                .flatten()
+                .ok_or_else(String::new)?;
+            template_answers.update(|answers| { answers.insert(name.clone(), answer.clone()); });
+            Ok(answer)
+        });
+        match filled {
+            Ok(filled) => {
+                if counted {
+                    template_counter.update(|counter| *counter += 1);
+                    spawn_local(async move {
+                        let store = store_load("store.json").await;
+                        store_set(store, "template_counter", template_counter.get_untracked()).await
+                            .expect("failed to save");
+                    });
+                }
+                Some(filled)
+            }
+            Err(err) if err.is_empty() => None,
+            Err(err) => {
+                window().alert_with_message(&err).unwrap();
+                None
+            }
+        }
+    };
+
+    // What typing `message` into the message box would send, placeholders and all
+    let message_preview = move |message: String| -> Result<Vec<u8>, String> {
+        let filled = template::expand(&message, |placeholder| Ok(placeholder_value(placeholder).unwrap_or_else(|| {
+            let Placeholder::Variable(name) = placeholder else { unreachable!() };
+            template_answers.get().get(name).cloned().unwrap_or_else(|| format!("{{{{{name}}}}}"))
+        })))?;
         let front = prepend_enabled.get().then(|| prepend.get()).unwrap_or_default();
-        let message = front + &message + map_append(&append.get());
-        logging::log!("would send {message}");
+        let payload = (front + &filled).into_bytes();
+        Ok(match protocol.get() {
+            Protocol::Scpi => [payload, b"\n".to_vec()].concat(),
+            Protocol::Redis => payload,
+            _ => {
+                let suffix = checksum.get().suffix(&payload);
+                [payload, suffix, library::line_ending(&append.get()).as_bytes().to_vec()].concat()
+            }
+        })
+    };
+
+    // How messages are sent unless their command says otherwise
+    let message_defaults = move || MessageDefaults {
+        protocol: protocol.get_untracked(),
+        prepend: prepend_enabled.get_untracked().then(|| prepend.get_untracked()).unwrap_or_default(),
+        append: append.get_untracked(),
+        checksum: checksum.get_untracked(),
+        scpi_check_errors: scpi_check_errors.get_untracked(),
+    };
+
+    // What goes before and after a message: the preset's, or the global prepend
+    // and append, with the ending replaced by a command's own terminator
+    let affixes = move |preset: Option<&Preset>, terminator: Option<&str>| message_defaults().affixes(preset, terminator);
+
+    // Fills in a message's placeholders and turns it into bytes, or says why it can't
+    let encode_message = move |message: String, encoding: Encoding| -> Option<Vec<u8>> {
+        let message = fill_template(message)?;
+        encoding.decode(&message)
+            .map_err(|err| window().alert_with_message(&err).unwrap())
+            .ok()
+    };
+
+    // For everything but raw, Telnet and HTTP mode, which can send any bytes
+    let message_text = move |bytes: Vec<u8>| -> Option<String> {
+        String::from_utf8(bytes)
+            .map_err(|_| window().alert_with_message("Only raw, Telnet and HTTP mode can send bytes that aren't text").unwrap())
+            .ok()
+    };
+
+    let transmit_message = move |message: String, checksum: Checksum, (front, ending): (String, String), encoding: Encoding| {
+        let Some(body) = encode_message(message, encoding) else {
+            return;
+        };
+        if !matches!(protocol.get(), Protocol::Raw | Protocol::Telnet | Protocol::Http) {
+            let Some(message) = message_text(body.clone()) else {
+                return;
+            };
+            if protocol.get() == Protocol::Scpi {
+                // The plugin adds the newline SCPI needs itself
+                run_scpi(front + &message, None);
+                return;
+            }
+            if protocol.get() == Protocol::Redis {
+                // RESP has its own framing, so there's nothing to append
+                run_redis(vec![front + &message]);
+                return;
+            }
+            if protocol.get() == Protocol::Gcode && message.trim().contains('\n') {
+                // Whole programs have to wait for each line to be acknowledged
+                stream_gcode(message);
+                return;
+            }
+        }
+        let mut suffix = checksum.suffix(&[front.as_bytes(), &body[..]].concat());
+        suffix.extend_from_slice(ending.as_bytes());
+        // Keep as much as possible as text, so the transcript stays readable
+        let request = match (String::from_utf8(body), String::from_utf8(suffix)) {
+            (Ok(body), Ok(suffix)) => TransmitRequest { message: Some(front + &body + &suffix), suffix: None },
+            (Ok(body), Err(suffix)) => TransmitRequest { message: Some(front + &body), suffix: Some(suffix.into_bytes()) },
+            (Err(body), suffix) => {
+                let suffix = suffix.map_or_else(|err| err.into_bytes(), String::into_bytes);
+                TransmitRequest { message: Some(front), suffix: Some([body.into_bytes(), suffix].concat()) }
+            }
+        };
+        logging::log!("would send {request:?}");
         spawn_local(async move {
-            let args = serde_wasm_bindgen::to_value(&TransmitArgs { message: &message }).unwrap(); 
-            let resp = invoke("plugin:tcp-client|transmit", args).await;
-            let result: TransmitResponse = serde_wasm_bindgen::from_value(resp).unwrap();
-            if let Some(err) = result.error {
+            let result = client::transmit(&request).await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.error.map_or(Ok(()), Err));
+            if let Err(err) = result {
                 window().alert_with_message(&err).unwrap();
             }
         });
@@ -203,10 +724,535 @@ pub fn App() -> impl IntoView {
 
     let transmit = move |ev: SubmitEvent| {
         ev.prevent_default();
-        transmit_message(message.get());
+        transmit_message(message.get(), checksum.get(), affixes(None, None), Encoding::Text);
+    };
+
+    // How a message in a sequence is sent, following `transmit_message`
+    let sequence_message = move |message: String, preset: Option<&Preset>, terminator: Option<&str>, own_checksum: Option<&Checksum>| -> Result<SequenceStep, String> {
+        template::no_placeholders(&message)?;
+        Ok(message_defaults().step(&message, preset, terminator, own_checksum))
+    };
+
+    // Follows a sequence's progress until it's over
+    let poll_sequence = move || {
+        if sequence_poll.get_value().is_some() {
+            return;
+        }
+        let handle = set_interval_with_handle(move || spawn_local(async move {
+            let Ok(status) = client::sequence_status().await else {
+                return;
+            };
+            if !matches!(status.state, SequenceState::Running | SequenceState::Paused) {
+                if let Some(handle) = sequence_poll.get_value() {
+                    handle.clear();
+                    sequence_poll.set_value(None);
+                }
+                if let Some(err) = &status.error {
+                    window().alert_with_message(&format!("Sequence failed: {err}")).unwrap();
+                }
+            }
+            sequence_status.set(status);
+        }), std::time::Duration::from_millis(250));
+        sequence_poll.set_value(handle.ok());
+    };
+
+    let run_sequence = move |name: Option<String>, items: Vec<SequenceItem>| {
+        let steps = match SequenceItem::resolve(&items, &saved.get_untracked(), &presets.get_untracked(), &sequence_message) {
+            Ok(steps) => steps,
+            Err(err) => {
+                window().alert_with_message(&err).unwrap();
+                return;
+            }
+        };
+        spawn_local(async move {
+            match client::sequence_run(&SequenceRunRequest { name, steps }).await {
+                Ok(SequenceStatus { error: Some(err), .. }) => window().alert_with_message(&err).unwrap(),
+                Ok(status) => {
+                    sequence_status.set(status);
+                    poll_sequence();
+                }
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
+        });
+    };
+
+    let control_sequence = move |action: SequenceAction| {
+        spawn_local(async move {
+            match client::sequence_control(&SequenceControlRequest { action }).await {
+                Ok(SequenceStatus { error: Some(err), .. }) => window().alert_with_message(&err).unwrap(),
+                Ok(status) => sequence_status.set(status),
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
+        });
+    };
+
+    let run_edited_sequence = move |_| {
+        match SequenceItem::parse(&sequence_text.get()) {
+            Ok(items) if items.is_empty() => window().alert_with_message("Sequence is empty!").unwrap(),
+            Ok(items) => run_sequence(Some(sequence_name.get()).filter(|name| !name.is_empty()), items),
+            Err(err) => window().alert_with_message(&err).unwrap(),
+        }
+    };
+
+    let save_sequence = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        let name = sequence_name.get();
+        if name.is_empty() {
+            window().alert_with_message("Sequence name field is empty!").unwrap();
+            return;
+        }
+        let items = match SequenceItem::parse(&sequence_text.get()) {
+            Ok(items) if items.is_empty() => Err("Sequence is empty!".to_string()),
+            result => result,
+        };
+        let items = match items {
+            Ok(items) => items,
+            Err(err) => {
+                window().alert_with_message(&err).unwrap();
+                return;
+            }
+        };
+        // Saving under an existing name replaces it, which is how sequences are edited
+        sequences.update(|sequences| { sequences.insert(name.clone(), items.clone()); });
+        spawn_local(async move {
+            let store = store_load("sequences.json").await;
+            store_set(store, &name, &items).await.expect("failed to save sequence");
+        });
+    };
+
+    let delete_sequence = move |name: String| {
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&ConfirmArgs { msg: &format!("Delete sequence '{name}'?") }).unwrap();
+            if let Ok(true) = serde_wasm_bindgen::from_value(invoke("ask", args).await) {
+                sequences.update(|sequences| { sequences.remove(&name); });
+                let store = store_load("sequences.json").await;
+                store_delete(store, &name).await.expect("failed to delete sequence");
+            }
+        });
+    };
+
+    let set_expectation = move |name: String, expectation: Option<Expectation>| {
+        expectations.update(|expectations| match &expectation {
+            Some(expectation) => { expectations.insert(name.clone(), expectation.clone()); }
+            None => { expectations.remove(&name); }
+        });
+        spawn_local(async move {
+            let store = store_load("expectations.json").await;
+            match expectation {
+                Some(expectation) => store_set(store, &name, &expectation).await.expect("failed to save expectation"),
+                None => { store_delete(store, &name).await.expect("failed to delete expectation"); }
+            }
+        });
+    };
+
+    let run_tests = move |_| {
+        let (saved, presets) = (saved.get_untracked(), presets.get_untracked());
+        let command_cases = test_commands.get_untracked().into_iter().map(|name| -> Result<TestCase, String> {
+            let steps = SequenceItem::resolve(&[SequenceItem::Command { name: name.clone() }], &saved, &presets, &sequence_message)?;
+            Ok(TestCase { expect: expectations.with_untracked(|e| e.get(&name).cloned()), name, steps })
+        });
+        let sequence_cases = test_sequences.get_untracked().into_iter().map(|name| -> Result<TestCase, String> {
+            let items = sequences.with_untracked(|sequences| sequences.get(&name).cloned()).unwrap_or_default();
+            let steps = SequenceItem::resolve(&items, &saved, &presets, &sequence_message)?;
+            Ok(TestCase { name, steps, expect: None })
+        });
+        let cases = match command_cases.chain(sequence_cases).collect::<Result<Vec<_>, _>>() {
+            Ok(cases) if cases.is_empty() => Err("No tests selected!".to_string()),
+            result => result,
+        };
+        let cases = match cases {
+            Ok(cases) => cases,
+            Err(err) => {
+                window().alert_with_message(&err).unwrap();
+                return;
+            }
+        };
+        test_running.set(true);
+        spawn_local(async move {
+            match client::test_run(&TestRunRequest { suite: None, cases }).await {
+                Ok(TestReport { error: Some(err), results, .. }) if results.is_empty() => window().alert_with_message(&err).unwrap(),
+                Ok(report) => test_report.set(Some(report)),
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
+            test_running.set(false);
+        });
+    };
+
+    let export_report = move |_| {
+        let Some(report) = test_report.get_untracked() else {
+            return;
+        };
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&SaveReportArgs { contents: &report.junit }).unwrap();
+            if let Ok(Some(err)) = serde_wasm_bindgen::from_value::<Option<String>>(invoke("save_report", args).await) {
+                window().alert_with_message(&format!("Couldn't save the report: {err}")).unwrap();
+            }
+        });
+    };
+
+    // Keeps the list of schedules current until none are left
+    let poll_schedules = move || {
+        if schedule_poll.get_value().is_some() {
+            return;
+        }
+        let handle = set_interval_with_handle(move || spawn_local(async move {
+            let Ok(list) = client::schedule_list().await else {
+                return;
+            };
+            if list.schedules.is_empty() {
+                if let Some(handle) = schedule_poll.get_value() {
+                    handle.clear();
+                    schedule_poll.set_value(None);
+                }
+            }
+            schedules.set(list.schedules);
+        }), std::time::Duration::from_millis(500));
+        schedule_poll.set_value(handle.ok());
+    };
+
+    let start_schedule = move |name: String| {
+        let limit = |text: String, what: &str| match text.trim() {
+            "" => Ok(None),
+            value => value.parse().map(Some).map_err(|_| format!("'{value}' isn't a valid {what}")),
+        };
+        let request = (|| -> Result<ScheduleRequest, String> {
+            let interval = schedule_interval.get_untracked();
+            let interval_ms = interval.trim().parse().map_err(|_| format!("'{interval}' isn't a valid interval"))?;
+            let command = [SequenceItem::Command { name: name.clone() }];
+            let step = SequenceItem::resolve(&command, &saved.get_untracked(), &presets.get_untracked(), &sequence_message)?.remove(0);
+            Ok(ScheduleRequest {
+                name: Some(name.clone()),
+                step,
+                interval_ms,
+                count: limit(schedule_count.get_untracked(), "count")?,
+                duration_ms: limit(schedule_duration.get_untracked(), "duration")?,
+            })
+        })();
+        let request = match request {
+            Ok(request) => request,
+            Err(err) => {
+                window().alert_with_message(&err).unwrap();
+                return;
+            }
+        };
+        spawn_local(async move {
+            match client::schedule_start(&request).await {
+                Ok(ScheduleStatus { error: Some(err), .. }) => window().alert_with_message(&err).unwrap(),
+                Ok(status) => {
+                    schedules.update(|schedules| schedules.push(status));
+                    poll_schedules();
+                }
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
+        });
+    };
+
+    let stop_schedule = move |id: Option<u64>| {
+        spawn_local(async move {
+            match client::schedule_stop(&ScheduleStopRequest { id }).await {
+                Ok(list) => schedules.set(list.schedules),
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
+        });
+    };
+
+    // Follows a script's output until it's done
+    let poll_script = move || {
+        if script_poll.get_value().is_some() {
+            return;
+        }
+        let handle = set_interval_with_handle(move || spawn_local(async move {
+            let after = script_console.get_untracked().len();
+            let args = serde_wasm_bindgen::to_value(&ScriptStatusArgs { after }).unwrap();
+            let Ok(status) = serde_wasm_bindgen::from_value::<ScriptStatus>(invoke("script_status", args).await) else {
+                return;
+            };
+            // A slow reply can overlap with the next tick, so only take lines that follow on
+            script_console.update(|console| if console.len() == after { console.extend(status.lines) });
+            script_running.set(status.running);
+            if !status.running {
+                if let Some(handle) = script_poll.get_value() {
+                    handle.clear();
+                    script_poll.set_value(None);
+                }
+                // The script may have changed template variables with set_var
+                let store = store_load("variables.json").await;
+                let entries = invoke("plugin:store|entries", serde_wasm_bindgen::to_value(&EntriesArgs { rid: store }).unwrap()).await;
+                if let Ok(entries) = serde_wasm_bindgen::from_value::<Vec<(String, serde_json::Value)>>(entries) {
+                    variables.set(entries.into_iter().collect());
+                }
+            }
+        }), std::time::Duration::from_millis(250));
+        script_poll.set_value(handle.ok());
+    };
+
+    let run_script = move |source: String| {
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&ScriptRunArgs { source: &source }).unwrap();
+            let status = serde_wasm_bindgen::from_value::<ScriptStatus>(invoke("script_run", args).await).unwrap_or_default();
+            if let Some(err) = &status.error {
+                window().alert_with_message(err).unwrap();
+            }
+            // Syntax errors end up in the console too, so it's read back either way
+            script_console.set(Vec::new());
+            poll_script();
+        });
+    };
+
+    let stop_script = move |_| {
+        spawn_local(async move {
+            invoke("script_stop", JsValue::null()).await;
+        });
+    };
+
+    let save_script = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        let name = script_name.get();
+        if name.is_empty() {
+            window().alert_with_message("Script name field is empty!").unwrap();
+            return;
+        }
+        let source = script_source.get();
+        scripts.update(|scripts| { scripts.insert(name.clone(), source.clone()); });
+        spawn_local(async move {
+            let store = store_load("scripts.json").await;
+            store_set(store, &name, &source).await.expect("failed to save script");
+        });
     };
 
-    let save_command = create_action(move |(name, message): &(String, String)| {
+    let delete_script = move |name: String| {
+        spawn_local(async move {
+            let args = serde_wasm_bindgen::to_value(&ConfirmArgs { msg: &format!("Delete script '{name}'?") }).unwrap();
+            if let Ok(true) = serde_wasm_bindgen::from_value(invoke("ask", args).await) {
+                scripts.update(|scripts| { scripts.remove(&name); });
+                let store = store_load("scripts.json").await;
+                store_delete(store, &name).await.expect("failed to delete script");
+            }
+        });
+    };
+
+    let modbus_request = move || -> Result<ModbusRequest, String> {
+        let unit = modbus_unit.get();
+        let unit_id = match unit.trim() {
+            "" => None,
+            unit => Some(unit.parse().map_err(|_| format!("'{unit}' isn't a valid unit id"))?),
+        };
+        let operation = modbus_operation(&modbus_function.get(), &modbus_address.get(), &modbus_data.get())?;
+        Ok(ModbusRequest { unit_id, operation })
+    };
+
+    let run_modbus = move |request: ModbusRequest| {
+        spawn_local(async move {
+            // The decoded result (or exception) also shows up in the transcript
+            let result = client::modbus(&request).await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.error.map_or(Ok(()), Err));
+            if let Err(err) = result {
+                window().alert_with_message(&err).unwrap();
+            }
+        });
+    };
+
+    let send_modbus = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        match modbus_request() {
+            Ok(request) => run_modbus(request),
+            Err(err) => window().alert_with_message(&err).unwrap(),
+        }
+    };
+
+    let update_mqtt_options = move |update: &dyn Fn(&mut MqttOptions)| {
+        mqtt_options.update(|options| update(options));
+        spawn_local(async move {
+            let store = store_load("store.json").await;
+            store_set(store, "mqtt_options", mqtt_options.get_untracked()).await.expect("failed to save");
+        });
+    };
+    // Empty inputs mean "use the default"
+    let non_empty = |value: String| Some(value).filter(|v| !v.trim().is_empty());
+
+    let mqtt_publish_request = move || -> Result<MqttPublishRequest, String> {
+        if mqtt_topic.get().is_empty() {
+            return Err("Topic is empty!".into());
+        }
+        Ok(MqttPublishRequest {
+            topic: mqtt_topic.get(),
+            payload: mqtt_payload.get(),
+            qos: Some(mqtt_qos.get()),
+            retain: Some(mqtt_retain.get()),
+        })
+    };
+
+    let run_publish = move |request: MqttPublishRequest| {
+        spawn_local(async move {
+            let result = client::mqtt_publish(&request).await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.error.map_or(Ok(()), Err));
+            if let Err(err) = result {
+                window().alert_with_message(&err).unwrap();
+            }
+        });
+    };
+
+    let publish = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        match mqtt_publish_request() {
+            Ok(request) => run_publish(request),
+            Err(err) => window().alert_with_message(&err).unwrap(),
+        }
+    };
+
+    let subscribe = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        let request = MqttSubscribeRequest { topic: mqtt_filter.get(), qos: Some(mqtt_qos.get()) };
+        if request.topic.is_empty() {
+            window().alert_with_message("Topic filter is empty!").unwrap();
+            return;
+        }
+        spawn_local(async move {
+            match client::mqtt_subscribe(&request).await {
+                Ok(MqttSubscribeResponse { granted_qos: Some(granted), .. }) => {
+                    mqtt_subscriptions.update(|subscriptions| { subscriptions.insert(request.topic, granted); });
+                }
+                Ok(MqttSubscribeResponse { error, .. }) => {
+                    window().alert_with_message(&error.unwrap_or_default()).unwrap();
+                }
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
+        });
+    };
+
+    let unsubscribe = move |topic: String| {
+        spawn_local(async move {
+            let result = client::mqtt_unsubscribe(&MqttUnsubscribeRequest { topic: topic.clone() }).await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.error.map_or(Ok(()), Err));
+            match result {
+                Ok(()) => mqtt_subscriptions.update(|subscriptions| { subscriptions.remove(&topic); }),
+                Err(err) => window().alert_with_message(&err).unwrap(),
+            }
+        });
+    };
+
+    let http_request = move || -> Result<HttpRequest, String> {
+        let headers = http_headers.get()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match line.split_once(':') {
+                Some((name, value)) => Ok(HttpHeader { name: name.trim().to_string(), value: value.trim().to_string() }),
+                None => Err(format!("'{line}' isn't a header; use 'Name: value'")),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(HttpRequest {
+            method: http_method.get(),
+            path: http_path.get(),
+            headers,
+            body: non_empty(http_body.get()),
+        })
+    };
+
+    let run_http = move |request: HttpRequest| {
+        spawn_local(async move {
+            match client::http(&request).await {
+                Ok(HttpResponse { error: Some(err), .. }) => window().alert_with_message(&err).unwrap(),
+                Ok(response) => http_response.set(Some(response)),
+                Err(err) => window().alert_with_message(&err.to_string()).unwrap(),
+            }
+        });
+    };
+
+    let send_http = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        match http_request() {
+            Ok(request) => run_http(request),
+            Err(err) => window().alert_with_message(&err).unwrap(),
+        }
+    };
+
+    let osc_request = move || -> Result<OscRequest, String> {
+        if osc_address.get().is_empty() {
+            return Err("Address is empty!".into());
+        }
+        let arguments = osc_arguments.get().iter()
+            .map(|(kind, value)| osc_argument(kind, value))
+            .collect::<Result<Vec<_>, _>>()?;
+        let delay = osc_delay.get();
+        let delay_ms = match delay.trim() {
+            "" => None,
+            delay => Some(delay.parse().map_err(|_| format!("'{delay}' isn't a valid delay"))?),
+        };
+        Ok(OscRequest {
+            messages: vec![OscMessage { address: osc_address.get(), arguments }],
+            bundle: Some(osc_bundle.get()),
+            delay_ms,
+        })
+    };
+
+    let run_osc = move |request: OscRequest| {
+        spawn_local(async move {
+            let result = client::osc(&request).await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result.error.map_or(Ok(()), Err));
+            if let Err(err) = result {
+                window().alert_with_message(&err).unwrap();
+            }
+        });
+    };
+
+    let send_osc = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        match osc_request() {
+            Ok(request) => run_osc(request),
+            Err(err) => window().alert_with_message(&err).unwrap(),
+        }
+    };
+
+    let send_pipeline = move |_| {
+        let commands = pipeline.get().iter()
+            .map(|name| match saved.get().get(name).map(|record| &record.command) {
+                Some(SavedCommand::Message(message) | SavedCommand::Wrapped { message, .. }) => Ok(message.clone()),
+                Some(_) => Err(format!("'{name}' isn't a Redis command")),
+                None => Err(format!("No command with name '{name}' found")),
+            })
+            .collect::<Result<Vec<_>, String>>();
+        match commands {
+            Ok(commands) if commands.is_empty() => window().alert_with_message("Pipeline is empty!").unwrap(),
+            Ok(commands) => {
+                if let Some(commands) = commands.into_iter().map(fill_template).collect::<Option<Vec<_>>>() {
+                    run_redis(commands);
+                }
+            }
+            Err(err) => window().alert_with_message(&err).unwrap(),
+        }
+    };
+
+    let send_message = move |name: String, message: String, preset: Option<Preset>, record: &CommandRecord| {
+        let affixes = affixes(preset.as_ref(), record.terminator.as_deref());
+        if protocol.get() == Protocol::Scpi {
+            let Some(message) = encode_message(message, record.encoding).and_then(message_text) else {
+                return;
+            };
+            // The plugin ends SCPI commands itself, so only the front is used
+            run_scpi(affixes.0 + &message, Some(name));
+            return;
+        }
+        let checksum = record.checksum.clone().unwrap_or_else(|| checksum.get());
+        transmit_message(message, checksum, affixes, record.encoding);
+    };
+
+    let send_command = move |name: String, record: CommandRecord| match record.command.clone() {
+        SavedCommand::Message(message) => send_message(name, message, None, &record),
+        SavedCommand::Wrapped { message, preset } => match presets.get().get(&preset).cloned() {
+            Some(preset) => send_message(name, message, Some(preset), &record),
+            None => window().alert_with_message(&format!("No preset with name '{preset}' found")).unwrap(),
+        },
+        SavedCommand::Modbus(request) => run_modbus(request),
+        SavedCommand::Publish(request) => run_publish(request),
+        SavedCommand::Http(request) => run_http(request),
+        SavedCommand::Osc(request) => run_osc(request),
+    };
+
+    let save_command = create_action(move |(name, message): &(String, CommandRecord)| {
         let name = name.clone();
         let message = message.clone();
         saved.update(|saved| { saved.insert(name.clone(), message.clone()); });
@@ -227,11 +1273,23 @@ pub fn App() -> impl IntoView {
             window().alert_with_message(&format!("Command with name '{name}' already exists")).unwrap();
             return;
         }
-        if message.get().is_empty() {
-            window().alert_with_message(&format!("Current message is empty!")).unwrap();
-            return;
-        }
-        save_command.dispatch((name, message.get()));
+        let command = match protocol.get() {
+            Protocol::Modbus => modbus_request().map(SavedCommand::Modbus),
+            Protocol::Mqtt => mqtt_publish_request().map(SavedCommand::Publish),
+            Protocol::Http => http_request().map(SavedCommand::Http),
+            Protocol::Osc => osc_request().map(SavedCommand::Osc),
+            Protocol::Gcode if !gcode_program.get().trim().is_empty() => Ok(SavedCommand::Message(gcode_program.get())),
+            _ if message.get().is_empty() => Err(format!("Current message is empty!")),
+            _ => Ok(SavedCommand::Message(message.get())),
+        };
+        let command = match command {
+            Ok(command) => command,
+            Err(err) => {
+                window().alert_with_message(&err).unwrap();
+                return;
+            }
+        };
+        save_command.dispatch((name, CommandRecord::new(command, js_sys::Date::now() as u64)));
     };
 
     let del = create_action(move |name: &String| {
@@ -257,19 +1315,43 @@ pub fn App() -> impl IntoView {
     };
     let send = move |ev| {
         let name = event_target_value(&ev);
-        if let Some(message) = saved.get().get(&name).cloned() {
-            transmit_message(message);
-        } else {
+        let Some(record) = saved.get().get(&name).cloned() else {
             window().alert_with_message(&format!("No command with name '{name}' found")).unwrap();
-        }
+            return;
+        };
+        spawn_local(async move {
+            if record.confirm {
+                let args = serde_wasm_bindgen::to_value(&ConfirmArgs { msg: &format!("Send '{name}'?") }).unwrap();
+                if !matches!(serde_wasm_bindgen::from_value(invoke("ask", args).await), Ok(true)) {
+                    return;
+                }
+            }
+            // Switch over to the server the command is bound to, unless that's where the fields already point
+            if let Some(target) = &record.target {
+                let same_transport = target.transport.as_ref().map_or(true, |t| *t == transport.get_untracked());
+                if address.get_untracked() != target.address || port.get_untracked() != target.port.to_string() || !same_transport {
+                    address.set(target.address.clone());
+                    port.set(target.port.to_string());
+                    if let Some(target_transport) = &target.transport {
+                        transport.set(target_transport.clone());
+                    }
+                    if let Err(err) = open_connection().await {
+                        window().alert_with_message(&err).unwrap();
+                        return;
+                    }
+                }
+            }
+            send_command(name, record);
+        });
     };
     let copy = move |ev| {
         let name = event_target_value(&ev);
-        if let Some(message) = saved.get().get(&name).cloned() {
-            spawn_local(async move {
-                writeText(message).await;
-            });
-        }
+        let Some(message) = saved.get().get(&name).map(|record| record.command.to_text()) else {
+            return;
+        };
+        spawn_local(async move {
+            writeText(message).await;
+        });
     };
 
     spawn_local(async move {
@@ -286,14 +1368,62 @@ pub fn App() -> impl IntoView {
         // Load saved commands
         let store = store_load("commands.json").await;
         let entries = invoke("plugin:store|entries", serde_wasm_bindgen::to_value(&EntriesArgs { rid: store }).unwrap()).await;
-        let entries = serde_wasm_bindgen::from_value::<Vec<(String, String)>>(entries).unwrap();
+        let entries = serde_wasm_bindgen::from_value::<Vec<(String, StoredCommand)>>(entries).unwrap();
         for (name, val) in entries.iter() {
-            logging::log!("Name: {name}, val: {val}");
+            logging::log!("Name: {name}, val: {val:?}");
+            let record = CommandRecord::from(val.clone());
+            // Older versions saved just the command, so save it again as a record
+            if let StoredCommand::Plain(_) = val {
+                store_set(store, name, &record).await.expect("failed to migrate command");
+            }
             saved.update(|saved| {
-                saved.insert(name.clone(), val.clone());
+                saved.insert(name.clone(), record);
             });
         }
         logging::log!("Entries: {entries:?}");
+        // Older versions kept commands' own checksums in checksums.json, so move them onto the records
+        let checksums = store_load("checksums.json").await;
+        let entries = invoke("plugin:store|entries", serde_wasm_bindgen::to_value(&EntriesArgs { rid: checksums }).unwrap()).await;
+        if let Ok(entries) = serde_wasm_bindgen::from_value::<Vec<(String, Checksum)>>(entries) {
+            for (name, own_checksum) in entries {
+                if let Some(record) = saved.get_untracked().get(&name).cloned() {
+                    let record = CommandRecord { checksum: Some(own_checksum), ..record };
+                    store_set(store, &name, &record).await.expect("failed to migrate checksum");
+                    saved.update(|saved| { saved.insert(name.clone(), record); });
+                }
+                store_delete(checksums, &name).await.expect("failed to delete checksum");
+            }
+        }
+
+        let store = store_load("sequences.json").await;
+        let entries = invoke("plugin:store|entries", serde_wasm_bindgen::to_value(&EntriesArgs { rid: store }).unwrap()).await;
+        if let Ok(entries) = serde_wasm_bindgen::from_value::<Vec<(String, Vec<SequenceItem>)>>(entries) {
+            sequences.set(entries.into_iter().collect());
+        }
+
+        let store = store_load("expectations.json").await;
+        let entries = invoke("plugin:store|entries", serde_wasm_bindgen::to_value(&EntriesArgs { rid: store }).unwrap()).await;
+        if let Ok(entries) = serde_wasm_bindgen::from_value::<Vec<(String, Expectation)>>(entries) {
+            expectations.set(entries.into_iter().collect());
+        }
+
+        let store = store_load("scripts.json").await;
+        let entries = invoke("plugin:store|entries", serde_wasm_bindgen::to_value(&EntriesArgs { rid: store }).unwrap()).await;
+        if let Ok(entries) = serde_wasm_bindgen::from_value::<Vec<(String, String)>>(entries) {
+            scripts.set(entries.into_iter().collect());
+        }
+
+        let store = store_load("variables.json").await;
+        let entries = invoke("plugin:store|entries", serde_wasm_bindgen::to_value(&EntriesArgs { rid: store }).unwrap()).await;
+        if let Ok(entries) = serde_wasm_bindgen::from_value::<Vec<(String, serde_json::Value)>>(entries) {
+            variables.set(entries.into_iter().collect());
+        }
+
+        let store = store_load("presets.json").await;
+        let entries = invoke("plugin:store|entries", serde_wasm_bindgen::to_value(&EntriesArgs { rid: store }).unwrap()).await;
+        if let Ok(entries) = serde_wasm_bindgen::from_value::<Vec<(String, Preset)>>(entries) {
+            presets.set(entries.into_iter().collect());
+        }
         
         let store = store_load("store.json").await;
         let last_append = store_get(store, "last_append")
@@ -306,9 +1436,155 @@ pub fn App() -> impl IntoView {
         let last_prepend_enabled = store_get(store, "last_prepend_enabled")
             .await.into_iter().next().flatten().is_some_and(|enabled| enabled);
         prepend_enabled.set(last_prepend_enabled);
+        if let Some(last_checksum) = store_get::<Checksum>(store, "checksum").await.ok().flatten() {
+            checksum.set(last_checksum);
+        }
+        let last_counter = store_get(store, "template_counter")
+            .await.into_iter().next().flatten().unwrap_or_default();
+        template_counter.set(last_counter);
+        let last_scpi_check_errors = store_get(store, "scpi_check_errors")
+            .await.into_iter().next().flatten().is_some_and(|enabled| enabled);
+        scpi_check_errors.set(last_scpi_check_errors);
+        let last_disconnect_mode = store_get(store, "last_disconnect_mode")
+            .await.into_iter().next().flatten().unwrap_or_default();
+        disconnect_mode.set(last_disconnect_mode);
+        let last_transport = store_get(store, "last_transport")
+            .await.into_iter().next().flatten().unwrap_or("tcp".into());
+        transport.set(last_transport);
+        let last_protocol = store_get(store, "last_protocol")
+            .await.into_iter().next().flatten().unwrap_or_default();
+        protocol.set(last_protocol);
+        if let Some(types) = store_get::<String>(store, "discovery_service_types").await.ok().flatten() {
+            service_types.set(types);
+        }
+        framing.set(store_get(store, "last_framing").await.ok().flatten());
+        if let Some(options) = store_get::<MqttOptions>(store, "mqtt_options").await.ok().flatten() {
+            mqtt_options.set(options);
+        }
+        if let Some(options) = store_get::<RedisOptions>(store, "redis_options").await.ok().flatten() {
+            redis_options.set(options);
+        }
+
+        // A stream keeps going in the plugin if the window is reloaded
+        if let Ok(status) = client::gcode_status().await {
+            if matches!(status.state, GcodeState::Running | GcodeState::Paused) {
+                poll_gcode();
+            }
+            gcode_status.set(status);
+        }
+        if let Ok(status) = client::sequence_status().await {
+            if matches!(status.state, SequenceState::Running | SequenceState::Paused) {
+                poll_sequence();
+            }
+            sequence_status.set(status);
+        }
+        // Schedules run in the plugin, so they outlive the window too
+        poll_schedules();
+        poll_script();
+
+        // Pick up anything received before the window loaded, then follow along
+        if let Ok(existing) = client::transcript(&TranscriptRequest::default()).await {
+            transcript.set(existing.entries);
+        }
+        client::on_transcript(move |entry| {
+            transcript.update(|transcript| {
+                if transcript.last().map_or(true, |last| last.id < entry.id) {
+                    transcript.push(entry);
+                }
+            });
+        }).await;
     });
 
+    let save_variable = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        let name = variable_name.get().trim().to_string();
+        if let Err(err) = template::placeholders(&format!("{{{{{name}}}}}")) {
+            window().alert_with_message(&err).unwrap();
+            return;
+        }
+        let value = serde_json::Value::String(variable_value.get());
+        variables.update(|variables| { variables.insert(name.clone(), value.clone()); });
+        variable_name.set(String::new());
+        variable_value.set(String::new());
+        spawn_local(async move {
+            let store = store_load("variables.json").await;
+            store_set(store, &name, &value).await.expect("failed to save variable");
+        });
+    };
+
+    let delete_variable = move |name: String| {
+        variables.update(|variables| { variables.remove(&name); });
+        spawn_local(async move {
+            let store = store_load("variables.json").await;
+            store_delete(store, &name).await.expect("failed to delete variable");
+        });
+    };
+
+    let save_preset = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        let name = preset_name.get().trim().to_string();
+        if name.is_empty() {
+            window().alert_with_message("Preset name field is empty!").unwrap();
+            return;
+        }
+        let preset = Preset { prefix: preset_prefix.get(), suffix: preset_suffix.get() };
+        if let Err(err) = preset.validate() {
+            window().alert_with_message(&err).unwrap();
+            return;
+        }
+        presets.update(|presets| { presets.insert(name.clone(), preset.clone()); });
+        preset_name.set(String::new());
+        preset_prefix.set(String::new());
+        preset_suffix.set(String::new());
+        spawn_local(async move {
+            let store = store_load("presets.json").await;
+            store_set(store, &name, &preset).await.expect("failed to save preset");
+        });
+    };
+
+    let delete_preset = move |name: String| {
+        let users: Vec<_> = saved.get().into_iter()
+            .filter(|(_, record)| record.command.preset() == Some(name.as_str()))
+            .map(|(command, _)| command)
+            .collect();
+        if !users.is_empty() {
+            window().alert_with_message(&format!("Preset '{name}' is used by {}", users.join(", "))).unwrap();
+            return;
+        }
+        presets.update(|presets| { presets.remove(&name); });
+        spawn_local(async move {
+            let store = store_load("presets.json").await;
+            store_delete(store, &name).await.expect("failed to delete preset");
+        });
+    };
+
+    let clear_transcript = move |_| {
+        transcript.set(Vec::new());
+        spawn_local(async move {
+            if let Err(e) = client::clear_transcript().await {
+                window().alert_with_message(&format!("Failed to clear transcript: {e}")).unwrap();
+            }
+        });
+    };
+
     let edit_popup = create_node_ref::<html::Dialog>();
+    // The edited command's own checksum, if it isn't to use the global one
+    let popup_own_checksum = create_rw_signal(false);
+    let popup_checksum = create_rw_signal(Checksum::default());
+    // Presets only apply to messages; an empty name means the global prepend and append
+    let popup_is_message = create_rw_signal(false);
+    let popup_preset = create_rw_signal(String::new());
+    let popup_description = create_rw_signal(String::new());
+    let popup_encoding = create_rw_signal(Encoding::default());
+    // Empty to use the global append or the preset's suffix
+    let popup_terminator = create_rw_signal(String::new());
+    // Comma-separated
+    let popup_tags = create_rw_signal(String::new());
+    let popup_confirm = create_rw_signal(false);
+    // An empty address leaves the command unbound, and an empty transport keeps the selected one
+    let popup_target_address = create_rw_signal(String::new());
+    let popup_target_port = create_rw_signal(String::new());
+    let popup_target_transport = create_rw_signal(String::new());
     let message_edit_input = create_node_ref::<html::Input>();
     let name_edit_input = create_node_ref::<html::Input>();
 
@@ -316,7 +1592,23 @@ pub fn App() -> impl IntoView {
         let name = event_target_value(&ev);
         if let Some(dialog) = edit_popup.get() {
             dialog.set_attribute("edit-target", &name).expect("failed to set edit-target");
-            message_edit_input.get().unwrap().set_value(&saved.get().get(&name).cloned().unwrap_or_default());
+            let record = saved.get().get(&name).cloned()
+                .unwrap_or_else(|| CommandRecord::new(SavedCommand::Message(String::new()), js_sys::Date::now() as u64));
+            message_edit_input.get().unwrap().set_value(&record.command.to_text());
+            popup_is_message.set(record.command.is_message());
+            popup_preset.set(record.command.preset().unwrap_or_default().to_string());
+            popup_description.set(record.description.clone());
+            popup_encoding.set(record.encoding);
+            popup_terminator.set(record.terminator.clone().unwrap_or_default());
+            popup_tags.set(record.tags.join(", "));
+            popup_confirm.set(record.confirm);
+            let target = record.target.clone().unwrap_or_default();
+            popup_target_address.set(target.address);
+            popup_target_port.set(record.target.as_ref().map(|target| target.port.to_string()).unwrap_or_default());
+            popup_target_transport.set(target.transport.unwrap_or_default());
+            let own = record.checksum.clone();
+            popup_own_checksum.set(own.is_some());
+            popup_checksum.set(own.unwrap_or_else(|| checksum.get()));
             name_edit_input.get().unwrap().set_value(&name);
             dialog.show_modal().unwrap();
         }
@@ -336,72 +1628,302 @@ pub fn App() -> impl IntoView {
             </button>
             <Sidebar open=sidebar_open>
                 <div>
-                    <h2>"Options"</h2>
+                    <h2>"Options"</h2>
+                </div>
+                <div>
+                    <p>"Append to Messages"</p>
+                    <select on:input=update_append>
+                        // Dynamic for responding to initialization
+                        <For
+                            each=move||["None", "LF", "CR", "CRLF"].map(|s| (s, s == append.get()))
+                            key=|(option, selected)| (option.to_string(), *selected)
+                            children=|(option, selected)| {
+                                if selected {
+                                    view! { <option value={option} selected="selected">{option}</option> }
+                                } else {
+                                    view! { <option value={option}>{option}</option> }
+                                }
+                            }
+                        />
+                    </select>
+                </div>
+                <div>
+                    <p>"Checksum"</p>
+                    <ChecksumFields checksum=checksum on_change=move |updated: Checksum| spawn_local(async move {
+                        let store = store_load("store.json").await;
+                        store_set(store, "checksum", &updated).await.expect("failed to save");
+                    })/>
+                </div>
+                <div>
+                    <p>"Prepend to Messages"</p>
+                    <form on:submit=move |e| {
+                        e.prevent_default();
+                        spawn_local(async move {
+                            let store = store_load("store.json").await;
+                            store_set(store, "last_prepend", prepend.get()).await.expect("failed to save");
+                        });
+                    }>
+                        <input
+                            on:input=update_prepend
+                            // Might need to use a different event
+                            on:blur=move |_| {
+                                spawn_local(async move {
+                                    let store = store_load("store.json").await;
+                                    store_set(store, "last_prepend", prepend.get()).await.expect("failed to save");
+                                })
+                            }
+                            value=move|| prepend.get()
+                            disabled=move|| !prepend_enabled.get()
+                        />
+                    </form>
+                    <button
+                        class="toggle"
+                        toggled=move|| prepend_enabled.get()
+                        on:click=move |e| {
+                            e.prevent_default();
+                            prepend_enabled.update(|b| *b = !*b);
+                            spawn_local(async move {
+                                let store = store_load("store.json").await;
+                                store_set(store, "last_prepend_enabled", prepend_enabled.get()).await
+                                    .expect("failed to save");
+                            })
+                        }
+                    >
+                    {move|| match prepend_enabled.get() {
+                        true => "Enabled",
+                        false => "Disabled"
+                    }}
+                    </button>
+                </div>
+                <div>
+                    <p>"Presets"</p>
+                    <For
+                        each=move || presets.get()
+                        key=|(name, preset)| (name.clone(), preset.clone())
+                        children=move |(name, preset)| {
+                            let target = name.clone();
+                            view! {
+                                <div class="row">
+                                    <code>{format!("{name}: {}…{}", preset.prefix, preset.suffix)}</code>
+                                    <button on:click=move |_| delete_preset(target.clone())>"✕"</button>
+                                </div>
+                            }
+                        }
+                    />
+                    <form class="row" on:submit=save_preset>
+                        <input
+                            placeholder="Name"
+                            on:input=move |ev| preset_name.set(event_target_value(&ev))
+                            prop:value=move || preset_name.get()
+                        />
+                        <input
+                            placeholder="Prefix, e.g. \\x02"
+                            on:input=move |ev| preset_prefix.set(event_target_value(&ev))
+                            prop:value=move || preset_prefix.get()
+                        />
+                        <input
+                            placeholder="Suffix, e.g. \\x03"
+                            on:input=move |ev| preset_suffix.set(event_target_value(&ev))
+                            prop:value=move || preset_suffix.get()
+                        />
+                        <button type="submit">"Save"</button>
+                    </form>
+                    <p>"Template Variables"</p>
+                    <For
+                        each=move || variables.get()
+                        key=|(name, value)| (name.clone(), value.to_string())
+                        children=move |(name, value)| {
+                            let target = name.clone();
+                            view! {
+                                <div class="row">
+                                    <code>{format!("{{{{{name}}}}} = {}", variable_text(&value))}</code>
+                                    <button on:click=move |_| delete_variable(target.clone())>"✕"</button>
+                                </div>
+                            }
+                        }
+                    />
+                    <form class="row" on:submit=save_variable>
+                        <input
+                            placeholder="Name"
+                            on:input=move |ev| variable_name.set(event_target_value(&ev))
+                            prop:value=move || variable_name.get()
+                        />
+                        <input
+                            placeholder="Value"
+                            on:input=move |ev| variable_value.set(event_target_value(&ev))
+                            prop:value=move || variable_value.get()
+                        />
+                        <button type="submit">"Set"</button>
+                    </form>
+                    <div class="row">
+                        <p>{move || format!("{{{{counter}}}} is at {}", template_counter.get())}</p>
+                        <button on:click=move |_| {
+                            template_counter.set(0);
+                            spawn_local(async move {
+                                let store = store_load("store.json").await;
+                                store_set(store, "template_counter", 0u64).await.expect("failed to save");
+                            });
+                        }>"Reset"</button>
+                    </div>
                 </div>
                 <div>
-                    <p>"Append to Messages"</p>
-                    <select on:input=update_append>
-                        // Dynamic for responding to initialization
+                    <p>"MQTT Session"</p>
+                    <select on:input=move |ev| {
+                        let version = serde_json::from_value(event_target_value(&ev).into()).ok();
+                        update_mqtt_options(&|options| options.version = version);
+                    }>
                         <For
-                            each=move||["None", "LF", "CR", "CRLF"].map(|s| (s, s == append.get()))
-                            key=|(option, selected)| (option.to_string(), *selected)
-                            children=|(option, selected)| {
+                            each=move||[(MqttVersion::V311, "v311", "MQTT 3.1.1"), (MqttVersion::V5, "v5", "MQTT 5")]
+                                .map(|(option, value, label)| (value, label, option == mqtt_options.get().version.unwrap_or_default()))
+                            key=|(value, _, selected)| (value.to_string(), *selected)
+                            children=|(value, label, selected)| {
                                 if selected {
-                                    view! { <option value={option} selected="selected">{option}</option> }
+                                    view! { <option value={value} selected="selected">{label}</option> }
                                 } else {
-                                    view! { <option value={option}>{option}</option> }
+                                    view! { <option value={value}>{label}</option> }
                                 }
                             }
                         />
                     </select>
+                    <input
+                        placeholder="Client ID (generated if empty)"
+                        on:change=move |ev| {
+                            let client_id = non_empty(event_target_value(&ev));
+                            update_mqtt_options(&|options| options.client_id = client_id.clone());
+                        }
+                        prop:value=move || mqtt_options.get().client_id.unwrap_or_default()
+                    />
+                    <input
+                        placeholder="Username"
+                        on:change=move |ev| {
+                            let username = non_empty(event_target_value(&ev));
+                            update_mqtt_options(&|options| options.username = username.clone());
+                        }
+                        prop:value=move || mqtt_options.get().username.unwrap_or_default()
+                    />
+                    <input
+                        type="password"
+                        placeholder="Password"
+                        on:input=move |ev| mqtt_password.set(event_target_value(&ev))
+                        prop:value=move || mqtt_password.get()
+                    />
+                    <input
+                        type="number"
+                        min="0" max="65535"
+                        placeholder="Keep alive (s), default 60"
+                        on:change=move |ev| {
+                            let keep_alive = event_target_value(&ev).parse().ok();
+                            update_mqtt_options(&|options| options.keep_alive_secs = keep_alive);
+                        }
+                        prop:value=move || mqtt_options.get().keep_alive_secs.map(|secs| secs.to_string()).unwrap_or_default()
+                    />
                 </div>
                 <div>
-                    <p>"Prepend to Messages"</p>
-                    <form on:submit=move |e| {
-                        e.prevent_default();
+                    <p>"Redis Protocol"</p>
+                    <select on:input=move |ev| {
+                        redis_options.update(|options| options.resp_version = event_target_value(&ev).parse().ok());
                         spawn_local(async move {
                             let store = store_load("store.json").await;
-                            store_set(store, "last_prepend", prepend.get()).await.expect("failed to save");
+                            store_set(store, "redis_options", redis_options.get_untracked()).await.expect("failed to save");
                         });
                     }>
-                        <input
-                            on:input=update_prepend
-                            // Might need to use a different event
-                            on:blur=move |_| {
-                                spawn_local(async move {
-                                    let store = store_load("store.json").await;
-                                    store_set(store, "last_prepend", prepend.get()).await.expect("failed to save");
-                                })
+                        <For
+                            each=move||[(2u8, "RESP2"), (3, "RESP3")]
+                                .map(|(version, label)| (version, label, version == redis_options.get().resp_version.unwrap_or(2)))
+                            key=|(version, _, selected)| (*version, *selected)
+                            children=|(version, label, selected)| {
+                                if selected {
+                                    view! { <option value={version} selected="selected">{label}</option> }
+                                } else {
+                                    view! { <option value={version}>{label}</option> }
+                                }
                             }
-                            value=move|| prepend.get()
-                            disabled=move|| !prepend_enabled.get()
                         />
-                    </form>
+                    </select>
+                </div>
+                <div>
+                    <p>"SCPI Error Checking"</p>
                     <button
                         class="toggle"
-                        toggled=move|| prepend_enabled.get()
+                        toggled=move|| scpi_check_errors.get()
                         on:click=move |e| {
                             e.prevent_default();
-                            prepend_enabled.update(|b| *b = !*b);
+                            scpi_check_errors.update(|b| *b = !*b);
                             spawn_local(async move {
                                 let store = store_load("store.json").await;
-                                store_set(store, "last_prepend_enabled", prepend_enabled.get()).await
+                                store_set(store, "scpi_check_errors", scpi_check_errors.get()).await
                                     .expect("failed to save");
                             })
                         }
                     >
-                    {move|| match prepend_enabled.get() {
-                        true => "Enabled",
+                    {move|| match scpi_check_errors.get() {
+                        true => "Query SYST:ERR? after each command",
                         false => "Disabled"
                     }}
                     </button>
                 </div>
+                <div>
+                    <p>"Split Received Data"</p>
+                    <select on:input=move |ev| {
+                        let updated: Option<Framing> = serde_json::from_value(event_target_value(&ev).into()).ok();
+                        framing.set(updated);
+                        spawn_local(async move {
+                            let store = store_load("store.json").await;
+                            store_set(store, "last_framing", updated).await.expect("failed to save");
+                        });
+                    }>
+                        <For
+                            each=move||[
+                                (None, "default", "Default"),
+                                (Some(Framing::Raw), "raw", "As received"),
+                                (Some(Framing::Lf), "lf", "On LF"),
+                                (Some(Framing::Cr), "cr", "On CR"),
+                                (Some(Framing::CrLf), "crLf", "On CRLF"),
+                                (Some(Framing::Nul), "nul", "On NUL"),
+                            ].map(|(option, value, label)| (value, label, option == framing.get()))
+                            key=|(value, _, selected)| (value.to_string(), *selected)
+                            children=|(value, label, selected)| {
+                                if selected {
+                                    view! { <option value={value} selected="selected">{label}</option> }
+                                } else {
+                                    view! { <option value={value}>{label}</option> }
+                                }
+                            }
+                        />
+                    </select>
+                </div>
+                <div>
+                    <p>"Disconnect Mode"</p>
+                    <select on:input=update_disconnect_mode>
+                        <For
+                            each=move||[
+                                (DisconnectMode::Graceful, "graceful", "Graceful (half-close)"),
+                                (DisconnectMode::Full, "full", "Full close"),
+                                (DisconnectMode::Abort, "abort", "Abort (RST)"),
+                            ].map(|(mode, value, label)| (value, label, mode == disconnect_mode.get()))
+                            key=|(value, _, selected)| (value.to_string(), *selected)
+                            children=|(value, label, selected)| {
+                                if selected {
+                                    view! { <option value={value} selected="selected">{label}</option> }
+                                } else {
+                                    view! { <option value={value}>{label}</option> }
+                                }
+                            }
+                        />
+                    </select>
+                </div>
             </Sidebar>
 
             <form on:submit=connect>
                 <div class="row">
                     <button type="submit">"Connect"</button>
                     <button on:click=disconnect>"Disconnect"</button>
+                    <button on:click=diagnose>"Diagnose"</button>
+                    <button on:click=move |e| {
+                        e.prevent_default();
+                        discover_popup.get().unwrap().show_modal().unwrap();
+                        discover.dispatch(());
+                    }>"Find Devices"</button>
                 </div>
                 <div class="row">
                     <input
@@ -421,19 +1943,340 @@ pub fn App() -> impl IntoView {
                         on:input=update_port
                         value={move || port.get()}
                     />
+                    <select on:input=move |ev| transport.set(event_target_value(&ev))>
+                        <For
+                            each=move||[("tcp", "TCP"), ("udp", "UDP"), ("tls", "TLS"), ("tls-insecure", "TLS (no cert check)")]
+                                .map(|(value, label)| (value, label, value == transport.get()))
+                            key=|(value, _, selected)| (value.to_string(), *selected)
+                            children=|(value, label, selected)| {
+                                if selected {
+                                    view! { <option value={value} selected="selected">{label}</option> }
+                                } else {
+                                    view! { <option value={value}>{label}</option> }
+                                }
+                            }
+                        />
+                    </select>
+                    <select on:input=move |ev| {
+                        if let Ok(updated) = serde_json::from_value(event_target_value(&ev).into()) {
+                            protocol.set(updated);
+                        }
+                    }>
+                        <For
+                            each=move||[(Protocol::Raw, "raw", "Raw"), (Protocol::Telnet, "telnet", "Telnet"), (Protocol::Modbus, "modbus", "Modbus TCP"), (Protocol::Scpi, "scpi", "SCPI"), (Protocol::Mqtt, "mqtt", "MQTT"), (Protocol::Http, "http", "HTTP"), (Protocol::Redis, "redis", "Redis"), (Protocol::Gcode, "gcode", "G-code"), (Protocol::Osc, "osc", "OSC")]
+                                .map(|(option, value, label)| (value, label, option == protocol.get()))
+                            key=|(value, _, selected)| (value.to_string(), *selected)
+                            children=|(value, label, selected)| {
+                                if selected {
+                                    view! { <option value={value} selected="selected">{label}</option> }
+                                } else {
+                                    view! { <option value={value}>{label}</option> }
+                                }
+                            }
+                        />
+                    </select>
                 </div>
             </form>
             <div style="display: flex; flex-direction: column;">
-                <form class="row" on:submit=transmit>
-                    <input
-                        class="pair-input"
-                        id="message-input"
-                        placeholder="Message"
-                        style:width="50%"
-                        on:input=update_message
-                    />
-                    <button class="pair-submit" type="submit">"Transmit"</button>
-                </form>
+                {move || if protocol.get() == Protocol::Modbus {
+                    view! {
+                        <form class="row" on:submit=send_modbus>
+                            <select on:input=move |ev| modbus_function.set(event_target_value(&ev))>
+                                <For
+                                    each=move||[
+                                        ("readCoils", "Read Coils"),
+                                        ("readDiscreteInputs", "Read Discrete Inputs"),
+                                        ("readHoldingRegisters", "Read Holding Registers"),
+                                        ("readInputRegisters", "Read Input Registers"),
+                                        ("writeSingleCoil", "Write Single Coil"),
+                                        ("writeSingleRegister", "Write Single Register"),
+                                        ("writeMultipleCoils", "Write Multiple Coils"),
+                                        ("writeMultipleRegisters", "Write Multiple Registers"),
+                                    ].map(|(value, label)| (value, label, value == modbus_function.get()))
+                                    key=|(value, _, selected)| (value.to_string(), *selected)
+                                    children=|(value, label, selected)| {
+                                        if selected {
+                                            view! { <option value={value} selected="selected">{label}</option> }
+                                        } else {
+                                            view! { <option value={value}>{label}</option> }
+                                        }
+                                    }
+                                />
+                            </select>
+                            <input
+                                placeholder="Unit"
+                                type="number"
+                                min="0" max="255"
+                                style:width="4em"
+                                on:input=move |ev| modbus_unit.set(event_target_value(&ev))
+                                prop:value=move || modbus_unit.get()
+                            />
+                            <input
+                                placeholder="Address"
+                                type="number"
+                                min="0" max="65535"
+                                style:width="6em"
+                                on:input=move |ev| modbus_address.set(event_target_value(&ev))
+                                prop:value=move || modbus_address.get()
+                            />
+                            <input
+                                class="pair-input"
+                                placeholder=move || if modbus_function.get().starts_with("read") { "Count" } else { "Values, e.g. 1,0,1" }
+                                on:input=move |ev| modbus_data.set(event_target_value(&ev))
+                                prop:value=move || modbus_data.get()
+                            />
+                            <button class="pair-submit" type="submit">"Send"</button>
+                        </form>
+                    }.into_view()
+                } else if protocol.get() == Protocol::Mqtt {
+                    view! {
+                        <form class="row" on:submit=publish>
+                            <input
+                                placeholder="Topic"
+                                style:width="30%"
+                                on:input=move |ev| mqtt_topic.set(event_target_value(&ev))
+                                prop:value=move || mqtt_topic.get()
+                            />
+                            <input
+                                class="pair-input"
+                                placeholder="Payload"
+                                on:input=move |ev| mqtt_payload.set(event_target_value(&ev))
+                                prop:value=move || mqtt_payload.get()
+                            />
+                            <select on:input=move |ev| mqtt_qos.set(event_target_value(&ev).parse().unwrap_or(0))>
+                                <For
+                                    each=move||[0u8, 1, 2].map(|qos| (qos, qos == mqtt_qos.get()))
+                                    key=|(qos, selected)| (*qos, *selected)
+                                    children=|(qos, selected)| {
+                                        if selected {
+                                            view! { <option value={qos} selected="selected">"QoS "{qos}</option> }
+                                        } else {
+                                            view! { <option value={qos}>"QoS "{qos}</option> }
+                                        }
+                                    }
+                                />
+                            </select>
+                            <button
+                                class="toggle"
+                                toggled=move|| mqtt_retain.get()
+                                on:click=move |e| {
+                                    e.prevent_default();
+                                    mqtt_retain.update(|b| *b = !*b);
+                                }
+                            >"Retain"</button>
+                            <button class="pair-submit" type="submit">"Publish"</button>
+                        </form>
+                        <form class="row" on:submit=subscribe>
+                            <input
+                                class="pair-input"
+                                placeholder="Topic filter, e.g. sensors/#"
+                                on:input=move |ev| mqtt_filter.set(event_target_value(&ev))
+                                prop:value=move || mqtt_filter.get()
+                            />
+                            <button class="pair-submit" type="submit">"Subscribe"</button>
+                        </form>
+                        <div class="row" style="flex-wrap: wrap;">
+                            <For
+                                each=move || mqtt_subscriptions.get()
+                                key=|subscription| subscription.clone()
+                                children=move |(topic, qos)| {
+                                    let target = topic.clone();
+                                    view! {
+                                        <button style="margin: 2px" on:click=move |_| unsubscribe(target.clone())>
+                                            {topic}" (QoS "{qos}") ✕"
+                                        </button>
+                                    }
+                                }
+                            />
+                        </div>
+                    }.into_view()
+                } else if protocol.get() == Protocol::Gcode {
+                    view! {
+                        <form class="row" on:submit=transmit>
+                            <input
+                                class="pair-input"
+                                placeholder="Command, e.g. G28"
+                                on:input=update_message
+                                prop:value=move || message.get()
+                            />
+                            <button class="pair-submit" type="submit">"Transmit"</button>
+                        </form>
+                        <textarea
+                            placeholder="G-code program, saved as a single command"
+                            style="height: 8em; font-family: monospace;"
+                            on:input=move |ev| gcode_program.set(event_target_value(&ev))
+                            prop:value=move || gcode_program.get()
+                        />
+                        <div class="row">
+                            <input type="file" accept=".gcode,.nc,.ngc,.txt" on:change=move |ev| {
+                                let input = JsValue::from(ev.target().unwrap());
+                                spawn_local(async move {
+                                    if let Some(program) = read_picked_file(input).await {
+                                        gcode_program.set(program);
+                                    }
+                                });
+                            }/>
+                            <button on:click=move |_| stream_gcode(gcode_program.get())>"Stream"</button>
+                            <button on:click=move |_| control_gcode(GcodeAction::Pause)>"Pause"</button>
+                            <button on:click=move |_| control_gcode(GcodeAction::Resume)>"Resume"</button>
+                            <button on:click=move |_| control_gcode(GcodeAction::Abort)>"Abort"</button>
+                        </div>
+                        {move || {
+                            let status = gcode_status.get();
+                            (status.state != GcodeState::Idle).then(|| view! {
+                                <div class="row">
+                                    <progress max={status.total} value={status.acknowledged}></progress>
+                                    <p>
+                                        {format!("{:?}: {} of {} lines", status.state, status.acknowledged, status.total)}
+                                        {(status.resends > 0).then(|| format!(", {} resent", status.resends))}
+                                    </p>
+                                </div>
+                            })
+                        }}
+                    }.into_view()
+                } else if protocol.get() == Protocol::Http {
+                    view! {
+                        <form on:submit=send_http>
+                            <div class="row">
+                                <select on:input=move |ev| http_method.set(event_target_value(&ev))>
+                                    <For
+                                        each=move||["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]
+                                            .map(|method| (method, method == http_method.get()))
+                                        key=|(method, selected)| (method.to_string(), *selected)
+                                        children=|(method, selected)| {
+                                            if selected {
+                                                view! { <option value={method} selected="selected">{method}</option> }
+                                            } else {
+                                                view! { <option value={method}>{method}</option> }
+                                            }
+                                        }
+                                    />
+                                </select>
+                                <input
+                                    class="pair-input"
+                                    placeholder="Path, e.g. /status"
+                                    on:input=move |ev| http_path.set(event_target_value(&ev))
+                                    prop:value=move || http_path.get()
+                                />
+                                <button class="pair-submit" type="submit">"Send"</button>
+                            </div>
+                            <div class="row">
+                                <textarea
+                                    placeholder="Headers, one 'Name: value' per line"
+                                    style:width="50%"
+                                    on:input=move |ev| http_headers.set(event_target_value(&ev))
+                                    prop:value=move || http_headers.get()
+                                />
+                                <textarea
+                                    placeholder="Body"
+                                    style:width="50%"
+                                    on:input=move |ev| http_body.set(event_target_value(&ev))
+                                    prop:value=move || http_body.get()
+                                />
+                            </div>
+                        </form>
+                        {move || http_response.get().map(|response| view! {
+                            <div class="light-contrast" style="padding: 6px; border-radius: 10px; margin: 4px; text-align: left;">
+                                <b>
+                                    {response.version.unwrap_or_default()}" "
+                                    {response.status.unwrap_or_default()}" "
+                                    {response.reason.unwrap_or_default()}
+                                </b>
+                                <table style="font-family: monospace;">
+                                    {response.headers.into_iter().map(|header| view! {
+                                        <tr><td>{header.name}":"</td><td>{header.value}</td></tr>
+                                    }).collect_view()}
+                                </table>
+                                <div style="overflow-y: scroll; max-height: 25vh; font-family: monospace; white-space: pre-wrap;">
+                                    {response.body.unwrap_or_default()}
+                                </div>
+                            </div>
+                        })}
+                    }.into_view()
+                } else if protocol.get() == Protocol::Osc {
+                    view! {
+                        <form on:submit=send_osc>
+                            <div class="row">
+                                <input
+                                    class="pair-input"
+                                    placeholder="Address, e.g. /mixer/ch/1/fader"
+                                    on:input=move |ev| osc_address.set(event_target_value(&ev))
+                                    prop:value=move || osc_address.get()
+                                />
+                                <input
+                                    placeholder="Delay (ms)"
+                                    type="number"
+                                    min="0"
+                                    style:width="6em"
+                                    on:input=move |ev| osc_delay.set(event_target_value(&ev))
+                                    prop:value=move || osc_delay.get()
+                                />
+                                <button
+                                    class="toggle"
+                                    toggled=move|| osc_bundle.get()
+                                    on:click=move |e| {
+                                        e.prevent_default();
+                                        osc_bundle.update(|b| *b = !*b);
+                                    }
+                                >"Bundle"</button>
+                                <button class="pair-submit" type="submit">"Send"</button>
+                            </div>
+                            {move || osc_arguments.get().into_iter().enumerate().map(|(i, (kind, value))| view! {
+                                <div class="row">
+                                    <select on:input=move |ev| osc_arguments.update(|arguments| arguments[i].0 = event_target_value(&ev))>
+                                        {[("int", "Int"), ("float", "Float"), ("string", "String"), ("blob", "Blob (hex)"), ("true", "True"), ("false", "False")]
+                                            .map(|(value, label)| if value == kind {
+                                                view! { <option value={value} selected="selected">{label}</option> }
+                                            } else {
+                                                view! { <option value={value}>{label}</option> }
+                                            })
+                                            .collect_view()}
+                                    </select>
+                                    // Only committed on change, so the row isn't rebuilt while typing
+                                    <input
+                                        class="pair-input"
+                                        placeholder="Value"
+                                        disabled={kind == "true" || kind == "false"}
+                                        on:change=move |ev| osc_arguments.update(|arguments| arguments[i].1 = event_target_value(&ev))
+                                        prop:value=value
+                                    />
+                                    <button on:click=move |e| {
+                                        e.prevent_default();
+                                        osc_arguments.update(|arguments| { arguments.remove(i); });
+                                    }>"✕"</button>
+                                </div>
+                            }).collect_view()}
+                            <div class="row">
+                                <button on:click=move |e| {
+                                    e.prevent_default();
+                                    osc_arguments.update(|arguments| arguments.push(("int".into(), String::new())));
+                                }>"+ Argument"</button>
+                            </div>
+                        </form>
+                    }.into_view()
+                } else {
+                    view! {
+                        <form class="row" on:submit=transmit>
+                            <input
+                                class="pair-input"
+                                id="message-input"
+                                placeholder=move || if protocol.get() == Protocol::Redis { "Command, e.g. SET foo \"bar baz\"" } else { "Message" }
+                                style:width="50%"
+                                on:input=update_message
+                                prop:value=move || message.get()
+                            />
+                            <button class="pair-submit" type="submit">"Transmit"</button>
+                        </form>
+                        {move || (!message.get().is_empty()).then(|| {
+                            let preview = message_preview(message.get()).map(|bytes| template::preview(&bytes));
+                            view! {
+                                <p style="font-family: monospace; margin: 0;">
+                                    {preview.unwrap_or_else(|err| format!("⚠ {err}"))}
+                                </p>
+                            }
+                        })}
+                    }.into_view()
+                }}
                 <form class="row" on:submit=save>
                     <input
                         class="pair-input"
@@ -444,22 +2287,397 @@ pub fn App() -> impl IntoView {
                     <button class="pair-submit" type="submit">"Save"</button>
                 </form>
             </div>
-            <div style="overflow-y: scroll; display: flex; flex-direction: column; margin-top: auto;">
+            <div class="light-contrast" style="padding: 6px; border-radius: 10px; margin: 4px; text-align: left;">
+                <div class="row" style="justify-content: space-between;">
+                    <b>"Received"</b>
+                    <button on:click=clear_transcript>"Clear"</button>
+                </div>
+                <div style="overflow-y: scroll; max-height: 25vh; font-family: monospace; white-space: pre-wrap;">
+                    <For
+                        each=move || transcript.get()
+                        key=|entry| entry.id
+                        children=|entry| {
+                            let marker = match entry.direction {
+                                Direction::Sent => "→ ",
+                                Direction::Received => "← ",
+                                Direction::Info => "• ",
+                            };
+                            view! { <div>{marker}{entry.data}</div> }
+                        }
+                    />
+                </div>
+            </div>
+            <div class="light-contrast" style="padding: 6px; border-radius: 10px; margin: 4px; text-align: left;">
+                <b>"Sequences"</b>
+                <textarea
+                    placeholder="One step per line: send <command>, message <text>, delay <ms>, wait [<ms> ms] <regex>, repeat <n> … end"
+                    style="width: 100%; height: 6em; font-family: monospace;"
+                    on:input=move |ev| sequence_text.set(event_target_value(&ev))
+                    prop:value=move || sequence_text.get()
+                />
+                <form class="row" on:submit=save_sequence>
+                    <input
+                        class="pair-input"
+                        placeholder="Sequence Name"
+                        on:input=move |ev| sequence_name.set(event_target_value(&ev))
+                        prop:value=move || sequence_name.get()
+                    />
+                    <button type="button" on:click=run_edited_sequence>"Run"</button>
+                    <button class="pair-submit" type="submit">"Save"</button>
+                </form>
+                {move || {
+                    let status = sequence_status.get();
+                    (status.state != SequenceState::Idle).then(|| view! {
+                        <div class="row">
+                            <progress max={status.total} value={status.completed}></progress>
+                            <p>
+                                {format!("{}{:?}: {} of {} steps", status.name.map(|name| name + " ").unwrap_or_default(), status.state, status.completed, status.total)}
+                                {status.current.map(|current| format!(" ({current})"))}
+                            </p>
+                            <button on:click=move |_| control_sequence(SequenceAction::Pause)>"Pause"</button>
+                            <button on:click=move |_| control_sequence(SequenceAction::Resume)>"Resume"</button>
+                            <button on:click=move |_| control_sequence(SequenceAction::Stop)>"Stop"</button>
+                        </div>
+                    })
+                }}
+                <For
+                    each=move || sequences.get()
+                    key=|sequence| sequence.0.clone() + &SequenceItem::to_text(&sequence.1)
+                    children=move |(name, items)| {
+                        let (run_name, edit_name, delete_name) = (name.clone(), name.clone(), name.clone());
+                        let (run_items, edit_items) = (items.clone(), items.clone());
+                        view! {
+                            <div class="row">
+                                <p style="flex-grow: 1;">{name}" ("{items.len()}" steps)"</p>
+                                <button on:click=move |_| run_sequence(Some(run_name.clone()), run_items.clone()) style="margin: 2px">"Run"</button>
+                                <button on:click=move |_| {
+                                    sequence_name.set(edit_name.clone());
+                                    sequence_text.set(SequenceItem::to_text(&edit_items));
+                                } style="margin: 2px">"Edit"</button>
+                                <button on:click=move |_| delete_sequence(delete_name.clone()) style="margin: 2px">"Delete"</button>
+                            </div>
+                        }
+                    }
+                />
+            </div>
+            <div class="light-contrast" style="padding: 6px; border-radius: 10px; margin: 4px; text-align: left;">
+                <b>"Tests"</b>
                 <For
                     each=move || saved.get()
-                    key=|command| command.0.clone() + &command.1
+                    key=|command| command.0.clone()
+                    children=move |(name, _)| {
+                        let name = store_value(name);
+                        let expectation = move || expectations.with(|e| e.get(&name.get_value()).cloned());
+                        let kind = move || expectation().map(|e| describe_expected_reply(&e.reply).0).unwrap_or_default();
+                        let update = move |build: &dyn Fn(Option<Expectation>) -> Result<Option<Expectation>, String>| {
+                            match build(expectation()) {
+                                Ok(expectation) => set_expectation(name.get_value(), expectation),
+                                Err(err) => window().alert_with_message(&err).unwrap(),
+                            }
+                        };
+                        view! {
+                            <div class="row">
+                                <input
+                                    type="checkbox"
+                                    prop:checked=move || test_commands.with(|selected| selected.contains(&name.get_value()))
+                                    on:change=move |ev| test_commands.update(|selected| {
+                                        if event_target_checked(&ev) {
+                                            selected.insert(name.get_value());
+                                        } else {
+                                            selected.remove(&name.get_value());
+                                        }
+                                    })
+                                />
+                                <p style="flex-grow: 1;">{name.get_value()}</p>
+                                <select on:change=move |ev| {
+                                    let kind = event_target_value(&ev);
+                                    update(&|current| {
+                                        if kind.is_empty() {
+                                            return Ok(None);
+                                        }
+                                        // Keep the value when it still makes sense for the new kind
+                                        let value = current.as_ref().map(|e| describe_expected_reply(&e.reply).1).unwrap_or_default();
+                                        let reply = expected_reply(&kind, &value).or_else(|_| expected_reply(&kind, "0"))?;
+                                        Ok(Some(Expectation { reply, timeout_ms: current.and_then(|e| e.timeout_ms) }))
+                                    })
+                                }>
+                                    <option value="" selected=move || kind().is_empty()>"Don't check"</option>
+                                    {EXPECTATION_KINDS.into_iter().map(|(value, label)| view! {
+                                        <option value=value selected=move || kind() == value>{label}</option>
+                                    }).collect_view()}
+                                </select>
+                                {move || matches!(kind(), "exact" | "regex" | "length").then(|| view! {
+                                    <input
+                                        placeholder="Expected reply"
+                                        prop:value=move || expectation().map(|e| describe_expected_reply(&e.reply).1).unwrap_or_default()
+                                        on:change=move |ev| {
+                                            let value = event_target_value(&ev);
+                                            update(&|current| {
+                                                let Some(current) = current else { return Ok(None) };
+                                                let reply = expected_reply(describe_expected_reply(&current.reply).0, &value)?;
+                                                Ok(Some(Expectation { reply, ..current }))
+                                            })
+                                        }
+                                    />
+                                })}
+                                {move || (!kind().is_empty()).then(|| view! {
+                                    <input
+                                        placeholder="Timeout (ms)"
+                                        type="number"
+                                        min="0"
+                                        style:width="7em"
+                                        prop:value=move || expectation().and_then(|e| e.timeout_ms).map(|ms| ms.to_string()).unwrap_or_default()
+                                        on:change=move |ev| {
+                                            let value = event_target_value(&ev);
+                                            update(&|current| {
+                                                let Some(current) = current else { return Ok(None) };
+                                                let timeout_ms = match value.trim() {
+                                                    "" => None,
+                                                    ms => Some(ms.parse().map_err(|_| format!("'{ms}' isn't a valid timeout"))?),
+                                                };
+                                                Ok(Some(Expectation { timeout_ms, ..current }))
+                                            })
+                                        }
+                                    />
+                                })}
+                            </div>
+                        }
+                    }
+                />
+                <For
+                    each=move || sequences.get()
+                    key=|sequence| sequence.0.clone()
+                    children=move |(name, _)| {
+                        let name = store_value(name);
+                        view! {
+                            <div class="row">
+                                <input
+                                    type="checkbox"
+                                    prop:checked=move || test_sequences.with(|selected| selected.contains(&name.get_value()))
+                                    on:change=move |ev| test_sequences.update(|selected| {
+                                        if event_target_checked(&ev) {
+                                            selected.insert(name.get_value());
+                                        } else {
+                                            selected.remove(&name.get_value());
+                                        }
+                                    })
+                                />
+                                <p style="flex-grow: 1;">{name.get_value()}" (sequence)"</p>
+                            </div>
+                        }
+                    }
+                />
+                <div class="row">
+                    <button on:click=run_tests disabled=move || test_running.get()>
+                        {move || if test_running.get() { "Running…" } else { "Run Tests" }}
+                    </button>
+                    <button on:click=move |_| control_sequence(SequenceAction::Stop) disabled=move || !test_running.get()>"Stop"</button>
+                    <button on:click=export_report disabled=move || test_report.with(Option::is_none)>"Export JUnit XML"</button>
+                </div>
+                {move || test_report.get().map(|report| view! {
+                    <p>
+                        {format!("{} passed, {} failed in {} ms", report.passed, report.failed, report.duration_ms)}
+                        {report.error.map(|error| format!(" ({error})"))}
+                    </p>
+                    {report.results.into_iter().map(|result| {
+                        let details = [
+                            result.expected.map(|expected| format!("Expected: {expected}")),
+                            result.actual.map(|actual| format!("Actual:   {actual}")),
+                            result.diff,
+                        ];
+                        let details = details.into_iter().flatten().collect::<Vec<_>>().join("\n");
+                        view! {
+                            <div>
+                                <p>
+                                    {if result.passed { "✔ " } else { "✘ " }}
+                                    {result.name}
+                                    {format!(" ({} ms)", result.duration_ms)}
+                                    {result.error.map(|err| format!(": {err}"))}
+                                </p>
+                                {(!result.passed && !details.is_empty()).then(|| view! {
+                                    <pre style="margin: 0 0 0 1.5em; white-space: pre-wrap;">{details}</pre>
+                                })}
+                            </div>
+                        }
+                    }).collect_view()}
+                })}
+            </div>
+            <div class="light-contrast" style="padding: 6px; border-radius: 10px; margin: 4px; text-align: left;">
+                <b>"Schedules"</b>
+                <div class="row">
+                    <input
+                        placeholder="Every (ms)"
+                        type="number"
+                        min="1"
+                        style:width="7em"
+                        on:input=move |ev| schedule_interval.set(event_target_value(&ev))
+                        prop:value=move || schedule_interval.get()
+                    />
+                    <input
+                        placeholder="Times"
+                        type="number"
+                        min="1"
+                        style:width="6em"
+                        on:input=move |ev| schedule_count.set(event_target_value(&ev))
+                        prop:value=move || schedule_count.get()
+                    />
+                    <input
+                        placeholder="For (ms)"
+                        type="number"
+                        min="1"
+                        style:width="7em"
+                        on:input=move |ev| schedule_duration.set(event_target_value(&ev))
+                        prop:value=move || schedule_duration.get()
+                    />
+                    {move || (schedules.get().len() > 1).then(|| view! {
+                        <button on:click=move |_| stop_schedule(None)>"Stop All"</button>
+                    })}
+                </div>
+                <For
+                    each=move || schedules.get()
+                    key=|schedule| (schedule.id, schedule.sent)
+                    children=move |schedule| {
+                        let id = schedule.id;
+                        let limits = [
+                            schedule.count.map(|count| format!("{count} times")),
+                            schedule.duration_ms.map(|ms| format!("for {ms} ms")),
+                        ];
+                        let limits = limits.into_iter().flatten().collect::<Vec<_>>().join(", ");
+                        view! {
+                            <div class="row">
+                                <p style="flex-grow: 1;">
+                                    {schedule.name.unwrap_or(schedule.description)}
+                                    {format!(" every {} ms", schedule.interval_ms)}
+                                    {(!limits.is_empty()).then(|| format!(" ({limits})"))}
+                                    {format!(": sent {}", schedule.sent)}
+                                </p>
+                                <button on:click=move |_| stop_schedule(Some(id)) style="margin: 2px">"Stop"</button>
+                            </div>
+                        }
+                    }
+                />
+            </div>
+            <div class="light-contrast" style="padding: 6px; border-radius: 10px; margin: 4px; text-align: left;">
+                <b>"Scripts"</b>
+                <textarea
+                    placeholder="Rhai script: connect(address, port), send(text), query(text), sleep(ms), log(value), get_var(name), set_var(name, value), assert(condition)"
+                    style="width: 100%; height: 8em; font-family: monospace;"
+                    on:input=move |ev| script_source.set(event_target_value(&ev))
+                    prop:value=move || script_source.get()
+                />
+                <form class="row" on:submit=save_script>
+                    <input
+                        class="pair-input"
+                        placeholder="Script Name"
+                        on:input=move |ev| script_name.set(event_target_value(&ev))
+                        prop:value=move || script_name.get()
+                    />
+                    <button type="button" on:click=move |_| run_script(script_source.get())>"Run"</button>
+                    <button type="button" on:click=stop_script disabled=move || !script_running.get()>"Stop"</button>
+                    <button class="pair-submit" type="submit">"Save"</button>
+                </form>
+                {move || (!script_console.get().is_empty()).then(|| view! {
+                    <div style="overflow-y: scroll; max-height: 20vh; font-family: monospace; white-space: pre-wrap;">
+                        {script_console.get().into_iter().map(|line| view! { <div>{line}</div> }).collect_view()}
+                    </div>
+                })}
+                <For
+                    each=move || scripts.get()
+                    key=|script| script.clone()
+                    children=move |(name, source)| {
+                        let (run_source, edit_name, delete_name) = (source.clone(), name.clone(), name.clone());
+                        view! {
+                            <div class="row">
+                                <p style="flex-grow: 1;">{name}</p>
+                                <button on:click=move |_| run_script(run_source.clone()) style="margin: 2px">"Run"</button>
+                                <button on:click=move |_| {
+                                    script_name.set(edit_name.clone());
+                                    script_source.set(source.clone());
+                                } style="margin: 2px">"Edit"</button>
+                                <button on:click=move |_| delete_script(delete_name.clone()) style="margin: 2px">"Delete"</button>
+                            </div>
+                        }
+                    }
+                />
+            </div>
+            <div style="overflow-y: scroll; display: flex; flex-direction: column; margin-top: auto;">
+                <input
+                    placeholder="Filter by name, description or tag"
+                    on:input=move |ev| command_filter.set(event_target_value(&ev))
+                    prop:value=move || command_filter.get()
+                    style="margin: 4px;"
+                />
+                {move || (protocol.get() == Protocol::Redis && !pipeline.get().is_empty()).then(|| view! {
+                    <div class="row light-contrast" style="padding: 6px; border-radius: 10px; margin: 4px;">
+                        <p>"Pipeline: "{pipeline.get().join(", ")}</p>
+                        <button on:click=send_pipeline style="margin: 2px">"Send Pipeline"</button>
+                        <button on:click=move |_| pipeline.set(Vec::new()) style="margin: 2px">"Clear"</button>
+                    </div>
+                })}
+                <For
+                    each=move || saved.get().into_iter()
+                        .filter(|(name, record)| record.matches(name, &command_filter.get()))
+                        .collect::<Vec<_>>()
+                    key=|command| command.0.clone() + &serde_json::to_string(&command.1).unwrap_or_default()
                     children=move |command|  {
                         let name = command.0;
-                        let message = command.1;
+                        let record = command.1;
+                        let message = record.command.describe();
+                        let modified = record.modified_ms.map(|ms| {
+                            String::from(js_sys::Date::new(&JsValue::from_f64(ms as f64)).to_locale_string("default", &JsValue::UNDEFINED))
+                        });
                         view ! {
                             <div class="light-contrast" style="padding: 10px; border-radius: 10px; margin: 4px;">
-                                <p>{&name}</p>
+                                <p>{&name}{record.confirm.then_some(" (asks first)")}</p>
                                 <p>{&message}</p>
+                                {(!record.description.is_empty()).then(|| view! { <p><i>{record.description.clone()}</i></p> })}
+                                {(!record.tags.is_empty()).then(|| view! { <p>"Tags: "{record.tags.join(", ")}</p> })}
+                                {record.target.as_ref().map(|target| view! { <p>"Sent to "{target.describe()}</p> })}
+                                {modified.map(|modified| view! { <p style="font-size: small;">"Modified "{modified}</p> })}
+                                {record.checksum.as_ref().map(|checksum| view! { <p>"Checksum: "{checksum.describe()}</p> })}
+                                {
+                                    let name = name.clone();
+                                    move || scpi_replies.get().get(&name).cloned().map(|reply| view! {
+                                        <p style="font-family: monospace;">"⇐ "{reply}</p>
+                                    })
+                                }
                                 <div class="row">
                                     <button value={&name} on:click=send style="margin: 2px">"Send"</button>
                                     <button value={&name} on:click=copy style="margin: 2px">"Copy"</button>
                                     <button value={&name} on:click=delete style="margin: 2px">"Delete"</button>
                                     <button value={&name} on:click=show_popup style="margin: 2px">"Edit"</button>
+                                    {
+                                        let name = name.clone();
+                                        view! {
+                                            <button
+                                                on:click=move |_| sequence_text.update(|text| {
+                                                    if !text.is_empty() && !text.ends_with('\n') {
+                                                        text.push('\n');
+                                                    }
+                                                    text.push_str(&format!("send {name}\n"));
+                                                })
+                                                style="margin: 2px"
+                                            >"+ Sequence"</button>
+                                        }
+                                    }
+                                    {
+                                        let name = name.clone();
+                                        view! {
+                                            <button on:click=move |_| start_schedule(name.clone()) style="margin: 2px">"Repeat"</button>
+                                        }
+                                    }
+                                    {
+                                        let name = name.clone();
+                                        move || (protocol.get() == Protocol::Redis).then(|| {
+                                            let name = name.clone();
+                                            view! {
+                                                <button
+                                                    on:click=move |_| pipeline.update(|pipeline| pipeline.push(name.clone()))
+                                                    style="margin: 2px"
+                                                >"+ Pipeline"</button>
+                                            }
+                                        })
+                                    }
                                 </div>
                             </div>
                         }
@@ -482,6 +2700,47 @@ pub fn App() -> impl IntoView {
                             "submit" => {
                                 let message = message_edit_input.get().expect("input should exist").value();
                                 let name = name_edit_input.get().expect("input should exist").value();
+                                let original = saved.get().get(&target).cloned()
+                                    .unwrap_or_else(|| CommandRecord::new(SavedCommand::Message(String::new()), js_sys::Date::now() as u64));
+                                let preset = Some(popup_preset.get()).filter(|preset| !preset.is_empty());
+                                let bound_to = match popup_target_address.get().trim() {
+                                    "" => Ok(None),
+                                    address => {
+                                        let port = popup_target_port.get();
+                                        port.trim().parse().map(|port| Some(CommandTarget {
+                                            address: address.to_string(),
+                                            port,
+                                            transport: Some(popup_target_transport.get()).filter(|transport| !transport.is_empty()),
+                                        })).map_err(|_| format!("'{port}' isn't a valid port to send to"))
+                                    }
+                                };
+                                let message = original.command.with_text(message).and_then(|command| {
+                                    let record = CommandRecord {
+                                        command: command.with_preset(preset),
+                                        description: popup_description.get().trim().to_string(),
+                                        encoding: popup_encoding.get(),
+                                        target: bound_to?,
+                                        terminator: Some(popup_terminator.get()).filter(|terminator| !terminator.is_empty()),
+                                        checksum: popup_own_checksum.get().then(|| popup_checksum.get()),
+                                        tags: popup_tags.get().split(',')
+                                            .map(|tag| tag.trim().to_string())
+                                            .filter(|tag| !tag.is_empty())
+                                            .collect(),
+                                        confirm: popup_confirm.get(),
+                                        ..original.clone()
+                                    };
+                                    if let Some(Err(e)) = record.message_bytes() {
+                                        return Err(e);
+                                    }
+                                    Ok(record)
+                                });
+                                let mut message = match message {
+                                    Ok(message) => message,
+                                    Err(e) => {
+                                        window().alert_with_message(&e).unwrap();
+                                        return;
+                                    }
+                                };
                                 
                                 if target != name && saved.get().contains_key(&name) {
                                     window().alert_with_message(
@@ -494,6 +2753,7 @@ pub fn App() -> impl IntoView {
                                 && (Some(&message) == saved.get().get(&target)) {
                                     window().alert_with_message("Message unchanged!").unwrap();
                                 } else {
+                                    message.modified_ms = Some(js_sys::Date::now() as u64);
                                     spawn_local(async move {
                                         let store = store_load("commands.json").await;
                                         if name != target {
@@ -521,11 +2781,218 @@ pub fn App() -> impl IntoView {
                             <label class="light-contrast" for="command-edit">"Message"</label>
                             <input autofocus type="text" id="command-edit" _ref=message_edit_input/>
                         </div>
+                        <div class="row">
+                            <label class="light-contrast" for="checksum-edit">"Own checksum"</label>
+                            <input
+                                type="checkbox"
+                                id="checksum-edit"
+                                prop:checked=move || popup_own_checksum.get()
+                                on:change=move |ev| popup_own_checksum.set(event_target_checked(&ev))
+                            />
+                        </div>
+                        {move || popup_own_checksum.get().then(|| view! {
+                            <div class="row">
+                                <ChecksumFields checksum=popup_checksum on_change=|_: Checksum| ()/>
+                            </div>
+                        })}
+                        <div class="row">
+                            <label class="light-contrast" for="description-edit">"Description"</label>
+                            <input
+                                type="text"
+                                id="description-edit"
+                                on:input=move |ev| popup_description.set(event_target_value(&ev))
+                                prop:value=move || popup_description.get()
+                            />
+                        </div>
+                        <div class="row">
+                            <label class="light-contrast" for="tags-edit">"Tags"</label>
+                            <input
+                                type="text"
+                                id="tags-edit"
+                                placeholder="Comma-separated"
+                                on:input=move |ev| popup_tags.set(event_target_value(&ev))
+                                prop:value=move || popup_tags.get()
+                            />
+                        </div>
+                        <div class="row">
+                            <label class="light-contrast">"Send to"</label>
+                            <input
+                                type="text"
+                                placeholder="Address (connected server if empty)"
+                                on:input=move |ev| popup_target_address.set(event_target_value(&ev))
+                                prop:value=move || popup_target_address.get()
+                            />
+                            <input
+                                type="number"
+                                placeholder="Port"
+                                on:input=move |ev| popup_target_port.set(event_target_value(&ev))
+                                prop:value=move || popup_target_port.get()
+                            />
+                            <select on:input=move |ev| popup_target_transport.set(event_target_value(&ev))>
+                                <For
+                                    each=move || [("", "Selected transport"), ("tcp", "TCP"), ("udp", "UDP"), ("tls", "TLS"), ("tls-insecure", "TLS (no cert check)")]
+                                        .map(|(value, label)| (value, label, value == popup_target_transport.get()))
+                                    key=|(value, _, selected)| (value.to_string(), *selected)
+                                    children=|(value, label, selected)| {
+                                        if selected {
+                                            view! { <option value={value} selected="selected">{label}</option> }
+                                        } else {
+                                            view! { <option value={value}>{label}</option> }
+                                        }
+                                    }
+                                />
+                            </select>
+                        </div>
+                        <div class="row">
+                            <label class="light-contrast" for="confirm-edit">"Ask before sending"</label>
+                            <input
+                                type="checkbox"
+                                id="confirm-edit"
+                                prop:checked=move || popup_confirm.get()
+                                on:change=move |ev| popup_confirm.set(event_target_checked(&ev))
+                            />
+                        </div>
+                        {move || popup_is_message.get().then(|| view! {
+                            <div class="row">
+                                <label class="light-contrast" for="encoding-edit">"Encoding"</label>
+                                <select id="encoding-edit" on:input=move |ev| {
+                                    if let Ok(updated) = serde_json::from_value(event_target_value(&ev).into()) {
+                                        popup_encoding.set(updated);
+                                    }
+                                }>
+                                    <For
+                                        each=move || Encoding::ALL.map(|encoding| (encoding, encoding == popup_encoding.get()))
+                                        key=|(encoding, selected)| (encoding.label(), *selected)
+                                        children=|(encoding, selected)| {
+                                            let value = serde_json::to_value(encoding).unwrap().as_str().unwrap_or_default().to_string();
+                                            if selected {
+                                                view! { <option value={value} selected="selected">{encoding.label()}</option> }
+                                            } else {
+                                                view! { <option value={value}>{encoding.label()}</option> }
+                                            }
+                                        }
+                                    />
+                                </select>
+                            </div>
+                            <div class="row">
+                                <label class="light-contrast" for="terminator-edit">"Terminator"</label>
+                                <select id="terminator-edit" on:input=move |ev| popup_terminator.set(event_target_value(&ev))>
+                                    <For
+                                        each=move || [("", "Global or preset's"), ("None", "None"), ("LF", "LF"), ("CR", "CR"), ("CRLF", "CRLF")]
+                                            .map(|(value, label)| (value, label, value == popup_terminator.get()))
+                                        key=|(value, _, selected)| (value.to_string(), *selected)
+                                        children=|(value, label, selected)| {
+                                            if selected {
+                                                view! { <option value={value} selected="selected">{label}</option> }
+                                            } else {
+                                                view! { <option value={value}>{label}</option> }
+                                            }
+                                        }
+                                    />
+                                </select>
+                            </div>
+                            <div class="row">
+                                <label class="light-contrast" for="preset-edit">"Preset"</label>
+                                <select id="preset-edit" on:input=move |ev| popup_preset.set(event_target_value(&ev))>
+                                    <For
+                                        each=move || std::iter::once((String::new(), "Global prepend/append".to_string()))
+                                            .chain(presets.get().into_keys().map(|name| (name.clone(), name)))
+                                            .map(|(value, label)| (popup_preset.get() == value, value, label))
+                                            .collect::<Vec<_>>()
+                                        key=|(selected, value, _)| (value.clone(), *selected)
+                                        children=|(selected, value, label)| {
+                                            if selected {
+                                                view! { <option value={value} selected="selected">{label}</option> }
+                                            } else {
+                                                view! { <option value={value}>{label}</option> }
+                                            }
+                                        }
+                                    />
+                                </select>
+                            </div>
+                        })}
                         <button value="submit">"Confirm"</button>
                         <button value="cancel">"Cancel"</button>
                     </form>
                 </div>
             </dialog>
+            <dialog class="light-contrast" _ref=discover_popup on:click=move|e| {
+                if event_target::<HtmlDialogElement>(&e) == *discover_popup.get().unwrap().unchecked_ref() {
+                    discover_popup.get().unwrap().close();
+                }
+            }>
+                <div on:click=move|e| e.stop_propagation()>
+                    <h2>"Find Devices"</h2>
+                    <form class="row" on:submit=move |e| {
+                        e.prevent_default();
+                        discover.dispatch(());
+                    }>
+                        <input
+                            class="pair-input"
+                            placeholder="Service types, e.g. _telnet._tcp"
+                            on:input=move |ev| service_types.set(event_target_value(&ev))
+                            value=move || service_types.get()
+                        />
+                        <button class="pair-submit" type="submit" disabled=move || discovering.get()>"Search"</button>
+                    </form>
+                    {move || if discovering.get() {
+                        view! { <p>"Searching..."</p> }.into_view()
+                    } else if discovered.get().is_empty() {
+                        view! { <p>"No devices found"</p> }.into_view()
+                    } else {
+                        discovered.get().into_iter().map(|service| {
+                            let summary = format!(
+                                "{} ({}:{})",
+                                service.hostname,
+                                service.addresses.join(", "),
+                                service.port,
+                            );
+                            let txt = service.txt.iter()
+                                .map(|(key, value)| format!("{key}={value}"))
+                                .collect::<Vec<_>>()
+                                .join(" ");
+                            let picked = service.clone();
+                            view! {
+                                <div class="light-contrast" style="padding: 10px; border-radius: 10px; margin: 4px;">
+                                    <p style="margin: 0;"><b>{service.instance_name}</b>" "<i>{service.service_type}</i></p>
+                                    <p style="margin: 0;">{summary}</p>
+                                    <p style="margin: 0;">{txt}</p>
+                                    <button on:click=move |_| pick_service(picked.clone())>"Use"</button>
+                                </div>
+                            }
+                        }).collect_view()
+                    }}
+                    <button on:click=move|_| discover_popup.get().unwrap().close()>"Close"</button>
+                </div>
+            </dialog>
+            <dialog class="light-contrast" _ref=diagnose_popup on:click=move|e| {
+                if event_target::<HtmlDialogElement>(&e) == *diagnose_popup.get().unwrap().unchecked_ref() {
+                    diagnose_popup.get().unwrap().close();
+                }
+            }>
+                <div on:click=move|e| e.stop_propagation() style="text-align: left;">
+                    <h2>"Connection Diagnostics"</h2>
+                    {move || match diagnostics.get() {
+                        None => view! { <p>"Running checks..."</p> }.into_view(),
+                        Some(steps) => steps.into_iter().map(|step| {
+                            let icon = match step.status {
+                                StepStatus::Passed => "✅",
+                                StepStatus::Warning => "⚠️",
+                                StepStatus::Failed => "❌",
+                                StepStatus::Skipped => "⏭️",
+                            };
+                            view! {
+                                <div style="margin: 4px 0;">
+                                    <p style="margin: 0;"><b>{icon}" "{step.name}</b></p>
+                                    <p style="margin: 0;">{step.detail}</p>
+                                    {step.hint.map(|hint| view! { <p style="margin: 0;"><i>"💡 "{hint}</i></p> })}
+                                </div>
+                            }
+                        }).collect_view(),
+                    }}
+                    <button on:click=move|_| diagnose_popup.get().unwrap().close()>"Close"</button>
+                </div>
+            </dialog>
         </main>
     }
 }
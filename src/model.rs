@@ -1,25 +1,55 @@
 use serde::{Deserialize, Serialize};
+use tcp_commander_models::ExpectedReply;
+pub use tcp_commander_models::library::{
+    CommandRecord, CommandTarget, Encoding, Preset, SavedCommand, SequenceItem, StoredCommand,
+};
 
-#[derive(Serialize, Deserialize)]
-pub struct ConnectArgs<'a> {
-    pub address: &'a str,
-    pub port: i32
+/// How a template variable from `variables.json` reads in a message. Scripts
+/// can save any JSON there, but strings shouldn't come out quoted.
+pub fn variable_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct ConnectResponse {
-    pub success: Option<bool>,
-    pub error: Option<String>
+/// The kinds of reply a saved command can expect, as shown in the test runner.
+pub const EXPECTATION_KINDS: [(&str, &str); 5] = [
+    ("any", "Any reply"),
+    ("exact", "Exactly"),
+    ("regex", "Matching"),
+    ("length", "Bytes"),
+    ("silence", "No reply"),
+];
+
+/// Builds an expected reply from the test runner's kind select and value field.
+pub fn expected_reply(kind: &str, value: &str) -> Result<ExpectedReply, String> {
+    Ok(match kind {
+        "any" => ExpectedReply::Any,
+        "exact" => ExpectedReply::Exact { text: value.to_string() },
+        "regex" => ExpectedReply::Regex { pattern: value.to_string() },
+        "length" => ExpectedReply::Length {
+            bytes: value.trim().parse().map_err(|_| format!("'{value}' isn't a valid length"))?,
+        },
+        "silence" => ExpectedReply::Silence,
+        other => return Err(format!("Unknown expectation '{other}'")),
+    })
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct TransmitArgs<'a> {
-    pub message: &'a str
+/// The kind and value `expected_reply` would build `reply` from.
+pub fn describe_expected_reply(reply: &ExpectedReply) -> (&'static str, String) {
+    match reply {
+        ExpectedReply::Any => ("any", String::new()),
+        ExpectedReply::Exact { text } => ("exact", text.clone()),
+        ExpectedReply::Regex { pattern } => ("regex", pattern.clone()),
+        ExpectedReply::Length { bytes } => ("length", bytes.to_string()),
+        ExpectedReply::Silence => ("silence", String::new()),
+    }
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct TransmitResponse {
-    pub error: Option<String>,
+pub struct SaveReportArgs<'a> {
+    pub contents: &'a str,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -27,6 +57,24 @@ pub struct ConfirmArgs<'a> {
     pub msg: &'a str,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ScriptRunArgs<'a> {
+    pub source: &'a str,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScriptStatusArgs {
+    pub after: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptStatus {
+    pub running: bool,
+    pub lines: Vec<String>,
+    pub error: Option<String>,
+}
+
 // For any single-argument commands (get, delete)
 #[derive(Serialize, Deserialize)]
 pub struct StoreKeyArg<'a> {
@@ -1,6 +1,8 @@
 package com.plugin.tcp
 
 import android.app.Activity
+import android.content.Context
+import android.net.wifi.WifiManager
 import app.tauri.annotation.Command
 import app.tauri.annotation.InvokeArg
 import app.tauri.annotation.TauriPlugin
@@ -27,9 +29,16 @@ class ConnectArgs {
     val port: Int? = null
 }
 
+@InvokeArg
+class DisconnectArgs {
+    val mode: String? = null
+}
+
 @InvokeArg
 class TransmitArgs {
     val message: String? = null
+    // Raw bytes sent after the message, such as a binary terminator
+    val suffix: IntArray? = null
 }
 
 @TauriPlugin
@@ -39,6 +48,7 @@ class TcpClientPlugin(private val activity: Activity): Plugin(activity) {
     private var current_address: String? = null
     private var current_port: Int? = null
     private var connecting: Boolean = false
+    private var multicastLock: WifiManager.MulticastLock? = null
 
     @Command
     fun ping(invoke: Invoke) {
@@ -98,8 +108,46 @@ class TcpClientPlugin(private val activity: Activity): Plugin(activity) {
             return
         }
 
-        active_socket?.close()
+        val args: DisconnectArgs = invoke.parseArgs(DisconnectArgs::class.java)
+        val socket = active_socket
         active_socket = null
+        current_address = null
+        current_port = null
+        if (socket == null) {
+            invoke.resolve()
+            return
+        }
+
+        try {
+            when (args.mode ?: "graceful") {
+                "full" -> socket.close()
+                "abort" -> {
+                    // Zero linger makes close() send RST instead of FIN
+                    socket.setSoLinger(true, 0)
+                    socket.close()
+                }
+                else -> {
+                    socket.shutdownOutput()
+                    // Keep reading until the server closes its side
+                    Thread({
+                        try {
+                            socket.soTimeout = 5000
+                            val input = socket.getInputStream()
+                            val buffer = ByteArray(1024)
+                            while (input.read(buffer) != -1) {}
+                        } catch (e: IOException) {
+                            println(e)
+                        } finally {
+                            socket.close()
+                        }
+                    }).start()
+                }
+            }
+        } catch (e: IOException) {
+            // Socket was already dead; nothing left to close
+            println(e)
+            try { socket.close() } catch (_: IOException) {}
+        }
         invoke.resolve()
     }
 
@@ -126,7 +174,8 @@ class TcpClientPlugin(private val activity: Activity): Plugin(activity) {
         Thread({
             try {
                 println("Transmitting message: " + args.message)
-                val bytes = args.message.toByteArray()
+                val suffix = args.suffix ?: IntArray(0)
+                val bytes = args.message.toByteArray() + ByteArray(suffix.size) { suffix[it].toByte() }
                 val outputStream = active_socket!!.getOutputStream()
                 outputStream.write(bytes)
             } catch (e: IOException) {
@@ -139,6 +188,26 @@ class TcpClientPlugin(private val activity: Activity): Plugin(activity) {
         }).start()
     }
 
+    // mDNS discovery runs on the Rust side, but Android drops multicast packets
+    // unless a multicast lock is held for the duration of the search
+    @Command
+    fun acquireMulticastLock(invoke: Invoke) {
+        if (multicastLock == null) {
+            val wifi = activity.applicationContext.getSystemService(Context.WIFI_SERVICE) as WifiManager
+            multicastLock = wifi.createMulticastLock("tcp-commander-discovery").apply {
+                setReferenceCounted(false)
+            }
+        }
+        multicastLock?.acquire()
+        invoke.resolve()
+    }
+
+    @Command
+    fun releaseMulticastLock(invoke: Invoke) {
+        multicastLock?.release()
+        invoke.resolve()
+    }
+
     @Command
     fun get_status(invoke: Invoke) {
         val msg = when (active_socket) {
@@ -1,4 +1,31 @@
-const COMMANDS: &[&str] = &["ping", "connect", "transmit", "disconnect"];
+const COMMANDS: &[&str] = &[
+  "ping",
+  "connect",
+  "transmit",
+  "disconnect",
+  "diagnose",
+  "discover",
+  "modbus",
+  "scpi",
+  "mqtt_publish",
+  "mqtt_subscribe",
+  "mqtt_unsubscribe",
+  "http",
+  "redis",
+  "osc",
+  "gcode_stream",
+  "gcode_control",
+  "gcode_status",
+  "sequence_run",
+  "sequence_control",
+  "sequence_status",
+  "schedule_start",
+  "schedule_stop",
+  "schedule_list",
+  "test_run",
+  "transcript",
+  "clear_transcript",
+];
 
 fn main() {
   tauri_plugin::Builder::new(COMMANDS)
@@ -1,27 +1,258 @@
-use tauri::{command, Runtime};
+use std::net::IpAddr;
+
+use tauri::{command, ipc::{CommandScope, GlobalScope}, Runtime};
 
 use crate::models::*;
-use crate::Result;
+use crate::scope::{self, ScopeEntry};
+use crate::{Error, Result};
 use crate::TcpClientExt;
 
+/// Rejects destinations the app's capabilities don't permit, trimming the
+/// address the same way it was checked and returning what it was looked up
+/// as. Incomplete destinations are let through as `None` so the command can
+/// report what's missing.
+fn check_scope(
+    command_scope: &CommandScope<ScopeEntry>,
+    global_scope: &GlobalScope<ScopeEntry>,
+    address: &mut Option<String>,
+    port: Option<i32>,
+) -> Result<Option<Vec<IpAddr>>> {
+    scope::check_request(
+        command_scope.allows().iter().chain(global_scope.allows()).map(AsRef::as_ref),
+        command_scope.denies().iter().chain(global_scope.denies()).map(AsRef::as_ref),
+        address,
+        port,
+    )
+    .map_err(Error::DestinationNotPermitted)
+}
+
 #[command]
 pub(crate) async fn connect<R: Runtime>(
     app: tauri::AppHandle<R>, 
+    command_scope: CommandScope<ScopeEntry>,
     address: Option<String>,
-    port: Option<i32>
+    port: Option<i32>,
+    transport: Option<String>,
+    framing: Option<Framing>,
+    protocol: Option<Protocol>,
+    mqtt: Option<MqttOptions>,
+    redis: Option<RedisOptions>,
 ) -> Result<ConnectResponse> {
-    app.tcp_client().connect(ConnectRequest { address, port })
+    // The plugin's global scope is applied by `connect` itself
+    app.tcp_client().connect_within(
+        ConnectRequest { address, port, transport, framing, protocol, mqtt, redis },
+        command_scope.allows(),
+        command_scope.denies(),
+    )
 }
 
 #[command]
-pub(crate) async fn disconnect<R: Runtime>(app: tauri::AppHandle<R>) -> Result<()> {
-  app.tcp_client().disconnect()
+pub(crate) async fn disconnect<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    mode: Option<DisconnectMode>,
+) -> Result<()> {
+  app.tcp_client().disconnect(DisconnectRequest { mode })
 }
 
 #[command]
 pub(crate) async fn transmit<R: Runtime>(
     app: tauri::AppHandle<R>, 
     message: Option<String>,
+    suffix: Option<Vec<u8>>,
 ) -> Result<TransmitResponse> {
-    app.tcp_client().transmit(TransmitRequest { message })
+    app.tcp_client().transmit(TransmitRequest { message, suffix })
+}
+
+#[command]
+pub(crate) async fn diagnose<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    command_scope: CommandScope<ScopeEntry>,
+    global_scope: GlobalScope<ScopeEntry>,
+    mut address: Option<String>,
+    port: Option<i32>,
+) -> Result<DiagnoseResponse> {
+    let addresses = check_scope(&command_scope, &global_scope, &mut address, port)?;
+    // Pinging and connecting block for seconds, which the async runtime's threads mustn't
+    let request = DiagnoseRequest { address, port, addresses };
+    tauri::async_runtime::spawn_blocking(move || app.tcp_client().diagnose(request)).await?
+}
+
+#[command]
+pub(crate) async fn discover<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    service_types: Option<Vec<String>>,
+    timeout_ms: Option<u64>,
+) -> Result<DiscoverResponse> {
+    // Browsing blocks until the timeout runs out
+    let request = DiscoverRequest { service_types, timeout_ms };
+    tauri::async_runtime::spawn_blocking(move || app.tcp_client().discover(request)).await?
+}
+
+#[command]
+pub(crate) async fn modbus<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    unit_id: Option<u8>,
+    operation: ModbusOperation,
+) -> Result<ModbusResponse> {
+    app.tcp_client().modbus(ModbusRequest { unit_id, operation })
+}
+
+#[command]
+pub(crate) async fn scpi<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    command: String,
+    check_errors: Option<bool>,
+) -> Result<ScpiResponse> {
+    app.tcp_client().scpi(ScpiRequest { command, check_errors })
+}
+
+#[command]
+pub(crate) async fn mqtt_publish<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    topic: String,
+    payload: String,
+    qos: Option<u8>,
+    retain: Option<bool>,
+) -> Result<MqttPublishResponse> {
+    app.tcp_client().mqtt_publish(MqttPublishRequest { topic, payload, qos, retain })
+}
+
+#[command]
+pub(crate) async fn mqtt_subscribe<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    topic: String,
+    qos: Option<u8>,
+) -> Result<MqttSubscribeResponse> {
+    app.tcp_client().mqtt_subscribe(MqttSubscribeRequest { topic, qos })
+}
+
+#[command]
+pub(crate) async fn mqtt_unsubscribe<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    topic: String,
+) -> Result<MqttSubscribeResponse> {
+    app.tcp_client().mqtt_unsubscribe(MqttUnsubscribeRequest { topic })
+}
+
+#[command]
+pub(crate) async fn http<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    method: String,
+    path: String,
+    headers: Option<Vec<HttpHeader>>,
+    body: Option<String>,
+) -> Result<HttpResponse> {
+    app.tcp_client().http(HttpRequest { method, path, headers: headers.unwrap_or_default(), body })
+}
+
+#[command]
+pub(crate) async fn redis<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    commands: Vec<String>,
+) -> Result<RedisResponse> {
+    app.tcp_client().redis(RedisRequest { commands })
+}
+
+#[command]
+pub(crate) async fn osc<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    messages: Vec<OscMessage>,
+    bundle: Option<bool>,
+    delay_ms: Option<u64>,
+) -> Result<OscResponse> {
+    app.tcp_client().osc(OscRequest { messages, bundle, delay_ms })
+}
+
+#[command]
+pub(crate) async fn gcode_stream<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    program: Option<String>,
+    path: Option<String>,
+    checksums: Option<bool>,
+) -> Result<GcodeStatus> {
+    app.tcp_client().gcode_stream(GcodeStreamRequest { program, path, checksums })
+}
+
+#[command]
+pub(crate) async fn gcode_control<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    action: GcodeAction,
+) -> Result<GcodeStatus> {
+    app.tcp_client().gcode_control(GcodeControlRequest { action })
+}
+
+#[command]
+pub(crate) async fn gcode_status<R: Runtime>(app: tauri::AppHandle<R>) -> Result<GcodeStatus> {
+    app.tcp_client().gcode_status()
+}
+
+#[command]
+pub(crate) async fn sequence_run<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    name: Option<String>,
+    steps: Vec<SequenceStep>,
+) -> Result<SequenceStatus> {
+    app.tcp_client().sequence_run(SequenceRunRequest { name, steps })
+}
+
+#[command]
+pub(crate) async fn sequence_control<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    action: SequenceAction,
+) -> Result<SequenceStatus> {
+    app.tcp_client().sequence_control(SequenceControlRequest { action })
+}
+
+#[command]
+pub(crate) async fn sequence_status<R: Runtime>(app: tauri::AppHandle<R>) -> Result<SequenceStatus> {
+    app.tcp_client().sequence_status()
+}
+
+#[command]
+pub(crate) async fn schedule_start<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    name: Option<String>,
+    step: SequenceStep,
+    interval_ms: u64,
+    count: Option<u64>,
+    duration_ms: Option<u64>,
+) -> Result<ScheduleStatus> {
+    app.tcp_client().schedule_start(ScheduleRequest { name, step, interval_ms, count, duration_ms })
+}
+
+#[command]
+pub(crate) async fn schedule_stop<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    id: Option<u64>,
+) -> Result<ScheduleListResponse> {
+    app.tcp_client().schedule_stop(ScheduleStopRequest { id })
+}
+
+#[command]
+pub(crate) async fn schedule_list<R: Runtime>(app: tauri::AppHandle<R>) -> Result<ScheduleListResponse> {
+    app.tcp_client().schedule_list()
+}
+
+#[command]
+pub(crate) async fn test_run<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    suite: Option<String>,
+    cases: Vec<TestCase>,
+) -> Result<TestReport> {
+    // Tests block until they're all done
+    let request = TestRunRequest { suite, cases };
+    tauri::async_runtime::spawn_blocking(move || app.tcp_client().test_run(request)).await?
+}
+
+#[command]
+pub(crate) async fn transcript<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    after: Option<u64>,
+) -> Result<TranscriptResponse> {
+    app.tcp_client().transcript(TranscriptRequest { after })
+}
+
+#[command]
+pub(crate) async fn clear_transcript<R: Runtime>(app: tauri::AppHandle<R>) -> Result<()> {
+    app.tcp_client().clear_transcript()
 }
@@ -1,81 +1,170 @@
-use std::{
-    borrow::BorrowMut, io::Write, net::{SocketAddr, TcpStream}, sync::Mutex
-};
+use std::sync::Arc;
 
-use serde::de::DeserializeOwned;
-use tauri::{plugin::PluginApi, AppHandle, Runtime};
+use tauri::{ipc::ScopeValue, plugin::PluginApi, AppHandle, Emitter, Runtime};
 
-use crate::models::*;
+use crate::{
+    config::Config,
+    manager::ConnectionManager,
+    models::*,
+    scope::{self, ScopeEntry},
+    transport::{Transport, Transports},
+    Error,
+};
 
-pub fn init<R: Runtime, C: DeserializeOwned>(
+pub fn init<R: Runtime>(
     app: &AppHandle<R>,
-    _api: PluginApi<R, C>,
+    api: PluginApi<R, Option<Config>>,
 ) -> crate::Result<TcpClient<R>> {
-    Ok(TcpClient(app.clone(), Mutex::new(PluginState::default())))
+    let config = api.config().clone().unwrap_or_default();
+    let manager = ConnectionManager::new(Transports::default(), config);
+    let handle = app.clone();
+    manager.set_listener(move |entry| {
+        if let Err(e) = handle.emit(TRANSCRIPT_EVENT, entry) {
+            log::warn!("Failed to emit transcript entry: {e}");
+        }
+    });
+    let scope = api.scope::<ScopeEntry>()?;
+    Ok(TcpClient(app.clone(), manager, scope))
 }
 
 /// Access to the tcp-client APIs.
-pub struct TcpClient<R: Runtime>(AppHandle<R>, Mutex<PluginState>);
+pub struct TcpClient<R: Runtime>(AppHandle<R>, ConnectionManager, ScopeValue<ScopeEntry>);
 
 impl<R: Runtime> TcpClient<R> {
+    /// Makes an additional transport available to `connect`.
+    pub fn register_transport(&self, transport: impl Transport + 'static) {
+        self.1.register_transport(transport);
+    }
+
+    /// Connects if the plugin's global scope permits the destination.
     pub fn connect(&self, payload: ConnectRequest) -> crate::Result<ConnectResponse> {
-        let mut state = self.1.lock().unwrap();
-        if let Some(socket) = state.active_socket.as_ref() {
-            socket.shutdown(std::net::Shutdown::Write)?;
-            state.active_socket = None;
-        }
+        self.connect_within(payload, &[], &[])
+    }
 
-        let addr = payload.address.ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing address")
-        })?;
-        let port = payload
-            .port
-            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing port"))?;
-
-        let socket = TcpStream::connect(SocketAddr::new(
-            addr.parse().expect("invalid ip address"),
-            port as u16,
-        ));
-
-        match socket {
-            Ok(socket) => {
-                state.active_socket = Some(socket);
-                Ok(ConnectResponse {
-                    success: Some(true),
-                    error: None,
-                })
-            }
-            Err(e) => {
+    /// Connects if both the plugin's global scope and a command's own `allow`
+    /// and `deny` entries permit the destination. A hostname is looked up
+    /// once, and only the addresses that were checked are connected to.
+    pub(crate) fn connect_within(
+        &self,
+        mut payload: ConnectRequest,
+        allow: &[Arc<ScopeEntry>],
+        deny: &[Arc<ScopeEntry>],
+    ) -> crate::Result<ConnectResponse> {
+        let checked = scope::check_request(
+            allow.iter().chain(self.2.allows()).map(AsRef::as_ref),
+            deny.iter().chain(self.2.denies()).map(AsRef::as_ref),
+            &mut payload.address,
+            payload.port,
+        )
+        .map_err(Error::DestinationNotPermitted)?;
+        match checked {
+            Some(addresses) if addresses.is_empty() => {
+                let address = payload.address.unwrap_or_default();
                 Ok(ConnectResponse {
                     success: Some(false),
-                    error: Some(format!("Failed to connect to server: {e}")),
+                    error: Some(format!("Failed to connect to server: couldn't look up '{address}'")),
                 })
             }
+            Some(addresses) => Ok(self.1.connect_to(payload, addresses)?),
+            None => Ok(self.1.connect(payload)?),
         }
     }
 
-    pub fn disconnect(&self) -> crate::Result<()> {
-        let mut  state = self.1.lock().unwrap();
-        if let Some(socket) = &state.active_socket {
-            socket.shutdown(std::net::Shutdown::Write).expect("Failed to shut down socket");
-            state.active_socket = None;
-        }
+    /// Closes the active connection, if any. Calling this while disconnected is a no-op.
+    pub fn disconnect(&self, payload: DisconnectRequest) -> crate::Result<()> {
+        Ok(self.1.disconnect(payload)?)
+    }
 
-        Ok(())
+    pub fn diagnose(&self, payload: DiagnoseRequest) -> crate::Result<DiagnoseResponse> {
+        Ok(crate::diagnostics::diagnose(payload))
+    }
+
+    pub fn discover(&self, payload: DiscoverRequest) -> crate::Result<DiscoverResponse> {
+        Ok(crate::discovery::discover(payload)?)
     }
 
     pub fn transmit(&self, payload: TransmitRequest) -> crate::Result<TransmitResponse> {
-        let mut state = self.1.lock().unwrap();
-        if let Some(ref mut socket) = state.active_socket.borrow_mut() {
-            if let Err(_e) = socket.write_all(payload.message.expect("missing message").as_bytes()) {
-                let _ = socket.shutdown(std::net::Shutdown::Write);
-                state.active_socket = None;
-                Ok(TransmitResponse { error: Some("Error transmitting: disconnected from server".into()) })
-            } else {
-                Ok(TransmitResponse { error: None })
-            }
-        } else {
-            Ok(TransmitResponse { error: Some("Error transmitting: not connected to a server".into()) })
-        }
+        Ok(self.1.transmit(payload)?)
+    }
+
+    pub fn modbus(&self, payload: ModbusRequest) -> crate::Result<ModbusResponse> {
+        Ok(self.1.modbus(payload)?)
+    }
+
+    pub fn scpi(&self, payload: ScpiRequest) -> crate::Result<ScpiResponse> {
+        Ok(self.1.scpi(payload)?)
+    }
+
+    pub fn mqtt_publish(&self, payload: MqttPublishRequest) -> crate::Result<MqttPublishResponse> {
+        Ok(self.1.mqtt_publish(payload)?)
+    }
+
+    pub fn mqtt_subscribe(&self, payload: MqttSubscribeRequest) -> crate::Result<MqttSubscribeResponse> {
+        Ok(self.1.mqtt_subscribe(payload)?)
+    }
+
+    pub fn mqtt_unsubscribe(&self, payload: MqttUnsubscribeRequest) -> crate::Result<MqttSubscribeResponse> {
+        Ok(self.1.mqtt_unsubscribe(payload)?)
+    }
+
+    pub fn http(&self, payload: HttpRequest) -> crate::Result<HttpResponse> {
+        Ok(self.1.http(payload)?)
+    }
+
+    pub fn redis(&self, payload: RedisRequest) -> crate::Result<RedisResponse> {
+        Ok(self.1.redis(payload)?)
+    }
+
+    pub fn osc(&self, payload: OscRequest) -> crate::Result<OscResponse> {
+        Ok(self.1.osc(payload)?)
+    }
+
+    pub fn gcode_stream(&self, payload: GcodeStreamRequest) -> crate::Result<GcodeStatus> {
+        Ok(self.1.gcode_stream(payload)?)
+    }
+
+    pub fn gcode_control(&self, payload: GcodeControlRequest) -> crate::Result<GcodeStatus> {
+        Ok(self.1.gcode_control(payload)?)
+    }
+
+    pub fn gcode_status(&self) -> crate::Result<GcodeStatus> {
+        Ok(self.1.gcode_status())
+    }
+
+    pub fn sequence_run(&self, payload: SequenceRunRequest) -> crate::Result<SequenceStatus> {
+        Ok(self.1.sequence_run(payload)?)
+    }
+
+    pub fn sequence_control(&self, payload: SequenceControlRequest) -> crate::Result<SequenceStatus> {
+        Ok(self.1.sequence_control(payload)?)
+    }
+
+    pub fn sequence_status(&self) -> crate::Result<SequenceStatus> {
+        Ok(self.1.sequence_status())
+    }
+
+    pub fn schedule_start(&self, payload: ScheduleRequest) -> crate::Result<ScheduleStatus> {
+        Ok(self.1.schedule_start(payload)?)
+    }
+
+    pub fn schedule_stop(&self, payload: ScheduleStopRequest) -> crate::Result<ScheduleListResponse> {
+        Ok(self.1.schedule_stop(payload)?)
+    }
+
+    pub fn schedule_list(&self) -> crate::Result<ScheduleListResponse> {
+        Ok(self.1.schedule_list())
+    }
+
+    pub fn test_run(&self, payload: TestRunRequest) -> crate::Result<TestReport> {
+        Ok(self.1.test_run(payload)?)
+    }
+
+    pub fn transcript(&self, payload: TranscriptRequest) -> crate::Result<TranscriptResponse> {
+        Ok(self.1.transcript(payload))
+    }
+
+    pub fn clear_transcript(&self) -> crate::Result<()> {
+        self.1.clear_transcript();
+        Ok(())
     }
 }
@@ -5,7 +5,11 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
   #[error(transparent)]
-  Io(#[from] std::io::Error),
+  Core(#[from] tcp_commander_core::Error),
+  #[error("Destination not permitted: {0}")]
+  DestinationNotPermitted(String),
+  #[error(transparent)]
+  Tauri(#[from] tauri::Error),
   #[cfg(mobile)]
   #[error(transparent)]
   PluginInvoke(#[from] tauri::plugin::mobile::PluginInvokeError),
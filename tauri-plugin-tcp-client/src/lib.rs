@@ -14,6 +14,15 @@ mod commands;
 mod error;
 mod models;
 
+pub mod scope;
+
+// The socket side lives in its own crate; these keep the plugin's paths working
+use tcp_commander_core::{diagnostics, discovery};
+pub use tcp_commander_core::{
+  config, framing, gcode, http, manager, modbus, mqtt, osc, resp, scpi, sequence, telnet, testing, transport,
+};
+
+pub use config::Config;
 pub use error::{Error, Result};
 
 #[cfg(desktop)]
@@ -34,11 +43,33 @@ impl<R: Runtime, T: Manager<R>> crate::TcpClientExt<R> for T {
 
 /// Initializes the plugin.
 pub fn init<R: Runtime>() -> TauriPlugin<R> {
-  Builder::new("tcp-client")
+  Builder::<R, Option<Config>>::new("tcp-client")
     .invoke_handler(tauri::generate_handler![
         commands::connect,
         commands::disconnect,
         commands::transmit,
+        commands::diagnose,
+        commands::discover,
+        commands::modbus,
+        commands::scpi,
+        commands::mqtt_publish,
+        commands::mqtt_subscribe,
+        commands::mqtt_unsubscribe,
+        commands::http,
+        commands::redis,
+        commands::osc,
+        commands::gcode_stream,
+        commands::gcode_control,
+        commands::gcode_status,
+        commands::sequence_run,
+        commands::sequence_control,
+        commands::sequence_status,
+        commands::schedule_start,
+        commands::schedule_stop,
+        commands::schedule_list,
+        commands::test_run,
+        commands::transcript,
+        commands::clear_transcript,
         // commands::get_status,
     ])
     .setup(|app, api| {
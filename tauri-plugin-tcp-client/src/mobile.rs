@@ -1,10 +1,17 @@
+use std::sync::Arc;
+
 use serde::de::DeserializeOwned;
 use tauri::{
+  ipc::ScopeValue,
   plugin::{PluginApi, PluginHandle},
   AppHandle, Runtime,
 };
 
-use crate::models::*;
+use crate::{
+  models::*,
+  scope::{self, ScopeEntry},
+  Error,
+};
 
 #[cfg(target_os = "ios")]
 tauri::ios_plugin_binding!(init_plugin_tcp_client);
@@ -18,14 +25,58 @@ pub fn init<R: Runtime, C: DeserializeOwned>(
   let handle = api.register_android_plugin("com.plugin.tcp", "TcpClientPlugin")?;
   #[cfg(target_os = "ios")]
   let handle = api.register_ios_plugin(init_plugin_tcp_client)?;
-  Ok(TcpClient(handle))
+  let scope = api.scope::<ScopeEntry>()?;
+  Ok(TcpClient(handle, scope))
 }
 
 /// Access to the tcp-client APIs.
-pub struct TcpClient<R: Runtime>(PluginHandle<R>);
+pub struct TcpClient<R: Runtime>(PluginHandle<R>, ScopeValue<ScopeEntry>);
 
 impl<R: Runtime> TcpClient<R> {
+  /// Connects if the plugin's global scope permits the destination.
   pub fn connect(&self, payload: ConnectRequest) -> crate::Result<ConnectResponse> {
+    self.connect_within(payload, &[], &[])
+  }
+
+  /// Connects if both the plugin's global scope and a command's own `allow`
+  /// and `deny` entries permit the destination.
+  pub(crate) fn connect_within(
+    &self,
+    mut payload: ConnectRequest,
+    allow: &[Arc<ScopeEntry>],
+    deny: &[Arc<ScopeEntry>],
+  ) -> crate::Result<ConnectResponse> {
+    let checked = scope::check_request(
+      allow.iter().chain(self.1.allows()).map(AsRef::as_ref),
+      deny.iter().chain(self.1.denies()).map(AsRef::as_ref),
+      &mut payload.address,
+      payload.port,
+    )
+    .map_err(Error::DestinationNotPermitted)?;
+    match checked {
+      Some(addresses) if addresses.is_empty() => {
+        let address = payload.address.unwrap_or_default();
+        return Ok(ConnectResponse {
+          success: Some(false),
+          error: Some(format!("Failed to connect to server: couldn't look up '{address}'")),
+        });
+      }
+      // The native side would look the name up again, so give it the address that was checked
+      Some(addresses) => payload.address = Some(addresses[0].to_string()),
+      None => {}
+    }
+    if let Some(transport) = payload.transport.as_deref().filter(|t| *t != "tcp") {
+      return Ok(ConnectResponse {
+        success: Some(false),
+        error: Some(format!("The '{transport}' transport isn't available on mobile")),
+      });
+    }
+    if let Some(protocol) = payload.protocol.filter(|p| *p != Protocol::Raw) {
+      return Ok(ConnectResponse {
+        success: Some(false),
+        error: Some(format!("{protocol:?} mode isn't available on mobile")),
+      });
+    }
     self
       .0
       .run_mobile_plugin("connect", payload)
@@ -39,10 +90,140 @@ impl<R: Runtime> TcpClient<R> {
       .map_err(Into::into)
   }
 
-  pub fn disconnect(&self) -> crate::Result<()> {
+  // Plain sockets work fine from Rust on mobile, so there's no need to round-trip through the native side
+  pub fn diagnose(&self, payload: DiagnoseRequest) -> crate::Result<DiagnoseResponse> {
+    Ok(crate::diagnostics::diagnose(payload))
+  }
+
+  pub fn discover(&self, payload: DiscoverRequest) -> crate::Result<DiscoverResponse> {
+    // Android filters out multicast traffic unless the app holds a multicast lock
+    #[cfg(target_os = "android")]
+    self.0.run_mobile_plugin::<()>("acquireMulticastLock", ())?;
+    let result = crate::discovery::discover(payload);
+    #[cfg(target_os = "android")]
+    self.0.run_mobile_plugin::<()>("releaseMulticastLock", ())?;
+    Ok(result?)
+  }
+
+  // Connecting in Modbus mode is already refused on mobile
+  pub fn modbus(&self, _payload: ModbusRequest) -> crate::Result<ModbusResponse> {
+    Ok(ModbusResponse {
+      result: None,
+      exception: None,
+      error: Some("Modbus mode isn't available on mobile".into()),
+    })
+  }
+
+  pub fn scpi(&self, _payload: ScpiRequest) -> crate::Result<ScpiResponse> {
+    Ok(ScpiResponse {
+      error: Some("SCPI mode isn't available on mobile".into()),
+      ..Default::default()
+    })
+  }
+
+  pub fn mqtt_publish(&self, _payload: MqttPublishRequest) -> crate::Result<MqttPublishResponse> {
+    Ok(MqttPublishResponse { error: Some("MQTT mode isn't available on mobile".into()) })
+  }
+
+  pub fn mqtt_subscribe(&self, _payload: MqttSubscribeRequest) -> crate::Result<MqttSubscribeResponse> {
+    Ok(MqttSubscribeResponse {
+      granted_qos: None,
+      error: Some("MQTT mode isn't available on mobile".into()),
+    })
+  }
+
+  pub fn mqtt_unsubscribe(&self, payload: MqttUnsubscribeRequest) -> crate::Result<MqttSubscribeResponse> {
+    self.mqtt_subscribe(MqttSubscribeRequest { topic: payload.topic, qos: None })
+  }
+
+  pub fn http(&self, _payload: HttpRequest) -> crate::Result<HttpResponse> {
+    Ok(HttpResponse {
+      error: Some("HTTP mode isn't available on mobile".into()),
+      ..Default::default()
+    })
+  }
+
+  pub fn redis(&self, _payload: RedisRequest) -> crate::Result<RedisResponse> {
+    Ok(RedisResponse {
+      replies: Vec::new(),
+      error: Some("Redis mode isn't available on mobile".into()),
+    })
+  }
+
+  pub fn osc(&self, _payload: OscRequest) -> crate::Result<OscResponse> {
+    Ok(OscResponse {
+      error: Some("OSC mode isn't available on mobile".into()),
+    })
+  }
+
+  pub fn gcode_stream(&self, _payload: GcodeStreamRequest) -> crate::Result<GcodeStatus> {
+    Ok(GcodeStatus {
+      state: GcodeState::Failed,
+      error: Some("G-code streaming isn't available on mobile".into()),
+      ..Default::default()
+    })
+  }
+
+  pub fn gcode_control(&self, _payload: GcodeControlRequest) -> crate::Result<GcodeStatus> {
+    self.gcode_stream(GcodeStreamRequest::default())
+  }
+
+  pub fn gcode_status(&self) -> crate::Result<GcodeStatus> {
+    Ok(GcodeStatus::default())
+  }
+
+  pub fn sequence_run(&self, _payload: SequenceRunRequest) -> crate::Result<SequenceStatus> {
+    Ok(SequenceStatus {
+      state: SequenceState::Failed,
+      error: Some("Sequences aren't available on mobile".into()),
+      ..Default::default()
+    })
+  }
+
+  pub fn sequence_control(&self, _payload: SequenceControlRequest) -> crate::Result<SequenceStatus> {
+    self.sequence_run(SequenceRunRequest { name: None, steps: Vec::new() })
+  }
+
+  pub fn sequence_status(&self) -> crate::Result<SequenceStatus> {
+    Ok(SequenceStatus::default())
+  }
+
+  pub fn schedule_start(&self, _payload: ScheduleRequest) -> crate::Result<ScheduleStatus> {
+    Ok(ScheduleStatus {
+      error: Some("Schedules aren't available on mobile".into()),
+      ..Default::default()
+    })
+  }
+
+  pub fn schedule_stop(&self, _payload: ScheduleStopRequest) -> crate::Result<ScheduleListResponse> {
+    Ok(ScheduleListResponse::default())
+  }
+
+  pub fn schedule_list(&self) -> crate::Result<ScheduleListResponse> {
+    Ok(ScheduleListResponse::default())
+  }
+
+  pub fn test_run(&self, payload: TestRunRequest) -> crate::Result<TestReport> {
+    Ok(TestReport {
+      suite: payload.suite.unwrap_or_default(),
+      error: Some("The test runner isn't available on mobile".into()),
+      ..Default::default()
+    })
+  }
+
+  // The native side doesn't report received data yet, so there's never anything to show
+  pub fn transcript(&self, _payload: TranscriptRequest) -> crate::Result<TranscriptResponse> {
+    Ok(TranscriptResponse { entries: Vec::new() })
+  }
+
+  pub fn clear_transcript(&self) -> crate::Result<()> {
+    Ok(())
+  }
+
+  pub fn disconnect(&self, payload: DisconnectRequest) -> crate::Result<()> {
     self
       .0
-      .run_mobile_plugin("disconnect", ())
+      .run_mobile_plugin("disconnect", payload)
       .map_err(Into::into)
   }
 }
@@ -1,46 +1 @@
-use std::net::TcpStream;
-
-use serde::{Deserialize, Serialize};
-
-#[derive(Default)]
-pub struct PluginState {
-  pub active_socket: Option<TcpStream>
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct PingRequest {
-  pub value: Option<String>,
-}
-
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct PingResponse {
-  pub value: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ConnectRequest {
-  pub address: Option<String>,
-  pub port: Option<i32>
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ConnectResponse {
-  pub success: Option<bool>,
-  pub error: Option<String>
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct TransmitRequest {
-  pub message: Option<String>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct TransmitResponse {
-  pub error: Option<String>,
-}
+pub use tcp_commander_models::*;
@@ -0,0 +1,289 @@
+//! Destination allow/deny lists for the `connect` and `diagnose` commands.
+//!
+//! Entries come from capability files, e.g.
+//!
+//! ```json
+//! {
+//!   "identifier": "tcp-client:allow-connect",
+//!   "allow": [{ "cidr": "192.168.86.0/24", "ports": "5000-5100" }],
+//!   "deny": [{ "host": "*.example.com" }]
+//! }
+//! ```
+//!
+//! Deny entries always win. If no allow entries are configured at all, any
+//! destination that isn't denied is permitted.
+
+use std::{
+    net::{IpAddr, ToSocketAddrs},
+    ops::RangeInclusive,
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+/// An IP network such as `10.0.0.0/8`. A bare address is treated as a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip.to_ipv4_mapped().is_some_and(|ip| self.contains(ip.into())),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = s.split_once('/').map_or((s, None), |(n, p)| (n, Some(p)));
+        let network: IpAddr = network
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid network address in '{s}': {e}"))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("invalid prefix length in '{s}'"))?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+fn parse_ports(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |p: &str| p.trim().parse::<u16>().map_err(|e| format!("invalid port '{p}': {e}"));
+    match s.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if start > end {
+                return Err(format!("port range '{s}' is backwards"));
+            }
+            Ok(start..=end)
+        }
+        None => parse(s).map(|p| p..=p),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawScopeEntry {
+    cidr: Option<String>,
+    host: Option<String>,
+    ports: Option<String>,
+}
+
+/// One allow or deny rule. Every field that is set has to match.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawScopeEntry")]
+pub struct ScopeEntry {
+    /// Network the destination's address has to be in.
+    pub cidr: Option<Cidr>,
+    /// Hostname as typed by the user; a leading `*.` matches any subdomain.
+    pub host: Option<String>,
+    pub ports: Option<RangeInclusive<u16>>,
+}
+
+impl TryFrom<RawScopeEntry> for ScopeEntry {
+    type Error = String;
+
+    fn try_from(raw: RawScopeEntry) -> Result<Self, Self::Error> {
+        Ok(Self {
+            cidr: raw.cidr.as_deref().map(str::parse).transpose()?,
+            host: raw.host.map(|h| h.to_ascii_lowercase()),
+            ports: raw.ports.as_deref().map(parse_ports).transpose()?,
+        })
+    }
+}
+
+/// A destination that is about to be connected to.
+pub struct Destination {
+    pub host: String,
+    pub port: u16,
+    /// Every address `host` resolves to.
+    pub addresses: Vec<IpAddr>,
+}
+
+impl Destination {
+    pub fn resolve(host: &str, port: u16) -> Self {
+        let addresses = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            // Resolution failures are reported by the connection attempt itself
+            Err(_) => (host, port)
+                .to_socket_addrs()
+                .map(|addrs| addrs.map(|a| a.ip()).collect())
+                .unwrap_or_default(),
+        };
+        Self { host: host.to_ascii_lowercase(), port, addresses }
+    }
+}
+
+impl ScopeEntry {
+    fn matches_host(&self, destination: &Destination, all_addresses: bool) -> bool {
+        let host = self.host.as_deref().map(|pattern| match pattern.strip_prefix("*.") {
+            Some(suffix) => destination.host.ends_with(&format!(".{suffix}")),
+            None => destination.host == pattern,
+        });
+        let cidr = self.cidr.map(|cidr| {
+            // An allow rule has to cover every address we might end up connecting to,
+            // while a deny rule applies if it covers any of them
+            let mut addresses = destination.addresses.iter();
+            if all_addresses {
+                !destination.addresses.is_empty() && addresses.all(|ip| cidr.contains(*ip))
+            } else {
+                addresses.any(|ip| cidr.contains(*ip))
+            }
+        });
+        match (host, cidr) {
+            (None, None) => true,
+            (Some(host), None) => host,
+            (None, Some(cidr)) => cidr,
+            (Some(host), Some(cidr)) => host && cidr,
+        }
+    }
+
+    fn matches(&self, destination: &Destination, all_addresses: bool) -> bool {
+        self.ports.as_ref().map_or(true, |ports| ports.contains(&destination.port))
+            && self.matches_host(destination, all_addresses)
+    }
+}
+
+/// Checks `destination` against the configured rules, returning a
+/// human-readable reason if it isn't permitted.
+pub fn check<'a>(
+    allow: impl IntoIterator<Item = &'a ScopeEntry>,
+    deny: impl IntoIterator<Item = &'a ScopeEntry>,
+    destination: &Destination,
+) -> Result<(), String> {
+    let target = format!("{}:{}", destination.host, destination.port);
+    if deny.into_iter().any(|entry| entry.matches(destination, false)) {
+        return Err(format!("{target} is explicitly denied"));
+    }
+    let mut allow = allow.into_iter().peekable();
+    if allow.peek().is_some() && !allow.any(|entry| entry.matches(destination, true)) {
+        return Err(format!("{target} is not in the list of allowed destinations"));
+    }
+    Ok(())
+}
+
+/// Checks where a `connect` or `diagnose` request is headed. The address is
+/// trimmed in place, so what gets checked is what gets used, and what it was
+/// looked up as is returned so exactly those addresses can be connected to.
+/// Incomplete destinations are let through as `None`, so the command can
+/// report what's missing.
+pub fn check_request<'a>(
+    allow: impl IntoIterator<Item = &'a ScopeEntry>,
+    deny: impl IntoIterator<Item = &'a ScopeEntry>,
+    address: &mut Option<String>,
+    port: Option<i32>,
+) -> Result<Option<Vec<IpAddr>>, String> {
+    let (Some(host), Some(Ok(port))) = (address.as_mut(), port.map(u16::try_from)) else {
+        return Ok(None);
+    };
+    *host = host.trim().to_string();
+    let destination = Destination::resolve(host, port);
+    check(allow, deny, &destination)?;
+    Ok(Some(destination.addresses))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(json: &str) -> ScopeEntry {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn destination(host: &str, port: u16, addresses: &[&str]) -> Destination {
+        Destination {
+            host: host.into(),
+            port,
+            addresses: addresses.iter().map(|ip| ip.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn cidr_matching() {
+        let lan: Cidr = "192.168.86.0/24".parse().unwrap();
+        assert!(lan.contains("192.168.86.42".parse().unwrap()));
+        assert!(!lan.contains("192.168.87.1".parse().unwrap()));
+        assert!(lan.contains("::ffff:192.168.86.1".parse().unwrap()));
+        let host: Cidr = "10.0.0.5".parse().unwrap();
+        assert!(host.contains("10.0.0.5".parse().unwrap()));
+        assert!(!host.contains("10.0.0.6".parse().unwrap()));
+        let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains("8.8.8.8".parse().unwrap()));
+        let v6: Cidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains("fd12::1".parse().unwrap()));
+        assert!(!v6.contains("10.0.0.1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn port_ranges() {
+        assert_eq!(parse_ports("5000-5100"), Ok(5000..=5100));
+        assert_eq!(parse_ports(" 502 "), Ok(502..=502));
+        assert!(parse_ports("5100-5000").is_err());
+        assert!(parse_ports("70000").is_err());
+
+        let entry = entry(r#"{ "ports": "5000-5100" }"#);
+        assert!(entry.matches(&destination("device", 5000, &[]), true));
+        assert!(entry.matches(&destination("device", 5100, &[]), true));
+        assert!(!entry.matches(&destination("device", 5101, &[]), true));
+    }
+
+    #[test]
+    fn wildcard_hosts() {
+        let entry = entry(r#"{ "host": "*.Example.com" }"#);
+        assert!(entry.matches(&destination("lab.example.com", 80, &[]), false));
+        assert!(entry.matches(&destination("a.b.example.com", 80, &[]), false));
+        assert!(!entry.matches(&destination("example.com", 80, &[]), false));
+        assert!(!entry.matches(&destination("badexample.com", 80, &[]), false));
+    }
+
+    #[test]
+    fn deny_wins_and_allow_covers_every_address() {
+        let allow = [entry(r#"{ "cidr": "192.168.86.0/24", "ports": "5000-5100" }"#)];
+        let deny = [entry(r#"{ "cidr": "192.168.86.1" }"#)];
+        assert!(check(&allow, &deny, &destination("192.168.86.42", 5000, &["192.168.86.42"])).is_ok());
+        assert_eq!(
+            check(&allow, &deny, &destination("192.168.86.1", 5000, &["192.168.86.1"])),
+            Err("192.168.86.1:5000 is explicitly denied".into())
+        );
+        assert!(check(&allow, &deny, &destination("192.168.86.42", 80, &["192.168.86.42"])).is_err());
+        // A name that also resolves outside the network isn't allowed, and one that didn't resolve can't be checked
+        assert!(check(&allow, &deny, &destination("printer", 5000, &["192.168.86.42", "10.0.0.1"])).is_err());
+        assert!(check(&allow, &deny, &destination("printer", 5000, &[])).is_err());
+        // Without allow entries, anything not denied goes
+        assert!(check(&[], &deny, &destination("10.0.0.1", 22, &["10.0.0.1"])).is_ok());
+    }
+
+    #[test]
+    fn requests_are_checked_as_trimmed() {
+        let allow = [entry(r#"{ "cidr": "127.0.0.1" }"#)];
+        let mut address = Some(" 127.0.0.1 ".to_string());
+        let addresses = check_request(&allow, &[], &mut address, Some(5025)).unwrap();
+        assert_eq!(address.as_deref(), Some("127.0.0.1"));
+        assert_eq!(addresses, Some(vec![IpAddr::from([127, 0, 0, 1])]));
+        assert_eq!(check_request(&allow, &[], &mut address, None), Ok(None));
+        assert!(check_request(&allow, &[], &mut Some("10.0.0.1".into()), Some(5025)).is_err());
+    }
+}
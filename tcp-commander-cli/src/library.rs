@@ -0,0 +1,305 @@
+//! The app's saved files, read from the same place the app keeps them.
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    fs,
+    hash::{BuildHasher, Hasher},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use tcp_commander_models::{
+    checksum::Checksum,
+    library::{CommandRecord, CommandTarget, MessageDefaults, Preset, SequenceItem, StoredCommand},
+    template::{self, Placeholder},
+    Framing, MqttOptions, Protocol, RedisOptions, SequenceStep,
+};
+
+/// The app's identifier from `tauri.conf.json`, which names its data directory.
+const APP_IDENTIFIER: &str = "com.tcp-commander.app";
+
+/// Where the app keeps its stores, as Tauri's `app_data_dir` works it out.
+pub fn default_data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_IDENTIFIER))
+}
+
+/// A store file as key and value pairs. A store the app hasn't written yet is empty.
+fn read_store(path: &Path) -> Result<Map<String, Value>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{} isn't a valid store: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Map::new()),
+        Err(e) => Err(format!("couldn't read {}: {e}", path.display())),
+    }
+}
+
+/// A value from a store, if it's there and of the right type.
+fn get<T: DeserializeOwned>(store: &Map<String, Value>, key: &str) -> Option<T> {
+    store.get(key).cloned().and_then(|value| serde_json::from_value(value).ok())
+}
+
+/// Entries that don't parse are skipped, as the app would fail to show them too.
+fn read_entries<T: DeserializeOwned>(path: &Path) -> Result<BTreeMap<String, T>, String> {
+    Ok(read_store(path)?
+        .into_iter()
+        .filter_map(|(name, value)| serde_json::from_value(value).ok().map(|value| (name, value)))
+        .collect())
+}
+
+/// Random bits without another dependency, from the keys std seeds every
+/// hash map with.
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// What the app last connected with and how it sends messages, from `store.json`.
+#[derive(Debug, Default)]
+pub struct Settings {
+    pub address: Option<String>,
+    pub port: Option<String>,
+    pub transport: Option<String>,
+    pub protocol: Protocol,
+    pub framing: Option<Framing>,
+    /// Only set while the app's prepend toggle is on.
+    pub prepend: String,
+    /// One of the app's "Append" choices, such as `CRLF`.
+    pub append: String,
+    /// Added to messages without a checksum of their own.
+    pub checksum: Checksum,
+    pub scpi_check_errors: bool,
+    pub mqtt: MqttOptions,
+    pub redis: RedisOptions,
+    /// Values for placeholders like `{{distance}}`, from `variables.json`
+    /// and `--var`.
+    pub variables: BTreeMap<String, String>,
+}
+
+impl Settings {
+    /// What a placeholder is filled in with. `{{counter}}` is only kept by
+    /// the app, so it isn't filled in here.
+    fn placeholder_value(&self, placeholder: &Placeholder) -> Result<String, String> {
+        match placeholder {
+            Placeholder::Counter => Err("{{counter}} is only counted by the app".into()),
+            Placeholder::Timestamp => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                Ok(now.as_millis().to_string())
+            }
+            Placeholder::Uuid => {
+                let mut bytes = [0; 16];
+                bytes[..8].copy_from_slice(&random_u64().to_le_bytes());
+                bytes[8..].copy_from_slice(&random_u64().to_le_bytes());
+                Ok(template::uuid_v4(bytes))
+            }
+            Placeholder::Random { min, max } => {
+                let unit = (random_u64() >> 11) as f64 / (1u64 << 53) as f64;
+                Ok(template::random_in(*min, *max, unit).to_string())
+            }
+            Placeholder::Variable(name) => self
+                .variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("{{{{{name}}}}} has no value; pass --var {name}=<value>")),
+        }
+    }
+
+    /// How a plain message is sent, the way the app's `transmit` does it:
+    /// placeholders filled in, then the affixes and checksum added as
+    /// [`MessageDefaults::step`] describes.
+    pub fn message_step(
+        &self,
+        message: &str,
+        preset: Option<&Preset>,
+        terminator: Option<&str>,
+        checksum: Option<&Checksum>,
+    ) -> Result<SequenceStep, String> {
+        let message = template::expand(message, |placeholder| self.placeholder_value(placeholder))?;
+        let defaults = MessageDefaults {
+            protocol: self.protocol,
+            prepend: self.prepend.clone(),
+            append: self.append.clone(),
+            checksum: self.checksum.clone(),
+            scpi_check_errors: self.scpi_check_errors,
+        };
+        Ok(defaults.step(&message, preset, terminator, checksum))
+    }
+}
+
+pub struct Library {
+    pub settings: Settings,
+    pub commands: BTreeMap<String, CommandRecord>,
+    pub presets: BTreeMap<String, Preset>,
+    pub sequences: BTreeMap<String, Vec<SequenceItem>>,
+}
+
+impl Library {
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let store = read_store(&dir.join("store.json"))?;
+        let prepend_enabled: Option<bool> = get(&store, "last_prepend_enabled");
+        let settings = Settings {
+            address: get(&store, "last_address"),
+            port: get(&store, "last_port"),
+            transport: get(&store, "last_transport"),
+            protocol: get(&store, "last_protocol").unwrap_or_default(),
+            framing: get(&store, "last_framing"),
+            prepend: prepend_enabled.unwrap_or(false).then(|| get(&store, "last_prepend")).flatten().unwrap_or_default(),
+            append: get(&store, "last_append").unwrap_or_else(|| "CRLF".into()),
+            checksum: get(&store, "checksum").unwrap_or_default(),
+            scpi_check_errors: get(&store, "scpi_check_errors").unwrap_or(false),
+            mqtt: get(&store, "mqtt_options").unwrap_or_default(),
+            redis: get(&store, "redis_options").unwrap_or_default(),
+            variables: read_entries::<Value>(&dir.join("variables.json"))?
+                .into_iter()
+                .map(|(name, value)| match value {
+                    Value::String(text) => (name, text),
+                    other => (name, other.to_string()),
+                })
+                .collect(),
+        };
+        Ok(Self {
+            settings,
+            commands: read_entries::<StoredCommand>(&dir.join("commands.json"))?
+                .into_iter()
+                .map(|(name, stored)| (name, stored.into()))
+                .collect(),
+            presets: read_entries(&dir.join("presets.json"))?,
+            sequences: read_entries(&dir.join("sequences.json"))?,
+        })
+    }
+
+    /// Looks up saved commands by name, turning each into the step that sends it.
+    pub fn commands(&self, names: &[String]) -> Result<Vec<SequenceStep>, String> {
+        let items: Vec<_> = names.iter().map(|name| SequenceItem::Command { name: name.clone() }).collect();
+        SequenceItem::resolve(&items, &self.commands, &self.presets, &|message, preset, terminator, checksum| {
+            self.settings.message_step(&message, preset, terminator, checksum)
+        })
+    }
+
+    /// Where commands sent on their own have to go, if any of them is bound
+    /// to a server. They can't be bound to different ones.
+    pub fn target(&self, names: &[String]) -> Result<Option<&CommandTarget>, String> {
+        let mut bound = names.iter().filter_map(|name| Some((name, self.commands.get(name)?.target.as_ref()?)));
+        let Some((first_name, first)) = bound.next() else {
+            return Ok(None);
+        };
+        match bound.find(|(_, target)| *target != first) {
+            Some((name, _)) => Err(format!("'{first_name}' and '{name}' are bound to different servers")),
+            None => Ok(Some(first)),
+        }
+    }
+
+    /// Messages with placeholders are refused, as the app refuses them in sequences.
+    pub fn sequence(&self, name: &str) -> Result<Vec<SequenceStep>, String> {
+        let items = self.sequences.get(name).ok_or_else(|| format!("No sequence with name '{name}' found"))?;
+        SequenceItem::resolve(items, &self.commands, &self.presets, &|message, preset, terminator, checksum| {
+            template::no_placeholders(&message)?;
+            self.settings.message_step(&message, preset, terminator, checksum)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use tcp_commander_models::library::SavedCommand;
+
+    use super::*;
+
+    /// A data directory of its own for each test, holding `files`.
+    fn data_dir(files: &[(&str, &str)]) -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!("tcp-commander-cli-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::SeqCst)));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn load(files: &[(&str, &str)]) -> Result<Library, String> {
+        let dir = data_dir(files);
+        let library = Library::load(&dir);
+        fs::remove_dir_all(dir).unwrap();
+        library
+    }
+
+    const COMMANDS: &str = r#"{
+        "Plain": "PING",
+        "Lab": { "command": "*IDN?", "target": { "address": "10.0.0.5", "port": 5025 } },
+        "Also lab": { "command": "*RST", "target": { "address": "10.0.0.5", "port": 5025 } },
+        "Bench": { "command": "*IDN?", "target": { "address": "10.0.0.6", "port": 5025 } },
+        "Broken": { "description": "no command" }
+    }"#;
+
+    #[test]
+    fn saved_files_are_loaded() {
+        let store = r#"{
+            "last_address": "10.0.0.5",
+            "last_port": "5025",
+            "last_protocol": "scpi",
+            "last_prepend": "$",
+            "last_prepend_enabled": false,
+            "last_append": "LF",
+            "scpi_check_errors": true
+        }"#;
+        let library = load(&[("store.json", store), ("commands.json", COMMANDS), ("variables.json", r#"{ "n": 5, "s": "text" }"#)]).unwrap();
+        let settings = &library.settings;
+        assert_eq!(settings.address.as_deref(), Some("10.0.0.5"));
+        assert_eq!(settings.port.as_deref(), Some("5025"));
+        assert_eq!(settings.protocol, Protocol::Scpi);
+        // The prepend only counts while it's switched on
+        assert_eq!(settings.prepend, "");
+        assert_eq!(settings.append, "LF");
+        assert!(settings.scpi_check_errors);
+        assert_eq!(settings.variables, BTreeMap::from([("n".into(), "5".into()), ("s".into(), "text".into())]));
+
+        // Entries that don't parse are left out
+        assert_eq!(library.commands.keys().collect::<Vec<_>>(), ["Also lab", "Bench", "Lab", "Plain"]);
+        assert_eq!(library.commands["Plain"].command, SavedCommand::Message("PING".into()));
+    }
+
+    #[test]
+    fn missing_files_are_empty() {
+        let library = load(&[]).unwrap();
+        assert!(library.commands.is_empty() && library.sequences.is_empty());
+        assert_eq!(library.settings.address, None);
+        assert_eq!(library.settings.append, "CRLF");
+        assert!(load(&[("store.json", "not json")]).is_err_and(|e| e.contains("isn't a valid store")));
+    }
+
+    #[test]
+    fn commands_have_to_share_a_target() {
+        let library = load(&[("commands.json", COMMANDS)]).unwrap();
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        assert_eq!(library.target(&names(&["Plain"])), Ok(None));
+        let lab = library.target(&names(&["Plain", "Lab", "Also lab"])).unwrap().unwrap();
+        assert_eq!(lab.describe(), "10.0.0.5:5025");
+        assert_eq!(library.target(&names(&["Lab", "Bench"])), Err("'Lab' and 'Bench' are bound to different servers".into()));
+    }
+
+    #[test]
+    fn messages_are_filled_in_and_framed() {
+        let settings = Settings {
+            prepend: ">".into(),
+            append: "CR".into(),
+            variables: BTreeMap::from([("axis".into(), "X".into())]),
+            ..Settings::default()
+        };
+        let transmit = |message: &str| SequenceStep::Transmit { message: message.into(), suffix: None };
+        assert_eq!(settings.message_step("G0 {{axis}}", None, None, None), Ok(transmit(">G0 X\r")));
+        assert_eq!(settings.message_step("G0", None, Some("LF"), None), Ok(transmit(">G0\n")));
+        assert_eq!(settings.message_step("{{counter}}", None, None, None), Err("{{counter}} is only counted by the app".into()));
+        assert_eq!(
+            settings.message_step("{{speed}}", None, None, None),
+            Err("{{speed}} has no value; pass --var speed=<value>".into())
+        );
+        let Ok(SequenceStep::Transmit { message, .. }) = settings.message_step("{{random:1-3}}", None, Some("None"), None) else {
+            panic!("random placeholder wasn't filled in");
+        };
+        assert!([">1", ">2", ">3"].contains(&message.as_str()));
+    }
+}
@@ -0,0 +1,341 @@
+//! TCP Commander without the GUI. Reads the saved commands, sequences and
+//! connection settings the app keeps, and drives the same connection code the
+//! plugin uses, without pulling in Tauri.
+
+use std::{
+    env, fs,
+    io::{self, BufRead},
+    path::PathBuf,
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
+
+use tcp_commander_core::{manager::ConnectionManager, transport::Transports, Config};
+use tcp_commander_models::{library::CommandTarget, *};
+
+mod library;
+
+use library::Library;
+
+const USAGE: &str = "\
+Usage: tcp-commander-cli [options] <command>
+
+Commands:
+  list                 Show the saved commands and sequences
+  connect              Connect, then disconnect; fails if the server can't be reached
+  send <name>...       Send saved commands in order, to the server they're bound to if
+                       they are
+  run <sequence>       Run a saved sequence
+  interactive          Send each line typed, printing whatever comes back, until end of input
+
+Options:
+  --data-dir <dir>     Where the app's saved files are [default: the app's data directory]
+  --config <file>      Plugin settings as JSON, as in tauri.conf.json
+  --address <host>     Server to connect to [default: the app's last address]
+  --port <port>        [default: the app's last port]
+  --transport <name>   tcp, udp, tls or tls-insecure [default: the app's last transport]
+  --protocol <name>    raw, telnet, scpi, ... [default: the app's last protocol]
+  --framing <name>     raw, lf, cr, crLf or nul [default: the app's last framing]
+  --prepend <text>     Put in front of every message [default: the app's prepend, if on]
+  --append <ending>    None, LF, CR or CRLF [default: the app's last choice]
+  --var <name>=<value> Fill in {{name}} in messages sent on their own, over the app's
+                       saved value; can be repeated
+  --yes                Send commands that ask for confirmation first
+  --wait <ms>          How long to keep printing replies once everything is sent
+                       [default: the reply timeout]
+
+Received data goes to standard output, and everything else in the transcript
+to standard error.";
+
+enum Command {
+    Help,
+    List,
+    Connect,
+    Send(Vec<String>),
+    Run(String),
+    Interactive,
+}
+
+#[derive(Default)]
+struct Options {
+    data_dir: Option<PathBuf>,
+    config: Option<PathBuf>,
+    address: Option<String>,
+    port: Option<String>,
+    transport: Option<String>,
+    protocol: Option<String>,
+    framing: Option<String>,
+    prepend: Option<String>,
+    append: Option<String>,
+    vars: Vec<(String, String)>,
+    yes: bool,
+    wait_ms: Option<u64>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Options, Command), String> {
+    let mut options = Options::default();
+    let mut words = Vec::new();
+    while let Some(arg) = args.next() {
+        let Some(flag) = arg.strip_prefix("--") else {
+            words.push(arg);
+            continue;
+        };
+        let mut value = || args.next().ok_or_else(|| format!("--{flag} needs a value"));
+        match flag {
+            "data-dir" => options.data_dir = Some(value()?.into()),
+            "config" => options.config = Some(value()?.into()),
+            "address" => options.address = Some(value()?),
+            "port" => options.port = Some(value()?),
+            "transport" => options.transport = Some(value()?),
+            "protocol" => options.protocol = Some(value()?),
+            "framing" => options.framing = Some(value()?),
+            "prepend" => options.prepend = Some(value()?),
+            "append" => options.append = Some(value()?),
+            "var" => {
+                let var = value()?;
+                let (name, value) = var.split_once('=').ok_or_else(|| format!("'{var}' needs to be <name>=<value>"))?;
+                options.vars.push((name.trim().to_string(), value.to_string()));
+            }
+            "wait" => {
+                let ms = value()?;
+                options.wait_ms = Some(ms.parse().map_err(|_| format!("'{ms}' isn't a valid number of ms"))?);
+            }
+            "yes" => options.yes = true,
+            "help" => return Ok((options, Command::Help)),
+            other => return Err(format!("unknown option --{other}")),
+        }
+    }
+    let mut words = words.into_iter();
+    let command = match words.next().as_deref() {
+        Some("list") => Command::List,
+        Some("connect") => Command::Connect,
+        Some("send") => {
+            let names: Vec<_> = words.by_ref().collect();
+            if names.is_empty() {
+                return Err("send needs the name of a saved command".into());
+            }
+            Command::Send(names)
+        }
+        Some("run") => Command::Run(words.next().ok_or("run needs the name of a saved sequence")?),
+        Some("interactive") => Command::Interactive,
+        Some(other) => return Err(format!("unknown command '{other}'")),
+        None => return Err(USAGE.into()),
+    };
+    if let Some(extra) = words.next() {
+        return Err(format!("unexpected '{extra}'"));
+    }
+    Ok((options, command))
+}
+
+/// Parses a flag the way the plugin's JSON arguments spell it.
+fn parse_name<T: serde::de::DeserializeOwned>(value: &str, what: &str) -> Result<T, String> {
+    serde_json::from_value(value.into()).map_err(|_| format!("unknown {what} '{value}'"))
+}
+
+fn connect(
+    manager: &ConnectionManager,
+    library: &Library,
+    target: Option<&CommandTarget>,
+    options: &Options,
+) -> Result<(), String> {
+    let settings = &library.settings;
+    // Flags win over the server commands are bound to, which wins over the app's last settings
+    let address = options.address.clone().or(target.map(|target| target.address.clone())).or(settings.address.clone());
+    let address = address.ok_or("no address; pass --address")?;
+    let port = options.port.clone().or(target.map(|target| target.port.to_string())).or(settings.port.clone());
+    let port = port.ok_or("no port; pass --port")?;
+    let port = port.trim().parse().map_err(|_| format!("'{port}' isn't a valid port"))?;
+    let protocol = settings.protocol;
+    let framing = match &options.framing {
+        Some(framing) => Some(parse_name(framing, "framing")?),
+        None => settings.framing,
+    };
+    let request = ConnectRequest {
+        address: Some(address),
+        port: Some(port),
+        transport: options
+            .transport
+            .clone()
+            .or(target.and_then(|target| target.transport.clone()))
+            .or(settings.transport.clone()),
+        framing,
+        protocol: Some(protocol),
+        mqtt: (protocol == Protocol::Mqtt).then(|| settings.mqtt.clone()),
+        redis: (protocol == Protocol::Redis).then(|| settings.redis.clone()),
+    };
+    let response = manager.connect(request).map_err(|e| e.to_string())?;
+    response.error.map_or(Ok(()), Err)
+}
+
+fn run(options: Options, command: Command) -> Result<(), String> {
+    if let Command::Help = command {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let data_dir = options
+        .data_dir
+        .clone()
+        .or_else(library::default_data_dir)
+        .ok_or("couldn't find the app's data directory; pass --data-dir")?;
+    let mut library = Library::load(&data_dir)?;
+    if let Some(protocol) = &options.protocol {
+        library.settings.protocol = parse_name(protocol, "protocol")?;
+    }
+    if let Some(prepend) = &options.prepend {
+        library.settings.prepend = prepend.clone();
+    }
+    if let Some(append) = &options.append {
+        library.settings.append = append.to_uppercase();
+    }
+    library.settings.variables.extend(options.vars.iter().cloned());
+
+    if let Command::List = command {
+        for (name, record) in &library.commands {
+            println!("{name}: {}", record.command.describe());
+            if !record.description.is_empty() {
+                println!("    {}", record.description);
+            }
+            if !record.tags.is_empty() {
+                println!("    tags: {}", record.tags.join(", "));
+            }
+            if let Some(target) = &record.target {
+                println!("    sent to {}", target.describe());
+            }
+        }
+        for (name, items) in &library.sequences {
+            println!("{name} (sequence, {} steps)", items.len());
+        }
+        return Ok(());
+    }
+
+    let config: Config = match &options.config {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+            serde_json::from_str(&text).map_err(|e| format!("{} isn't valid plugin settings: {e}", path.display()))?
+        }
+        None => Config::default(),
+    };
+    let wait = Duration::from_millis(options.wait_ms.unwrap_or(config.reply_timeout_ms));
+    let manager = ConnectionManager::new(Transports::default(), config);
+    manager.set_listener(|entry| match entry.direction {
+        Direction::Received => println!("{}", entry.data),
+        Direction::Sent => eprintln!("→ {}", entry.data),
+        Direction::Info => eprintln!("• {}", entry.data),
+    });
+
+    // Look everything up before connecting, so a typo doesn't send half of it
+    let (steps, target) = match &command {
+        Command::Send(names) => {
+            let unconfirmed = names.iter().find(|name| library.commands.get(*name).is_some_and(|record| record.confirm));
+            if let (Some(name), false) = (unconfirmed, options.yes) {
+                return Err(format!("'{name}' asks for confirmation before it's sent; pass --yes"));
+            }
+            (library.commands(names)?, library.target(names)?)
+        }
+        Command::Run(name) => (library.sequence(name)?, None),
+        _ => (Vec::new(), None),
+    };
+    connect(&manager, &library, target, &options)?;
+    let result = match command {
+        Command::Help | Command::List | Command::Connect => Ok(()),
+        Command::Send(_) => steps.iter().try_for_each(|step| manager.send_step(step)).map(|()| thread::sleep(wait)),
+        Command::Run(name) => run_sequence(&manager, name, steps),
+        Command::Interactive => {
+            let result = io::stdin().lock().lines().try_for_each(|line| {
+                let line = line.map_err(|e| e.to_string())?;
+                // Keep going after a bad line, as typing it again is easy
+                let step = library.settings.message_step(&line, None, None, None);
+                if let Err(e) = step.and_then(|step| manager.send_step(&step)) {
+                    eprintln!("error: {e}");
+                }
+                Ok(())
+            });
+            thread::sleep(wait);
+            result
+        }
+    };
+    manager.disconnect(DisconnectRequest::default()).map_err(|e| e.to_string())?;
+    result
+}
+
+fn run_sequence(manager: &ConnectionManager, name: String, steps: Vec<SequenceStep>) -> Result<(), String> {
+    let status = manager.sequence_run(SequenceRunRequest { name: Some(name), steps }).map_err(|e| e.to_string())?;
+    if let Some(error) = status.error {
+        return Err(error);
+    }
+    loop {
+        let status = manager.sequence_status();
+        match status.state {
+            SequenceState::Running | SequenceState::Paused => thread::sleep(Duration::from_millis(50)),
+            SequenceState::Finished => return Ok(()),
+            _ => return Err(status.error.unwrap_or_else(|| format!("sequence {:?}", status.state).to_lowercase())),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let (options, command) = match parse_args(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) if e == USAGE => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+        Err(e) => {
+            eprintln!("error: {e}\n\nRun with --help for usage.");
+            return ExitCode::from(2);
+        }
+    };
+    match run(options, command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<(Options, Command), String> {
+        parse_args(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn options_go_anywhere() {
+        let (options, command) =
+            parse("--address 10.0.0.5 send --port 5025 Home --var axis=X --var speed=fast=1 Park --yes --wait 200").unwrap();
+        assert!(matches!(command, Command::Send(names) if names == ["Home", "Park"]));
+        assert_eq!(options.address.as_deref(), Some("10.0.0.5"));
+        assert_eq!(options.port.as_deref(), Some("5025"));
+        // Only the first `=` splits, so values can hold more
+        assert_eq!(options.vars, [("axis".into(), "X".into()), ("speed".into(), "fast=1".into())]);
+        assert!(options.yes);
+        assert_eq!(options.wait_ms, Some(200));
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse("list").unwrap().1, Command::List));
+        assert!(matches!(parse("connect").unwrap().1, Command::Connect));
+        assert!(matches!(parse("interactive").unwrap().1, Command::Interactive));
+        assert!(matches!(parse("run demo").unwrap().1, Command::Run(name) if name == "demo"));
+        assert!(matches!(parse("send Home --help").unwrap().1, Command::Help));
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        let error = |args| parse(args).err().unwrap();
+        assert_eq!(error(""), USAGE);
+        assert_eq!(error("send"), "send needs the name of a saved command");
+        assert_eq!(error("run"), "run needs the name of a saved sequence");
+        assert_eq!(error("run one two"), "unexpected 'two'");
+        assert_eq!(error("launch"), "unknown command 'launch'");
+        assert_eq!(error("list --colour"), "unknown option --colour");
+        assert_eq!(error("list --port"), "--port needs a value");
+        assert_eq!(error("list --var axis"), "'axis' needs to be <name>=<value>");
+        assert_eq!(error("list --wait soon"), "'soon' isn't a valid number of ms");
+    }
+}
@@ -0,0 +1,125 @@
+//! Timeouts and limits for [`ConnectionManager`](crate::manager::ConnectionManager). The plugin
+//! reads them from `plugins.tcp-client` in `tauri.conf.json`:
+//!
+//! ```json
+//! "plugins": {
+//!   "tcp-client": {
+//!     "connectTimeoutMs": 5000,
+//!     "maxConnections": 4,
+//!     "receiveBufferSize": 4096,
+//!     "transcriptRetention": 1000,
+//!     "defaultFraming": "crLf",
+//!     "telnet": { "terminalType": "VT100", "windowWidth": 132 },
+//!     "gcode": { "acknowledgement": "ok", "checksums": true }
+//!   }
+//! }
+//! ```
+//!
+//! Every field is optional.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::models::Framing;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    /// How long to wait for a connection (and TLS handshake) before giving up.
+    pub connect_timeout_ms: u64,
+    /// How long a single send may block before the connection is considered dead.
+    pub write_timeout_ms: u64,
+    /// How long request/response helpers wait for a reply.
+    pub reply_timeout_ms: u64,
+    /// Sockets that may be open at once, including ones still draining after a graceful close.
+    pub max_connections: usize,
+    /// Size of the buffer incoming data is read into. A frame still missing
+    /// its end after 4096 times this much is shown as it is.
+    pub receive_buffer_size: usize,
+    /// Number of transcript entries kept before the oldest are dropped.
+    pub transcript_retention: usize,
+    /// How received data is split into transcript entries unless `connect` says otherwise.
+    pub default_framing: Framing,
+    /// What to tell servers that ask about our terminal in Telnet mode.
+    pub telnet: TelnetConfig,
+    /// How G-code is streamed.
+    pub gcode: GcodeConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TelnetConfig {
+    /// Reported in response to TERMINAL-TYPE.
+    pub terminal_type: String,
+    /// Reported in response to NAWS, in characters.
+    pub window_width: u16,
+    pub window_height: u16,
+}
+
+impl Default for TelnetConfig {
+    fn default() -> Self {
+        Self {
+            terminal_type: "ANSI".into(),
+            window_width: 80,
+            window_height: 24,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GcodeConfig {
+    /// Reply that lets the next line go, matched at the start of a line.
+    pub acknowledgement: String,
+    /// Send line numbers and checksums unless a stream says otherwise.
+    pub checksums: bool,
+    /// How long the firmware may stay silent before the stream fails; 0 waits forever.
+    pub ack_timeout_ms: u64,
+}
+
+impl Default for GcodeConfig {
+    fn default() -> Self {
+        Self {
+            acknowledgement: "ok".into(),
+            checksums: false,
+            ack_timeout_ms: 0,
+        }
+    }
+}
+
+impl GcodeConfig {
+    pub fn ack_timeout(&self) -> Option<Duration> {
+        (self.ack_timeout_ms > 0).then(|| Duration::from_millis(self.ack_timeout_ms))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 10_000,
+            write_timeout_ms: 5_000,
+            reply_timeout_ms: 2_000,
+            max_connections: 4,
+            receive_buffer_size: 4096,
+            transcript_retention: 1000,
+            default_framing: Framing::Raw,
+            telnet: TelnetConfig::default(),
+            gcode: GcodeConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        (self.connect_timeout_ms > 0).then(|| Duration::from_millis(self.connect_timeout_ms))
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        (self.write_timeout_ms > 0).then(|| Duration::from_millis(self.write_timeout_ms))
+    }
+
+    pub fn reply_timeout(&self) -> Duration {
+        Duration::from_millis(self.reply_timeout_ms)
+    }
+}
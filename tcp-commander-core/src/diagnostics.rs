@@ -0,0 +1,358 @@
+//! Step-by-step connection troubleshooting.
+//!
+//! Each step only runs if the ones it depends on succeeded; the rest are
+//! reported as skipped so the UI can always show the full checklist.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use crate::models::*;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const PING_TIMEOUT_SECS: u32 = 2;
+
+// Raw OS error codes for unreachable networks/hosts, since the matching
+// `io::ErrorKind` variants are newer than our minimum supported Rust version
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const UNREACHABLE_CODES: (i32, i32) = (101, 113);
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const UNREACHABLE_CODES: (i32, i32) = (51, 65);
+#[cfg(windows)]
+const UNREACHABLE_CODES: (i32, i32) = (10051, 10065);
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    windows
+)))]
+const UNREACHABLE_CODES: (i32, i32) = (-1, -1);
+
+pub fn diagnose(payload: DiagnoseRequest) -> DiagnoseResponse {
+    let mut steps = Vec::new();
+
+    let address = payload.address.unwrap_or_default().trim().to_string();
+    let syntax = check_syntax(&address, payload.port);
+    let ok = syntax.status == StepStatus::Passed;
+    steps.push(syntax);
+    let port = match payload.port {
+        Some(port) if ok => port as u16,
+        _ => {
+            steps.extend(skipped_after("address syntax"));
+            return DiagnoseResponse { steps };
+        }
+    };
+
+    let (resolve, targets) = resolve(&address, port, payload.addresses);
+    steps.push(resolve);
+    if targets.is_empty() {
+        steps.extend(skipped_after("DNS resolution").into_iter().skip(1));
+        return DiagnoseResponse { steps };
+    }
+
+    steps.push(local_route(targets[0]));
+    steps.push(ping(targets[0].ip()));
+    steps.push(tcp_connect(&targets));
+
+    DiagnoseResponse { steps }
+}
+
+fn step(name: &str, status: StepStatus, detail: impl Into<String>, hint: Option<&str>) -> DiagnosticStep {
+    DiagnosticStep {
+        name: name.into(),
+        status,
+        detail: detail.into(),
+        hint: hint.map(str::to_string),
+    }
+}
+
+fn skipped_after(failed: &str) -> Vec<DiagnosticStep> {
+    ["DNS resolution", "Local route", "ICMP reachability", "TCP connect"]
+        .into_iter()
+        .map(|name| step(name, StepStatus::Skipped, format!("Skipped because {failed} failed"), None))
+        .collect()
+}
+
+/// Underscores aren't allowed in hostnames proper, but resolvers look them up
+/// and plenty of devices on local networks are named with them.
+fn is_valid_hostname(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        // Something like "192.168.1" is almost certainly a mistyped IP, not a hostname
+        && !host.split('.').all(|label| label.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn check_syntax(address: &str, port: Option<i32>) -> DiagnosticStep {
+    const NAME: &str = "Address syntax";
+    if address.is_empty() {
+        return step(NAME, StepStatus::Failed, "No address entered", Some("Enter the server's IP address, e.g. 192.168.1.42"));
+    }
+    let kind = if let Ok(ip) = address.parse::<IpAddr>() {
+        if ip.is_ipv4() { "IPv4 address" } else { "IPv6 address" }
+    } else if is_valid_hostname(address) {
+        "hostname"
+    } else {
+        return step(
+            NAME,
+            StepStatus::Failed,
+            format!("'{address}' is neither a valid IP address nor a hostname"),
+            Some("Check for typos: IPv4 addresses are four numbers from 0-255 separated by dots, e.g. 192.168.1.42"),
+        );
+    };
+    match port {
+        Some(port) if (1..=65535).contains(&port) => {
+            step(NAME, StepStatus::Passed, format!("'{address}' is a valid {kind}, port {port}"), None)
+        }
+        Some(port) => step(NAME, StepStatus::Failed, format!("Port {port} is out of range"), Some("Ports must be between 1 and 65535")),
+        None => step(NAME, StepStatus::Failed, "No port entered", Some("Enter the port the server is listening on")),
+    }
+}
+
+/// Looks `address` up, unless it was already looked up as `pinned`.
+fn resolve(address: &str, port: u16, pinned: Option<Vec<IpAddr>>) -> (DiagnosticStep, Vec<SocketAddr>) {
+    const NAME: &str = "DNS resolution";
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        let detail = "Address is a literal IP; no lookup needed";
+        return (step(NAME, StepStatus::Skipped, detail, None), vec![SocketAddr::new(ip, port)]);
+    }
+    let looked_up = match pinned {
+        Some(pinned) if pinned.is_empty() => Err(io::Error::new(io::ErrorKind::NotFound, "no addresses found")),
+        Some(pinned) => Ok(pinned.into_iter().map(|ip| SocketAddr::new(ip, port)).collect::<Vec<_>>()),
+        None => (address, port).to_socket_addrs().map(Iterator::collect),
+    };
+    match looked_up {
+        Ok(addrs) => {
+            if addrs.is_empty() {
+                let hint = Some("The name exists but has no addresses; ask whoever runs the server for its IP");
+                (step(NAME, StepStatus::Failed, format!("'{address}' has no addresses"), hint), addrs)
+            } else {
+                let list = addrs.iter().map(|a| a.ip().to_string()).collect::<Vec<_>>().join(", ");
+                (step(NAME, StepStatus::Passed, format!("'{address}' resolved to {list}"), None), addrs)
+            }
+        }
+        Err(e) => {
+            let hint = Some("Check the spelling of the name, or use the server's IP address directly");
+            (step(NAME, StepStatus::Failed, format!("Could not resolve '{address}': {e}"), hint), Vec::new())
+        }
+    }
+}
+
+fn local_route(target: SocketAddr) -> DiagnosticStep {
+    const NAME: &str = "Local route";
+    // Connecting a UDP socket sends nothing, but makes the OS pick the outgoing route
+    let bind: SocketAddr = if target.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let local = UdpSocket::bind(bind)
+        .and_then(|socket| socket.connect(target).map(|_| socket))
+        .and_then(|socket| socket.local_addr());
+    let local = match local {
+        Ok(local) => local.ip(),
+        Err(e) => {
+            return step(
+                NAME,
+                StepStatus::Failed,
+                format!("No route to {}: {e}", target.ip()),
+                Some("This device has no network that can reach the server; make sure you're connected to the right Wi-Fi or network"),
+            );
+        }
+    };
+
+    let interface = if_addrs::get_if_addrs()
+        .ok()
+        .and_then(|interfaces| interfaces.into_iter().find(|i| i.ip() == local));
+    let Some(interface) = interface else {
+        return step(NAME, StepStatus::Passed, format!("Outgoing traffic uses local address {local}"), None);
+    };
+
+    let same_subnet = match (&interface.addr, target.ip()) {
+        (if_addrs::IfAddr::V4(v4), IpAddr::V4(ip)) => {
+            u32::from(v4.ip) & u32::from(v4.netmask) == u32::from(ip) & u32::from(v4.netmask)
+        }
+        (if_addrs::IfAddr::V6(v6), IpAddr::V6(ip)) => {
+            u128::from(v6.ip) & u128::from(v6.netmask) == u128::from(ip) & u128::from(v6.netmask)
+        }
+        _ => false,
+    };
+    let detail = format!("Outgoing traffic uses interface {} ({local})", interface.name);
+    if interface.is_loopback() && !target.ip().is_loopback() {
+        step(NAME, StepStatus::Warning, detail, Some("Traffic is going through the loopback interface; this device may not be connected to any network"))
+    } else if same_subnet || target.ip().is_loopback() {
+        step(NAME, StepStatus::Passed, format!("{detail}; server is on the same subnet"), None)
+    } else {
+        step(
+            NAME,
+            StepStatus::Warning,
+            format!("{detail}; server is on a different subnet"),
+            Some("Traffic has to go through a router; if the server is in the same room, you may be on the wrong network"),
+        )
+    }
+}
+
+fn ping(ip: IpAddr) -> DiagnosticStep {
+    const NAME: &str = "ICMP reachability";
+    let mut command = Command::new("ping");
+    if cfg!(windows) {
+        command.args(["-n", "1", "-w", &(PING_TIMEOUT_SECS * 1000).to_string()]);
+    } else if cfg!(any(target_os = "macos", target_os = "ios")) {
+        command.args(["-c", "1", "-t", &PING_TIMEOUT_SECS.to_string()]);
+    } else {
+        command.args(["-c", "1", "-W", &PING_TIMEOUT_SECS.to_string()]);
+    }
+    if ip.is_ipv6() && !cfg!(windows) {
+        command.arg("-6");
+    }
+    let status = command
+        .arg(ip.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    match status {
+        Ok(status) if status.success() => step(NAME, StepStatus::Passed, format!("{ip} answered a ping"), None),
+        Ok(_) => step(
+            NAME,
+            StepStatus::Warning,
+            format!("{ip} did not answer a ping"),
+            Some("The server may be offline, or a firewall is blocking pings; many networks block them, so this alone isn't conclusive"),
+        ),
+        Err(e) => step(NAME, StepStatus::Skipped, format!("Couldn't run ping on this device: {e}"), None),
+    }
+}
+
+fn tcp_connect(targets: &[SocketAddr]) -> DiagnosticStep {
+    const NAME: &str = "TCP connect";
+    let mut last_error = None;
+    for target in targets {
+        match TcpStream::connect_timeout(target, CONNECT_TIMEOUT) {
+            Ok(socket) => {
+                let _ = socket.shutdown(std::net::Shutdown::Both);
+                return step(NAME, StepStatus::Passed, format!("Connected to {target}"), None);
+            }
+            Err(e) => last_error = Some((target, e)),
+        }
+    }
+    let (target, e) = last_error.expect("at least one target");
+    step(NAME, StepStatus::Failed, format!("Connecting to {target} failed: {e}"), Some(connect_hint(&e)))
+}
+
+/// Plain-language explanation of why a connection attempt failed.
+pub fn connect_hint(e: &io::Error) -> &'static str {
+    let (network_unreachable, host_unreachable) = UNREACHABLE_CODES;
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused => {
+            "The server's computer is reachable but nothing is listening on that port; check the server program is running and the port is correct"
+        }
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+            "No response at all; the server may be offline, or a firewall is silently dropping the connection"
+        }
+        io::ErrorKind::PermissionDenied => {
+            "This device refused to make the connection; check local firewall or app network permissions"
+        }
+        io::ErrorKind::AddrNotAvailable => "The address can't be used from this device; check it was typed correctly",
+        _ if e.raw_os_error() == Some(host_unreachable) => {
+            "No route to host (NoRouteToHostException); usually a firewall on the server, or the server is offline. Try pinging it from another device"
+        }
+        _ if e.raw_os_error() == Some(network_unreachable) => {
+            "This device isn't connected to a network that can reach the server; check your Wi-Fi"
+        }
+        _ => "Unexpected error; try again, and ask for help if it keeps happening",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_addresses_are_not_looked_up_again() {
+        let pinned = vec![IpAddr::from([127, 0, 0, 1])];
+        let (step, targets) = resolve("device.invalid", 5025, Some(pinned));
+        assert_eq!(step.status, StepStatus::Passed);
+        assert_eq!(targets, [SocketAddr::from(([127, 0, 0, 1], 5025))]);
+
+        let (step, targets) = resolve("device.invalid", 5025, Some(Vec::new()));
+        assert_eq!(step.status, StepStatus::Failed);
+        assert!(targets.is_empty());
+
+        // A literal address needs no lookup either way
+        let (step, targets) = resolve("10.0.0.5", 80, None);
+        assert_eq!(step.status, StepStatus::Skipped);
+        assert_eq!(targets, [SocketAddr::from(([10, 0, 0, 5], 80))]);
+    }
+
+    #[test]
+    fn hostnames() {
+        assert!(is_valid_hostname("robot.lab"));
+        assert!(is_valid_hostname("robot.lab."));
+        assert!(is_valid_hostname("plc-01"));
+        assert!(is_valid_hostname("esp_32.local"));
+        assert!(is_valid_hostname(&"a".repeat(63)));
+        assert!(is_valid_hostname(&["a".repeat(63), "b".repeat(63), "c".repeat(63), "d".repeat(61)].join(".")));
+
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname("."));
+        assert!(!is_valid_hostname("robot..lab"));
+        assert!(!is_valid_hostname(&"a".repeat(64)));
+        assert!(!is_valid_hostname(&["a".repeat(63), "b".repeat(63), "c".repeat(63), "d".repeat(62)].join(".")));
+        assert!(!is_valid_hostname("-robot.lab"));
+        assert!(!is_valid_hostname("robot-.lab"));
+        assert!(!is_valid_hostname("robot lab"));
+        assert!(!is_valid_hostname("röbot.lab"));
+        assert!(!is_valid_hostname("192.168.1"));
+    }
+
+    #[test]
+    fn syntax() {
+        let check = |address, port| {
+            let step = check_syntax(address, port);
+            (step.status, step.detail)
+        };
+        assert_eq!(check("192.168.1.42", Some(23)), (StepStatus::Passed, "'192.168.1.42' is a valid IPv4 address, port 23".into()));
+        assert_eq!(check("::1", Some(23)), (StepStatus::Passed, "'::1' is a valid IPv6 address, port 23".into()));
+        assert_eq!(check("robot.lab", Some(65535)), (StepStatus::Passed, "'robot.lab' is a valid hostname, port 65535".into()));
+        assert_eq!(check("robot.lab", Some(1)).0, StepStatus::Passed);
+
+        assert_eq!(check("", Some(23)), (StepStatus::Failed, "No address entered".into()));
+        assert_eq!(
+            check("192.168.1.256", Some(23)),
+            (StepStatus::Failed, "'192.168.1.256' is neither a valid IP address nor a hostname".into())
+        );
+        assert_eq!(check("robot.lab", None), (StepStatus::Failed, "No port entered".into()));
+        assert_eq!(check("robot.lab", Some(0)), (StepStatus::Failed, "Port 0 is out of range".into()));
+        assert_eq!(check("robot.lab", Some(65536)), (StepStatus::Failed, "Port 65536 is out of range".into()));
+        assert_eq!(check("robot.lab", Some(-23)), (StepStatus::Failed, "Port -23 is out of range".into()));
+    }
+
+    #[test]
+    fn connect_hints() {
+        let hint = |kind| connect_hint(&io::Error::from(kind));
+        assert!(hint(io::ErrorKind::ConnectionRefused).contains("nothing is listening on that port"));
+        assert!(hint(io::ErrorKind::TimedOut).starts_with("No response at all"));
+        assert_eq!(hint(io::ErrorKind::WouldBlock), hint(io::ErrorKind::TimedOut));
+        assert!(hint(io::ErrorKind::PermissionDenied).contains("local firewall"));
+        assert!(hint(io::ErrorKind::AddrNotAvailable).contains("typed correctly"));
+        assert!(hint(io::ErrorKind::Other).starts_with("Unexpected error"));
+
+        let (network_unreachable, host_unreachable) = UNREACHABLE_CODES;
+        if host_unreachable >= 0 {
+            assert!(connect_hint(&io::Error::from_raw_os_error(host_unreachable)).starts_with("No route to host"));
+            assert!(connect_hint(&io::Error::from_raw_os_error(network_unreachable)).contains("check your Wi-Fi"));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! LAN device discovery over mDNS/DNS-SD.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+
+use crate::models::*;
+
+pub const DEFAULT_SERVICE_TYPES: &[&str] = &["_tcp-commander._tcp", "_telnet._tcp"];
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Longer browses are cut short; whatever hasn't answered by then isn't going to.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Turns `_telnet._tcp` or `_telnet._tcp.local` into the fully qualified `_telnet._tcp.local.`
+fn qualify(service_type: &str) -> String {
+    let service_type = service_type.trim().trim_end_matches('.');
+    if service_type.ends_with(".local") {
+        format!("{service_type}.")
+    } else {
+        format!("{service_type}.local.")
+    }
+}
+
+/// Browses for every requested service type at once and returns whatever
+/// resolved before the timeout, at most [`MAX_TIMEOUT`], ran out.
+pub fn discover(payload: DiscoverRequest) -> crate::Result<DiscoverResponse> {
+    let service_types: Vec<String> = match payload.service_types {
+        Some(types) if !types.is_empty() => types.iter().map(|t| qualify(t)).collect(),
+        _ => DEFAULT_SERVICE_TYPES.iter().map(|t| qualify(t)).collect(),
+    };
+    let timeout = payload
+        .timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_TIMEOUT)
+        .min(MAX_TIMEOUT);
+
+    let daemon = ServiceDaemon::new()?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for service_type in &service_types {
+        let events = daemon.browse(service_type)?;
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(sender);
+
+    // Keyed by full name so the same instance announced on several interfaces shows up once
+    let mut found = BTreeMap::<String, DiscoveredService>::new();
+    let deadline = Instant::now() + timeout;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = receiver.recv_timeout(remaining) else {
+            break;
+        };
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+
+        let service_type = info.get_type().to_string();
+        let fullname = info.get_fullname().to_string();
+        let entry = found.entry(fullname.clone()).or_insert_with(|| DiscoveredService {
+            instance_name: fullname
+                .strip_suffix(&service_type)
+                .map(|name| name.trim_end_matches('.'))
+                .unwrap_or(&fullname)
+                .to_string(),
+            service_type: service_type.clone(),
+            hostname: info.get_hostname().trim_end_matches('.').to_string(),
+            addresses: Vec::new(),
+            port: info.get_port(),
+            txt: BTreeMap::new(),
+        });
+        for address in info.get_addresses() {
+            let address = address.to_string();
+            if !entry.addresses.contains(&address) {
+                entry.addresses.push(address);
+            }
+        }
+        // IPv4 first, since that's what people are used to typing
+        entry.addresses.sort_by_key(|address| address.contains(':'));
+        for property in info.get_properties().iter() {
+            entry.txt.insert(property.key().to_string(), property.val_str().to_string());
+        }
+    }
+
+    for service_type in &service_types {
+        let _ = daemon.stop_browse(service_type);
+    }
+    let _ = daemon.shutdown();
+
+    Ok(DiscoverResponse {
+        services: found.into_values().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdns_sd::ServiceInfo;
+
+    #[test]
+    fn service_types_are_qualified() {
+        assert_eq!(qualify("_telnet._tcp"), "_telnet._tcp.local.");
+        assert_eq!(qualify(" _telnet._tcp.local"), "_telnet._tcp.local.");
+        assert_eq!(qualify("_telnet._tcp.local."), "_telnet._tcp.local.");
+    }
+
+    #[test]
+    fn registered_service_is_found() {
+        // A type of its own, so nothing else on the network gets in the way
+        let service_type = format!("_tcpc-test-{}._tcp.local.", std::process::id() % 10_000);
+        let daemon = ServiceDaemon::new().unwrap();
+        let info = ServiceInfo::new(
+            &service_type,
+            "Bench PSU",
+            "bench-psu.local.",
+            "",
+            5025,
+            &[("model", "E36312A"), ("protocol", "scpi")][..],
+        )
+        .unwrap()
+        .enable_addr_auto();
+        daemon.register(info).unwrap();
+
+        let response = discover(DiscoverRequest { service_types: Some(vec![service_type.clone()]), timeout_ms: Some(3000) }).unwrap();
+        let _ = daemon.shutdown();
+        let [service] = response.services.as_slice() else {
+            panic!("expected one service, found {:?}", response.services);
+        };
+        assert_eq!(service.instance_name, "Bench PSU");
+        assert_eq!(service.service_type, service_type);
+        assert_eq!(service.hostname, "bench-psu.local");
+        assert_eq!(service.port, 5025);
+        assert!(!service.addresses.is_empty());
+        assert_eq!(service.txt.get("model").map(String::as_str), Some("E36312A"));
+        assert_eq!(service.txt.get("protocol").map(String::as_str), Some("scpi"));
+    }
+}
@@ -0,0 +1,9 @@
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error(transparent)]
+  Io(#[from] std::io::Error),
+  #[error("mDNS discovery failed: {0}")]
+  Discovery(#[from] mdns_sd::Error),
+}
@@ -0,0 +1,145 @@
+//! Splitting a byte stream into messages.
+
+use crate::models::Framing;
+
+/// How many reads' worth of bytes may wait for the end of a frame before
+/// they're handed on as one anyway.
+pub const MAX_PENDING_READS: usize = 4096;
+
+/// Works out the length of a whole frame from its start, or `None` if more
+/// bytes are needed to tell.
+pub type FrameLength = fn(&[u8]) -> Option<usize>;
+
+/// Accumulates received bytes and hands back complete frames, without their delimiter.
+#[derive(Debug)]
+pub struct Framer {
+    framing: Framing,
+    length: Option<FrameLength>,
+    pending: Vec<u8>,
+    limit: usize,
+}
+
+impl Framer {
+    pub fn new(framing: Framing) -> Self {
+        Self { framing, length: None, pending: Vec::new(), limit: usize::MAX }
+    }
+
+    /// Splits frames that announce their own length, such as Modbus TCP's.
+    /// A length of 0 can't be right, so everything pending is handed on as
+    /// it is rather than waiting for a frame that never ends.
+    pub fn with_length(length: FrameLength) -> Self {
+        Self { framing: Framing::Raw, length: Some(length), pending: Vec::new(), limit: usize::MAX }
+    }
+
+    /// Hands pending bytes on as a frame once more than `limit` of them are
+    /// waiting, so a missing delimiter or a bogus length can't hold on to
+    /// everything received after it.
+    pub fn limit(self, limit: usize) -> Self {
+        Self { limit, ..self }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        if let Some(length) = self.length {
+            self.pending.extend_from_slice(bytes);
+            let mut frames = Vec::new();
+            while !self.pending.is_empty() {
+                match length(&self.pending) {
+                    Some(0) => frames.extend(self.finish()),
+                    Some(len) if len <= self.pending.len() => frames.push(self.pending.drain(..len).collect()),
+                    _ => break,
+                }
+            }
+            frames.extend(self.overflow());
+            return frames;
+        }
+        let Some(delimiter) = self.framing.delimiter() else {
+            return if bytes.is_empty() { Vec::new() } else { vec![bytes.to_vec()] };
+        };
+
+        // Only rescan the tail that could contain a delimiter split across reads
+        let mut search_from = self.pending.len().saturating_sub(delimiter.len() - 1);
+        self.pending.extend_from_slice(bytes);
+        let mut frames = Vec::new();
+        while let Some(offset) = self.pending[search_from..]
+            .windows(delimiter.len())
+            .position(|window| window == delimiter)
+        {
+            let end = search_from + offset;
+            let mut frame: Vec<u8> = self.pending.drain(..end + delimiter.len()).collect();
+            frame.truncate(end);
+            frames.push(frame);
+            search_from = 0;
+        }
+        frames.extend(self.overflow());
+        frames
+    }
+
+    fn overflow(&mut self) -> Option<Vec<u8>> {
+        (self.pending.len() > self.limit).then(|| std::mem::take(&mut self.pending))
+    }
+
+    /// Whatever is left over once the stream has ended.
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        (!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames whose first byte is their length, that byte included.
+    fn first_byte(bytes: &[u8]) -> Option<usize> {
+        bytes.first().map(|len| *len as usize)
+    }
+
+    #[test]
+    fn delimiters_split_across_reads() {
+        let mut framer = Framer::new(Framing::CrLf);
+        assert!(framer.push(b"one\r").is_empty());
+        assert_eq!(framer.push(b"\ntwo"), [b"one".to_vec()]);
+        assert_eq!(framer.push(b"\r\n"), [b"two".to_vec()]);
+        assert_eq!(framer.finish(), None);
+    }
+
+    #[test]
+    fn several_frames_in_one_read() {
+        let mut framer = Framer::new(Framing::Lf);
+        assert_eq!(framer.push(b"a\n\nbc\nd"), [b"a".to_vec(), Vec::new(), b"bc".to_vec()]);
+        let mut framer = Framer::with_length(first_byte);
+        assert_eq!(framer.push(&[2, 0xAA, 3, 0xBB, 0xCC, 2]), [vec![2, 0xAA], vec![3, 0xBB, 0xCC]]);
+        assert_eq!(framer.push(&[0xDD]), [vec![2, 0xDD]]);
+    }
+
+    #[test]
+    fn finish_flushes_the_rest() {
+        let mut framer = Framer::new(Framing::Lf);
+        assert_eq!(framer.push(b"done\npartial"), [b"done".to_vec()]);
+        assert_eq!(framer.finish(), Some(b"partial".to_vec()));
+        assert_eq!(framer.finish(), None);
+
+        let mut framer = Framer::with_length(first_byte);
+        assert!(framer.push(&[4, 1]).is_empty());
+        assert_eq!(framer.finish(), Some(vec![4, 1]));
+    }
+
+    #[test]
+    fn zero_lengths_are_handed_on() {
+        let mut framer = Framer::with_length(first_byte);
+        assert_eq!(framer.push(&[0, 1, 2]), [vec![0, 1, 2]]);
+        // And framing picks up again after them
+        assert_eq!(framer.push(&[2, 9]), [vec![2, 9]]);
+    }
+
+    #[test]
+    fn pending_bytes_are_limited() {
+        let mut framer = Framer::new(Framing::Lf).limit(4);
+        assert!(framer.push(b"abcd").is_empty());
+        assert_eq!(framer.push(b"ef"), [b"abcdef".to_vec()]);
+        assert_eq!(framer.push(b"g\n"), [b"g".to_vec()]);
+
+        let mut framer = Framer::with_length(first_byte).limit(4);
+        assert!(framer.push(&[200, 1, 2]).is_empty());
+        assert_eq!(framer.push(&[3, 4]), [vec![200, 1, 2, 3, 4]]);
+    }
+}
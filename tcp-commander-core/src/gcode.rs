@@ -0,0 +1,125 @@
+//! G-code for networked 3D printers and CNC controllers, which take one
+//! line at a time and acknowledge each one before they want the next.
+//!
+//! Optional line numbers and checksums follow the RepRap convention of
+//! `N<line> <command>*<checksum>`, which lets the firmware ask for a
+//! corrupted line to be resent.
+
+/// Resets the firmware's line counter before a numbered stream.
+pub const RESET_LINE_NUMBER: &str = "M110 N0";
+
+/// What a line received from the firmware means for the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Ack,
+    /// Send again starting at this line number.
+    Resend(usize),
+    Error(String),
+    /// Temperatures, `echo:` output and other chatter.
+    Other,
+}
+
+/// The commands in a program, with comments, blank lines and surrounding
+/// whitespace removed.
+pub fn prepare(program: &str) -> Vec<String> {
+    program
+        .lines()
+        .map(|line| {
+            let line = line.split(';').next().unwrap_or_default();
+            // Parenthesised comments can sit in the middle of a line
+            let mut command = String::with_capacity(line.len());
+            let mut depth = 0;
+            for c in line.chars() {
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth > 0 => depth -= 1,
+                    c if depth == 0 => command.push(c),
+                    _ => {}
+                }
+            }
+            command.trim().to_string()
+        })
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+/// XOR of every byte, as RepRap firmware computes it.
+pub fn checksum(line: &str) -> u8 {
+    line.bytes().fold(0, |sum, b| sum ^ b)
+}
+
+/// Adds a line number and checksum to `command`.
+pub fn number(line: usize, command: &str) -> String {
+    let numbered = format!("N{line} {command}");
+    let checksum = checksum(&numbered);
+    format!("{numbered}*{checksum}")
+}
+
+/// Adds the newline firmware waits for before acting on a line.
+pub fn terminate(line: &str) -> String {
+    format!("{}\n", line.trim_end_matches(['\r', '\n']))
+}
+
+/// Works out what a received line means. `ack` is matched case-insensitively
+/// at the start, so `ok T:210.0 /210.0` counts as an acknowledgement.
+pub fn classify(line: &str, ack: &str) -> Reply {
+    let line = line.trim();
+    let lower = line.to_ascii_lowercase();
+    if lower.starts_with(&ack.to_ascii_lowercase()) {
+        return Reply::Ack;
+    }
+    // `Resend: 12` from Marlin and RepRapFirmware, `rs N12` from Smoothieware
+    let resend = lower.strip_prefix("resend:").or_else(|| lower.strip_prefix("rs "));
+    if let Some(line) = resend.and_then(|rest| rest.trim().trim_start_matches('n').parse().ok()) {
+        return Reply::Resend(line);
+    }
+    if lower.starts_with("error") || lower.starts_with("alarm") || lower.starts_with("!!") {
+        return Reply::Error(line.to_string());
+    }
+    Reply::Other
+}
+
+/// Whether an error is about a corrupted or out-of-order line, which the
+/// firmware follows up with a resend request.
+pub fn is_recoverable(error: &str) -> bool {
+    let error = error.to_ascii_lowercase();
+    ["checksum", "line number", "last line"].iter().any(|hint| error.contains(hint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_and_blank_lines_are_dropped() {
+        let program = "; start\nG28 (home all)\n\n  G1 X10 ; move\n(only a comment)\nM104 S200";
+        assert_eq!(prepare(program), ["G28", "G1 X10", "M104 S200"]);
+    }
+
+    #[test]
+    fn lines_are_numbered_with_a_checksum() {
+        assert_eq!(checksum("N1 G28"), 18);
+        assert_eq!(number(0, RESET_LINE_NUMBER), "N0 M110 N0*125");
+        assert_eq!(number(123, "G1 X10"), "N123 G1 X10*81");
+        assert_eq!(terminate("G28\r\n"), "G28\n");
+    }
+
+    #[test]
+    fn replies_are_classified() {
+        assert_eq!(classify("ok T:210.0 /210.0", "ok"), Reply::Ack);
+        assert_eq!(classify("OK", "ok"), Reply::Ack);
+        assert_eq!(classify("Resend: 12", "ok"), Reply::Resend(12));
+        assert_eq!(classify("rs N7", "ok"), Reply::Resend(7));
+        assert_eq!(classify("Error:Printer halted", "ok"), Reply::Error("Error:Printer halted".into()));
+        assert_eq!(classify("ALARM:1", "ok"), Reply::Error("ALARM:1".into()));
+        assert_eq!(classify("echo:busy: processing", "ok"), Reply::Other);
+        assert_eq!(classify("Resend: soon", "ok"), Reply::Other);
+    }
+
+    #[test]
+    fn resend_errors_are_recoverable() {
+        assert!(is_recoverable("Error:checksum mismatch, Last Line: 3"));
+        assert!(is_recoverable("Error:Line Number is not Last Line Number+1"));
+        assert!(!is_recoverable("Error:Printer halted. kill() called!"));
+    }
+}
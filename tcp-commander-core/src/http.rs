@@ -0,0 +1,244 @@
+//! Just enough HTTP/1.1 to poke at embedded web servers: requests are built
+//! with proper CRLF framing, and responses are parsed from the raw bytes,
+//! whether their body is sized by `Content-Length`, chunked, or runs until
+//! the server closes the connection.
+
+use crate::models::{HttpHeader, HttpRequest, HttpResponse};
+
+const CRLF: &[u8] = b"\r\n";
+
+/// Rejects anything that would break the request's framing, such as a
+/// header value with a line break in it.
+pub fn validate(request: &HttpRequest) -> Result<(), String> {
+    let is_token = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b));
+    if !is_token(&request.method) {
+        return Err(format!("'{}' isn't a valid method", request.method));
+    }
+    if request.path.is_empty() || request.path.bytes().any(|b| b.is_ascii_whitespace() || b.is_ascii_control()) {
+        return Err(format!("'{}' isn't a valid request target", request.path));
+    }
+    for header in &request.headers {
+        if !is_token(&header.name) {
+            return Err(format!("'{}' isn't a valid header name", header.name));
+        }
+        if header.value.contains(['\r', '\n']) {
+            return Err(format!("The {} header can't contain line breaks", header.name));
+        }
+    }
+    Ok(())
+}
+
+/// The `Host` header for a server at `address:port`.
+pub fn host(address: &str, port: u16) -> String {
+    let address = if address.contains(':') { format!("[{address}]") } else { address.to_string() };
+    match port {
+        80 | 443 => address,
+        port => format!("{address}:{port}"),
+    }
+}
+
+/// Builds a complete request, adding `Host` and `Content-Length` if they're missing.
+pub fn encode(request: &HttpRequest, host: &str) -> Vec<u8> {
+    let has = |name: &str| request.headers.iter().any(|header| header.name.eq_ignore_ascii_case(name));
+    let body = request.body.as_deref().unwrap_or_default();
+    let mut out = format!("{} {} HTTP/1.1\r\n", request.method, request.path).into_bytes();
+    if !has("Host") {
+        out.extend_from_slice(format!("Host: {host}\r\n").as_bytes());
+    }
+    for header in &request.headers {
+        out.extend_from_slice(format!("{}: {}\r\n", header.name, header.value).as_bytes());
+    }
+    if !body.is_empty() && !has("Content-Length") && !has("Transfer-Encoding") {
+        out.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+    }
+    out.extend_from_slice(CRLF);
+    out.extend_from_slice(body.as_bytes());
+    out
+}
+
+fn find(bytes: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    bytes.get(from..)?.windows(needle.len()).position(|window| window == needle).map(|i| from + i)
+}
+
+/// Undoes chunked encoding, returning the body and where the message ends.
+fn dechunk(bytes: &[u8]) -> Result<Option<(Vec<u8>, usize)>, String> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+    loop {
+        let Some(end) = find(bytes, CRLF, pos) else {
+            return Ok(None);
+        };
+        let line = String::from_utf8_lossy(&bytes[pos..end]);
+        // Chunk extensions after a `;` carry nothing we need
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| format!("'{size}' isn't a valid chunk size"))?;
+        pos = end + CRLF.len();
+        if size == 0 {
+            // Skip any trailer fields up to the empty line that ends the message
+            loop {
+                let Some(end) = find(bytes, CRLF, pos) else {
+                    return Ok(None);
+                };
+                if end == pos {
+                    return Ok(Some((body, end + CRLF.len())));
+                }
+                pos = end + CRLF.len();
+            }
+        }
+        let Some(chunk) = bytes.get(pos..pos + size + CRLF.len()) else {
+            return Ok(None);
+        };
+        if &chunk[size..] != CRLF {
+            return Err("chunk isn't followed by CRLF".into());
+        }
+        body.extend_from_slice(&chunk[..size]);
+        pos += size + CRLF.len();
+    }
+}
+
+/// Parses the response at the start of `bytes`, or returns `None` if more
+/// is needed. `head` says whether it answers a HEAD request, which gets no
+/// body whatever the headers say, and `closed` whether the server has
+/// closed the connection, which ends a body without a length.
+///
+/// Interim `1xx` responses are skipped.
+pub fn parse(bytes: &[u8], head: bool, closed: bool) -> Result<Option<HttpResponse>, String> {
+    let mut start = 0;
+    loop {
+        let Some(end) = find(bytes, b"\r\n\r\n", start) else {
+            return Ok(None);
+        };
+        let text = String::from_utf8_lossy(&bytes[start..end]);
+        let mut lines = text.split("\r\n");
+        let status_line = lines.next().unwrap_or_default();
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        let status = parts.next().and_then(|status| status.parse::<u16>().ok());
+        let (true, Some(status)) = (version.starts_with("HTTP/"), status) else {
+            return Err(format!("'{status_line}' isn't an HTTP status line"));
+        };
+        let reason = parts.next().unwrap_or_default();
+        let headers = lines
+            .map(|line| {
+                let (name, value) = line.split_once(':').ok_or_else(|| format!("'{line}' isn't a header"))?;
+                Ok(HttpHeader { name: name.trim().to_string(), value: value.trim().to_string() })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let body_start = end + 4;
+        if (100..200).contains(&status) && status != 101 {
+            start = body_start;
+            continue;
+        }
+
+        let header = |name: &str| {
+            headers.iter().find(|header| header.name.eq_ignore_ascii_case(name)).map(|header| header.value.as_str())
+        };
+        let rest = &bytes[body_start..];
+        let body = if head || matches!(status, 101 | 204 | 304) {
+            Vec::new()
+        } else if header("Transfer-Encoding").is_some_and(|value| value.to_ascii_lowercase().contains("chunked")) {
+            match dechunk(rest)? {
+                Some((body, _)) => body,
+                None => return Ok(None),
+            }
+        } else if let Some(length) = header("Content-Length") {
+            let length: usize = length.parse().map_err(|_| format!("'{length}' isn't a valid Content-Length"))?;
+            match rest.get(..length) {
+                Some(body) => body.to_vec(),
+                None => return Ok(None),
+            }
+        } else if closed {
+            rest.to_vec()
+        } else {
+            return Ok(None);
+        };
+
+        return Ok(Some(HttpResponse {
+            version: Some(version.to_string()),
+            status: Some(status),
+            reason: Some(reason.to_string()),
+            headers,
+            body: Some(String::from_utf8_lossy(&body).into_owned()),
+            error: None,
+        }));
+    }
+}
+
+/// One-line summary of a response for the transcript.
+pub fn describe(response: &HttpResponse) -> String {
+    format!(
+        "{} {} {} ({} header(s), {} byte body)",
+        response.version.as_deref().unwrap_or_default(),
+        response.status.unwrap_or_default(),
+        response.reason.as_deref().unwrap_or_default(),
+        response.headers.len(),
+        response.body.as_ref().map_or(0, String::len),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(bytes: &[u8], head: bool, closed: bool) -> Option<String> {
+        parse(bytes, head, closed).unwrap().map(|response| response.body.unwrap())
+    }
+
+    #[test]
+    fn requests_get_host_and_length() {
+        let request = HttpRequest {
+            method: "POST".into(),
+            path: "/api".into(),
+            headers: vec![HttpHeader { name: "Accept".into(), value: "*/*".into() }],
+            body: Some("on=1".into()),
+        };
+        assert!(validate(&request).is_ok());
+        assert_eq!(
+            encode(&request, &host("192.168.1.5", 8080)),
+            b"POST /api HTTP/1.1\r\nHost: 192.168.1.5:8080\r\nAccept: */*\r\nContent-Length: 4\r\n\r\non=1"
+        );
+        assert_eq!(host("::1", 80), "[::1]");
+        let smuggled = HttpRequest {
+            headers: vec![HttpHeader { name: "X".into(), value: "a\r\nB: c".into() }],
+            ..request
+        };
+        assert!(validate(&smuggled).is_err());
+    }
+
+    #[test]
+    fn content_length_sizes_the_body() {
+        let response = parse(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello", false, false).unwrap().unwrap();
+        assert_eq!(response.status, Some(200));
+        assert_eq!(response.reason.as_deref(), Some("OK"));
+        assert_eq!(response.body.as_deref(), Some("hello"));
+        assert_eq!(body(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhel", false, false), None);
+        assert!(parse(b"HTTP/1.1 200 OK\r\nContent-Length: five\r\n\r\n", false, false).is_err());
+    }
+
+    #[test]
+    fn chunked_bodies_are_joined() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nTrailer: x\r\n\r\n";
+        assert_eq!(body(response, false, false).as_deref(), Some("Wikipedia in \r\n\r\nchunks."));
+        assert_eq!(body(&response[..response.len() - 2], false, false), None);
+        assert!(parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", false, false).is_err());
+    }
+
+    #[test]
+    fn interim_responses_are_skipped() {
+        let response = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 102 Processing\r\n\r\nHTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok";
+        let response = parse(response, false, false).unwrap().unwrap();
+        assert_eq!(response.status, Some(201));
+        assert_eq!(response.body.as_deref(), Some("ok"));
+        assert_eq!(body(b"HTTP/1.1 100 Continue\r\n\r\n", false, false), None);
+    }
+
+    #[test]
+    fn some_responses_have_no_body() {
+        assert_eq!(body(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n", true, false).as_deref(), Some(""));
+        assert_eq!(body(b"HTTP/1.1 204 No Content\r\n\r\n", false, false).as_deref(), Some(""));
+        // Without a length the body runs until the server closes the connection
+        assert_eq!(body(b"HTTP/1.0 200 OK\r\n\r\npartial", false, false), None);
+        assert_eq!(body(b"HTTP/1.0 200 OK\r\n\r\npartial", false, true).as_deref(), Some("partial"));
+        assert!(parse(b"SSH-2.0-OpenSSH\r\n\r\n", false, true).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+//! Everything TCP Commander does with sockets: connections over pluggable
+//! transports, protocol modes, framing, sequences and the transcript. None of
+//! it depends on Tauri, so the plugin and the command-line client both drive
+//! the same [`manager::ConnectionManager`].
+
+pub use models::*;
+
+mod error;
+mod models;
+
+pub mod config;
+pub mod diagnostics;
+pub mod discovery;
+pub mod framing;
+pub mod gcode;
+pub mod http;
+pub mod manager;
+pub mod modbus;
+pub mod mqtt;
+pub mod osc;
+pub mod resp;
+pub mod scpi;
+pub mod sequence;
+pub mod telnet;
+pub mod testing;
+pub mod transport;
+
+pub use config::Config;
+pub use error::{Error, Result};
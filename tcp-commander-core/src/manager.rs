@@ -0,0 +1,1734 @@
+//! Connection lifecycle, independent of Tauri so it can be driven by a
+//! [`MockTransport`](crate::transport::MockTransport) in tests.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    io::{self, ErrorKind, Read, Write},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, MutexGuard, PoisonError, RwLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::Config,
+    framing::{FrameLength, Framer, MAX_PENDING_READS},
+    gcode, http, modbus,
+    models::*,
+    mqtt::{self, Packet},
+    osc, resp, scpi, sequence,
+    testing,
+    telnet::{self, Telnet},
+    transport::{Connection, Target, Transport, Transports},
+};
+
+pub const DEFAULT_TRANSPORT: &str = "tcp";
+
+/// How long the reader holds the connection per poll, which bounds how long a send waits for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+type Listener = Arc<dyn Fn(&TranscriptEntry) + Send + Sync>;
+
+/// Counts a socket as open until dropped.
+struct OpenGuard(Arc<AtomicUsize>);
+
+impl OpenGuard {
+    fn new(open: &Arc<AtomicUsize>) -> Self {
+        open.fetch_add(1, Ordering::SeqCst);
+        Self(open.clone())
+    }
+}
+
+impl Drop for OpenGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Settings of an MQTT connection that outlive the handshake.
+#[derive(Debug, Clone, Copy)]
+struct MqttSession {
+    version: MqttVersion,
+    /// How often to ping the broker, at half the negotiated keep alive.
+    ping_interval: Option<Duration>,
+}
+
+/// Work running in the background, such as a G-code stream, a sequence or a
+/// schedule, shared with the thread running it.
+#[derive(Default)]
+struct Job<S> {
+    status: Mutex<S>,
+    paused: AtomicBool,
+    aborted: AtomicBool,
+}
+
+type GcodeJob = Job<GcodeStatus>;
+type SequenceJob = Job<SequenceStatus>;
+type ScheduleJob = Job<ScheduleStatus>;
+
+impl<S> Job<S> {
+    fn update(&self, update: impl FnOnce(&mut S)) {
+        update(&mut lock(&self.status));
+    }
+
+    /// Waits out a pause, returning whether the job has been aborted.
+    fn hold(&self) -> bool {
+        while self.paused.load(Ordering::SeqCst) && !self.aborted.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+        }
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps for `duration`, returning `false` if the job is aborted first.
+    fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        while Instant::now() < deadline {
+            if self.aborted.load(Ordering::SeqCst) {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+        }
+        !self.aborted.load(Ordering::SeqCst)
+    }
+}
+
+impl GcodeJob {
+    fn is_active(&self) -> bool {
+        matches!(lock(&self.status).state, GcodeState::Running | GcodeState::Paused)
+    }
+}
+
+impl SequenceJob {
+    fn is_active(&self) -> bool {
+        matches!(lock(&self.status).state, SequenceState::Running | SequenceState::Paused)
+    }
+}
+
+/// How waiting for a G-code acknowledgement ended.
+enum GcodeAck {
+    Next,
+    Resend(usize),
+    Aborted,
+}
+
+struct Active {
+    id: u64,
+    connection: Box<dyn Connection>,
+    protocol: Protocol,
+    mqtt: Option<MqttSession>,
+    /// What goes in the `Host` header of HTTP requests.
+    host: String,
+    /// Whether OSC packets are SLIP-framed, as they are over byte streams.
+    slip: bool,
+    _guard: OpenGuard,
+}
+
+struct Shared {
+    config: Config,
+    transports: RwLock<Transports>,
+    active: Mutex<Option<Active>>,
+    next_connection_id: AtomicU64,
+    open: Arc<AtomicUsize>,
+    transcript: Mutex<VecDeque<TranscriptEntry>>,
+    next_entry_id: AtomicU64,
+    listener: RwLock<Option<Listener>>,
+    /// Get a copy of every received frame, for commands that wait for a reply.
+    subscribers: Mutex<Vec<Sender<Vec<u8>>>>,
+    next_transaction_id: AtomicU16,
+    gcode: Mutex<Option<Arc<GcodeJob>>>,
+    sequence: Mutex<Option<Arc<SequenceJob>>>,
+    /// Schedules that are running, by id; each one removes itself when it's over.
+    schedules: Mutex<BTreeMap<u64, Arc<ScheduleJob>>>,
+    next_schedule_id: AtomicU64,
+}
+
+pub struct ConnectionManager(Arc<Shared>);
+
+/// Reads the first frame a server sends, for handshakes that happen before the reader starts.
+fn first_frame(connection: &mut dyn Connection, length: FrameLength, timeout: Option<Duration>) -> io::Result<Vec<u8>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut buf = [0; 256];
+    let mut framer = Framer::with_length(length).limit(buf.len() * MAX_PENDING_READS);
+    loop {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(io::Error::new(ErrorKind::TimedOut, "no reply from the server"));
+        }
+        let n = match connection.read(&mut buf) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "the server closed the connection")),
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(frame) = framer.push(&buf[..n]).into_iter().next() {
+            return Ok(frame);
+        }
+    }
+}
+
+/// Sends CONNECT and waits for the broker to accept it.
+fn mqtt_handshake(connection: &mut dyn Connection, options: &MqttOptions, timeout: Option<Duration>) -> io::Result<()> {
+    let version = options.version.unwrap_or_default();
+    connection.write_all(&mqtt::connect(options))?;
+    connection.flush()?;
+
+    match mqtt::decode(version, &first_frame(connection, mqtt::frame_length, timeout)?) {
+        Ok(Packet::ConnAck { code: 0, .. }) => Ok(()),
+        Ok(Packet::ConnAck { code, .. }) => {
+            Err(io::Error::new(ErrorKind::ConnectionRefused, mqtt::connect_error(version, code)))
+        }
+        Ok(other) => Err(io::Error::new(ErrorKind::InvalidData, format!("expected CONNACK, got {other:?}"))),
+        Err(e) => Err(io::Error::new(ErrorKind::InvalidData, e)),
+    }
+}
+
+/// Switches to RESP3 with `HELLO 3`.
+fn redis_handshake(connection: &mut dyn Connection, timeout: Option<Duration>) -> io::Result<()> {
+    connection.write_all(&resp::encode(resp::HELLO_3).expect("valid command"))?;
+    connection.flush()?;
+
+    match resp::decode(&first_frame(connection, resp::frame_length, timeout)?) {
+        Ok(RedisValue::Error(message)) => {
+            Err(io::Error::new(ErrorKind::Unsupported, format!("server doesn't speak RESP3: {message}")))
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(io::Error::new(ErrorKind::InvalidData, e)),
+    }
+}
+
+impl Default for ConnectionManager {
+    fn default() -> Self {
+        Self::new(Transports::default(), Config::default())
+    }
+}
+
+// A panic while holding a lock shouldn't take every later command down with it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Shared {
+    fn record(&self, direction: Direction, data: impl Into<String>) {
+        let entry = TranscriptEntry {
+            id: self.next_entry_id.fetch_add(1, Ordering::SeqCst),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            direction,
+            data: data.into(),
+        };
+        {
+            let mut transcript = lock(&self.transcript);
+            transcript.push_back(entry.clone());
+            while transcript.len() > self.config.transcript_retention {
+                transcript.pop_front();
+            }
+        }
+        let listener = self.listener.read().unwrap_or_else(PoisonError::into_inner).clone();
+        if let Some(listener) = listener {
+            listener(&entry);
+        }
+    }
+
+    fn receive(&self, id: u64, protocol: Protocol, mqtt: Option<MqttSession>, frame: Vec<u8>) {
+        let text = match (protocol, mqtt) {
+            (Protocol::Modbus, _) => Some(modbus::hex(&frame)),
+            (Protocol::Scpi, _) => Some(scpi::describe(&frame)),
+            (Protocol::Redis, _) => Some(resp::describe_frame(&frame)),
+            (Protocol::Gcode, _) => Some(String::from_utf8_lossy(&frame).trim_end().to_string()),
+            (Protocol::Osc, _) => Some(osc::describe(&frame)),
+            (Protocol::Mqtt, Some(session)) => self.receive_mqtt(id, session, &frame),
+            _ => Some(String::from_utf8_lossy(&frame).into_owned()),
+        };
+        if let Some(text) = text {
+            self.record(Direction::Received, text);
+        }
+        lock(&self.subscribers).retain(|subscriber| subscriber.send(frame.clone()).is_ok());
+    }
+
+    /// Acknowledges incoming messages, returning what to show in the transcript.
+    /// Acknowledgements for our own requests are left to whoever is waiting on them.
+    fn receive_mqtt(&self, id: u64, session: MqttSession, frame: &[u8]) -> Option<String> {
+        match mqtt::decode(session.version, frame) {
+            Ok(Packet::Publish { topic, payload, qos, packet_id, .. }) => {
+                match (qos, packet_id) {
+                    (1, Some(packet_id)) => self.write_if_current(id, &mqtt::puback(packet_id)),
+                    (2, Some(packet_id)) => self.write_if_current(id, &mqtt::pubrec(packet_id)),
+                    _ => {}
+                }
+                Some(format!("{topic}: {}", String::from_utf8_lossy(&payload)))
+            }
+            Ok(Packet::PubRel { packet_id }) => {
+                self.write_if_current(id, &mqtt::pubcomp(packet_id));
+                None
+            }
+            Ok(Packet::Disconnect { code }) => {
+                self.record(Direction::Info, format!("Broker is disconnecting ({code:#04x})"));
+                None
+            }
+            Ok(_) => None,
+            Err(e) => {
+                self.record(Direction::Info, format!("Malformed MQTT packet: {e}"));
+                None
+            }
+        }
+    }
+
+    /// Writes protocol housekeeping that doesn't belong in the transcript.
+    /// Failures are left for the reader to notice.
+    fn write_if_current(&self, id: u64, bytes: &[u8]) {
+        let mut active = lock(&self.active);
+        if let Some(active) = active.as_mut().filter(|a| a.id == id) {
+            let _ = active.connection.write_all(bytes).and_then(|_| active.connection.flush());
+        }
+    }
+
+    /// Closes `active` without holding up the caller; it keeps counting as
+    /// open until it has finished closing.
+    fn retire(active: Active, mode: DisconnectMode) {
+        if mode == DisconnectMode::Graceful {
+            thread::spawn(move || {
+                let Active { connection, _guard, .. } = active;
+                connection.close(mode);
+            });
+        } else {
+            active.connection.close(mode);
+        }
+    }
+
+    /// Takes the active connection if it is still the one with `id`.
+    fn take_if_current(&self, id: u64) -> Option<Active> {
+        let mut active = lock(&self.active);
+        if active.as_ref().is_some_and(|a| a.id == id) {
+            active.take()
+        } else {
+            None
+        }
+    }
+
+    fn read_loop(self: Arc<Self>, id: u64, framing: Framing, protocol: Protocol, mqtt: Option<MqttSession>, slip: bool) {
+        let mut buf = vec![0; self.config.receive_buffer_size.max(1)];
+        let mut framer = match protocol {
+            Protocol::Modbus => Framer::with_length(modbus::frame_length),
+            Protocol::Scpi => Framer::with_length(scpi::frame_length),
+            Protocol::Mqtt => Framer::with_length(mqtt::frame_length),
+            Protocol::Redis => Framer::with_length(resp::frame_length),
+            Protocol::Gcode => Framer::new(Framing::Lf),
+            Protocol::Osc if slip => Framer::with_length(osc::slip::frame_length),
+            // Each read is a whole datagram
+            Protocol::Osc => Framer::new(Framing::Raw),
+            // Responses are pieced together by whoever sent the request, from the bytes as they arrived
+            Protocol::Http => Framer::new(Framing::Raw),
+            _ => Framer::new(framing),
+        }
+        .limit(buf.len().saturating_mul(MAX_PENDING_READS));
+        let mut telnet = (protocol == Protocol::Telnet).then(|| Telnet::new(self.config.telnet.clone()));
+        // SLIP frames are unescaped, and the empty ones between back-to-back ENDs dropped
+        let unframe = |frame: Vec<u8>| match slip {
+            true => Some(osc::slip::decode(&frame)).filter(|packet| !packet.is_empty()),
+            false => Some(frame),
+        };
+        let mut last_ping = Instant::now();
+        loop {
+            let result = {
+                let mut active = lock(&self.active);
+                match active.as_mut() {
+                    Some(active) if active.id == id => {
+                        let connection = &mut active.connection;
+                        let ping_interval = mqtt.and_then(|session| session.ping_interval);
+                        let ping = ping_interval.filter(|interval| last_ping.elapsed() >= *interval).map(|_| {
+                            last_ping = Instant::now();
+                            connection.write_all(&mqtt::PINGREQ).and_then(|_| connection.flush())
+                        });
+                        // `None` once the server has closed its side
+                        ping.unwrap_or(Ok(())).and_then(|_| connection.read(&mut buf)).and_then(|n| match telnet.as_mut() {
+                            _ if n == 0 => Ok(None),
+                            // Answer negotiation straight away; the server may not send
+                            // anything else until it has its replies
+                            Some(telnet) => {
+                                let received = telnet.receive(&buf[..n]);
+                                if !received.reply.is_empty() {
+                                    connection.write_all(&received.reply)?;
+                                    connection.flush()?;
+                                }
+                                Ok(Some(received.data))
+                            }
+                            None => Ok(Some(buf[..n].to_vec())),
+                        })
+                    }
+                    // Disconnected or replaced by a newer connection
+                    _ => break,
+                }
+            };
+            match result {
+                Ok(None) => {
+                    if let Some(rest) = framer.finish().and_then(unframe) {
+                        self.receive(id, protocol, mqtt, rest);
+                    }
+                    if let Some(active) = self.take_if_current(id) {
+                        self.record(Direction::Info, "Connection closed by server");
+                        Shared::retire(active, DisconnectMode::Full);
+                    }
+                    break;
+                }
+                Ok(Some(data)) => {
+                    for frame in framer.push(&data).into_iter().filter_map(unframe) {
+                        self.receive(id, protocol, mqtt, frame);
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {
+                    // Give senders waiting on the lock a chance to get in
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(e) => {
+                    if let Some(active) = self.take_if_current(id) {
+                        self.record(Direction::Info, format!("Connection lost: {e}"));
+                        Shared::retire(active, DisconnectMode::Abort);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl ConnectionManager {
+    pub fn new(transports: Transports, config: Config) -> Self {
+        Self(Arc::new(Shared {
+            config,
+            transports: RwLock::new(transports),
+            active: Mutex::new(None),
+            next_connection_id: AtomicU64::new(0),
+            open: Arc::new(AtomicUsize::new(0)),
+            transcript: Mutex::new(VecDeque::new()),
+            next_entry_id: AtomicU64::new(0),
+            listener: RwLock::new(None),
+            subscribers: Mutex::new(Vec::new()),
+            next_transaction_id: AtomicU16::new(0),
+            gcode: Mutex::new(None),
+            sequence: Mutex::new(None),
+            schedules: Mutex::new(BTreeMap::new()),
+            next_schedule_id: AtomicU64::new(1),
+        }))
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.0.config
+    }
+
+    /// Makes `transport` available to `connect`, replacing any existing one with the same name.
+    pub fn register_transport(&self, transport: impl Transport + 'static) {
+        self.0
+            .transports
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .register(transport);
+    }
+
+    /// Calls `listener` with every transcript entry as it is recorded.
+    pub fn set_listener(&self, listener: impl Fn(&TranscriptEntry) + Send + Sync + 'static) {
+        *self.0.listener.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(listener));
+    }
+
+    pub fn is_connected(&self) -> bool {
+        lock(&self.0.active).is_some()
+    }
+
+    /// Connects, replacing the active connection. The lock on the active
+    /// connection is only taken to swap it out, so sends and the reader
+    /// aren't held up by a slow connect or handshake.
+    pub fn connect(&self, payload: ConnectRequest) -> crate::Result<ConnectResponse> {
+        self.connect_to(payload, Vec::new())
+    }
+
+    /// Like [`connect`](Self::connect), but only to `addresses`, which the
+    /// request's address has already been looked up as, e.g. to check it
+    /// against a scope. Empty means look it up as usual.
+    pub fn connect_to(&self, payload: ConnectRequest, addresses: Vec<IpAddr>) -> crate::Result<ConnectResponse> {
+        let previous = lock(&self.0.active).take();
+        if let Some(previous) = previous {
+            Shared::retire(previous, DisconnectMode::Graceful);
+        }
+
+        let addr = payload.address.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing address")
+        })?;
+        let port = payload
+            .port
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing port"))?;
+        let port = u16::try_from(port)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "port out of range"))?;
+
+        let open = self.0.open.load(Ordering::SeqCst);
+        if open >= self.0.config.max_connections {
+            return Ok(ConnectResponse {
+                success: Some(false),
+                error: Some(format!("Too many open connections ({open}); wait for earlier ones to finish closing")),
+            });
+        }
+
+        let name = payload.transport.as_deref().unwrap_or(DEFAULT_TRANSPORT);
+        let transports = self.0.transports.read().unwrap_or_else(PoisonError::into_inner);
+        let Some(transport) = transports.get(name) else {
+            let available = transports.names().collect::<Vec<_>>().join(", ");
+            return Ok(ConnectResponse {
+                success: Some(false),
+                error: Some(format!("Unknown transport '{name}'; available transports are {available}")),
+            });
+        };
+        drop(transports);
+
+        let protocol = payload.protocol.unwrap_or_default();
+        let mqtt_options = payload.mqtt.unwrap_or_default();
+        let mqtt = (protocol == Protocol::Mqtt).then(|| MqttSession {
+            version: mqtt_options.version.unwrap_or_default(),
+            ping_interval: match mqtt_options.keep_alive_secs.unwrap_or(mqtt::DEFAULT_KEEP_ALIVE_SECS) {
+                0 => None,
+                secs => Some(Duration::from_secs(secs.into()) / 2),
+            },
+        });
+        let resp3 = match payload.redis.and_then(|redis| redis.resp_version) {
+            _ if protocol != Protocol::Redis => false,
+            None | Some(2) => false,
+            Some(3) => true,
+            Some(version) => {
+                return Ok(ConnectResponse {
+                    success: Some(false),
+                    error: Some(format!("RESP version must be 2 or 3, not {version}")),
+                });
+            }
+        };
+        let host = http::host(&addr, port);
+        // Counts while connecting too, so the limit covers connects still under way
+        let guard = OpenGuard::new(&self.0.open);
+        let connection = transport
+            .connect(&Target::new(addr, port).with_addresses(addresses), self.0.config.connect_timeout())
+            .and_then(|mut connection| {
+                connection.set_read_timeout(Some(POLL_INTERVAL))?;
+                connection.set_write_timeout(self.0.config.write_timeout())?;
+                if mqtt.is_some() {
+                    mqtt_handshake(connection.as_mut(), &mqtt_options, self.0.config.connect_timeout())?;
+                }
+                if resp3 {
+                    redis_handshake(connection.as_mut(), self.0.config.connect_timeout())?;
+                }
+                Ok(connection)
+            });
+        match connection {
+            Ok(connection) => {
+                let id = self.0.next_connection_id.fetch_add(1, Ordering::SeqCst);
+                let peer = connection.peer();
+                let slip = protocol == Protocol::Osc && !connection.is_datagram();
+                let replaced = lock(&self.0.active).replace(Active {
+                    id,
+                    connection,
+                    protocol,
+                    mqtt,
+                    host,
+                    slip,
+                    _guard: guard,
+                });
+                // Another connect finished while this one was under way
+                if let Some(replaced) = replaced {
+                    Shared::retire(replaced, DisconnectMode::Graceful);
+                }
+
+                self.0.record(Direction::Info, format!("Connected to {peer} ({name})"));
+                let framing = payload.framing.unwrap_or(self.0.config.default_framing);
+                let shared = self.0.clone();
+                thread::spawn(move || shared.read_loop(id, framing, protocol, mqtt, slip));
+                Ok(ConnectResponse {
+                    success: Some(true),
+                    error: None,
+                })
+            }
+            Err(e) => {
+                Ok(ConnectResponse {
+                    success: Some(false),
+                    error: Some(format!("Failed to connect to server: {e}")),
+                })
+            }
+        }
+    }
+
+    /// Closes the active connection, if any. Calling this while disconnected is a no-op.
+    pub fn disconnect(&self, payload: DisconnectRequest) -> crate::Result<()> {
+        let active = lock(&self.0.active).take();
+        if let Some(mut active) = active {
+            let mode = payload.mode.unwrap_or_default();
+            // Tell the broker this is on purpose, so it doesn't publish our will; an
+            // abortive close is meant to look like a dropped connection
+            if active.mqtt.is_some() && mode != DisconnectMode::Abort {
+                let _ = active.connection.write_all(&mqtt::DISCONNECT);
+            }
+            self.0.record(Direction::Info, format!("Disconnected ({mode:?})"));
+            Shared::retire(active, mode);
+        }
+
+        Ok(())
+    }
+
+    /// Returns every frame received from now on, until the receiver is dropped.
+    pub fn subscribe(&self) -> Receiver<Vec<u8>> {
+        let (sender, receiver) = mpsc::channel();
+        lock(&self.0.subscribers).push(sender);
+        receiver
+    }
+
+    /// Writes to the active connection. `encode` gets the connection's
+    /// protocol (and MQTT version) and returns the bytes to send along with
+    /// how to show them in the transcript, if at all, or an error message if
+    /// they can't be sent.
+    fn send<'a>(
+        &self,
+        encode: impl FnOnce(Protocol, Option<MqttVersion>) -> Result<(Cow<'a, [u8]>, Option<String>), String>,
+    ) -> Result<(), String> {
+        let mut active = lock(&self.0.active);
+        let Some(current) = active.as_mut() else {
+            return Err("not connected to a server".into());
+        };
+        let (bytes, text) = encode(current.protocol, current.mqtt.map(|session| session.version))?;
+        let connection = &mut current.connection;
+        if let Err(e) = connection.write_all(&bytes).and_then(|_| connection.flush()) {
+            if let Some(active) = active.take() {
+                Shared::retire(active, DisconnectMode::Abort);
+            }
+            drop(active);
+            self.0.record(Direction::Info, format!("Connection lost: {e}"));
+            return Err("disconnected from server".into());
+        }
+        drop(active);
+        if let Some(text) = text {
+            self.0.record(Direction::Sent, text);
+        }
+        Ok(())
+    }
+
+    pub fn transmit(&self, payload: TransmitRequest) -> crate::Result<TransmitResponse> {
+        let message = payload.message.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing message")
+        })?;
+
+        let streaming = lock(&self.0.gcode)
+            .as_ref()
+            .is_some_and(|job| job.is_active() && !job.paused.load(Ordering::SeqCst));
+        if streaming {
+            return Ok(TransmitResponse {
+                error: Some("Error transmitting: a G-code stream is running; pause it first".into()),
+            });
+        }
+
+        let suffix = payload.suffix.unwrap_or_default();
+        let result = self.send(|protocol, _| match protocol {
+            Protocol::Raw | Protocol::Http | Protocol::Telnet if !suffix.is_empty() => {
+                let bytes = [message.as_bytes(), &suffix].concat();
+                // Show the suffix as text if it is, so line endings don't turn into hex
+                let text = match std::str::from_utf8(&suffix) {
+                    Ok(suffix) => format!("{message}{suffix}"),
+                    Err(_) => format!("{message}[{}]", modbus::hex(&suffix)),
+                };
+                let bytes = if protocol == Protocol::Telnet { telnet::escape(&bytes).into_owned() } else { bytes };
+                Ok((bytes.into(), Some(text)))
+            }
+            _ if !suffix.is_empty() => Err(format!("raw bytes can't be added to messages in {protocol:?} mode")),
+            Protocol::Raw | Protocol::Http => Ok((message.as_bytes().into(), Some(message.clone()))),
+            Protocol::Telnet => Ok((telnet::escape(message.as_bytes()), Some(message.clone()))),
+            Protocol::Scpi => Ok((scpi::terminate(&message).into_bytes().into(), Some(message.clone()))),
+            Protocol::Modbus => Err("use Modbus requests while in Modbus mode".into()),
+            Protocol::Redis => resp::encode(&message).map(|command| (command.into(), Some(message.clone()))),
+            Protocol::Gcode => Ok((gcode::terminate(&message).into_bytes().into(), Some(message.clone()))),
+            Protocol::Mqtt => Err("publish messages while in MQTT mode".into()),
+            Protocol::Osc => Err("use OSC messages while in OSC mode".into()),
+        });
+        Ok(TransmitResponse {
+            error: result.err().map(|e| format!("Error transmitting: {e}")),
+        })
+    }
+
+    /// Waits up to the reply timeout for a frame that `accept`s.
+    fn await_reply(&self, replies: &Receiver<Vec<u8>>, accept: impl Fn(&[u8]) -> bool) -> Result<Vec<u8>, String> {
+        let deadline = Instant::now() + self.0.config.reply_timeout();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match replies.recv_timeout(remaining) {
+                Ok(reply) if accept(&reply) => return Ok(reply),
+                Ok(_) => continue,
+                Err(_) => {
+                    let error = format!("No reply within {} ms", self.0.config.reply_timeout_ms);
+                    self.0.record(Direction::Info, &error);
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Sends a Modbus request and waits up to the reply timeout for the matching response.
+    pub fn modbus(&self, payload: ModbusRequest) -> crate::Result<ModbusResponse> {
+        let failed = |error: String| ModbusResponse { result: None, exception: None, error: Some(error) };
+        if let Err(e) = modbus::validate(&payload.operation) {
+            return Ok(failed(e));
+        }
+        let unit = payload.unit_id.unwrap_or(modbus::DEFAULT_UNIT_ID);
+        let transaction = self.0.next_transaction_id.fetch_add(1, Ordering::SeqCst);
+        let frame = modbus::encode(transaction, unit, &payload.operation);
+
+        let replies = self.subscribe();
+        let sent = self.send(|protocol, _| match protocol {
+            Protocol::Modbus => Ok((frame.as_slice().into(), Some(modbus::hex(&frame)))),
+            _ => Err("connect in Modbus mode to send Modbus requests".into()),
+        });
+        if let Err(e) = sent {
+            return Ok(failed(format!("Error sending Modbus request: {e}")));
+        }
+        self.0.record(Direction::Info, modbus::describe(unit, &payload.operation));
+
+        // Anything else is a stale reply to a request that already timed out
+        let reply = match self.await_reply(&replies, |reply| modbus::transaction(reply) == Some(transaction)) {
+            Ok(reply) => reply,
+            Err(e) => return Ok(failed(e)),
+        };
+
+        Ok(match modbus::decode(&payload.operation, &reply) {
+            Ok(result) => {
+                self.0.record(Direction::Info, modbus::describe_result(&result));
+                ModbusResponse { result: Some(result), exception: None, error: None }
+            }
+            Err(e) => {
+                self.0.record(Direction::Info, e.to_string());
+                ModbusResponse {
+                    result: None,
+                    exception: match e {
+                        modbus::DecodeError::Exception(code) => Some(code),
+                        modbus::DecodeError::Malformed(_) => None,
+                    },
+                    error: Some(e.to_string()),
+                }
+            }
+        })
+    }
+
+    /// Sends a single SCPI command, waiting for the reply if it's a query.
+    fn scpi_command(&self, command: &str) -> Result<Option<scpi::Reply>, String> {
+        let replies = scpi::is_query(command).then(|| self.subscribe());
+        let line = scpi::terminate(command);
+        self.send(|protocol, _| match protocol {
+            Protocol::Scpi => Ok((line.as_bytes().into(), Some(command.trim().to_string()))),
+            _ => Err("connect in SCPI mode to send SCPI commands".into()),
+        })
+        .map_err(|e| format!("Error sending SCPI command: {e}"))?;
+        replies
+            .map(|replies| self.await_reply(&replies, |_| true).map(|frame| scpi::parse(&frame)))
+            .transpose()
+    }
+
+    /// Sends an SCPI command, returning the reply to queries and, if asked
+    /// to, whatever errors the instrument queued up.
+    pub fn scpi(&self, payload: ScpiRequest) -> crate::Result<ScpiResponse> {
+        let mut response = ScpiResponse::default();
+        match self.scpi_command(&payload.command) {
+            Ok(Some(scpi::Reply::Text(text))) => response.reply = Some(text),
+            Ok(Some(scpi::Reply::Block(data))) => response.block = Some(data),
+            Ok(None) => {}
+            Err(e) => {
+                response.error = Some(e);
+                return Ok(response);
+            }
+        }
+
+        if payload.check_errors.unwrap_or(false) {
+            for _ in 0..scpi::MAX_QUEUED_ERRORS {
+                match self.scpi_command(scpi::ERROR_QUERY) {
+                    Ok(Some(scpi::Reply::Text(reply))) if scpi::is_no_error(&reply) => break,
+                    Ok(Some(scpi::Reply::Text(reply))) => response.errors.push(reply),
+                    Ok(_) => {
+                        response.error = Some(format!("Unexpected reply to {}", scpi::ERROR_QUERY));
+                        break;
+                    }
+                    Err(e) => {
+                        response.error = Some(format!("Error checking for errors: {e}"));
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    /// MQTT packet ids have to be non-zero.
+    fn next_packet_id(&self) -> u16 {
+        loop {
+            let id = self.0.next_transaction_id.fetch_add(1, Ordering::SeqCst);
+            if id != 0 {
+                return id;
+            }
+        }
+    }
+
+    /// Sends an MQTT packet built by `encode` for the connection's version.
+    fn send_mqtt(&self, encode: impl FnOnce(MqttVersion) -> (Vec<u8>, Option<String>)) -> Result<MqttVersion, String> {
+        let mut version = None;
+        self.send(|protocol, mqtt| match (protocol, mqtt) {
+            (Protocol::Mqtt, Some(mqtt)) => {
+                version = Some(mqtt);
+                let (bytes, text) = encode(mqtt);
+                Ok((bytes.into(), text))
+            }
+            _ => Err("connect in MQTT mode first".into()),
+        })?;
+        Ok(version.expect("set when sent"))
+    }
+
+    /// Waits for the MQTT packet `accept` picks out.
+    fn await_mqtt<T>(
+        &self,
+        replies: &Receiver<Vec<u8>>,
+        version: MqttVersion,
+        accept: impl Fn(Packet) -> Option<T>,
+    ) -> Result<T, String> {
+        let frame = self.await_reply(replies, |frame| {
+            mqtt::decode(version, frame).ok().and_then(&accept).is_some()
+        })?;
+        Ok(mqtt::decode(version, &frame).ok().and_then(accept).expect("accepted above"))
+    }
+
+    /// Publishes a message, waiting for the broker's acknowledgement at QoS 1 and 2.
+    pub fn mqtt_publish(&self, payload: MqttPublishRequest) -> crate::Result<MqttPublishResponse> {
+        let qos = payload.qos.unwrap_or(0);
+        if qos > 2 {
+            return Ok(MqttPublishResponse { error: Some(format!("QoS must be 0, 1 or 2, not {qos}")) });
+        }
+        let packet_id = self.next_packet_id();
+        let replies = (qos > 0).then(|| self.subscribe());
+        let result = self
+            .send_mqtt(|version| {
+                let packet = mqtt::publish(
+                    version,
+                    &payload.topic,
+                    payload.payload.as_bytes(),
+                    qos,
+                    payload.retain.unwrap_or(false),
+                    packet_id,
+                );
+                (packet, Some(format!("{}: {}", payload.topic, payload.payload)))
+            })
+            .and_then(|version| {
+                let Some(replies) = replies else {
+                    return Ok(());
+                };
+                let code = match qos {
+                    1 => self.await_mqtt(&replies, version, |packet| match packet {
+                        Packet::PubAck { packet_id: id, code } if id == packet_id => Some(code),
+                        _ => None,
+                    })?,
+                    _ => {
+                        let code = self.await_mqtt(&replies, version, |packet| match packet {
+                            Packet::PubRec { packet_id: id, code } if id == packet_id => Some(code),
+                            _ => None,
+                        })?;
+                        if code < 0x80 {
+                            self.send_mqtt(|_| (mqtt::pubrel(packet_id), None))?;
+                            self.await_mqtt(&replies, version, |packet| match packet {
+                                Packet::PubComp { packet_id: id } if id == packet_id => Some(()),
+                                _ => None,
+                            })?;
+                        }
+                        code
+                    }
+                };
+                if code >= 0x80 {
+                    return Err(format!("Broker rejected the message ({code:#04x})"));
+                }
+                Ok(())
+            });
+        Ok(MqttPublishResponse { error: result.err() })
+    }
+
+    pub fn mqtt_subscribe(&self, payload: MqttSubscribeRequest) -> crate::Result<MqttSubscribeResponse> {
+        let qos = payload.qos.unwrap_or(0);
+        if qos > 2 {
+            return Ok(MqttSubscribeResponse {
+                granted_qos: None,
+                error: Some(format!("QoS must be 0, 1 or 2, not {qos}")),
+            });
+        }
+        let packet_id = self.next_packet_id();
+        let replies = self.subscribe();
+        let result = self
+            .send_mqtt(|version| (mqtt::subscribe(version, packet_id, &payload.topic, qos), None))
+            .and_then(|version| {
+                self.await_mqtt(&replies, version, |packet| match packet {
+                    Packet::SubAck { packet_id: id, codes } if id == packet_id => Some(codes.first().copied()),
+                    _ => None,
+                })
+            });
+        Ok(match result {
+            Ok(Some(granted)) if granted < 0x80 => {
+                self.0.record(Direction::Info, format!("Subscribed to {} (QoS {granted})", payload.topic));
+                MqttSubscribeResponse { granted_qos: Some(granted), error: None }
+            }
+            Ok(code) => MqttSubscribeResponse {
+                granted_qos: None,
+                error: Some(format!(
+                    "Broker rejected the subscription to {} ({:#04x})",
+                    payload.topic,
+                    code.unwrap_or(0x80)
+                )),
+            },
+            Err(e) => MqttSubscribeResponse { granted_qos: None, error: Some(e) },
+        })
+    }
+
+    pub fn mqtt_unsubscribe(&self, payload: MqttUnsubscribeRequest) -> crate::Result<MqttSubscribeResponse> {
+        let packet_id = self.next_packet_id();
+        let replies = self.subscribe();
+        let result = self
+            .send_mqtt(|version| (mqtt::unsubscribe(version, packet_id, &payload.topic), None))
+            .and_then(|version| {
+                self.await_mqtt(&replies, version, |packet| match packet {
+                    Packet::UnsubAck { packet_id: id } if id == packet_id => Some(()),
+                    _ => None,
+                })
+            });
+        if result.is_ok() {
+            self.0.record(Direction::Info, format!("Unsubscribed from {}", payload.topic));
+        }
+        Ok(MqttSubscribeResponse { granted_qos: None, error: result.err() })
+    }
+
+    /// Sends an HTTP request and waits up to the reply timeout for the whole response.
+    pub fn http(&self, payload: HttpRequest) -> crate::Result<HttpResponse> {
+        let failed = |error: String| HttpResponse { error: Some(error), ..Default::default() };
+        if let Err(e) = http::validate(&payload) {
+            return Ok(failed(e));
+        }
+        let host = lock(&self.0.active).as_ref().map(|active| active.host.clone()).unwrap_or_default();
+        let replies = self.subscribe();
+        let sent = self.send(|protocol, _| match protocol {
+            Protocol::Http => {
+                let request = http::encode(&payload, &host);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                Ok((request.into(), Some(text)))
+            }
+            _ => Err("connect in HTTP mode to send HTTP requests".into()),
+        });
+        if let Err(e) = sent {
+            return Ok(failed(format!("Error sending HTTP request: {e}")));
+        }
+
+        let head = payload.method.eq_ignore_ascii_case("HEAD");
+        let deadline = Instant::now() + self.0.config.reply_timeout();
+        let mut received = Vec::new();
+        loop {
+            // Check before draining, so everything sent before the close is seen
+            let closed = !self.is_connected();
+            received.extend(replies.try_iter().flatten());
+            let error = match http::parse(&received, head, closed) {
+                Ok(Some(response)) => {
+                    self.0.record(Direction::Info, http::describe(&response));
+                    return Ok(response);
+                }
+                Ok(None) if closed => "Connection closed before the response was complete".to_string(),
+                Ok(None) if Instant::now() >= deadline => {
+                    format!("No complete response within {} ms", self.0.config.reply_timeout_ms)
+                }
+                Ok(None) => {
+                    if let Ok(chunk) = replies.recv_timeout(POLL_INTERVAL) {
+                        received.extend(chunk);
+                    }
+                    continue;
+                }
+                Err(e) => format!("Malformed HTTP response: {e}"),
+            };
+            self.0.record(Direction::Info, &error);
+            return Ok(failed(error));
+        }
+    }
+
+    /// Sends Redis commands in one go and waits for a reply to each.
+    pub fn redis(&self, payload: RedisRequest) -> crate::Result<RedisResponse> {
+        let failed = |error: String| RedisResponse { replies: Vec::new(), error: Some(error) };
+        if payload.commands.is_empty() {
+            return Ok(failed("no commands to send".into()));
+        }
+        let mut pipeline = Vec::new();
+        for command in &payload.commands {
+            match resp::encode(command) {
+                Ok(encoded) => pipeline.extend(encoded),
+                Err(e) => return Ok(failed(format!("Invalid command '{command}': {e}"))),
+            }
+        }
+
+        let replies = self.subscribe();
+        let sent = self.send(|protocol, _| match protocol {
+            Protocol::Redis => Ok((pipeline.as_slice().into(), Some(payload.commands.join("\n")))),
+            _ => Err("connect in Redis mode to send Redis commands".into()),
+        });
+        if let Err(e) = sent {
+            return Ok(failed(format!("Error sending Redis commands: {e}")));
+        }
+
+        let mut response = RedisResponse::default();
+        while response.replies.len() < payload.commands.len() {
+            // Pushes (pub/sub messages and the like) aren't replies to anything
+            let reply = self.await_reply(&replies, |frame| !matches!(resp::decode(frame), Ok(RedisValue::Push(_))));
+            match reply.and_then(|frame| resp::decode(&frame)) {
+                Ok(value) => response.replies.push(value),
+                Err(e) => {
+                    response.error = Some(e);
+                    break;
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    /// Sends OSC messages, as one bundle if there are several of them.
+    pub fn osc(&self, payload: OscRequest) -> crate::Result<OscResponse> {
+        let packet = match osc::encode(&payload) {
+            Ok(packet) => packet,
+            Err(e) => return Ok(OscResponse { error: Some(format!("Invalid OSC message: {e}")) }),
+        };
+        let slip = lock(&self.0.active).as_ref().is_some_and(|active| active.slip);
+        let result = self.send(|protocol, _| match protocol {
+            Protocol::Osc => {
+                let text = osc::describe(&packet);
+                let bytes = if slip { osc::slip::encode(&packet) } else { packet };
+                Ok((bytes.into(), Some(text)))
+            }
+            _ => Err("connect in OSC mode to send OSC messages".into()),
+        });
+        Ok(OscResponse { error: result.err().map(|e| format!("Error sending OSC message: {e}")) })
+    }
+
+    /// Starts streaming a G-code program in the background.
+    pub fn gcode_stream(&self, payload: GcodeStreamRequest) -> crate::Result<GcodeStatus> {
+        let failed = |error: String| GcodeStatus { state: GcodeState::Failed, error: Some(error), ..Default::default() };
+        let program = match (payload.program, payload.path) {
+            (Some(program), _) => program,
+            (None, Some(path)) => match std::fs::read_to_string(&path) {
+                Ok(program) => program,
+                Err(e) => return Ok(failed(format!("Failed to read {path}: {e}"))),
+            },
+            (None, None) => return Ok(failed("missing program".into())),
+        };
+        let lines = gcode::prepare(&program);
+        if lines.is_empty() {
+            return Ok(failed("program has no commands".into()));
+        }
+        match lock(&self.0.active).as_ref() {
+            Some(active) if active.protocol == Protocol::Gcode => {}
+            Some(_) => return Ok(failed("connect in G-code mode to stream G-code".into())),
+            None => return Ok(failed("not connected to a server".into())),
+        }
+
+        let job = {
+            let mut current = lock(&self.0.gcode);
+            if current.as_ref().is_some_and(|job| job.is_active()) {
+                return Ok(failed("a G-code stream is already running".into()));
+            }
+            let job = Arc::new(GcodeJob::default());
+            job.update(|status| {
+                status.state = GcodeState::Running;
+                status.total = lines.len();
+            });
+            *current = Some(job.clone());
+            job
+        };
+        let status = lock(&job.status).clone();
+
+        let checksums = payload.checksums.unwrap_or(self.0.config.gcode.checksums);
+        let replies = self.subscribe();
+        let manager = ConnectionManager(self.0.clone());
+        thread::spawn(move || {
+            let total = lines.len();
+            let result = manager.run_gcode(&job, &lines, checksums, &replies);
+            let acknowledged = lock(&job.status).acknowledged;
+            let (state, note) = match &result {
+                Ok(GcodeState::Aborted) => {
+                    (GcodeState::Aborted, format!("G-code stream aborted after {acknowledged} of {total} lines"))
+                }
+                Ok(state) => (*state, format!("G-code stream finished, {total} lines sent")),
+                Err(e) => (GcodeState::Failed, format!("G-code stream failed at line {}: {e}", acknowledged + 1)),
+            };
+            job.update(|status| {
+                status.state = state;
+                status.error = result.err();
+            });
+            manager.0.record(Direction::Info, note);
+        });
+        Ok(status)
+    }
+
+    /// Sends `lines` one at a time, returning `Finished` or `Aborted`.
+    fn run_gcode(
+        &self,
+        job: &GcodeJob,
+        lines: &[String],
+        checksums: bool,
+        replies: &Receiver<Vec<u8>>,
+    ) -> Result<GcodeState, String> {
+        let send = |line: String| {
+            // Anything still queued answered something else, such as a command sent while paused
+            replies.try_iter().for_each(drop);
+            self.send(|protocol, _| match protocol {
+                Protocol::Gcode => Ok((gcode::terminate(&line).into_bytes().into(), Some(line.clone()))),
+                _ => Err("connect in G-code mode to stream G-code".into()),
+            })
+        };
+
+        if checksums {
+            send(gcode::RESET_LINE_NUMBER.into())?;
+            if let GcodeAck::Aborted = self.await_gcode_ack(job, replies, checksums)? {
+                return Ok(GcodeState::Aborted);
+            }
+        }
+        let mut next = 0;
+        while next < lines.len() {
+            if job.hold() {
+                return Ok(GcodeState::Aborted);
+            }
+            // Line numbers start at 1, following the `M110 N0`
+            send(if checksums { gcode::number(next + 1, &lines[next]) } else { lines[next].clone() })?;
+            match self.await_gcode_ack(job, replies, checksums)? {
+                GcodeAck::Next => next += 1,
+                GcodeAck::Resend(line) if (1..=next + 1).contains(&line) => {
+                    next = line - 1;
+                    job.update(|status| status.resends += 1);
+                }
+                GcodeAck::Resend(line) => return Err(format!("firmware asked for line {line}, which wasn't sent")),
+                GcodeAck::Aborted => return Ok(GcodeState::Aborted),
+            }
+            job.update(|status| status.acknowledged = next);
+        }
+        Ok(GcodeState::Finished)
+    }
+
+    /// Waits for the firmware to acknowledge the line just sent.
+    fn await_gcode_ack(&self, job: &GcodeJob, replies: &Receiver<Vec<u8>>, checksums: bool) -> Result<GcodeAck, String> {
+        let config = &self.0.config.gcode;
+        let mut resend = None;
+        let mut last_heard = Instant::now();
+        loop {
+            if job.aborted.load(Ordering::SeqCst) {
+                return Ok(GcodeAck::Aborted);
+            }
+            if config.ack_timeout().is_some_and(|timeout| last_heard.elapsed() >= timeout) {
+                return Err(format!("no reply within {} ms", config.ack_timeout_ms));
+            }
+            let Ok(frame) = replies.recv_timeout(POLL_INTERVAL) else {
+                if !self.is_connected() {
+                    return Err("disconnected from server".into());
+                }
+                continue;
+            };
+            last_heard = Instant::now();
+            match gcode::classify(&String::from_utf8_lossy(&frame), &config.acknowledgement) {
+                // Firmware acknowledges the bad line after asking for the resend
+                gcode::Reply::Ack => return Ok(resend.map_or(GcodeAck::Next, GcodeAck::Resend)),
+                gcode::Reply::Resend(line) if checksums => resend = Some(line),
+                gcode::Reply::Error(error) if checksums && gcode::is_recoverable(&error) => {}
+                gcode::Reply::Error(error) => return Err(error),
+                gcode::Reply::Resend(_) | gcode::Reply::Other => {}
+            }
+        }
+    }
+
+    pub fn gcode_control(&self, payload: GcodeControlRequest) -> crate::Result<GcodeStatus> {
+        let job = lock(&self.0.gcode).clone().filter(|job| job.is_active());
+        let Some(job) = job else {
+            return Ok(GcodeStatus { error: Some("no G-code stream is running".into()), ..self.gcode_status() });
+        };
+        match payload.action {
+            GcodeAction::Pause => {
+                job.paused.store(true, Ordering::SeqCst);
+                job.update(|status| status.state = GcodeState::Paused);
+            }
+            GcodeAction::Resume => {
+                job.paused.store(false, Ordering::SeqCst);
+                job.update(|status| status.state = GcodeState::Running);
+            }
+            GcodeAction::Abort => job.aborted.store(true, Ordering::SeqCst),
+        }
+        self.0.record(Direction::Info, format!("G-code stream: {:?}", payload.action));
+        Ok(self.gcode_status())
+    }
+
+    pub fn gcode_status(&self) -> GcodeStatus {
+        lock(&self.0.gcode).as_ref().map(|job| lock(&job.status).clone()).unwrap_or_default()
+    }
+
+    /// Starts running a sequence in the background.
+    pub fn sequence_run(&self, payload: SequenceRunRequest) -> crate::Result<SequenceStatus> {
+        let failed = |error: String| SequenceStatus { state: SequenceState::Failed, error: Some(error), ..Default::default() };
+        if payload.steps.is_empty() {
+            return Ok(failed("sequence has no steps".into()));
+        }
+        if let Err(e) = sequence::validate(&payload.steps) {
+            return Ok(failed(e));
+        }
+
+        let job = {
+            let mut current = lock(&self.0.sequence);
+            if current.as_ref().is_some_and(|job| job.is_active()) {
+                return Ok(failed("a sequence is already running".into()));
+            }
+            let job = Arc::new(SequenceJob::default());
+            job.update(|status| {
+                status.state = SequenceState::Running;
+                status.name = payload.name.clone();
+                status.total = sequence::count(&payload.steps);
+            });
+            *current = Some(job.clone());
+            job
+        };
+        let status = lock(&job.status).clone();
+
+        let name = payload.name.map_or_else(|| "Sequence".to_string(), |name| format!("Sequence '{name}'"));
+        self.0.record(Direction::Info, format!("{name} started"));
+        let manager = ConnectionManager(self.0.clone());
+        thread::spawn(move || {
+            let mut mark = manager.0.next_entry_id.load(Ordering::SeqCst);
+            let result = manager.run_sequence(&job, &payload.steps, &mut mark);
+            let completed = lock(&job.status).completed;
+            let (state, note) = match &result {
+                Ok(SequenceState::Stopped) => (SequenceState::Stopped, format!("{name} stopped after {completed} steps")),
+                Ok(state) => (*state, format!("{name} finished")),
+                Err(e) => (SequenceState::Failed, format!("{name} failed at step {}: {e}", completed + 1)),
+            };
+            job.update(|status| {
+                status.state = state;
+                status.current = None;
+                status.error = result.err();
+            });
+            manager.0.record(Direction::Info, note);
+        });
+        Ok(status)
+    }
+
+    /// Runs `steps` in order, returning `Finished` or `Stopped`. `mark` is the
+    /// id of the first transcript entry a wait may match.
+    fn run_sequence(&self, job: &SequenceJob, steps: &[SequenceStep], mark: &mut u64) -> Result<SequenceState, String> {
+        for step in steps {
+            if job.hold() {
+                return Ok(SequenceState::Stopped);
+            }
+            if let SequenceStep::Repeat { times, steps } = step {
+                for _ in 0..*times {
+                    if self.run_sequence(job, steps, mark)? == SequenceState::Stopped {
+                        return Ok(SequenceState::Stopped);
+                    }
+                }
+                continue;
+            }
+            job.update(|status| status.current = Some(sequence::describe(step)));
+            match step {
+                SequenceStep::Delay { ms } => {
+                    if !job.sleep(Duration::from_millis(*ms)) {
+                        return Ok(SequenceState::Stopped);
+                    }
+                }
+                SequenceStep::WaitFor { pattern, timeout_ms } => {
+                    let timeout = timeout_ms.map_or(self.0.config.reply_timeout(), Duration::from_millis);
+                    if !self.await_match(job, pattern, timeout, mark)? {
+                        return Ok(SequenceState::Stopped);
+                    }
+                }
+                SequenceStep::Repeat { .. } => unreachable!("repeats are handled above"),
+                step => {
+                    *mark = self.0.next_entry_id.load(Ordering::SeqCst);
+                    self.send_step(step)?;
+                }
+            }
+            job.update(|status| status.completed += 1);
+        }
+        Ok(SequenceState::Finished)
+    }
+
+    /// Sends what a step sends, failing if the command would report an error.
+    pub fn send_step(&self, step: &SequenceStep) -> Result<(), String> {
+        let error = match step {
+            SequenceStep::Transmit { message, suffix } => {
+                self.transmit(TransmitRequest { message: Some(message.clone()), suffix: suffix.clone() }).map_err(|e| e.to_string())?.error
+            }
+            SequenceStep::Scpi(request) => self.scpi(request.clone()).map_err(|e| e.to_string())?.error,
+            SequenceStep::Modbus(request) => {
+                let response = self.modbus(request.clone()).map_err(|e| e.to_string())?;
+                response.error.or(response.exception.map(|code| format!("device replied with exception {code:#04x}")))
+            }
+            SequenceStep::Publish(publish) => self.mqtt_publish(publish.clone()).map_err(|e| e.to_string())?.error,
+            SequenceStep::Http(request) => self.http(request.clone()).map_err(|e| e.to_string())?.error,
+            SequenceStep::Osc(request) => self.osc(request.clone()).map_err(|e| e.to_string())?.error,
+            SequenceStep::Delay { .. } | SequenceStep::WaitFor { .. } | SequenceStep::Repeat { .. } => {
+                return Err(format!("{} doesn't send anything", sequence::describe(step)));
+            }
+        };
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Waits for a received entry from `mark` on to match `pattern`, moving
+    /// `mark` past it. Returns `false` if the sequence was stopped first.
+    fn await_match(&self, job: &SequenceJob, pattern: &str, timeout: Duration, mark: &mut u64) -> Result<bool, String> {
+        let regex = regex::Regex::new(pattern).map_err(|e| e.to_string())?;
+        let deadline = (!timeout.is_zero()).then(|| Instant::now() + timeout);
+        loop {
+            if job.aborted.load(Ordering::SeqCst) {
+                return Ok(false);
+            }
+            let matched = lock(&self.0.transcript)
+                .iter()
+                .find(|entry| entry.id >= *mark && entry.direction == Direction::Received && regex.is_match(&entry.data))
+                .map(|entry| entry.id);
+            if let Some(id) = matched {
+                *mark = id + 1;
+                return Ok(true);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(format!("nothing matching /{pattern}/ within {} ms", timeout.as_millis()));
+            }
+            if !self.is_connected() {
+                return Err("disconnected from server".into());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    pub fn sequence_control(&self, payload: SequenceControlRequest) -> crate::Result<SequenceStatus> {
+        let job = lock(&self.0.sequence).clone().filter(|job| job.is_active());
+        let Some(job) = job else {
+            return Ok(SequenceStatus { error: Some("no sequence is running".into()), ..self.sequence_status() });
+        };
+        match payload.action {
+            SequenceAction::Pause => {
+                job.paused.store(true, Ordering::SeqCst);
+                job.update(|status| status.state = SequenceState::Paused);
+            }
+            SequenceAction::Resume => {
+                job.paused.store(false, Ordering::SeqCst);
+                job.update(|status| status.state = SequenceState::Running);
+            }
+            SequenceAction::Stop => job.aborted.store(true, Ordering::SeqCst),
+        }
+        self.0.record(Direction::Info, format!("Sequence: {:?}", payload.action));
+        Ok(self.sequence_status())
+    }
+
+    pub fn sequence_status(&self) -> SequenceStatus {
+        lock(&self.0.sequence).as_ref().map(|job| lock(&job.status).clone()).unwrap_or_default()
+    }
+
+    /// Starts sending a step every `interval_ms` in the background. Any number
+    /// of schedules can run at once.
+    pub fn schedule_start(&self, payload: ScheduleRequest) -> crate::Result<ScheduleStatus> {
+        let failed = |error: String| ScheduleStatus { error: Some(error), ..Default::default() };
+        if payload.interval_ms == 0 {
+            return Ok(failed("interval has to be at least 1 ms".into()));
+        }
+        if matches!(payload.step, SequenceStep::Delay { .. } | SequenceStep::WaitFor { .. } | SequenceStep::Repeat { .. }) {
+            return Ok(failed("schedules can only send commands".into()));
+        }
+        if payload.count == Some(0) || payload.duration_ms == Some(0) {
+            return Ok(failed("schedule would never send anything".into()));
+        }
+
+        let id = self.0.next_schedule_id.fetch_add(1, Ordering::SeqCst);
+        let job = Arc::new(ScheduleJob::default());
+        job.update(|status| {
+            *status = ScheduleStatus {
+                id,
+                name: payload.name.clone(),
+                description: sequence::describe(&payload.step),
+                interval_ms: payload.interval_ms,
+                count: payload.count,
+                duration_ms: payload.duration_ms,
+                sent: 0,
+                error: None,
+            }
+        });
+        lock(&self.0.schedules).insert(id, job.clone());
+        let status = lock(&job.status).clone();
+
+        let name = payload.name.as_ref().map_or_else(|| format!("Schedule {id}"), |name| format!("Schedule '{name}'"));
+        self.0.record(Direction::Info, format!("{name} started, every {} ms", payload.interval_ms));
+        let manager = ConnectionManager(self.0.clone());
+        thread::spawn(move || {
+            let result = manager.run_schedule(&job, &payload);
+            lock(&manager.0.schedules).remove(&id);
+            let sent = lock(&job.status).sent;
+            let note = match result {
+                Ok(()) if job.aborted.load(Ordering::SeqCst) => format!("{name} stopped after {sent} sends"),
+                Ok(()) => format!("{name} finished after {sent} sends"),
+                Err(e) => format!("{name} failed after {sent} sends: {e}"),
+            };
+            manager.0.record(Direction::Info, note);
+        });
+        Ok(status)
+    }
+
+    /// Sends `step` until the count or duration runs out or the schedule is
+    /// stopped. Sends keep to the interval, but one that overruns it is
+    /// followed straight away rather than by a burst of catching up.
+    fn run_schedule(&self, job: &ScheduleJob, schedule: &ScheduleRequest) -> Result<(), String> {
+        let interval = Duration::from_millis(schedule.interval_ms);
+        let start = Instant::now();
+        let end = schedule.duration_ms.map(|duration| start + Duration::from_millis(duration));
+        let mut next = start;
+        loop {
+            if !job.sleep(next.saturating_duration_since(Instant::now())) {
+                return Ok(());
+            }
+            self.send_step(&schedule.step)?;
+            job.update(|status| status.sent += 1);
+            if schedule.count.is_some_and(|count| lock(&job.status).sent >= count) {
+                return Ok(());
+            }
+            next = (next + interval).max(Instant::now());
+            if end.is_some_and(|end| next >= end) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Stops one schedule, or all of them, before their next send.
+    pub fn schedule_stop(&self, payload: ScheduleStopRequest) -> crate::Result<ScheduleListResponse> {
+        let schedules = lock(&self.0.schedules).clone();
+        for (id, job) in schedules {
+            if payload.id.is_none() || payload.id == Some(id) {
+                job.aborted.store(true, Ordering::SeqCst);
+            }
+        }
+        Ok(self.schedule_list())
+    }
+
+    pub fn schedule_list(&self) -> ScheduleListResponse {
+        let schedules = lock(&self.0.schedules)
+            .values()
+            .filter(|job| !job.aborted.load(Ordering::SeqCst))
+            .map(|job| lock(&job.status).clone())
+            .collect();
+        ScheduleListResponse { schedules }
+    }
+
+    /// Runs each test in turn and reports how they went. Blocks until they're
+    /// all done; the tests take the place of a sequence meanwhile, so
+    /// `sequence_control` can pause or stop them.
+    pub fn test_run(&self, payload: TestRunRequest) -> crate::Result<TestReport> {
+        let suite = payload.suite.unwrap_or_else(|| "tcp-commander".to_string());
+        if payload.cases.is_empty() {
+            return Ok(TestReport { suite, error: Some("no tests to run".into()), ..Default::default() });
+        }
+        if !self.is_connected() {
+            return Ok(TestReport { suite, error: Some("not connected to a server".into()), ..Default::default() });
+        }
+
+        let job = {
+            let mut current = lock(&self.0.sequence);
+            if current.as_ref().is_some_and(|job| job.is_active()) {
+                return Ok(TestReport { suite, error: Some("a sequence is already running".into()), ..Default::default() });
+            }
+            let job = Arc::new(SequenceJob::default());
+            job.update(|status| {
+                status.state = SequenceState::Running;
+                status.name = Some(suite.clone());
+                status.total = payload.cases.iter().map(|case| sequence::count(&case.steps)).sum();
+            });
+            *current = Some(job.clone());
+            job
+        };
+
+        self.0.record(Direction::Info, format!("Running {} tests", payload.cases.len()));
+        let start = Instant::now();
+        let mut results = Vec::new();
+        let mut done = 0;
+        for case in &payload.cases {
+            if job.hold() {
+                break;
+            }
+            results.push(self.run_test(&job, case));
+            done += sequence::count(&case.steps);
+            job.update(|status| status.completed = done);
+        }
+        let stopped = job.aborted.load(Ordering::SeqCst);
+        job.update(|status| {
+            status.state = if stopped { SequenceState::Stopped } else { SequenceState::Finished };
+            status.current = None;
+        });
+
+        let passed = results.iter().filter(|result| result.passed).count();
+        let mut report = TestReport {
+            suite,
+            failed: results.len() - passed,
+            passed,
+            error: stopped.then(|| format!("stopped after {} of {} tests", results.len(), payload.cases.len())),
+            results,
+            duration_ms: start.elapsed().as_millis() as u64,
+            junit: String::new(),
+        };
+        report.junit = testing::junit(&report);
+        self.0.record(Direction::Info, format!("Tests done: {} passed, {} failed", report.passed, report.failed));
+        Ok(report)
+    }
+
+    fn run_test(&self, job: &SequenceJob, case: &TestCase) -> TestResult {
+        let start = Instant::now();
+        let mut result = TestResult {
+            name: case.name.clone(),
+            expected: case.expect.as_ref().map(|expect| testing::describe(&expect.reply)),
+            ..Default::default()
+        };
+        let outcome = testing::validate(case).and_then(|()| {
+            // With an expectation, the last step is sent here so its reply can be caught
+            let (steps, last) = match &case.expect {
+                Some(expect) => {
+                    let (last, steps) = case.steps.split_last().expect("validated to have a last step");
+                    (steps, Some((last, expect)))
+                }
+                None => (case.steps.as_slice(), None),
+            };
+            let mut mark = self.0.next_entry_id.load(Ordering::SeqCst);
+            if self.run_sequence(job, steps, &mut mark)? == SequenceState::Stopped {
+                return Err("stopped".into());
+            }
+            let Some((last, expect)) = last else {
+                return Ok(());
+            };
+            if job.hold() {
+                return Err("stopped".into());
+            }
+
+            let replies = self.subscribe();
+            let mark = self.0.next_entry_id.load(Ordering::SeqCst);
+            job.update(|status| status.current = Some(sequence::describe(last)));
+            self.send_step(last)?;
+            let timeout = expect.timeout_ms.map_or(self.0.config.reply_timeout(), Duration::from_millis);
+            // Waited for a little at a time so stopping doesn't have to sit out the timeout
+            let deadline = Instant::now() + timeout;
+            let frame = loop {
+                let left = deadline.saturating_duration_since(Instant::now());
+                match replies.recv_timeout(POLL_INTERVAL.min(left)) {
+                    Ok(frame) => break Some(frame),
+                    Err(_) if job.aborted.load(Ordering::SeqCst) => return Err("stopped".into()),
+                    Err(RecvTimeoutError::Timeout) if !left.is_zero() => {}
+                    Err(_) => break None,
+                }
+            };
+            let reply = frame.map(|frame| {
+                // Received frames are recorded before they're passed on, so the entry is there by now
+                let text = lock(&self.0.transcript)
+                    .iter()
+                    .find(|entry| entry.id >= mark && entry.direction == Direction::Received)
+                    .map_or_else(|| String::from_utf8_lossy(&frame).into_owned(), |entry| entry.data.clone());
+                (text, frame.len())
+            });
+            result.actual = reply.as_ref().map(|(text, _)| text.clone());
+            if let (ExpectedReply::Exact { text }, Some((actual, _))) = (&expect.reply, &reply) {
+                result.diff = testing::diff(text, actual);
+            }
+            testing::check(&expect.reply, reply.as_ref().map(|(text, length)| (text.as_str(), *length)))
+        });
+        result.duration_ms = start.elapsed().as_millis() as u64;
+        result.passed = outcome.is_ok();
+        result.error = outcome.err();
+        let note = match &result.error {
+            None => format!("Test '{}' passed", case.name),
+            Some(e) => format!("Test '{}' failed: {e}", case.name),
+        };
+        self.0.record(Direction::Info, note);
+        result
+    }
+
+    pub fn transcript(&self, payload: TranscriptRequest) -> TranscriptResponse {
+        let transcript = lock(&self.0.transcript);
+        let entries = match payload.after {
+            Some(after) => transcript.iter().filter(|e| e.id > after).cloned().collect(),
+            None => transcript.iter().cloned().collect(),
+        };
+        TranscriptResponse { entries }
+    }
+
+    pub fn clear_transcript(&self) {
+        lock(&self.0.transcript).clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MockConnection, MockTransport};
+
+    fn manager() -> (ConnectionManager, MockTransport) {
+        let transport = MockTransport::new();
+        let manager = ConnectionManager::new(Transports::default(), Config::default());
+        manager.register_transport(transport.clone());
+        (manager, transport)
+    }
+
+    fn request() -> ConnectRequest {
+        ConnectRequest {
+            address: Some("device".into()),
+            port: Some(5000),
+            transport: Some("mock".into()),
+            framing: Some(Framing::Lf),
+            protocol: None,
+            mqtt: None,
+            redis: None,
+        }
+    }
+
+    /// Connects and returns the server's end of the connection.
+    fn connect(manager: &ConnectionManager, transport: &MockTransport) -> MockConnection {
+        assert_eq!(manager.connect(request()).unwrap().error, None);
+        let (target, server) = transport.accept().unwrap();
+        assert_eq!(target, Target::new("device", 5000));
+        server
+    }
+
+    fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for {what}");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn wait_for(manager: &ConnectionManager, direction: Direction, data: &str) {
+        wait_until(&format!("{direction:?} {data:?}"), || {
+            let entries = manager.transcript(TranscriptRequest::default()).entries;
+            entries.iter().any(|entry| entry.direction == direction && entry.data == data)
+        });
+    }
+
+    fn open(manager: &ConnectionManager) -> usize {
+        manager.0.open.load(Ordering::SeqCst)
+    }
+
+    /// Reads until the manager closes its side.
+    fn read_to_end(server: &mut MockConnection) -> Vec<u8> {
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).unwrap();
+        received
+    }
+
+    #[test]
+    fn transmit_reaches_the_server() {
+        let (manager, transport) = manager();
+        let mut server = connect(&manager, &transport);
+        wait_for(&manager, Direction::Info, "Connected to mock://client (mock)");
+        assert!(manager.is_connected());
+
+        let request = TransmitRequest { message: Some("*IDN?".into()), suffix: Some(b"\r\n".to_vec()) };
+        assert_eq!(manager.transmit(request).unwrap().error, None);
+        wait_for(&manager, Direction::Sent, "*IDN?\r\n");
+        manager.disconnect(DisconnectRequest { mode: Some(DisconnectMode::Full) }).unwrap();
+        assert_eq!(read_to_end(&mut server), b"*IDN?\r\n");
+    }
+
+    #[test]
+    fn received_data_is_framed() {
+        let (manager, transport) = manager();
+        let mut server = connect(&manager, &transport);
+        server.write_all(b"one\ntw").unwrap();
+        wait_for(&manager, Direction::Received, "one");
+        server.write_all(b"o\n").unwrap();
+        wait_for(&manager, Direction::Received, "two");
+    }
+
+    #[test]
+    fn server_closing_is_noticed() {
+        let (manager, transport) = manager();
+        let mut server = connect(&manager, &transport);
+        server.write_all(b"bye").unwrap();
+        server.shutdown_write();
+        // What's left without a line ending still makes it into the transcript
+        wait_for(&manager, Direction::Received, "bye");
+        wait_for(&manager, Direction::Info, "Connection closed by server");
+        assert!(!manager.is_connected());
+        wait_until("the connection to close", || open(&manager) == 0);
+        assert!(manager.transmit(TransmitRequest { message: Some("late".into()), suffix: None }).unwrap().error.is_some());
+    }
+
+    #[test]
+    fn full_and_abortive_disconnects_close_at_once() {
+        for mode in [DisconnectMode::Full, DisconnectMode::Abort] {
+            let (manager, transport) = manager();
+            let mut server = connect(&manager, &transport);
+            manager.disconnect(DisconnectRequest { mode: Some(mode) }).unwrap();
+            assert!(!manager.is_connected());
+            assert_eq!(open(&manager), 0);
+            assert_eq!(read_to_end(&mut server), b"");
+            wait_for(&manager, Direction::Info, &format!("Disconnected ({mode:?})"));
+        }
+    }
+
+    #[test]
+    fn graceful_disconnect_waits_for_the_server() {
+        let (manager, transport) = manager();
+        let mut server = connect(&manager, &transport);
+        manager.disconnect(DisconnectRequest { mode: Some(DisconnectMode::Graceful) }).unwrap();
+        assert!(!manager.is_connected());
+        assert_eq!(read_to_end(&mut server), b"");
+        // Still open until the server closes its side too
+        assert_eq!(open(&manager), 1);
+        server.write_all(b"late reply\n").unwrap();
+        server.shutdown_write();
+        wait_until("the connection to close", || open(&manager) == 0);
+        wait_for(&manager, Direction::Info, "Disconnected (Graceful)");
+    }
+
+    #[test]
+    fn reconnecting_replaces_the_connection() {
+        let (manager, transport) = manager();
+        let mut first = connect(&manager, &transport);
+        let mut second = connect(&manager, &transport);
+        // The old connection is closed gracefully
+        assert_eq!(read_to_end(&mut first), b"");
+        first.shutdown_write();
+        second.write_all(b"second\n").unwrap();
+        wait_for(&manager, Direction::Received, "second");
+        wait_until("the old connection to close", || open(&manager) == 1);
+    }
+
+    #[test]
+    fn handshake_doesnt_hold_up_the_manager() {
+        let (manager, transport) = manager();
+        // Kept open past the scope, or the manager could notice it closing before the last check
+        let _server = thread::scope(|scope| {
+            let connecting = scope.spawn(|| {
+                let request = ConnectRequest { protocol: Some(Protocol::Mqtt), ..request() };
+                manager.connect(request).unwrap()
+            });
+            let mut server = loop {
+                match transport.accept() {
+                    Some((_, server)) => break server,
+                    None => thread::sleep(Duration::from_millis(5)),
+                }
+            };
+            // The broker hasn't answered yet, and the manager can still be asked about
+            assert!(!manager.is_connected());
+            let mut connect = [0; 2];
+            server.read_exact(&mut connect).unwrap();
+            assert_eq!(connect[0], 0x10);
+            let mut rest = vec![0; connect[1] as usize];
+            server.read_exact(&mut rest).unwrap();
+            server.write_all(&[0x20, 2, 0, 0]).unwrap();
+            assert_eq!(connecting.join().unwrap().error, None);
+            server
+        });
+        assert!(manager.is_connected());
+    }
+
+    #[test]
+    fn refused_connection_is_reported() {
+        let (manager, transport) = manager();
+        transport.set_refusing(true);
+        let response = manager.connect(request()).unwrap();
+        assert_eq!(response.success, Some(false));
+        assert!(response.error.unwrap().starts_with("Failed to connect to server"));
+        assert!(!manager.is_connected());
+        assert!(transport.accept().is_none());
+    }
+}
@@ -0,0 +1,292 @@
+//! Modbus TCP framing: MBAP headers around Modbus application PDUs.
+//!
+//! Every frame starts with a 7 byte header of transaction id, protocol id
+//! (always 0), the length of what follows, and the unit id.
+
+use crate::models::{ModbusOperation, ModbusResult};
+
+pub const DEFAULT_UNIT_ID: u8 = 1;
+
+const HEADER_LEN: usize = 7;
+
+/// Flagged in the function code of a response that reports an exception.
+const EXCEPTION_FLAG: u8 = 0x80;
+
+/// Why a response couldn't be turned into a [`ModbusResult`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The device answered with an exception code.
+    Exception(u8),
+    Malformed(String),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Exception(code) => {
+                write!(f, "Modbus exception {code:#04x} ({})", exception_name(*code))
+            }
+            DecodeError::Malformed(reason) => write!(f, "Malformed Modbus response: {reason}"),
+        }
+    }
+}
+
+pub fn exception_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "illegal function",
+        0x02 => "illegal data address",
+        0x03 => "illegal data value",
+        0x04 => "server device failure",
+        0x05 => "acknowledge",
+        0x06 => "server device busy",
+        0x08 => "memory parity error",
+        0x0A => "gateway path unavailable",
+        0x0B => "gateway target device failed to respond",
+        _ => "unknown exception",
+    }
+}
+
+fn function_code(operation: &ModbusOperation) -> u8 {
+    match operation {
+        ModbusOperation::ReadCoils { .. } => 0x01,
+        ModbusOperation::ReadDiscreteInputs { .. } => 0x02,
+        ModbusOperation::ReadHoldingRegisters { .. } => 0x03,
+        ModbusOperation::ReadInputRegisters { .. } => 0x04,
+        ModbusOperation::WriteSingleCoil { .. } => 0x05,
+        ModbusOperation::WriteSingleRegister { .. } => 0x06,
+        ModbusOperation::WriteMultipleCoils { .. } => 0x0F,
+        ModbusOperation::WriteMultipleRegisters { .. } => 0x10,
+    }
+}
+
+fn pack_bits(values: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0; values.len().div_ceil(8)];
+    for (i, _) in values.iter().enumerate().filter(|(_, on)| **on) {
+        bytes[i / 8] |= 1 << (i % 8);
+    }
+    bytes
+}
+
+/// Checks the limits the spec puts on quantities, so the device isn't
+/// left to reject them with a less helpful exception.
+pub fn validate(operation: &ModbusOperation) -> Result<(), String> {
+    let (count, max) = match operation {
+        ModbusOperation::ReadCoils { count, .. } | ModbusOperation::ReadDiscreteInputs { count, .. } => {
+            (*count as usize, 2000)
+        }
+        ModbusOperation::ReadHoldingRegisters { count, .. } | ModbusOperation::ReadInputRegisters { count, .. } => {
+            (*count as usize, 125)
+        }
+        ModbusOperation::WriteMultipleCoils { values, .. } => (values.len(), 1968),
+        ModbusOperation::WriteMultipleRegisters { values, .. } => (values.len(), 123),
+        ModbusOperation::WriteSingleCoil { .. } | ModbusOperation::WriteSingleRegister { .. } => return Ok(()),
+    };
+    if !(1..=max).contains(&count) {
+        return Err(format!("Quantity must be between 1 and {max}, not {count}"));
+    }
+    Ok(())
+}
+
+/// Builds a complete request frame.
+pub fn encode(transaction: u16, unit: u8, operation: &ModbusOperation) -> Vec<u8> {
+    let mut pdu = vec![function_code(operation)];
+    match operation {
+        ModbusOperation::ReadCoils { address, count }
+        | ModbusOperation::ReadDiscreteInputs { address, count }
+        | ModbusOperation::ReadHoldingRegisters { address, count }
+        | ModbusOperation::ReadInputRegisters { address, count } => {
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&count.to_be_bytes());
+        }
+        ModbusOperation::WriteSingleCoil { address, value } => {
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(if *value { &[0xFF, 0x00] } else { &[0x00, 0x00] });
+        }
+        ModbusOperation::WriteSingleRegister { address, value } => {
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&value.to_be_bytes());
+        }
+        ModbusOperation::WriteMultipleCoils { address, values } => {
+            let packed = pack_bits(values);
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&(values.len() as u16).to_be_bytes());
+            pdu.push(packed.len() as u8);
+            pdu.extend_from_slice(&packed);
+        }
+        ModbusOperation::WriteMultipleRegisters { address, values } => {
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&(values.len() as u16).to_be_bytes());
+            pdu.push((values.len() * 2) as u8);
+            for value in values {
+                pdu.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_LEN + pdu.len());
+    frame.extend_from_slice(&transaction.to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    frame.push(unit);
+    frame.extend_from_slice(&pdu);
+    frame
+}
+
+/// Length of the frame at the start of `bytes`, for [`Framer::with_length`](crate::framing::Framer::with_length).
+pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+    let length = u16::from_be_bytes(bytes.get(4..6)?.try_into().ok()?);
+    Some(6 + length as usize)
+}
+
+/// Transaction id of a complete frame.
+pub fn transaction(frame: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes(frame.get(0..2)?.try_into().ok()?))
+}
+
+/// Decodes the response to `operation`.
+pub fn decode(operation: &ModbusOperation, frame: &[u8]) -> Result<ModbusResult, DecodeError> {
+    let malformed = |reason: &str| DecodeError::Malformed(reason.to_string());
+    let pdu = frame.get(HEADER_LEN..).filter(|pdu| !pdu.is_empty()).ok_or_else(|| malformed("empty PDU"))?;
+    let expected = function_code(operation);
+    if pdu[0] == expected | EXCEPTION_FLAG {
+        return Err(DecodeError::Exception(*pdu.get(1).ok_or_else(|| malformed("missing exception code"))?));
+    }
+    if pdu[0] != expected {
+        return Err(DecodeError::Malformed(format!(
+            "expected function {expected:#04x}, got {:#04x}",
+            pdu[0]
+        )));
+    }
+
+    let word = |offset: usize| {
+        pdu.get(offset..offset + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(|| malformed("response is truncated"))
+    };
+    match operation {
+        ModbusOperation::ReadCoils { count, .. } | ModbusOperation::ReadDiscreteInputs { count, .. } => {
+            // `get(2..)` only succeeds once the byte count itself is there
+            let data = pdu.get(2..).filter(|data| data.len() == pdu[1] as usize);
+            let data = data.ok_or_else(|| malformed("byte count doesn't match the data"))?;
+            if data.len() * 8 < *count as usize {
+                return Err(malformed("fewer bits than requested"));
+            }
+            let values = (0..*count as usize).map(|i| data[i / 8] & (1 << (i % 8)) != 0).collect();
+            Ok(ModbusResult::Bits { values })
+        }
+        ModbusOperation::ReadHoldingRegisters { count, .. } | ModbusOperation::ReadInputRegisters { count, .. } => {
+            let data = pdu.get(2..).filter(|data| data.len() == pdu[1] as usize);
+            let data = data.ok_or_else(|| malformed("byte count doesn't match the data"))?;
+            if data.len() != *count as usize * 2 {
+                return Err(malformed("register count doesn't match the request"));
+            }
+            let values = data.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+            Ok(ModbusResult::Registers { values })
+        }
+        ModbusOperation::WriteSingleCoil { .. } | ModbusOperation::WriteSingleRegister { .. } => {
+            Ok(ModbusResult::Written { address: word(1)?, count: 1 })
+        }
+        ModbusOperation::WriteMultipleCoils { .. } | ModbusOperation::WriteMultipleRegisters { .. } => {
+            Ok(ModbusResult::Written { address: word(1)?, count: word(3)? })
+        }
+    }
+}
+
+/// One-line summary of a request for the transcript.
+pub fn describe(unit: u8, operation: &ModbusOperation) -> String {
+    let action = match operation {
+        ModbusOperation::ReadCoils { address, count } => format!("Read {count} coil(s) from {address}"),
+        ModbusOperation::ReadDiscreteInputs { address, count } => {
+            format!("Read {count} discrete input(s) from {address}")
+        }
+        ModbusOperation::ReadHoldingRegisters { address, count } => {
+            format!("Read {count} holding register(s) from {address}")
+        }
+        ModbusOperation::ReadInputRegisters { address, count } => {
+            format!("Read {count} input register(s) from {address}")
+        }
+        ModbusOperation::WriteSingleCoil { address, value } => {
+            format!("Write coil {address} = {}", if *value { "ON" } else { "OFF" })
+        }
+        ModbusOperation::WriteSingleRegister { address, value } => format!("Write register {address} = {value}"),
+        ModbusOperation::WriteMultipleCoils { address, values } => {
+            format!("Write {} coil(s) from {address}: {values:?}", values.len())
+        }
+        ModbusOperation::WriteMultipleRegisters { address, values } => {
+            format!("Write {} register(s) from {address}: {values:?}", values.len())
+        }
+    };
+    format!("{action} (unit {unit})")
+}
+
+/// One-line summary of a response for the transcript.
+pub fn describe_result(result: &ModbusResult) -> String {
+    match result {
+        ModbusResult::Bits { values } => {
+            let bits = values.iter().map(|on| if *on { "1" } else { "0" }).collect::<Vec<_>>();
+            format!("Bits: {}", bits.join(" "))
+        }
+        ModbusResult::Registers { values } => format!("Registers: {values:?}"),
+        ModbusResult::Written { address, count } => format!("Wrote {count} value(s) from {address}"),
+    }
+}
+
+/// Space-separated hex, which is how frames are shown in the transcript.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READ: ModbusOperation = ModbusOperation::ReadHoldingRegisters { address: 107, count: 3 };
+
+    #[test]
+    fn requests_get_an_mbap_header() {
+        // The "read holding registers" example from the Modbus application protocol spec
+        assert_eq!(encode(0x1234, 0x11, &READ), [0x12, 0x34, 0, 0, 0, 6, 0x11, 0x03, 0x00, 0x6B, 0x00, 0x03]);
+        let write = ModbusOperation::WriteMultipleCoils { address: 19, values: vec![true, false, true, true, false, false, true, true, true, false] };
+        assert_eq!(encode(1, 1, &write), [0, 1, 0, 0, 0, 9, 1, 0x0F, 0x00, 0x13, 0x00, 0x0A, 0x02, 0xCD, 0x01]);
+        assert_eq!(encode(2, 1, &ModbusOperation::WriteSingleCoil { address: 172, value: true })[7..], [0x05, 0x00, 0xAC, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn responses_are_framed_by_their_length() {
+        let response = [0x12, 0x34, 0, 0, 0, 9, 0x11, 0x03, 0x06, 0x02, 0x2B, 0x00, 0x00, 0x00, 0x64, 0xAA];
+        assert_eq!(frame_length(&response), Some(15));
+        assert_eq!(frame_length(&response[..5]), None);
+        assert_eq!(transaction(&response), Some(0x1234));
+        assert_eq!(decode(&READ, &response[..15]), Ok(ModbusResult::Registers { values: vec![555, 0, 100] }));
+    }
+
+    #[test]
+    fn bits_and_writes_are_decoded() {
+        let read = ModbusOperation::ReadCoils { address: 19, count: 10 };
+        let response = [0, 1, 0, 0, 0, 5, 1, 0x01, 0x02, 0xCD, 0x01];
+        let values = vec![true, false, true, true, false, false, true, true, true, false];
+        assert_eq!(decode(&read, &response), Ok(ModbusResult::Bits { values }));
+        let write = ModbusOperation::WriteMultipleRegisters { address: 1, values: vec![10, 258] };
+        let response = [0, 1, 0, 0, 0, 6, 1, 0x10, 0x00, 0x01, 0x00, 0x02];
+        assert_eq!(decode(&write, &response), Ok(ModbusResult::Written { address: 1, count: 2 }));
+    }
+
+    #[test]
+    fn exceptions_are_reported() {
+        let response = [0, 1, 0, 0, 0, 3, 1, 0x83, 0x02];
+        assert_eq!(decode(&READ, &response), Err(DecodeError::Exception(2)));
+        assert_eq!(
+            DecodeError::Exception(2).to_string(),
+            "Modbus exception 0x02 (illegal data address)"
+        );
+        assert!(matches!(decode(&READ, &[0, 1, 0, 0, 0, 2, 1, 0x04]), Err(DecodeError::Malformed(_))));
+        assert!(matches!(decode(&READ, &[0, 1, 0, 0, 0, 4, 1, 0x03, 0x06, 0x02]), Err(DecodeError::Malformed(_))));
+    }
+
+    #[test]
+    fn quantities_are_checked() {
+        assert!(validate(&READ).is_ok());
+        assert!(validate(&ModbusOperation::ReadHoldingRegisters { address: 0, count: 126 }).is_err());
+        assert!(validate(&ModbusOperation::ReadCoils { address: 0, count: 0 }).is_err());
+    }
+}
@@ -0,0 +1 @@
+pub use tcp_commander_models::*;
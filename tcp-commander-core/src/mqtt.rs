@@ -0,0 +1,346 @@
+//! MQTT 3.1.1 and 5.0 control packets, just enough for a client that
+//! publishes and subscribes. No MQTT 5 properties are sent, and any that are
+//! received are skipped.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::models::{MqttOptions, MqttVersion};
+
+pub const DEFAULT_KEEP_ALIVE_SECS: u16 = 60;
+
+pub const PINGREQ: [u8; 2] = [0xC0, 0x00];
+pub const DISCONNECT: [u8; 2] = [0xE0, 0x00];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    ConnAck { session_present: bool, code: u8 },
+    Publish { topic: String, payload: Vec<u8>, qos: u8, retain: bool, packet_id: Option<u16> },
+    PubAck { packet_id: u16, code: u8 },
+    PubRec { packet_id: u16, code: u8 },
+    PubRel { packet_id: u16 },
+    PubComp { packet_id: u16 },
+    SubAck { packet_id: u16, codes: Vec<u8> },
+    UnsubAck { packet_id: u16 },
+    PingResp,
+    Disconnect { code: u8 },
+}
+
+fn encode_length(mut length: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+/// Reads a variable byte integer, returning it along with how many bytes it took.
+fn decode_length(bytes: &[u8]) -> Option<Result<(usize, usize), String>> {
+    let mut value = 0;
+    for (i, byte) in bytes.iter().enumerate().take(4) {
+        value += ((byte & 0x7F) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some(Ok((value, i + 1)));
+        }
+    }
+    if bytes.len() >= 4 {
+        return Some(Err("malformed remaining length".into()));
+    }
+    None
+}
+
+fn put_str(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s);
+}
+
+fn packet(first: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut out = vec![first];
+    encode_length(body.len(), &mut out);
+    out.extend(body);
+    out
+}
+
+/// A client id unique enough to not kick another instance off the broker.
+pub fn generate_client_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+    format!("tcp-commander-{}-{nanos:08x}", std::process::id())
+}
+
+pub fn connect(options: &MqttOptions) -> Vec<u8> {
+    let version = options.version.unwrap_or_default();
+    let mut body = Vec::new();
+    put_str(&mut body, b"MQTT");
+    body.push(match version {
+        MqttVersion::V311 => 4,
+        MqttVersion::V5 => 5,
+    });
+    let mut flags = 0;
+    if options.clean_start.unwrap_or(true) {
+        flags |= 0x02;
+    }
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&options.keep_alive_secs.unwrap_or(DEFAULT_KEEP_ALIVE_SECS).to_be_bytes());
+    if version == MqttVersion::V5 {
+        body.push(0);
+    }
+    let client_id = options.client_id.clone().unwrap_or_else(generate_client_id);
+    put_str(&mut body, client_id.as_bytes());
+    if let Some(username) = &options.username {
+        put_str(&mut body, username.as_bytes());
+    }
+    if let Some(password) = &options.password {
+        put_str(&mut body, password.as_bytes());
+    }
+    packet(0x10, body)
+}
+
+pub fn publish(version: MqttVersion, topic: &str, payload: &[u8], qos: u8, retain: bool, packet_id: u16) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_str(&mut body, topic.as_bytes());
+    if qos > 0 {
+        body.extend_from_slice(&packet_id.to_be_bytes());
+    }
+    if version == MqttVersion::V5 {
+        body.push(0);
+    }
+    body.extend_from_slice(payload);
+    packet(0x30 | (qos << 1) | u8::from(retain), body)
+}
+
+pub fn subscribe(version: MqttVersion, packet_id: u16, topic: &str, qos: u8) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    if version == MqttVersion::V5 {
+        body.push(0);
+    }
+    put_str(&mut body, topic.as_bytes());
+    body.push(qos);
+    packet(0x82, body)
+}
+
+pub fn unsubscribe(version: MqttVersion, packet_id: u16, topic: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    if version == MqttVersion::V5 {
+        body.push(0);
+    }
+    put_str(&mut body, topic.as_bytes());
+    packet(0xA2, body)
+}
+
+pub fn puback(packet_id: u16) -> Vec<u8> {
+    packet(0x40, packet_id.to_be_bytes().to_vec())
+}
+
+pub fn pubrec(packet_id: u16) -> Vec<u8> {
+    packet(0x50, packet_id.to_be_bytes().to_vec())
+}
+
+pub fn pubrel(packet_id: u16) -> Vec<u8> {
+    packet(0x62, packet_id.to_be_bytes().to_vec())
+}
+
+pub fn pubcomp(packet_id: u16) -> Vec<u8> {
+    packet(0x70, packet_id.to_be_bytes().to_vec())
+}
+
+/// Length of the packet at the start of `bytes`, for [`Framer::with_length`](crate::framing::Framer::with_length).
+pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+    match decode_length(bytes.get(1..)?)? {
+        Ok((length, size)) => Some(1 + size + length),
+        // Hand the garbage over as a frame of its own so it gets reported instead of stalling
+        Err(_) => Some(bytes.len()),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("packet is truncated".into());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "string isn't valid UTF-8".into())
+    }
+
+    fn skip_properties(&mut self) -> Result<(), String> {
+        let (length, size) = decode_length(self.bytes).ok_or("properties are truncated")??;
+        self.take(size + length).map(|_| ())
+    }
+
+    /// MQTT 5 reason code, which may be left off when it's 0 (success).
+    fn reason_code(&mut self) -> Result<u8, String> {
+        if self.bytes.is_empty() {
+            Ok(0)
+        } else {
+            self.u8()
+        }
+    }
+}
+
+/// Decodes a complete packet.
+pub fn decode(version: MqttVersion, frame: &[u8]) -> Result<Packet, String> {
+    let first = *frame.first().ok_or("empty packet")?;
+    let (_, size) = decode_length(&frame[1..]).ok_or("packet is truncated")??;
+    let mut reader = Reader { bytes: &frame[1 + size..] };
+    let v5 = version == MqttVersion::V5;
+    Ok(match first >> 4 {
+        2 => Packet::ConnAck { session_present: reader.u8()? & 1 == 1, code: reader.u8()? },
+        3 => {
+            let qos = (first >> 1) & 0x03;
+            let topic = reader.string()?;
+            let packet_id = if qos > 0 { Some(reader.u16()?) } else { None };
+            if v5 {
+                reader.skip_properties()?;
+            }
+            Packet::Publish { topic, payload: reader.bytes.to_vec(), qos, retain: first & 1 == 1, packet_id }
+        }
+        4 => Packet::PubAck { packet_id: reader.u16()?, code: reader.reason_code()? },
+        5 => Packet::PubRec { packet_id: reader.u16()?, code: reader.reason_code()? },
+        6 => Packet::PubRel { packet_id: reader.u16()? },
+        7 => Packet::PubComp { packet_id: reader.u16()? },
+        9 => {
+            let packet_id = reader.u16()?;
+            if v5 {
+                reader.skip_properties()?;
+            }
+            Packet::SubAck { packet_id, codes: reader.bytes.to_vec() }
+        }
+        11 => Packet::UnsubAck { packet_id: reader.u16()? },
+        13 => Packet::PingResp,
+        14 => Packet::Disconnect { code: reader.reason_code()? },
+        other => return Err(format!("unexpected packet type {other}")),
+    })
+}
+
+/// Explains why the broker refused a connection.
+pub fn connect_error(version: MqttVersion, code: u8) -> String {
+    let reason = match (version, code) {
+        (MqttVersion::V311, 1) | (MqttVersion::V5, 0x84) => "unsupported protocol version",
+        (MqttVersion::V311, 2) | (MqttVersion::V5, 0x85) => "client identifier rejected",
+        (MqttVersion::V311, 3) | (MqttVersion::V5, 0x88) => "server unavailable",
+        (MqttVersion::V311, 4) | (MqttVersion::V5, 0x86) => "bad username or password",
+        (MqttVersion::V311, 5) | (MqttVersion::V5, 0x87) => "not authorized",
+        (MqttVersion::V5, 0x89) => "server busy",
+        (MqttVersion::V5, 0x8A) => "banned",
+        _ => "unknown reason",
+    };
+    format!("Broker refused the connection: {reason} ({code:#04x})")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(version: MqttVersion) -> MqttOptions {
+        MqttOptions {
+            version: Some(version),
+            client_id: Some("c1".into()),
+            username: Some("u".into()),
+            password: Some("p".into()),
+            keep_alive_secs: Some(30),
+            clean_start: None,
+        }
+    }
+
+    #[test]
+    fn connect_packets() {
+        let header = [0, 4, b'M', b'Q', b'T', b'T'];
+        let mut v311 = vec![0x10, 20];
+        v311.extend_from_slice(&header);
+        v311.extend_from_slice(&[4, 0xC2, 0, 30, 0, 2, b'c', b'1', 0, 1, b'u', 0, 1, b'p']);
+        assert_eq!(connect(&options(MqttVersion::V311)), v311);
+
+        // MQTT 5 adds an empty property list after the keep alive
+        let mut v5 = vec![0x10, 21];
+        v5.extend_from_slice(&header);
+        v5.extend_from_slice(&[5, 0xC2, 0, 30, 0, 0, 2, b'c', b'1', 0, 1, b'u', 0, 1, b'p']);
+        assert_eq!(connect(&options(MqttVersion::V5)), v5);
+    }
+
+    #[test]
+    fn publish_round_trips() {
+        for version in [MqttVersion::V311, MqttVersion::V5] {
+            let packet = publish(version, "a/b", b"on", 1, true, 10);
+            assert_eq!(frame_length(&packet), Some(packet.len()));
+            assert_eq!(
+                decode(version, &packet),
+                Ok(Packet::Publish { topic: "a/b".into(), payload: b"on".to_vec(), qos: 1, retain: true, packet_id: Some(10) })
+            );
+            let packet = publish(version, "t", b"", 0, false, 10);
+            assert_eq!(
+                decode(version, &packet),
+                Ok(Packet::Publish { topic: "t".into(), payload: Vec::new(), qos: 0, retain: false, packet_id: None })
+            );
+        }
+        assert_eq!(publish(MqttVersion::V311, "t", b"x", 2, false, 0x0102), [0x34, 6, 0, 1, b't', 1, 2, b'x']);
+        assert_eq!(publish(MqttVersion::V5, "t", b"x", 0, false, 0), [0x30, 5, 0, 1, b't', 0, b'x']);
+    }
+
+    #[test]
+    fn long_payloads_take_more_length_bytes() {
+        let packet = publish(MqttVersion::V311, "t", &[0; 200], 0, false, 0);
+        assert_eq!(packet[1..3], [0xCB, 0x01]);
+        assert_eq!(frame_length(&packet), Some(packet.len()));
+        assert_eq!(frame_length(&packet[..2]), None);
+        assert_eq!(frame_length(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0]), Some(6));
+    }
+
+    #[test]
+    fn subscribe_and_suback() {
+        assert_eq!(subscribe(MqttVersion::V311, 1, "a/#", 1), [0x82, 8, 0, 1, 0, 3, b'a', b'/', b'#', 1]);
+        assert_eq!(subscribe(MqttVersion::V5, 1, "a/#", 1), [0x82, 9, 0, 1, 0, 0, 3, b'a', b'/', b'#', 1]);
+        assert_eq!(
+            decode(MqttVersion::V311, &[0x90, 3, 0, 1, 0x01]),
+            Ok(Packet::SubAck { packet_id: 1, codes: vec![1] })
+        );
+        // A reason string property is skipped over
+        assert_eq!(
+            decode(MqttVersion::V5, &[0x90, 8, 0, 1, 4, 0x1F, 0, 1, b'!', 0x80]),
+            Ok(Packet::SubAck { packet_id: 1, codes: vec![0x80] })
+        );
+    }
+
+    #[test]
+    fn acknowledgements() {
+        assert_eq!(decode(MqttVersion::V311, &[0x20, 2, 1, 0]), Ok(Packet::ConnAck { session_present: true, code: 0 }));
+        assert_eq!(decode(MqttVersion::V311, &puback(7)), Ok(Packet::PubAck { packet_id: 7, code: 0 }));
+        assert_eq!(decode(MqttVersion::V5, &[0x50, 3, 0, 7, 0x10]), Ok(Packet::PubRec { packet_id: 7, code: 0x10 }));
+        assert_eq!(decode(MqttVersion::V5, &pubrel(7)), Ok(Packet::PubRel { packet_id: 7 }));
+        assert_eq!(decode(MqttVersion::V5, &pubcomp(7)), Ok(Packet::PubComp { packet_id: 7 }));
+        assert_eq!(decode(MqttVersion::V311, &[0xD0, 0]), Ok(Packet::PingResp));
+        assert!(decode(MqttVersion::V311, &[0x20, 1, 0]).is_err());
+        assert_eq!(
+            connect_error(MqttVersion::V5, 0x86),
+            "Broker refused the connection: bad username or password (0x86)"
+        );
+    }
+}
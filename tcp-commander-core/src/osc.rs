@@ -0,0 +1,324 @@
+//! Open Sound Control 1.0 packets, plus the SLIP framing OSC 1.1 uses to
+//! carry them over byte streams.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::models::{OscArgument, OscMessage, OscRequest};
+
+/// The special timetag meaning "as soon as it arrives".
+pub const IMMEDIATELY: u64 = 1;
+
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+/// Bundles nested deeper than this are rejected rather than risk the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// An OSC packet as received.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Message(OscMessage),
+    Bundle { timetag: u64, packets: Vec<Packet> },
+}
+
+/// Strings end in at least one NUL and, like everything else, are padded to 4 bytes.
+fn put_padded(out: &mut Vec<u8>, bytes: &[u8], terminate: bool) {
+    out.extend_from_slice(bytes);
+    if terminate {
+        out.push(0);
+    }
+    while out.len() % 4 != 0 {
+        out.push(0);
+    }
+}
+
+pub fn encode_message(message: &OscMessage) -> Result<Vec<u8>, String> {
+    if !message.address.starts_with('/') {
+        return Err(format!("address '{}' has to start with '/'", message.address));
+    }
+    let mut tags = String::from(",");
+    let mut data = Vec::new();
+    for argument in &message.arguments {
+        match argument {
+            OscArgument::Int(value) => {
+                tags.push('i');
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            OscArgument::Float(value) => {
+                tags.push('f');
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            OscArgument::String(value) => {
+                if value.contains('\0') {
+                    return Err("strings can't contain NUL characters".into());
+                }
+                tags.push('s');
+                put_padded(&mut data, value.as_bytes(), true);
+            }
+            OscArgument::Blob(value) => {
+                tags.push('b');
+                data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+                put_padded(&mut data, value, false);
+            }
+            OscArgument::True => tags.push('T'),
+            OscArgument::False => tags.push('F'),
+            other => return Err(format!("{other:?} arguments can't be sent")),
+        }
+    }
+    let mut out = Vec::new();
+    put_padded(&mut out, message.address.as_bytes(), true);
+    put_padded(&mut out, tags.as_bytes(), true);
+    out.extend(data);
+    Ok(out)
+}
+
+/// The NTP timetag `delay` from now.
+pub fn timetag_after(delay: Duration) -> u64 {
+    let at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default() + delay;
+    let fraction = (u64::from(at.subsec_nanos()) << 32) / 1_000_000_000;
+    ((at.as_secs() + NTP_UNIX_OFFSET) << 32) | fraction
+}
+
+pub fn encode_bundle(timetag: u64, messages: &[OscMessage]) -> Result<Vec<u8>, String> {
+    let mut out = BUNDLE_TAG.to_vec();
+    out.extend_from_slice(&timetag.to_be_bytes());
+    for message in messages {
+        let element = encode_message(message)?;
+        out.extend_from_slice(&(element.len() as u32).to_be_bytes());
+        out.extend(element);
+    }
+    Ok(out)
+}
+
+/// Builds the packet a request describes.
+pub fn encode(request: &OscRequest) -> Result<Vec<u8>, String> {
+    match request.messages.as_slice() {
+        [] => Err("no messages to send".into()),
+        [message] if !request.bundle.unwrap_or(false) && request.delay_ms.is_none() => encode_message(message),
+        messages => {
+            let timetag = request.delay_ms.map_or(IMMEDIATELY, |delay| timetag_after(Duration::from_millis(delay)));
+            encode_bundle(timetag, messages)
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("packet is truncated".into());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let end = self.bytes.iter().position(|b| *b == 0).ok_or("string isn't terminated")?;
+        let string = String::from_utf8_lossy(&self.bytes[..end]).into_owned();
+        self.take((end + 4) & !3)?;
+        Ok(string)
+    }
+
+    fn blob(&mut self) -> Result<Vec<u8>, String> {
+        let length = u32::from_be_bytes(self.array()?) as usize;
+        let data = self.take(length)?.to_vec();
+        self.take((4 - length % 4) % 4)?;
+        Ok(data)
+    }
+}
+
+fn decode_message(bytes: &[u8]) -> Result<OscMessage, String> {
+    let mut reader = Reader { bytes };
+    let address = reader.string()?;
+    // Very old implementations leave the type tags out entirely
+    let tags = if reader.bytes.is_empty() { ",".to_string() } else { reader.string()? };
+    let tags = tags.strip_prefix(',').ok_or("type tags are missing")?;
+    let mut arguments = Vec::new();
+    for tag in tags.chars() {
+        arguments.push(match tag {
+            'i' => OscArgument::Int(i32::from_be_bytes(reader.array()?)),
+            'f' => OscArgument::Float(f32::from_be_bytes(reader.array()?)),
+            's' | 'S' => OscArgument::String(reader.string()?),
+            'b' => OscArgument::Blob(reader.blob()?),
+            'T' => OscArgument::True,
+            'F' => OscArgument::False,
+            'h' => OscArgument::Int64(i64::from_be_bytes(reader.array()?)),
+            'd' => OscArgument::Double(f64::from_be_bytes(reader.array()?)),
+            'N' => OscArgument::Nil,
+            'I' => OscArgument::Impulse,
+            // Timetags, colours and MIDI messages are shown as plain numbers
+            't' => OscArgument::Int64(i64::from_be_bytes(reader.array()?)),
+            'c' | 'r' | 'm' => OscArgument::Int(i32::from_be_bytes(reader.array()?)),
+            other => return Err(format!("unsupported type tag '{other}'")),
+        });
+    }
+    Ok(OscMessage { address, arguments })
+}
+
+fn decode_packet(bytes: &[u8], depth: usize) -> Result<Packet, String> {
+    let Some(rest) = bytes.strip_prefix(BUNDLE_TAG) else {
+        return decode_message(bytes).map(Packet::Message);
+    };
+    if depth > MAX_DEPTH {
+        return Err("bundles are nested too deeply".into());
+    }
+    let mut reader = Reader { bytes: rest };
+    let timetag = u64::from_be_bytes(reader.array()?);
+    let mut packets = Vec::new();
+    while !reader.bytes.is_empty() {
+        let length = u32::from_be_bytes(reader.array()?) as usize;
+        packets.push(decode_packet(reader.take(length)?, depth + 1)?);
+    }
+    Ok(Packet::Bundle { timetag, packets })
+}
+
+/// Decodes a whole packet.
+pub fn decode(bytes: &[u8]) -> Result<Packet, String> {
+    decode_packet(bytes, 0)
+}
+
+fn describe_argument(argument: &OscArgument) -> String {
+    match argument {
+        OscArgument::Int(value) => value.to_string(),
+        OscArgument::Float(value) => format!("{value:?}"),
+        OscArgument::String(value) => format!("{value:?}"),
+        OscArgument::Blob(value) => format!("<blob, {} bytes>", value.len()),
+        OscArgument::True => "true".into(),
+        OscArgument::False => "false".into(),
+        OscArgument::Int64(value) => format!("{value}L"),
+        OscArgument::Double(value) => format!("{value:?}d"),
+        OscArgument::Nil => "nil".into(),
+        OscArgument::Impulse => "impulse".into(),
+    }
+}
+
+pub fn describe_message(message: &OscMessage) -> String {
+    let mut text = message.address.clone();
+    for argument in &message.arguments {
+        text.push(' ');
+        text.push_str(&describe_argument(argument));
+    }
+    text
+}
+
+fn describe_timetag(timetag: u64) -> String {
+    if timetag == IMMEDIATELY {
+        return "immediately".into();
+    }
+    let unix = (timetag >> 32).saturating_sub(NTP_UNIX_OFFSET);
+    let millis = ((timetag & 0xFFFF_FFFF) * 1000) >> 32;
+    format!("at {unix}.{millis:03}")
+}
+
+pub fn describe_packet(packet: &Packet) -> String {
+    match packet {
+        Packet::Message(message) => describe_message(message),
+        Packet::Bundle { timetag, packets } => {
+            let mut lines = vec![format!("#bundle {}", describe_timetag(*timetag))];
+            for packet in packets {
+                lines.extend(describe_packet(packet).lines().map(|line| format!("  {line}")));
+            }
+            lines.join("\n")
+        }
+    }
+}
+
+/// How a received packet is shown in the transcript.
+pub fn describe(bytes: &[u8]) -> String {
+    match decode(bytes) {
+        Ok(packet) => describe_packet(&packet),
+        Err(e) => format!("Malformed OSC packet: {e}"),
+    }
+}
+
+/// SLIP (RFC 1055) framing, which OSC 1.1 uses over TCP.
+pub mod slip {
+    const END: u8 = 0xC0;
+    const ESC: u8 = 0xDB;
+    const ESC_END: u8 = 0xDC;
+    const ESC_ESC: u8 = 0xDD;
+
+    /// Frames a packet, with an END on both sides as OSC 1.1 asks for.
+    pub fn encode(packet: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(packet.len() + 2);
+        out.push(END);
+        for &byte in packet {
+            match byte {
+                END => out.extend_from_slice(&[ESC, ESC_END]),
+                ESC => out.extend_from_slice(&[ESC, ESC_ESC]),
+                byte => out.push(byte),
+            }
+        }
+        out.push(END);
+        out
+    }
+
+    /// Length of the frame at the start of `bytes` up to and including its
+    /// END, for [`Framer::with_length`](crate::framing::Framer::with_length).
+    /// The empty frames between back-to-back ENDs come out as frames of their own.
+    pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+        bytes.iter().position(|b| *b == END).map(|end| end + 1)
+    }
+
+    /// Undoes the escaping in a frame.
+    pub fn decode(frame: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(frame.len());
+        let mut escaped = false;
+        for &byte in frame.strip_suffix(&[END]).unwrap_or(frame) {
+            match (escaped, byte) {
+                (false, ESC) => escaped = true,
+                (true, ESC_END) => {
+                    out.push(END);
+                    escaped = false;
+                }
+                (true, ESC_ESC) => {
+                    out.push(ESC);
+                    escaped = false;
+                }
+                (_, byte) => {
+                    out.push(byte);
+                    escaped = false;
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundles_round_trip() {
+        let message = OscMessage {
+            address: "/fader/1".into(),
+            arguments: vec![OscArgument::Float(0.5), OscArgument::String("hi".into())],
+        };
+        let bytes = encode_bundle(IMMEDIATELY, std::slice::from_ref(&message)).unwrap();
+        assert_eq!(decode(&bytes), Ok(Packet::Bundle { timetag: IMMEDIATELY, packets: vec![Packet::Message(message)] }));
+    }
+
+    #[test]
+    fn deeply_nested_bundles_are_refused() {
+        let mut packet = encode_message(&OscMessage { address: "/x".into(), arguments: Vec::new() }).unwrap();
+        for _ in 0..10_000 {
+            let mut bundle = BUNDLE_TAG.to_vec();
+            bundle.extend_from_slice(&IMMEDIATELY.to_be_bytes());
+            bundle.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            bundle.extend_from_slice(&packet);
+            packet = bundle;
+        }
+        assert_eq!(decode(&packet), Err("bundles are nested too deeply".into()));
+    }
+}
@@ -0,0 +1,316 @@
+//! Redis serialization protocol (RESP2 and RESP3).
+//!
+//! Commands go out as arrays of bulk strings, split from a command line the
+//! way `redis-cli` does it. Replies of either version are decoded, and shown
+//! in the transcript as the same kind of tree `redis-cli` prints.
+
+use crate::models::RedisValue;
+
+/// Switches the connection to RESP3.
+pub const HELLO_3: &str = "HELLO 3";
+
+/// Aggregates nested deeper than this are rejected rather than risk the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Redis caps strings at 512 MB, so anything longer is garbage.
+const MAX_LENGTH: usize = 512 * 1024 * 1024;
+
+/// Splits a command line into arguments. Arguments are separated by
+/// whitespace and may be quoted: double quotes understand `\n`, `\r`, `\t`,
+/// `\"`, `\\` and `\xHH` escapes, single quotes only `\'`.
+pub fn split(line: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+        let mut arg = Vec::new();
+        match first {
+            '"' => {
+                chars.next();
+                loop {
+                    match chars.next().ok_or("unbalanced quotes")? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or("unbalanced quotes")? {
+                            'n' => arg.push(b'\n'),
+                            'r' => arg.push(b'\r'),
+                            't' => arg.push(b'\t'),
+                            'a' => arg.push(0x07),
+                            'b' => arg.push(0x08),
+                            'x' => {
+                                let hex: String = chars.by_ref().take(2).collect();
+                                let byte = u8::from_str_radix(&hex, 16)
+                                    .ok()
+                                    .filter(|_| hex.len() == 2)
+                                    .ok_or_else(|| format!("'\\x{hex}' isn't a valid escape"))?;
+                                arg.push(byte);
+                            }
+                            c => push(&mut arg, c),
+                        },
+                        c => push(&mut arg, c),
+                    }
+                }
+            }
+            '\'' => {
+                chars.next();
+                loop {
+                    match chars.next().ok_or("unbalanced quotes")? {
+                        '\'' => break,
+                        '\\' if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            arg.push(b'\'');
+                        }
+                        c => push(&mut arg, c),
+                    }
+                }
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    push(&mut arg, c);
+                }
+                args.push(arg);
+                continue;
+            }
+        }
+        // A closing quote has to end the argument, as in `redis-cli`
+        if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return Err("closing quote must be followed by a space".into());
+        }
+        args.push(arg);
+    }
+}
+
+fn push(arg: &mut Vec<u8>, c: char) {
+    arg.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// Encodes a command line as a RESP array.
+pub fn encode(line: &str) -> Result<Vec<u8>, String> {
+    let args = split(line)?;
+    if args.is_empty() {
+        return Err("command is empty".into());
+    }
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(&arg);
+        out.extend_from_slice(b"\r\n");
+    }
+    Ok(out)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    /// The rest of the current line, or `None` if it hasn't all arrived yet.
+    fn line(&mut self) -> Option<&str> {
+        let rest = &self.bytes[self.pos..];
+        let end = rest.windows(2).position(|window| window == b"\r\n")?;
+        self.pos += end + 2;
+        // Anything that isn't UTF-8 fails to parse as a number further on
+        Some(std::str::from_utf8(&rest[..end]).unwrap_or_default())
+    }
+
+    fn length(&mut self) -> Option<Result<Option<usize>, String>> {
+        let line = self.line()?;
+        Some(match line {
+            "-1" => Ok(None),
+            "?" => Err("streamed replies aren't supported".into()),
+            _ => match line.parse() {
+                Ok(length) if length <= MAX_LENGTH => Ok(Some(length)),
+                _ => Err(format!("'{line}' isn't a valid length")),
+            },
+        })
+    }
+
+    fn blob(&mut self, length: usize) -> Option<Result<Vec<u8>, String>> {
+        let data = self.bytes.get(self.pos..self.pos + length + 2)?;
+        self.pos += length + 2;
+        Some(match data.split_at(length) {
+            (data, b"\r\n") => Ok(data.to_vec()),
+            _ => Err("string isn't followed by CRLF".into()),
+        })
+    }
+
+    fn values(&mut self, count: usize, depth: usize) -> Option<Result<Vec<RedisValue>, String>> {
+        let mut values = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            match self.value(depth + 1)? {
+                Ok(value) => values.push(value),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok(values))
+    }
+
+    /// `None` if more bytes are needed.
+    fn value(&mut self, depth: usize) -> Option<Result<RedisValue, String>> {
+        if depth > MAX_DEPTH {
+            return Some(Err("reply is nested too deeply".into()));
+        }
+        let kind = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        let lossy = |data: Vec<u8>| String::from_utf8_lossy(&data).into_owned();
+        Some(Ok(match kind {
+            b'+' => RedisValue::Simple(self.line()?.to_string()),
+            b'-' => RedisValue::Error(self.line()?.to_string()),
+            b':' => {
+                let line = self.line()?;
+                match line.parse() {
+                    Ok(value) => RedisValue::Integer(value),
+                    Err(_) => return Some(Err(format!("'{line}' isn't a valid integer"))),
+                }
+            }
+            b',' => {
+                let line = self.line()?;
+                match line.parse() {
+                    Ok(value) => RedisValue::Double(value),
+                    Err(_) => return Some(Err(format!("'{line}' isn't a valid double"))),
+                }
+            }
+            b'(' => RedisValue::BigNumber(self.line()?.to_string()),
+            b'#' => match self.line()? {
+                "t" => RedisValue::Boolean(true),
+                "f" => RedisValue::Boolean(false),
+                line => return Some(Err(format!("'{line}' isn't a valid boolean"))),
+            },
+            b'_' => {
+                self.line()?;
+                RedisValue::Null
+            }
+            b'$' | b'!' | b'=' => match self.length()? {
+                Ok(None) => RedisValue::Null,
+                Ok(Some(length)) => match self.blob(length)? {
+                    Ok(data) if kind == b'!' => RedisValue::Error(lossy(data)),
+                    // Verbatim strings start with their format, as in `txt:`
+                    Ok(data) if kind == b'=' => RedisValue::Bulk(lossy(data.get(4..).unwrap_or_default().to_vec())),
+                    Ok(data) => RedisValue::Bulk(lossy(data)),
+                    Err(e) => return Some(Err(e)),
+                },
+                Err(e) => return Some(Err(e)),
+            },
+            b'*' | b'~' | b'>' | b'%' | b'|' => {
+                let count = match self.length()? {
+                    Ok(None) => return Some(Ok(RedisValue::Null)),
+                    Ok(Some(count)) => count,
+                    Err(e) => return Some(Err(e)),
+                };
+                let paired = matches!(kind, b'%' | b'|');
+                let values = match self.values(if paired { count * 2 } else { count }, depth)? {
+                    Ok(values) => values,
+                    Err(e) => return Some(Err(e)),
+                };
+                match kind {
+                    b'*' => RedisValue::Array(values),
+                    b'~' => RedisValue::Set(values),
+                    b'>' => RedisValue::Push(values),
+                    b'%' => {
+                        let mut values = values.into_iter();
+                        RedisValue::Map(std::iter::from_fn(|| Some((values.next()?, values.next()?))).collect())
+                    }
+                    // Attributes annotate the reply that follows; only the reply is kept
+                    _ => return self.value(depth + 1),
+                }
+            }
+            other => return Some(Err(format!("unexpected type byte {:?}", other as char))),
+        }))
+    }
+}
+
+/// Decodes the reply at the start of `bytes`, returning it with its length,
+/// or `None` if it hasn't all arrived yet.
+pub fn parse(bytes: &[u8]) -> Option<Result<(RedisValue, usize), String>> {
+    let mut parser = Parser { bytes, pos: 0 };
+    Some(parser.value(0)?.map(|value| (value, parser.pos)))
+}
+
+/// Length of the reply at the start of `bytes`, for [`Framer::with_length`](crate::framing::Framer::with_length).
+pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+    match parse(bytes)? {
+        Ok((_, length)) => Some(length),
+        // Hand the garbage over as a frame of its own so it gets reported instead of stalling
+        Err(_) => Some(bytes.len()),
+    }
+}
+
+/// Decodes a complete frame.
+pub fn decode(frame: &[u8]) -> Result<RedisValue, String> {
+    parse(frame).ok_or("reply is truncated")?.map(|(value, _)| value)
+}
+
+/// Shows a reply the way `redis-cli` does, with aggregates as numbered,
+/// indented lines.
+pub fn describe(value: &RedisValue) -> String {
+    let items = |values: Vec<String>, empty: &str| {
+        if values.is_empty() {
+            return format!("({empty})");
+        }
+        let width = values.len().to_string().len();
+        let mut out = Vec::new();
+        for (i, value) in values.iter().enumerate() {
+            let prefix = format!("{:>width$}) ", i + 1);
+            let indent = " ".repeat(prefix.len());
+            for (j, line) in value.lines().enumerate() {
+                out.push(if j == 0 { format!("{prefix}{line}") } else { format!("{indent}{line}") });
+            }
+        }
+        out.join("\n")
+    };
+    match value {
+        RedisValue::Simple(value) => value.clone(),
+        RedisValue::Error(message) => format!("(error) {message}"),
+        RedisValue::Integer(value) => format!("(integer) {value}"),
+        RedisValue::Bulk(value) => format!("{value:?}"),
+        RedisValue::Null => "(nil)".into(),
+        RedisValue::Boolean(value) => format!("({value})"),
+        RedisValue::Double(value) => format!("(double) {value}"),
+        RedisValue::BigNumber(value) => format!("(big number) {value}"),
+        RedisValue::Array(values) | RedisValue::Set(values) => {
+            items(values.iter().map(describe).collect(), "empty array")
+        }
+        RedisValue::Push(values) => format!("(push)\n{}", items(values.iter().map(describe).collect(), "empty array")),
+        RedisValue::Map(pairs) => items(
+            pairs.iter().map(|(key, value)| format!("{} => {}", describe(key), describe(value))).collect(),
+            "empty map",
+        ),
+    }
+}
+
+/// How a received frame is shown in the transcript.
+pub fn describe_frame(frame: &[u8]) -> String {
+    match decode(frame) {
+        Ok(value) => describe(&value),
+        Err(e) => format!("Malformed RESP reply: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nested_replies() {
+        let (value, length) = parse(b"*2\r\n:1\r\n$3\r\nfoo\r\nextra").unwrap().unwrap();
+        assert_eq!(value, RedisValue::Array(vec![RedisValue::Integer(1), RedisValue::Bulk("foo".into())]));
+        assert_eq!(length, 17);
+        assert_eq!(parse(b"*2\r\n:1\r\n"), None);
+    }
+
+    #[test]
+    fn attributes_are_skipped() {
+        let value = decode(b"|1\r\n+ttl\r\n:3600\r\n+OK\r\n").unwrap();
+        assert_eq!(value, RedisValue::Simple("OK".into()));
+    }
+
+    #[test]
+    fn deep_attribute_chain_is_refused() {
+        let chain = b"|0\r\n".repeat(100_000);
+        assert_eq!(decode(&chain), Err("reply is nested too deeply".into()));
+        assert_eq!(frame_length(&chain), Some(chain.len()));
+    }
+}
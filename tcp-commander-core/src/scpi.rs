@@ -0,0 +1,144 @@
+//! SCPI over raw sockets (usually port 5025).
+//!
+//! Commands and replies are newline-terminated, except that a reply can be an
+//! IEEE 488.2 definite-length binary block (`#<n><len><data>`), whose data may
+//! contain newlines of its own.
+
+pub const ERROR_QUERY: &str = "SYST:ERR?";
+
+/// `SYST:ERR?` is drained at most this many times, in case an instrument
+/// never reports that its queue is empty.
+pub const MAX_QUEUED_ERRORS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Text(String),
+    Block(Vec<u8>),
+}
+
+/// Whether `command` expects a reply: its last header ends in `?`, as in
+/// `MEAS:VOLT? DC` or `*RST; *OPC?`.
+pub fn is_query(command: &str) -> bool {
+    command
+        .trim()
+        .rsplit(';')
+        .next()
+        .and_then(|last| last.split_whitespace().next())
+        .is_some_and(|header| header.ends_with('?'))
+}
+
+/// Adds the newline instruments wait for before acting on a command.
+pub fn terminate(command: &str) -> String {
+    let command = command.trim_end_matches(['\r', '\n']);
+    format!("{command}\n")
+}
+
+/// Length of the reply at the start of `bytes`, including its terminator, for
+/// [`Framer::with_length`](crate::framing::Framer::with_length).
+pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+    let line = || bytes.iter().position(|b| *b == b'\n').map(|end| end + 1);
+    let digits = match bytes {
+        [b'#', digits, ..] if digits.is_ascii_digit() => (digits - b'0') as usize,
+        [b'#'] => return None,
+        // `#0` blocks run until the final newline, same as text
+        _ => return line(),
+    };
+    if digits == 0 {
+        return line();
+    }
+    let length = bytes.get(2..2 + digits)?;
+    let Some(length) = std::str::from_utf8(length).ok().and_then(|length| length.parse::<usize>().ok()) else {
+        // Hand the garbage over as a frame of its own so it gets reported instead of stalling
+        return Some(bytes.len());
+    };
+    let end = 2 + digits + length;
+    // The block is followed by the usual newline, but don't insist on it
+    match bytes.get(end) {
+        None => None,
+        Some(b'\n') => Some(end + 1),
+        Some(_) => Some(end),
+    }
+}
+
+/// Splits a complete frame into text or binary block data.
+pub fn parse(frame: &[u8]) -> Reply {
+    let trim = |bytes: &[u8]| {
+        let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+        bytes.strip_suffix(b"\r").unwrap_or(bytes).to_vec()
+    };
+    if let [b'#', digits, rest @ ..] = frame {
+        if digits.is_ascii_digit() {
+            let digits = (digits - b'0') as usize;
+            if digits == 0 {
+                return Reply::Block(trim(rest));
+            }
+            // Go by the announced length, since the data can end in a newline itself
+            let length = rest
+                .get(..digits)
+                .and_then(|length| std::str::from_utf8(length).ok()?.parse::<usize>().ok());
+            if let Some(data) = length.and_then(|length| rest.get(digits..digits + length)) {
+                return Reply::Block(data.to_vec());
+            }
+        }
+    }
+    Reply::Text(String::from_utf8_lossy(&trim(frame)).into_owned())
+}
+
+/// How a reply is shown in the transcript.
+pub fn describe(frame: &[u8]) -> String {
+    match parse(frame) {
+        Reply::Text(text) => text,
+        Reply::Block(data) => format!("<binary block, {} bytes>", data.len()),
+    }
+}
+
+/// Whether a `SYST:ERR?` reply such as `+0,"No error"` means the queue is empty.
+pub fn is_no_error(reply: &str) -> bool {
+    let code = reply.split(',').next().unwrap_or_default().trim();
+    code.parse::<i32>().is_ok_and(|code| code == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_are_recognised() {
+        assert!(is_query("*IDN?"));
+        assert!(is_query("MEAS:VOLT? DC"));
+        assert!(is_query("*RST; *OPC?"));
+        assert!(!is_query("*RST"));
+        assert!(!is_query("*OPC?; *RST"));
+        assert!(!is_query("SYST:BEEP 'why?'"));
+    }
+
+    #[test]
+    fn blocks_are_framed_by_length() {
+        // The data contains a newline, which mustn't end the frame early
+        let reply = b"#15a\nbcd\n+1\n";
+        assert_eq!(frame_length(reply), Some(9));
+        assert_eq!(parse(&reply[..9]), Reply::Block(b"a\nbcd".to_vec()));
+        assert_eq!(frame_length(b"#15a\nb"), None);
+        assert_eq!(frame_length(b"#2"), None);
+        assert_eq!(frame_length(b"+1.5E-3\r\nrest"), Some(9));
+        assert_eq!(parse(b"+1.5E-3\r\n"), Reply::Text("+1.5E-3".into()));
+    }
+
+    #[test]
+    fn indefinite_blocks_run_to_the_newline() {
+        assert_eq!(frame_length(b"#0abc\nnext"), Some(6));
+        assert_eq!(parse(b"#0abc\n"), Reply::Block(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn bad_block_length_is_handed_over() {
+        assert_eq!(frame_length(b"#2x9garbage"), Some(11));
+        assert_eq!(parse(b"#2x9garbage"), Reply::Text("#2x9garbage".into()));
+    }
+
+    #[test]
+    fn error_queue_replies() {
+        assert!(is_no_error("+0,\"No error\""));
+        assert!(!is_no_error("-113,\"Undefined header\""));
+    }
+}
@@ -0,0 +1,65 @@
+//! Sequences of commands, delays and waits that run in the background, for
+//! demos and checks that need the same steps with the same timing every time.
+
+use regex::Regex;
+
+use crate::{
+    modbus,
+    models::{ModbusOperation, SequenceStep},
+};
+
+/// Compiles the pattern of every wait up front, so a typo fails the whole
+/// sequence before anything is sent.
+pub fn validate(steps: &[SequenceStep]) -> Result<(), String> {
+    for step in steps {
+        match step {
+            SequenceStep::WaitFor { pattern, .. } => {
+                Regex::new(pattern).map_err(|e| format!("'{pattern}' isn't a valid pattern: {e}"))?;
+            }
+            SequenceStep::Repeat { steps, .. } => validate(steps)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Steps the sequence takes, counting each repetition.
+pub fn count(steps: &[SequenceStep]) -> usize {
+    steps.iter().fold(0usize, |total, step| {
+        total.saturating_add(match step {
+            SequenceStep::Repeat { times, steps } => count(steps).saturating_mul(*times as usize),
+            _ => 1,
+        })
+    })
+}
+
+/// One-line summary of a step, for the status and the transcript.
+pub fn describe(step: &SequenceStep) -> String {
+    match step {
+        SequenceStep::Transmit { message, suffix: None } => format!("Send {message:?}"),
+        SequenceStep::Transmit { message, suffix: Some(suffix) } => format!("Send {message:?} [{}]", modbus::hex(suffix)),
+        SequenceStep::Scpi(request) => format!("SCPI {}", request.command),
+        SequenceStep::Modbus(request) => {
+            let function = match request.operation {
+                ModbusOperation::ReadCoils { .. } => "read coils",
+                ModbusOperation::ReadDiscreteInputs { .. } => "read discrete inputs",
+                ModbusOperation::ReadHoldingRegisters { .. } => "read holding registers",
+                ModbusOperation::ReadInputRegisters { .. } => "read input registers",
+                ModbusOperation::WriteSingleCoil { .. } => "write coil",
+                ModbusOperation::WriteSingleRegister { .. } => "write register",
+                ModbusOperation::WriteMultipleCoils { .. } => "write coils",
+                ModbusOperation::WriteMultipleRegisters { .. } => "write registers",
+            };
+            format!("Modbus {function}")
+        }
+        SequenceStep::Publish(publish) => format!("Publish to {}", publish.topic),
+        SequenceStep::Http(request) => format!("HTTP {} {}", request.method, request.path),
+        SequenceStep::Osc(request) => {
+            let addresses = request.messages.iter().map(|message| message.address.as_str());
+            format!("OSC {}", addresses.collect::<Vec<_>>().join(", "))
+        }
+        SequenceStep::Delay { ms } => format!("Delay {ms} ms"),
+        SequenceStep::WaitFor { pattern, .. } => format!("Wait for /{pattern}/"),
+        SequenceStep::Repeat { times, .. } => format!("Repeat ×{times}"),
+    }
+}
@@ -0,0 +1,250 @@
+//! Telnet (RFC 854) option negotiation, so consoles that expect a proper
+//! Telnet client get answers to their option requests and the transcript
+//! only sees the text in between.
+//!
+//! Supported options are ECHO (RFC 857), SUPPRESS-GO-AHEAD (RFC 858),
+//! TERMINAL-TYPE (RFC 1091) and NAWS (RFC 1073); everything else is refused.
+
+use std::borrow::Cow;
+
+use crate::config::TelnetConfig;
+
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const TERMINAL_TYPE: u8 = 24;
+const NAWS: u8 = 31;
+
+const TERMINAL_TYPE_IS: u8 = 0;
+const TERMINAL_TYPE_SEND: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    /// Just saw a CR; a following NUL is padding.
+    Cr,
+    Iac,
+    /// Saw IAC followed by WILL, WONT, DO or DONT.
+    Negotiate(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Decodes received bytes and works out the replies the server expects.
+pub struct Telnet {
+    config: TelnetConfig,
+    state: State,
+    /// Options we have agreed to perform.
+    local: [bool; 256],
+    /// Options the server has agreed to perform.
+    remote: [bool; 256],
+    subnegotiation: Vec<u8>,
+}
+
+/// The result of feeding received bytes through [`Telnet::receive`].
+#[derive(Debug, Default)]
+pub struct Received {
+    /// Text with all Telnet commands removed.
+    pub data: Vec<u8>,
+    /// Bytes that have to be sent back to the server.
+    pub reply: Vec<u8>,
+}
+
+impl Telnet {
+    pub fn new(config: TelnetConfig) -> Self {
+        Self {
+            config,
+            state: State::Data,
+            local: [false; 256],
+            remote: [false; 256],
+            subnegotiation: Vec::new(),
+        }
+    }
+
+    pub fn receive(&mut self, bytes: &[u8]) -> Received {
+        let mut received = Received::default();
+        for &byte in bytes {
+            self.state = match (self.state, byte) {
+                (State::Data | State::Cr, IAC) => State::Iac,
+                (State::Cr, 0) => State::Data,
+                (State::Data | State::Cr, b'\r') => {
+                    received.data.push(byte);
+                    State::Cr
+                }
+                (State::Data | State::Cr, _) => {
+                    received.data.push(byte);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    received.data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Negotiate(byte),
+                (State::Iac, SB) => {
+                    self.subnegotiation.clear();
+                    State::Subnegotiation
+                }
+                // GA, NOP, AYT and the like carry nothing worth showing
+                (State::Iac, _) => State::Data,
+                (State::Negotiate(command), option) => {
+                    self.negotiate(command, option, &mut received.reply);
+                    State::Data
+                }
+                (State::Subnegotiation, IAC) => State::SubnegotiationIac,
+                (State::Subnegotiation, _) => {
+                    self.subnegotiation.push(byte);
+                    State::Subnegotiation
+                }
+                (State::SubnegotiationIac, SE) => {
+                    self.subnegotiate(&mut received.reply);
+                    State::Data
+                }
+                (State::SubnegotiationIac, _) => {
+                    // IAC IAC inside a subnegotiation is an escaped 0xFF
+                    self.subnegotiation.push(byte);
+                    State::Subnegotiation
+                }
+            };
+        }
+        received
+    }
+
+    fn negotiate(&mut self, command: u8, option: u8, reply: &mut Vec<u8>) {
+        let index = option as usize;
+        // Only answer requests that change an option's state, so the two
+        // sides can't end up acknowledging each other forever
+        match command {
+            DO => {
+                let supported = matches!(option, SUPPRESS_GO_AHEAD | TERMINAL_TYPE | NAWS);
+                if supported != self.local[index] || !supported {
+                    self.local[index] = supported;
+                    reply.extend_from_slice(&[IAC, if supported { WILL } else { WONT }, option]);
+                    if supported && option == NAWS {
+                        self.send_window_size(reply);
+                    }
+                }
+            }
+            DONT => {
+                if self.local[index] {
+                    self.local[index] = false;
+                    reply.extend_from_slice(&[IAC, WONT, option]);
+                }
+            }
+            WILL => {
+                let supported = matches!(option, ECHO | SUPPRESS_GO_AHEAD);
+                if supported != self.remote[index] || !supported {
+                    self.remote[index] = supported;
+                    reply.extend_from_slice(&[IAC, if supported { DO } else { DONT }, option]);
+                }
+            }
+            WONT => {
+                if self.remote[index] {
+                    self.remote[index] = false;
+                    reply.extend_from_slice(&[IAC, DONT, option]);
+                }
+            }
+            _ => unreachable!("only called for negotiation commands"),
+        }
+    }
+
+    fn subnegotiate(&mut self, reply: &mut Vec<u8>) {
+        if self.subnegotiation.as_slice() == [TERMINAL_TYPE, TERMINAL_TYPE_SEND] && self.local[TERMINAL_TYPE as usize] {
+            reply.extend_from_slice(&[IAC, SB, TERMINAL_TYPE, TERMINAL_TYPE_IS]);
+            reply.extend(escape(self.config.terminal_type.as_bytes()).iter());
+            reply.extend_from_slice(&[IAC, SE]);
+        }
+    }
+
+    fn send_window_size(&self, reply: &mut Vec<u8>) {
+        let mut size = Vec::with_capacity(4);
+        size.extend_from_slice(&self.config.window_width.to_be_bytes());
+        size.extend_from_slice(&self.config.window_height.to_be_bytes());
+        reply.extend_from_slice(&[IAC, SB, NAWS]);
+        reply.extend(escape(&size).iter());
+        reply.extend_from_slice(&[IAC, SE]);
+    }
+}
+
+/// Doubles every 0xFF so it isn't mistaken for the start of a command.
+pub fn escape(bytes: &[u8]) -> Cow<'_, [u8]> {
+    if !bytes.contains(&IAC) {
+        return Cow::Borrowed(bytes);
+    }
+    let mut escaped = Vec::with_capacity(bytes.len() + 1);
+    for &byte in bytes {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn telnet() -> Telnet {
+        Telnet::new(TelnetConfig { terminal_type: "XTERM".into(), window_width: 255, window_height: 24 })
+    }
+
+    #[test]
+    fn commands_are_taken_out_of_the_text() {
+        let received = telnet().receive(&[b'o', b'k', IAC, 241, b'\r', 0, IAC, IAC, b'\n']);
+        assert_eq!(received.data, b"ok\r\xff\n");
+        assert!(received.reply.is_empty());
+    }
+
+    #[test]
+    fn supported_options_are_agreed_to_once() {
+        let mut telnet = telnet();
+        let received = telnet.receive(&[IAC, WILL, ECHO, IAC, DO, SUPPRESS_GO_AHEAD, IAC, DO, 99, IAC, WILL, 99]);
+        assert_eq!(
+            received.reply,
+            [IAC, DO, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD, IAC, WONT, 99, IAC, DONT, 99]
+        );
+        // Asking again doesn't change anything, so there's nothing to answer
+        assert!(telnet.receive(&[IAC, WILL, ECHO, IAC, DO, SUPPRESS_GO_AHEAD]).reply.is_empty());
+        assert_eq!(telnet.receive(&[IAC, WONT, ECHO]).reply, [IAC, DONT, ECHO]);
+    }
+
+    #[test]
+    fn window_size_is_sent_escaped() {
+        let received = telnet().receive(&[IAC, DO, NAWS]);
+        assert_eq!(received.reply, [IAC, WILL, NAWS, IAC, SB, NAWS, 0, 255, 255, 0, 24, IAC, SE]);
+    }
+
+    #[test]
+    fn terminal_type_is_sent_when_asked() {
+        let mut telnet = telnet();
+        // Not agreed to yet, so the request is ignored
+        assert!(telnet.receive(&[IAC, SB, TERMINAL_TYPE, TERMINAL_TYPE_SEND, IAC, SE]).reply.is_empty());
+        let received = telnet.receive(&[IAC, DO, TERMINAL_TYPE, IAC, SB, TERMINAL_TYPE, TERMINAL_TYPE_SEND, IAC, SE]);
+        let mut expected = vec![IAC, WILL, TERMINAL_TYPE, IAC, SB, TERMINAL_TYPE, TERMINAL_TYPE_IS];
+        expected.extend_from_slice(b"XTERM");
+        expected.extend_from_slice(&[IAC, SE]);
+        assert_eq!(received.reply, expected);
+    }
+
+    #[test]
+    fn commands_split_across_reads() {
+        let mut telnet = telnet();
+        assert_eq!(telnet.receive(&[b'a', IAC]).data, b"a");
+        let received = telnet.receive(&[DO, NAWS, b'b']);
+        assert_eq!(received.data, b"b");
+        assert_eq!(&received.reply[..3], [IAC, WILL, NAWS]);
+    }
+
+    #[test]
+    fn escaping_doubles_iac() {
+        assert!(matches!(escape(b"plain"), Cow::Borrowed(_)));
+        assert_eq!(escape(&[1, IAC, 2]).as_ref(), [1, IAC, IAC, 2]);
+    }
+}
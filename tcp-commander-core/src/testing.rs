@@ -0,0 +1,206 @@
+//! Checking replies against what a test expects, and reporting the results
+//! as JUnit XML for CI systems to pick up.
+
+use regex::Regex;
+
+use crate::models::{ExpectedReply, SequenceStep, TestCase, TestReport};
+
+/// Catches what would fail every run of a test before anything is sent.
+pub fn validate(case: &TestCase) -> Result<(), String> {
+    crate::sequence::validate(&case.steps)?;
+    let Some(expect) = &case.expect else {
+        return Ok(());
+    };
+    if let ExpectedReply::Regex { pattern } = &expect.reply {
+        Regex::new(pattern).map_err(|e| format!("'{pattern}' isn't a valid pattern: {e}"))?;
+    }
+    match case.steps.last() {
+        None | Some(SequenceStep::Delay { .. } | SequenceStep::WaitFor { .. } | SequenceStep::Repeat { .. }) => {
+            Err("a test with an expected reply has to end by sending something".into())
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// The expectation as text, for the report.
+pub fn describe(expected: &ExpectedReply) -> String {
+    match expected {
+        ExpectedReply::Any => "any reply".into(),
+        ExpectedReply::Exact { text } => text.clone(),
+        ExpectedReply::Regex { pattern } => format!("/{pattern}/"),
+        ExpectedReply::Length { bytes } => format!("{bytes} bytes"),
+        ExpectedReply::Silence => "no reply".into(),
+    }
+}
+
+/// Checks a reply, given as its transcript text and its length in bytes.
+pub fn check(expected: &ExpectedReply, reply: Option<(&str, usize)>) -> Result<(), String> {
+    match (expected, reply) {
+        (ExpectedReply::Silence, None) => Ok(()),
+        (ExpectedReply::Silence, Some(_)) => Err("expected no reply".into()),
+        (_, None) => Err("no reply in time".into()),
+        (ExpectedReply::Any, Some(_)) => Ok(()),
+        (ExpectedReply::Exact { text }, Some((actual, _))) if text == actual => Ok(()),
+        (ExpectedReply::Exact { .. }, Some(_)) => Err("reply doesn't match".into()),
+        (ExpectedReply::Regex { pattern }, Some((actual, _))) => {
+            let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+            match regex.is_match(actual) {
+                true => Ok(()),
+                false => Err(format!("reply doesn't match /{pattern}/")),
+            }
+        }
+        (ExpectedReply::Length { bytes }, Some((_, length))) if *bytes == length => Ok(()),
+        (ExpectedReply::Length { bytes }, Some((_, length))) => Err(format!("reply is {length} bytes, not {bytes}")),
+    }
+}
+
+/// Shows the first line where `actual` departs from `expected`, and where on it.
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let (expected_lines, actual_lines): (Vec<_>, Vec<_>) = (expected.split('\n').collect(), actual.split('\n').collect());
+    let line = (0..expected_lines.len().max(actual_lines.len()))
+        .find(|&i| expected_lines.get(i) != actual_lines.get(i))
+        .unwrap_or_default();
+    let (want, got) = (expected_lines.get(line).copied(), actual_lines.get(line).copied());
+    let column = want
+        .unwrap_or_default()
+        .chars()
+        .zip(got.unwrap_or_default().chars())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let show = |text: Option<&str>| text.map_or_else(|| "(no line)".to_string(), |text| format!("{text:?}"));
+    Some(format!(
+        "- {}\n+ {}\nfirst difference at line {}, column {}",
+        show(want),
+        show(got),
+        line + 1,
+        column + 1
+    ))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // XML 1.0 can't carry most control characters even escaped
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+/// The report as JUnit XML, with one `testcase` per result.
+pub fn junit(report: &TestReport) -> String {
+    let suite = escape(&report.suite);
+    let tests = report.results.len();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"{suite}\" tests=\"{tests}\" failures=\"{}\" time=\"{}\">\n",
+        report.failed,
+        seconds(report.duration_ms)
+    ));
+    xml.push_str(&format!(
+        "  <testsuite name=\"{suite}\" tests=\"{tests}\" failures=\"{}\" errors=\"0\" skipped=\"0\" time=\"{}\">\n",
+        report.failed,
+        seconds(report.duration_ms)
+    ));
+    for result in &report.results {
+        let open = format!(
+            "    <testcase name=\"{}\" classname=\"{suite}\" time=\"{}\"",
+            escape(&result.name),
+            seconds(result.duration_ms)
+        );
+        if result.passed {
+            xml.push_str(&open);
+            xml.push_str("/>\n");
+            continue;
+        }
+        let message = result.error.as_deref().unwrap_or("failed");
+        let mut details = Vec::new();
+        if let Some(expected) = &result.expected {
+            details.push(format!("Expected: {expected}"));
+        }
+        if let Some(actual) = &result.actual {
+            details.push(format!("Actual: {actual}"));
+        }
+        if let Some(diff) = &result.diff {
+            details.push(diff.clone());
+        }
+        xml.push_str(&format!(
+            "{open}>\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+            escape(message),
+            escape(&details.join("\n"))
+        ));
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TestResult;
+
+    #[test]
+    fn each_expectation_is_checked() {
+        assert_eq!(check(&ExpectedReply::Any, Some(("ok", 2))), Ok(()));
+        assert_eq!(check(&ExpectedReply::Any, None), Err("no reply in time".into()));
+
+        let exact = ExpectedReply::Exact { text: "ok".into() };
+        assert_eq!(check(&exact, Some(("ok", 2))), Ok(()));
+        assert_eq!(check(&exact, Some(("OK", 2))), Err("reply doesn't match".into()));
+
+        let regex = ExpectedReply::Regex { pattern: "^v\\d+".into() };
+        assert_eq!(check(&regex, Some(("v12", 3))), Ok(()));
+        assert_eq!(check(&regex, Some(("x12", 3))), Err("reply doesn't match /^v\\d+/".into()));
+
+        let length = ExpectedReply::Length { bytes: 4 };
+        assert_eq!(check(&length, Some(("abcd", 4))), Ok(()));
+        assert_eq!(check(&length, Some(("ab", 2))), Err("reply is 2 bytes, not 4".into()));
+
+        assert_eq!(check(&ExpectedReply::Silence, None), Ok(()));
+        assert_eq!(check(&ExpectedReply::Silence, Some(("ok", 2))), Err("expected no reply".into()));
+    }
+
+    #[test]
+    fn diff_points_at_the_first_difference() {
+        assert_eq!(diff("same", "same"), None);
+        assert_eq!(
+            diff("line one\nline two", "line one\nline 2"),
+            Some("- \"line two\"\n+ \"line 2\"\nfirst difference at line 2, column 6".into())
+        );
+        assert_eq!(diff("a\nb", "a"), Some("- \"b\"\n+ (no line)\nfirst difference at line 2, column 1".into()));
+    }
+
+    #[test]
+    fn junit_escapes_text() {
+        let report = TestReport {
+            suite: "a<b & \"c\"".into(),
+            failed: 1,
+            results: vec![TestResult {
+                name: "bell\u{7}".into(),
+                error: Some("reply doesn't match".into()),
+                expected: Some("<ok>".into()),
+                actual: Some("line\nnext".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let xml = junit(&report);
+        assert!(xml.contains("<testsuites name=\"a&lt;b &amp; &quot;c&quot;\""));
+        assert!(xml.contains("<testcase name=\"bell\\u{7}\""));
+        assert!(xml.contains("<failure message=\"reply doesn&apos;t match\">Expected: &lt;ok&gt;\nActual: line\nnext</failure>"));
+    }
+}
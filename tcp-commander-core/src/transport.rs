@@ -0,0 +1,561 @@
+//! Pluggable transports.
+//!
+//! A [`Transport`] knows how to open a [`Connection`] to a target; the rest of
+//! the crate only ever talks to `dyn Connection`, so adding a transport means
+//! implementing these two traits and registering it in [`Transports`].
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, ErrorKind, Read, Write},
+    net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned,
+};
+
+use crate::models::DisconnectMode;
+
+/// How long a gracefully closed connection keeps draining before it is dropped anyway.
+pub const GRACEFUL_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where a transport should connect to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+    /// What `host` was already looked up as. When set, these are connected to
+    /// instead of looking it up again, which might give different answers.
+    pub addresses: Vec<IpAddr>,
+}
+
+impl Target {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self { host: host.into(), port, addresses: Vec::new() }
+    }
+
+    pub fn with_addresses(self, addresses: Vec<IpAddr>) -> Self {
+        Self { addresses, ..self }
+    }
+
+    fn resolve(&self) -> io::Result<Vec<SocketAddr>> {
+        if !self.addresses.is_empty() {
+            return Ok(self.addresses.iter().map(|ip| SocketAddr::new(*ip, self.port)).collect());
+        }
+        let addrs: Vec<_> = (self.host.as_str(), self.port).to_socket_addrs()?.collect();
+        if addrs.is_empty() {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("'{}' has no addresses", self.host)));
+        }
+        Ok(addrs)
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+/// An open, bidirectional byte stream.
+pub trait Connection: Read + Write + Send {
+    /// Human-readable description of the remote end.
+    fn peer(&self) -> String;
+
+    /// Whether every write is sent, and every read received, as one datagram
+    /// rather than as part of a byte stream.
+    fn is_datagram(&self) -> bool {
+        false
+    }
+
+    /// `None` blocks forever; reads that time out fail with `WouldBlock` or `TimedOut`.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// `None` blocks forever.
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Tears down the connection. Errors are swallowed, since there is
+    /// nothing useful to do about a connection that is already dead.
+    ///
+    /// A graceful close blocks until the peer closes its side, for up to
+    /// [`GRACEFUL_CLOSE_TIMEOUT`]; run it on another thread if that matters.
+    fn close(self: Box<Self>, mode: DisconnectMode);
+}
+
+/// Opens connections of one particular kind.
+pub trait Transport: Send + Sync {
+    /// Identifier used to select this transport, e.g. `"tcp"`.
+    fn name(&self) -> &'static str;
+
+    fn connect(&self, target: &Target, timeout: Option<Duration>) -> io::Result<Box<dyn Connection>>;
+}
+
+/// Registry of the transports a connection can be opened with.
+#[derive(Clone)]
+pub struct Transports(BTreeMap<&'static str, Arc<dyn Transport>>);
+
+impl Default for Transports {
+    fn default() -> Self {
+        let mut transports = Self(BTreeMap::new());
+        transports.register(TcpTransport);
+        transports.register(UdpTransport);
+        transports.register(TlsTransport::new());
+        transports.register(TlsTransport::insecure());
+        transports
+    }
+}
+
+impl Transports {
+    /// Adds `transport`, replacing any existing one with the same name.
+    pub fn register(&mut self, transport: impl Transport + 'static) {
+        self.0.insert(transport.name(), Arc::new(transport));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Transport>> {
+        self.0.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.0.keys().copied()
+    }
+}
+
+fn tcp_connect(target: &Target, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in target.resolve()? {
+        let socket = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match socket {
+            Ok(socket) => return Ok(socket),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.expect("resolve returns at least one address"))
+}
+
+/// Reads and discards until EOF, an error, or [`GRACEFUL_CLOSE_TIMEOUT`] runs out.
+fn drain(reader: &mut impl Read) {
+    let deadline = Instant::now() + GRACEFUL_CLOSE_TIMEOUT;
+    let mut buf = [0; 1024];
+    while Instant::now() < deadline {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+fn close_tcp(mut socket: TcpStream, mode: DisconnectMode) {
+    match mode {
+        DisconnectMode::Graceful => {
+            if let Err(e) = socket.shutdown(Shutdown::Write) {
+                if e.kind() != ErrorKind::NotConnected {
+                    log::warn!("Failed to half-close socket: {e}");
+                }
+                return;
+            }
+            // Keep reading whatever the peer still has to say until it closes its side
+            let _ = socket.set_read_timeout(Some(GRACEFUL_CLOSE_TIMEOUT));
+            drain(&mut socket);
+        }
+        DisconnectMode::Full => {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+        DisconnectMode::Abort => {
+            // A zero linger makes the OS discard unsent data and send RST on close
+            if let Err(e) = socket2::SockRef::from(&socket).set_linger(Some(Duration::ZERO)) {
+                log::warn!("Failed to set linger for abortive close: {e}");
+            }
+        }
+    }
+}
+
+/// Plain TCP.
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn connect(&self, target: &Target, timeout: Option<Duration>) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(tcp_connect(target, timeout)?))
+    }
+}
+
+impl Connection for TcpStream {
+    fn peer(&self) -> String {
+        self.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "disconnected".into())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn close(self: Box<Self>, mode: DisconnectMode) {
+        close_tcp(*self, mode);
+    }
+}
+
+/// Connected UDP socket; every write is sent as one datagram.
+pub struct UdpTransport;
+
+pub struct UdpConnection(UdpSocket);
+
+impl Transport for UdpTransport {
+    fn name(&self) -> &'static str {
+        "udp"
+    }
+
+    fn connect(&self, target: &Target, _timeout: Option<Duration>) -> io::Result<Box<dyn Connection>> {
+        let addr = target.resolve()?[0];
+        let bind: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(addr)?;
+        Ok(Box::new(UdpConnection(socket)))
+    }
+}
+
+impl Read for UdpConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for UdpConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for UdpConnection {
+    fn peer(&self) -> String {
+        self.0.peer_addr().map(|a| format!("udp://{a}")).unwrap_or_else(|_| "udp".into())
+    }
+
+    fn is_datagram(&self) -> bool {
+        true
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.set_write_timeout(timeout)
+    }
+
+    // UDP has no connection to tear down
+    fn close(self: Box<Self>, _mode: DisconnectMode) {}
+}
+
+/// TLS over TCP, using rustls.
+pub struct TlsTransport {
+    name: &'static str,
+    config: Arc<ClientConfig>,
+}
+
+pub type TlsConnection = StreamOwned<ClientConnection, TcpStream>;
+
+impl TlsTransport {
+    /// Verifies the server against the bundled Mozilla root certificates.
+    pub fn new() -> Self {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Self { name: "tls", config: Arc::new(config) }
+    }
+
+    /// Accepts any certificate. Lab devices almost always use self-signed ones.
+    pub fn insecure() -> Self {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth();
+        Self { name: "tls-insecure", config: Arc::new(config) }
+    }
+}
+
+impl Default for TlsTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for TlsTransport {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn connect(&self, target: &Target, timeout: Option<Duration>) -> io::Result<Box<dyn Connection>> {
+        let server_name = ServerName::try_from(target.host.clone())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let session = ClientConnection::new(self.config.clone(), server_name)
+            .map_err(io::Error::other)?;
+        let socket = tcp_connect(target, timeout)?;
+        // The connect timeout covers the handshake too, so a server that never answers can't hang us
+        socket.set_read_timeout(timeout)?;
+        socket.set_write_timeout(timeout)?;
+        let mut stream = StreamOwned::new(session, socket);
+        // Complete the handshake up front so certificate problems surface as connect errors
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        stream.sock.set_read_timeout(None)?;
+        stream.sock.set_write_timeout(None)?;
+        Ok(Box::new(stream))
+    }
+}
+
+impl Connection for TlsConnection {
+    fn peer(&self) -> String {
+        self.sock.peer_addr().map(|a| format!("tls://{a}")).unwrap_or_else(|_| "tls".into())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+
+    fn close(self: Box<Self>, mode: DisconnectMode) {
+        let StreamOwned { mut conn, mut sock } = *self;
+        if mode != DisconnectMode::Abort {
+            conn.send_close_notify();
+            let _ = conn.complete_io(&mut sock);
+        }
+        close_tcp(sock, mode);
+    }
+}
+
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<rustls::crypto::CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[derive(Default)]
+struct PipeState {
+    buffer: VecDeque<u8>,
+    closed: bool,
+}
+
+/// One direction of an in-memory duplex stream.
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+/// One end of an in-memory duplex stream, see [`MockConnection::pair`].
+pub struct MockConnection {
+    name: String,
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Option<Duration>,
+}
+
+impl MockConnection {
+    /// Creates two connected ends; whatever is written to one can be read from the other.
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(Pipe::default());
+        let b = Arc::new(Pipe::default());
+        let client = Self {
+            name: "mock://client".into(),
+            incoming: a.clone(),
+            outgoing: b.clone(),
+            read_timeout: None,
+        };
+        let server = Self {
+            name: "mock://server".into(),
+            incoming: b,
+            outgoing: a,
+            read_timeout: None,
+        };
+        (client, server)
+    }
+
+    /// Closes this end's outgoing direction, so the peer reads EOF.
+    pub fn shutdown_write(&self) {
+        self.outgoing.close();
+    }
+}
+
+impl Read for MockConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = self.read_timeout.map(|t| Instant::now() + t);
+        let mut state = self.incoming.state.lock().unwrap();
+        while state.buffer.is_empty() && !state.closed {
+            state = match deadline {
+                None => self.incoming.readable.wait(state).unwrap(),
+                Some(deadline) => {
+                    let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                        return Err(ErrorKind::WouldBlock.into());
+                    };
+                    self.incoming.readable.wait_timeout(state, remaining).unwrap().0
+                }
+            };
+        }
+        let n = buf.len().min(state.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+        state.buffer.extend(buf);
+        self.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for MockConnection {
+    fn peer(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    // Writes never block, since the buffer is unbounded
+    fn set_write_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(mut self: Box<Self>, mode: DisconnectMode) {
+        self.outgoing.close();
+        if mode == DisconnectMode::Graceful {
+            self.read_timeout = Some(GRACEFUL_CLOSE_TIMEOUT);
+            drain(&mut self);
+        }
+        self.incoming.close();
+    }
+}
+
+impl Drop for MockConnection {
+    fn drop(&mut self) {
+        self.outgoing.close();
+    }
+}
+
+/// In-memory transport for exercising connection logic without sockets.
+///
+/// Every successful `connect` hands the other end of the new connection to
+/// [`MockTransport::accept`], where test code can play the part of the server.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    pending: Arc<Mutex<VecDeque<(Target, MockConnection)>>>,
+    refuse: Arc<Mutex<bool>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes subsequent connection attempts fail with `ConnectionRefused`.
+    pub fn set_refusing(&self, refuse: bool) {
+        *self.refuse.lock().unwrap() = refuse;
+    }
+
+    /// Server end of the oldest connection that hasn't been accepted yet.
+    pub fn accept(&self) -> Option<(Target, MockConnection)> {
+        self.pending.lock().unwrap().pop_front()
+    }
+}
+
+impl Transport for MockTransport {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn connect(&self, target: &Target, _timeout: Option<Duration>) -> io::Result<Box<dyn Connection>> {
+        if *self.refuse.lock().unwrap() {
+            return Err(ErrorKind::ConnectionRefused.into());
+        }
+        let (client, server) = MockConnection::pair();
+        self.pending.lock().unwrap().push_back((target.clone(), server));
+        Ok(Box::new(client))
+    }
+}
@@ -0,0 +1,475 @@
+//! The connection manager against real sockets on the loopback interface.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, UdpSocket},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tcp_commander_core::{manager::ConnectionManager, transport::Transports, Config};
+use tcp_commander_models::*;
+
+/// Accepts connections and answers every line with `ECHO <line>`.
+fn echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { return };
+            thread::spawn(move || {
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                for line in reader.lines() {
+                    let Ok(line) = line else { return };
+                    if stream.write_all(format!("ECHO {line}\n").as_bytes()).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+fn manager() -> ConnectionManager {
+    ConnectionManager::new(Transports::default(), Config::default())
+}
+
+fn connect(manager: &ConnectionManager, addr: SocketAddr, transport: &str) -> ConnectResponse {
+    manager
+        .connect(ConnectRequest {
+            address: Some(addr.ip().to_string()),
+            port: Some(addr.port().into()),
+            transport: Some(transport.into()),
+            framing: Some(Framing::Lf),
+            protocol: None,
+            mqtt: None,
+            redis: None,
+        })
+        .unwrap()
+}
+
+fn transmit(manager: &ConnectionManager, message: &str) -> TransmitResponse {
+    manager.transmit(TransmitRequest { message: Some(message.into()), suffix: None }).unwrap()
+}
+
+/// Waits for the transcript to hold an entry like `direction`/`data`.
+fn wait_for(manager: &ConnectionManager, direction: Direction, data: &str) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let entries = manager.transcript(TranscriptRequest::default()).entries;
+        if entries.iter().any(|entry| entry.direction == direction && entry.data == data) {
+            return;
+        }
+        let seen: Vec<_> = entries.iter().map(|entry| (entry.direction, entry.data.as_str())).collect();
+        assert!(Instant::now() < deadline, "no {direction:?} {data:?} in {seen:?}");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn tcp_round_trip() {
+    let addr = echo_server();
+    let manager = manager();
+    let response = connect(&manager, addr, "tcp");
+    assert_eq!(response.error, None);
+    assert!(manager.is_connected());
+    wait_for(&manager, Direction::Info, &format!("Connected to {addr} (tcp)"));
+
+    assert_eq!(transmit(&manager, "hello\nworld\n").error, None);
+    wait_for(&manager, Direction::Sent, "hello\nworld\n");
+    wait_for(&manager, Direction::Received, "ECHO hello");
+    wait_for(&manager, Direction::Received, "ECHO world");
+
+    manager.disconnect(DisconnectRequest::default()).unwrap();
+    assert!(!manager.is_connected());
+    wait_for(&manager, Direction::Info, "Disconnected (Graceful)");
+    assert!(transmit(&manager, "late\n").error.is_some());
+}
+
+#[test]
+fn udp_round_trip() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+            let reply = [b"ECHO ", &buffer[..length]].concat();
+            let _ = socket.send_to(&reply, peer);
+        }
+    });
+
+    let manager = manager();
+    assert_eq!(connect(&manager, addr, "udp").error, None);
+    assert_eq!(transmit(&manager, "ping\n").error, None);
+    wait_for(&manager, Direction::Received, "ECHO ping");
+}
+
+#[test]
+fn binary_suffix_is_sent_as_is() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, received) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut bytes = [0; 6];
+        stream.read_exact(&mut bytes).unwrap();
+        sender.send(bytes).unwrap();
+    });
+
+    let manager = manager();
+    assert_eq!(connect(&manager, addr, "tcp").error, None);
+    let request = TransmitRequest { message: Some("READ".into()), suffix: Some(vec![0xC1, 0x0D]) };
+    assert_eq!(manager.transmit(request).unwrap().error, None);
+    assert_eq!(received.recv_timeout(Duration::from_secs(5)).unwrap(), *b"READ\xC1\r");
+    wait_for(&manager, Direction::Sent, "READ[C1 0D]");
+}
+
+#[test]
+fn refused_connection_is_reported() {
+    // Bind and drop a listener to find a port nothing is listening on
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let manager = manager();
+    let response = connect(&manager, addr, "tcp");
+    assert_eq!(response.success, Some(false));
+    assert!(response.error.unwrap().starts_with("Failed to connect to server"));
+    assert!(!manager.is_connected());
+}
+
+#[test]
+fn server_closing_is_noticed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"bye\n").unwrap();
+    });
+
+    let manager = manager();
+    assert_eq!(connect(&manager, addr, "tcp").error, None);
+    wait_for(&manager, Direction::Received, "bye");
+    wait_for(&manager, Direction::Info, "Connection closed by server");
+}
+
+#[test]
+fn looked_up_addresses_are_used() {
+    let addr = echo_server();
+    let manager = manager();
+    let request = ConnectRequest {
+        // Never resolves, so only the address given alongside can work
+        address: Some("echo.invalid".into()),
+        port: Some(addr.port().into()),
+        transport: None,
+        framing: Some(Framing::Lf),
+        protocol: None,
+        mqtt: None,
+        redis: None,
+    };
+    assert_eq!(manager.connect_to(request, vec![addr.ip()]).unwrap().error, None);
+    assert_eq!(transmit(&manager, "pinned\n").error, None);
+    wait_for(&manager, Direction::Received, "ECHO pinned");
+}
+
+#[test]
+fn unknown_transport_is_refused() {
+    let manager = manager();
+    let response = connect(&manager, echo_server(), "carrier-pigeon");
+    assert!(response.error.unwrap().starts_with("Unknown transport 'carrier-pigeon'"));
+}
+
+#[test]
+fn sequence_waits_for_replies() {
+    let manager = manager();
+    assert_eq!(connect(&manager, echo_server(), "tcp").error, None);
+    let steps = vec![
+        SequenceStep::Transmit { message: "first\n".into(), suffix: None },
+        SequenceStep::WaitFor { pattern: "^ECHO first$".into(), timeout_ms: Some(2000) },
+        SequenceStep::Repeat { times: 2, steps: vec![SequenceStep::Transmit { message: "again\n".into(), suffix: None }] },
+        SequenceStep::WaitFor { pattern: "never".into(), timeout_ms: Some(100) },
+    ];
+    let status = manager.sequence_run(SequenceRunRequest { name: Some("demo".into()), steps }).unwrap();
+    assert_eq!(status.error, None);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        let status = manager.sequence_status();
+        if !matches!(status.state, SequenceState::Running | SequenceState::Paused) {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "sequence still running: {status:?}");
+        thread::sleep(Duration::from_millis(10));
+    };
+    // Everything up to the wait that can't succeed ran
+    assert_eq!(status.state, SequenceState::Failed);
+    assert_eq!(status.completed, 4);
+    wait_for(&manager, Direction::Received, "ECHO again");
+}
+
+#[test]
+fn test_run_checks_replies() {
+    let manager = manager();
+    assert_eq!(connect(&manager, echo_server(), "tcp").error, None);
+    let case = |name: &str, message: &str, reply| TestCase {
+        name: name.into(),
+        steps: vec![SequenceStep::Transmit { message: message.into(), suffix: None }],
+        expect: Some(Expectation { reply, timeout_ms: Some(1000) }),
+    };
+    let report = manager
+        .test_run(TestRunRequest {
+            suite: Some("loopback".into()),
+            cases: vec![
+                case("exact", "*IDN?\n", ExpectedReply::Exact { text: "ECHO *IDN?".into() }),
+                case("regex", "42\n", ExpectedReply::Regex { pattern: r"^ECHO \d+$".into() }),
+                case("wrong", "abc\n", ExpectedReply::Exact { text: "ECHO abd".into() }),
+            ],
+        })
+        .unwrap();
+
+    assert_eq!(report.error, None);
+    assert_eq!((report.passed, report.failed), (2, 1));
+    let wrong = &report.results[2];
+    assert!(!wrong.passed);
+    assert_eq!(wrong.actual.as_deref(), Some("ECHO abc"));
+    assert!(wrong.diff.as_deref().unwrap().contains("column 8"));
+    assert!(report.junit.contains("<testcase name=\"wrong\" classname=\"loopback\""));
+}
+
+#[test]
+fn test_run_can_be_stopped() {
+    let manager = manager();
+    assert_eq!(connect(&manager, echo_server(), "tcp").error, None);
+    let slow = |name: &str| TestCase { name: name.into(), steps: vec![SequenceStep::Delay { ms: 5000 }], expect: None };
+    let start = Instant::now();
+    let report = thread::scope(|scope| {
+        let run = scope.spawn(|| manager.test_run(TestRunRequest { suite: None, cases: vec![slow("first"), slow("second")] }).unwrap());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while manager.sequence_status().state != SequenceState::Running {
+            assert!(Instant::now() < deadline, "tests never started");
+            thread::sleep(Duration::from_millis(10));
+        }
+        manager.sequence_control(SequenceControlRequest { action: SequenceAction::Stop }).unwrap();
+        run.join().unwrap()
+    });
+
+    assert!(start.elapsed() < Duration::from_secs(4));
+    assert_eq!(report.error.as_deref(), Some("stopped after 1 of 2 tests"));
+    assert_eq!(report.results.len(), 1);
+    assert_eq!(report.results[0].error.as_deref(), Some("stopped"));
+    assert_eq!(manager.sequence_status().state, SequenceState::Stopped);
+}
+
+fn schedule(name: &str, step: SequenceStep, interval_ms: u64, count: Option<u64>, duration_ms: Option<u64>) -> ScheduleRequest {
+    ScheduleRequest { name: Some(name.into()), step, interval_ms, count, duration_ms }
+}
+
+fn tick() -> SequenceStep {
+    SequenceStep::Transmit { message: "tick\n".into(), suffix: None }
+}
+
+/// Waits for an info entry starting with `prefix` and returns it.
+fn wait_for_note(manager: &ConnectionManager, prefix: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let entries = manager.transcript(TranscriptRequest::default()).entries;
+        if let Some(entry) = entries.iter().find(|entry| entry.direction == Direction::Info && entry.data.starts_with(prefix)) {
+            return entry.data.clone();
+        }
+        assert!(Instant::now() < deadline, "no note starting {prefix:?}");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn ticks_sent(manager: &ConnectionManager) -> usize {
+    let entries = manager.transcript(TranscriptRequest::default()).entries;
+    entries.iter().filter(|entry| entry.direction == Direction::Sent && entry.data == "tick\n").count()
+}
+
+#[test]
+fn schedule_stops_after_count() {
+    let manager = manager();
+    assert_eq!(connect(&manager, echo_server(), "tcp").error, None);
+    let status = manager.schedule_start(schedule("counted", tick(), 20, Some(3), None)).unwrap();
+    assert_eq!(status.error, None);
+
+    assert_eq!(wait_for_note(&manager, "Schedule 'counted' finished"), "Schedule 'counted' finished after 3 sends");
+    assert_eq!(ticks_sent(&manager), 3);
+    assert!(manager.schedule_list().schedules.is_empty());
+    wait_for(&manager, Direction::Received, "ECHO tick");
+}
+
+#[test]
+fn schedule_stops_after_duration() {
+    let manager = manager();
+    assert_eq!(connect(&manager, echo_server(), "tcp").error, None);
+    let start = Instant::now();
+    let status = manager.schedule_start(schedule("timed", tick(), 50, None, Some(175))).unwrap();
+    assert_eq!(status.error, None);
+
+    let note = wait_for_note(&manager, "Schedule 'timed' finished");
+    // Sends at 0, 50, 100 and 150 ms, unless a slow one pushed the rest back
+    assert!(start.elapsed() >= Duration::from_millis(150), "{note} too early");
+    let sent = ticks_sent(&manager);
+    assert!((2..=4).contains(&sent), "{sent} sends");
+    assert_eq!(note, format!("Schedule 'timed' finished after {sent} sends"));
+    assert!(manager.schedule_list().schedules.is_empty());
+}
+
+#[test]
+fn schedules_are_stopped_by_id_or_all_at_once() {
+    let manager = manager();
+    assert_eq!(connect(&manager, echo_server(), "tcp").error, None);
+    let first = manager.schedule_start(schedule("first", tick(), 1000, None, None)).unwrap();
+    let second = manager.schedule_start(schedule("second", tick(), 1000, None, None)).unwrap();
+    let third = manager.schedule_start(schedule("third", tick(), 1000, None, None)).unwrap();
+    let ids = |list: ScheduleListResponse| list.schedules.iter().map(|schedule| schedule.id).collect::<Vec<_>>();
+    assert_eq!(ids(manager.schedule_list()), [first.id, second.id, third.id]);
+
+    let left = manager.schedule_stop(ScheduleStopRequest { id: Some(second.id) }).unwrap();
+    assert_eq!(ids(left), [first.id, third.id]);
+    wait_for_note(&manager, "Schedule 'second' stopped after");
+
+    assert!(manager.schedule_stop(ScheduleStopRequest { id: None }).unwrap().schedules.is_empty());
+    wait_for_note(&manager, "Schedule 'first' stopped after");
+    wait_for_note(&manager, "Schedule 'third' stopped after");
+    assert!(manager.schedule_list().schedules.is_empty());
+}
+
+#[test]
+fn schedules_only_send_commands() {
+    let manager = manager();
+    assert_eq!(connect(&manager, echo_server(), "tcp").error, None);
+    for step in [
+        SequenceStep::Delay { ms: 10 },
+        SequenceStep::WaitFor { pattern: "ok".into(), timeout_ms: None },
+        SequenceStep::Repeat { times: 2, steps: vec![tick()] },
+    ] {
+        let status = manager.schedule_start(schedule("invalid", step, 20, Some(1), None)).unwrap();
+        assert_eq!(status.error.as_deref(), Some("schedules can only send commands"));
+    }
+    assert!(manager.schedule_list().schedules.is_empty());
+    assert_eq!(ticks_sent(&manager), 0);
+}
+
+/// Reads one MQTT packet, returning its first byte and the rest after the remaining length.
+fn read_packet(stream: &mut impl Read) -> Option<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte).ok()?;
+    let first = byte[0];
+    let (mut length, mut shift) = (0, 0);
+    loop {
+        stream.read_exact(&mut byte).ok()?;
+        length |= ((byte[0] & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).ok()?;
+    Some((first, body))
+}
+
+/// Just enough of a broker for one client: every message published to a topic
+/// the client has subscribed to is delivered back to it at QoS 1. What the
+/// client sends is reported on the returned channel.
+fn mqtt_broker(version: MqttVersion) -> (SocketAddr, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, events) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let v5 = version == MqttVersion::V5;
+        // MQTT 5 puts an empty property list after the variable header
+        let properties = if v5 { &[0][..] } else { &[] };
+        let (first, body) = read_packet(&mut stream).unwrap();
+        assert_eq!((first, body[6]), (0x10, if v5 { 5 } else { 4 }));
+        stream.write_all(&[&[0x20, 2 + properties.len() as u8, 0, 0][..], properties].concat()).unwrap();
+
+        let mut filter = None;
+        while let Some((first, body)) = read_packet(&mut stream) {
+            let id = body.get(..2).map_or([0, 0], |id| [id[0], id[1]]);
+            match first >> 4 {
+                3 => {
+                    let qos = (first >> 1) & 3;
+                    let topic_end = 2 + u16::from_be_bytes(id) as usize;
+                    let topic = String::from_utf8(body[2..topic_end].to_vec()).unwrap();
+                    let id = [body[topic_end], body[topic_end + 1]];
+                    let payload = &body[topic_end + 2 + properties.len()..];
+                    sender.send(format!("PUBLISH {topic} {} QoS {qos}", String::from_utf8_lossy(payload))).unwrap();
+                    let ack = if qos == 1 { 0x40 } else { 0x50 };
+                    stream.write_all(&[ack, 2, id[0], id[1]]).unwrap();
+                    if filter.as_ref().is_some_and(|filter: &String| topic.starts_with(filter.trim_end_matches('#'))) {
+                        let mut deliver = vec![0x32, (2 + topic.len() + 2 + properties.len() + payload.len()) as u8];
+                        deliver.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+                        deliver.extend_from_slice(topic.as_bytes());
+                        deliver.extend_from_slice(&[0, 100]);
+                        deliver.extend_from_slice(properties);
+                        deliver.extend_from_slice(payload);
+                        stream.write_all(&deliver).unwrap();
+                    }
+                }
+                4 => sender.send(format!("PUBACK {}", u16::from_be_bytes(id))).unwrap(),
+                6 => {
+                    sender.send(format!("PUBREL {}", u16::from_be_bytes(id))).unwrap();
+                    stream.write_all(&[0x70, 2, id[0], id[1]]).unwrap();
+                }
+                8 => {
+                    let rest = &body[2 + properties.len()..];
+                    let topic_end = 2 + u16::from_be_bytes([rest[0], rest[1]]) as usize;
+                    filter = Some(String::from_utf8(rest[2..topic_end].to_vec()).unwrap());
+                    let granted = rest[topic_end];
+                    let suback = [&[0x90, 3 + properties.len() as u8, id[0], id[1]][..], properties, &[granted]].concat();
+                    stream.write_all(&suback).unwrap();
+                }
+                14 => return sender.send("DISCONNECT".into()).unwrap(),
+                other => panic!("unexpected packet type {other}"),
+            }
+        }
+    });
+    (addr, events)
+}
+
+#[test]
+fn mqtt_publish_and_subscribe() {
+    for version in [MqttVersion::V311, MqttVersion::V5] {
+        let (addr, events) = mqtt_broker(version);
+        let event = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+        let manager = manager();
+        let response = manager
+            .connect(ConnectRequest {
+                address: Some(addr.ip().to_string()),
+                port: Some(addr.port().into()),
+                transport: None,
+                framing: None,
+                protocol: Some(Protocol::Mqtt),
+                mqtt: Some(MqttOptions { version: Some(version), client_id: Some("test".into()), ..Default::default() }),
+                redis: None,
+            })
+            .unwrap();
+        assert_eq!(response.error, None);
+
+        let subscribe = MqttSubscribeRequest { topic: "lights/#".into(), qos: Some(1) };
+        assert_eq!(manager.mqtt_subscribe(subscribe).unwrap().granted_qos, Some(1));
+        wait_for(&manager, Direction::Info, "Subscribed to lights/# (QoS 1)");
+
+        let publish = |topic: &str, payload: &str, qos| {
+            let request = MqttPublishRequest { topic: topic.into(), payload: payload.into(), qos: Some(qos), retain: None };
+            manager.mqtt_publish(request).unwrap().error
+        };
+        assert_eq!(publish("lights/hall", "on", 1), None);
+        assert_eq!(event(), "PUBLISH lights/hall on QoS 1");
+        // The broker delivers it back, and it's acknowledged
+        wait_for(&manager, Direction::Received, "lights/hall: on");
+        assert_eq!(event(), "PUBACK 100");
+
+        assert_eq!(publish("lights/porch", "off", 2), None);
+        assert_eq!(event(), "PUBLISH lights/porch off QoS 2");
+        assert!(event().starts_with("PUBREL "));
+        wait_for(&manager, Direction::Received, "lights/porch: off");
+        assert_eq!(event(), "PUBACK 100");
+
+        manager.disconnect(DisconnectRequest::default()).unwrap();
+        assert_eq!(event(), "DISCONNECT");
+    }
+}
@@ -0,0 +1,170 @@
+//! Checksums that serial devices expect at the end of each message.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChecksumAlgorithm {
+  #[default]
+  None,
+  /// Every byte XORed together, often called an LRC.
+  Xor,
+  /// The sum of every byte, modulo 256.
+  Sum8,
+  /// Polynomial 0x8005 reflected, starting at 0xFFFF, sent low byte first.
+  Crc16Modbus,
+  /// Polynomial 0x1021, starting at 0xFFFF, sent high byte first.
+  Crc16Ccitt,
+  /// The CRC-32 of zip and Ethernet, sent high byte first.
+  Crc32,
+}
+
+impl ChecksumAlgorithm {
+  pub const ALL: [ChecksumAlgorithm; 6] = [
+    ChecksumAlgorithm::None,
+    ChecksumAlgorithm::Xor,
+    ChecksumAlgorithm::Sum8,
+    ChecksumAlgorithm::Crc16Modbus,
+    ChecksumAlgorithm::Crc16Ccitt,
+    ChecksumAlgorithm::Crc32,
+  ];
+
+  pub fn label(self) -> &'static str {
+    match self {
+      ChecksumAlgorithm::None => "None",
+      ChecksumAlgorithm::Xor => "XOR / LRC",
+      ChecksumAlgorithm::Sum8 => "Sum8",
+      ChecksumAlgorithm::Crc16Modbus => "CRC-16/MODBUS",
+      ChecksumAlgorithm::Crc16Ccitt => "CRC-16/CCITT",
+      ChecksumAlgorithm::Crc32 => "CRC-32",
+    }
+  }
+
+  /// The checksum of `bytes`, in the order it's sent.
+  pub fn compute(self, bytes: &[u8]) -> Vec<u8> {
+    match self {
+      ChecksumAlgorithm::None => Vec::new(),
+      ChecksumAlgorithm::Xor => vec![bytes.iter().fold(0, |checksum, byte| checksum ^ byte)],
+      ChecksumAlgorithm::Sum8 => vec![bytes.iter().fold(0u8, |checksum, byte| checksum.wrapping_add(*byte))],
+      ChecksumAlgorithm::Crc16Modbus => {
+        let crc = bytes.iter().fold(0xFFFFu16, |mut crc, byte| {
+          crc ^= u16::from(*byte);
+          for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+          }
+          crc
+        });
+        crc.to_le_bytes().to_vec()
+      }
+      ChecksumAlgorithm::Crc16Ccitt => {
+        let crc = bytes.iter().fold(0xFFFFu16, |mut crc, byte| {
+          crc ^= u16::from(*byte) << 8;
+          for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+          }
+          crc
+        });
+        crc.to_be_bytes().to_vec()
+      }
+      ChecksumAlgorithm::Crc32 => {
+        let crc = bytes.iter().fold(0xFFFF_FFFFu32, |mut crc, byte| {
+          crc ^= u32::from(*byte);
+          for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+          }
+          crc
+        });
+        (!crc).to_be_bytes().to_vec()
+      }
+    }
+  }
+}
+
+/// How the checksum is written after the message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChecksumFormat {
+  /// The checksum bytes themselves.
+  #[default]
+  Raw,
+  /// Two uppercase hex digits per byte, as text.
+  Hex,
+}
+
+/// Which checksum to add and what it covers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Checksum {
+  pub algorithm: ChecksumAlgorithm,
+  pub format: ChecksumFormat,
+  /// Bytes at the start of the message left out, such as an STX or a `:`.
+  pub skip_start: usize,
+  /// Bytes at the end of the message left out.
+  pub skip_end: usize,
+}
+
+impl Checksum {
+  /// The bytes to send after `message`, or nothing without an algorithm.
+  pub fn suffix(&self, message: &[u8]) -> Vec<u8> {
+    let end = message.len().saturating_sub(self.skip_end);
+    let covered = message.get(self.skip_start.min(end)..end).unwrap_or_default();
+    let checksum = self.algorithm.compute(covered);
+    match self.format {
+      ChecksumFormat::Raw => checksum,
+      ChecksumFormat::Hex => checksum.iter().flat_map(|byte| format!("{byte:02X}").into_bytes()).collect(),
+    }
+  }
+
+  /// A short description for settings and command cards, e.g. `CRC-16/MODBUS (hex, from byte 2)`.
+  pub fn describe(&self) -> String {
+    let mut details = vec![match self.format {
+      ChecksumFormat::Raw => "raw".to_string(),
+      ChecksumFormat::Hex => "hex".to_string(),
+    }];
+    if self.skip_start > 0 {
+      details.push(format!("from byte {}", self.skip_start + 1));
+    }
+    if self.skip_end > 0 {
+      details.push(format!("leaving out the last {}", self.skip_end));
+    }
+    format!("{} ({})", self.algorithm.label(), details.join(", "))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CHECK: &[u8] = b"123456789";
+
+  #[test]
+  fn check_values() {
+    assert!(ChecksumAlgorithm::None.compute(CHECK).is_empty());
+    assert_eq!(ChecksumAlgorithm::Xor.compute(CHECK), [0x31]);
+    assert_eq!(ChecksumAlgorithm::Sum8.compute(CHECK), [0xDD]);
+    // 0x4B37, low byte first
+    assert_eq!(ChecksumAlgorithm::Crc16Modbus.compute(CHECK), [0x37, 0x4B]);
+    // CRC-16/CCITT-FALSE
+    assert_eq!(ChecksumAlgorithm::Crc16Ccitt.compute(CHECK), [0x29, 0xB1]);
+    assert_eq!(ChecksumAlgorithm::Crc32.compute(CHECK), [0xCB, 0xF4, 0x39, 0x26]);
+  }
+
+  #[test]
+  fn skipped_bytes_are_left_out() {
+    let checksum = Checksum { algorithm: ChecksumAlgorithm::Crc16Modbus, format: ChecksumFormat::Raw, skip_start: 1, skip_end: 1 };
+    assert_eq!(checksum.suffix(b"\x02123456789\x03"), [0x37, 0x4B]);
+    let checksum = Checksum { format: ChecksumFormat::Hex, ..checksum };
+    assert_eq!(checksum.suffix(b"\x02123456789\x03"), *b"374B");
+    // Skipping more than there is covers nothing, rather than panicking
+    let checksum = Checksum { algorithm: ChecksumAlgorithm::Xor, format: ChecksumFormat::Raw, skip_start: 5, skip_end: 5 };
+    assert_eq!(checksum.suffix(b"abc"), [0]);
+    assert!(Checksum::default().suffix(CHECK).is_empty());
+  }
+
+  #[test]
+  fn descriptions() {
+    let checksum = Checksum { algorithm: ChecksumAlgorithm::Crc16Modbus, format: ChecksumFormat::Hex, skip_start: 1, skip_end: 2 };
+    assert_eq!(checksum.describe(), "CRC-16/MODBUS (hex, from byte 2, leaving out the last 2)");
+    assert_eq!(Checksum { algorithm: ChecksumAlgorithm::Xor, ..Checksum::default() }.describe(), "XOR / LRC (raw)");
+  }
+}
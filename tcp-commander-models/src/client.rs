@@ -0,0 +1,177 @@
+//! Typed wrappers around the plugin's commands.
+//!
+//! Each request struct serializes to exactly the argument names the matching
+//! command expects, so these just forward it to Tauri's `invoke`.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::*;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "core"], js_name = invoke, catch)]
+    async fn tauri_invoke(cmd: &str, args: JsValue) -> std::result::Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "event"], js_name = listen)]
+    async fn tauri_listen(event: &str, handler: &Closure<dyn FnMut(JsValue)>) -> JsValue;
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The arguments or the result didn't match the expected shape.
+    Serde(serde_wasm_bindgen::Error),
+    /// The command itself returned an error.
+    Command(String),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Serde(e) => write!(f, "{e}"),
+            ClientError::Command(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<serde_wasm_bindgen::Error> for ClientError {
+    fn from(e: serde_wasm_bindgen::Error) -> Self {
+        ClientError::Serde(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+async fn invoke<A: Serialize, T: DeserializeOwned>(command: &str, args: &A) -> Result<T> {
+    // Plain objects rather than `Map`s, which is what Tauri expects on the other end
+    let args = args.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?;
+    let response = tauri_invoke(&format!("plugin:tcp-client|{command}"), args)
+        .await
+        .map_err(|e| ClientError::Command(e.as_string().unwrap_or_else(|| format!("{e:?}"))))?;
+    Ok(serde_wasm_bindgen::from_value(response)?)
+}
+
+pub async fn connect(request: &ConnectRequest) -> Result<ConnectResponse> {
+    invoke("connect", request).await
+}
+
+pub async fn disconnect(request: &DisconnectRequest) -> Result<()> {
+    invoke("disconnect", request).await
+}
+
+pub async fn transmit(request: &TransmitRequest) -> Result<TransmitResponse> {
+    invoke("transmit", request).await
+}
+
+pub async fn diagnose(request: &DiagnoseRequest) -> Result<DiagnoseResponse> {
+    invoke("diagnose", request).await
+}
+
+pub async fn discover(request: &DiscoverRequest) -> Result<DiscoverResponse> {
+    invoke("discover", request).await
+}
+
+pub async fn modbus(request: &ModbusRequest) -> Result<ModbusResponse> {
+    invoke("modbus", request).await
+}
+
+pub async fn scpi(request: &ScpiRequest) -> Result<ScpiResponse> {
+    invoke("scpi", request).await
+}
+
+pub async fn mqtt_publish(request: &MqttPublishRequest) -> Result<MqttPublishResponse> {
+    invoke("mqtt_publish", request).await
+}
+
+pub async fn mqtt_subscribe(request: &MqttSubscribeRequest) -> Result<MqttSubscribeResponse> {
+    invoke("mqtt_subscribe", request).await
+}
+
+pub async fn mqtt_unsubscribe(request: &MqttUnsubscribeRequest) -> Result<MqttSubscribeResponse> {
+    invoke("mqtt_unsubscribe", request).await
+}
+
+pub async fn http(request: &HttpRequest) -> Result<HttpResponse> {
+    invoke("http", request).await
+}
+
+pub async fn redis(request: &RedisRequest) -> Result<RedisResponse> {
+    invoke("redis", request).await
+}
+
+pub async fn gcode_stream(request: &GcodeStreamRequest) -> Result<GcodeStatus> {
+    invoke("gcode_stream", request).await
+}
+
+pub async fn gcode_control(request: &GcodeControlRequest) -> Result<GcodeStatus> {
+    invoke("gcode_control", request).await
+}
+
+pub async fn gcode_status() -> Result<GcodeStatus> {
+    invoke("gcode_status", &()).await
+}
+
+pub async fn osc(request: &OscRequest) -> Result<OscResponse> {
+    invoke("osc", request).await
+}
+
+pub async fn sequence_run(request: &SequenceRunRequest) -> Result<SequenceStatus> {
+    invoke("sequence_run", request).await
+}
+
+pub async fn sequence_control(request: &SequenceControlRequest) -> Result<SequenceStatus> {
+    invoke("sequence_control", request).await
+}
+
+pub async fn sequence_status() -> Result<SequenceStatus> {
+    invoke("sequence_status", &()).await
+}
+
+pub async fn schedule_start(request: &ScheduleRequest) -> Result<ScheduleStatus> {
+    invoke("schedule_start", request).await
+}
+
+pub async fn schedule_stop(request: &ScheduleStopRequest) -> Result<ScheduleListResponse> {
+    invoke("schedule_stop", request).await
+}
+
+pub async fn schedule_list() -> Result<ScheduleListResponse> {
+    invoke("schedule_list", &()).await
+}
+
+pub async fn test_run(request: &TestRunRequest) -> Result<TestReport> {
+    invoke("test_run", request).await
+}
+
+pub async fn transcript(request: &TranscriptRequest) -> Result<TranscriptResponse> {
+    invoke("transcript", request).await
+}
+
+pub async fn clear_transcript() -> Result<()> {
+    invoke("clear_transcript", &()).await
+}
+
+#[derive(Deserialize)]
+struct Event<T> {
+    payload: T,
+}
+
+/// Calls `callback` with every transcript entry recorded from now on, for the rest of the app's lifetime.
+pub async fn on_transcript(mut callback: impl FnMut(TranscriptEntry) + 'static) {
+    let handler = Closure::new(move |event: JsValue| {
+        match serde_wasm_bindgen::from_value::<Event<TranscriptEntry>>(event) {
+            Ok(event) => callback(event.payload),
+            Err(e) => web_log(&format!("Malformed transcript event: {e}")),
+        }
+    });
+    tauri_listen(TRANSCRIPT_EVENT, &handler).await;
+    handler.forget();
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = console, js_name = error)]
+    fn web_log(message: &str);
+}
@@ -0,0 +1,735 @@
+//! Request and response types for the `tcp-client` plugin.
+//!
+//! Both the plugin and the frontend depend on this crate, so changing a
+//! command's arguments or result breaks the build on both sides at once.
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PingRequest {
+  pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PingResponse {
+  pub value: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectRequest {
+  pub address: Option<String>,
+  pub port: Option<i32>,
+  /// Name of a registered transport; defaults to `tcp`.
+  pub transport: Option<String>,
+  /// How received data is split up; defaults to the plugin's configured framing.
+  pub framing: Option<Framing>,
+  /// Protocol spoken on top of the transport; defaults to raw bytes.
+  pub protocol: Option<Protocol>,
+  /// Session settings when `protocol` is MQTT.
+  pub mqtt: Option<MqttOptions>,
+  /// Session settings when `protocol` is Redis.
+  pub redis: Option<RedisOptions>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectResponse {
+  pub success: Option<bool>,
+  pub error: Option<String>
+}
+
+/// How an open connection should be torn down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DisconnectMode {
+  /// Half-close: stop sending and keep reading until the peer closes its side.
+  #[default]
+  Graceful,
+  /// Close both directions immediately.
+  Full,
+  /// Abortive close; the peer receives a RST instead of a FIN.
+  Abort,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisconnectRequest {
+  pub mode: Option<DisconnectMode>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransmitRequest {
+  pub message: Option<String>,
+  /// Bytes sent straight after the message that may not be valid text, such
+  /// as a binary checksum. Only for raw, Telnet and HTTP mode.
+  pub suffix: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransmitResponse {
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnoseRequest {
+  pub address: Option<String>,
+  pub port: Option<i32>,
+  /// What the plugin's scope check looked the address up as. When set, only
+  /// these are pinged and connected to, so a name that looks up differently
+  /// the second time can't slip past the check.
+  #[serde(skip)]
+  pub addresses: Option<Vec<IpAddr>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StepStatus {
+  Passed,
+  /// Not conclusive on its own, but worth the user's attention.
+  Warning,
+  Failed,
+  Skipped,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticStep {
+  pub name: String,
+  pub status: StepStatus,
+  pub detail: String,
+  /// Plain-language suggestion for what to try next.
+  pub hint: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnoseResponse {
+  pub steps: Vec<DiagnosticStep>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoverRequest {
+  /// Service types to browse for, e.g. `_telnet._tcp`; the `.local.` suffix is optional.
+  pub service_types: Option<Vec<String>>,
+  /// How long to browse; 3 seconds by default, and at most 30.
+  pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoveredService {
+  pub instance_name: String,
+  pub service_type: String,
+  pub hostname: String,
+  pub addresses: Vec<String>,
+  pub port: u16,
+  pub txt: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoverResponse {
+  pub services: Vec<DiscoveredService>,
+}
+
+/// Event emitted with a [`TranscriptEntry`] whenever one is recorded.
+pub const TRANSCRIPT_EVENT: &str = "tcp-client://transcript";
+
+/// How a stream of received bytes is split into transcript entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Framing {
+  /// Whatever arrived in a single read becomes one entry.
+  #[default]
+  Raw,
+  Lf,
+  Cr,
+  CrLf,
+  /// Null-terminated messages.
+  Nul,
+}
+
+impl Framing {
+  pub fn delimiter(self) -> Option<&'static [u8]> {
+    match self {
+      Framing::Raw => None,
+      Framing::Lf => Some(b"\n"),
+      Framing::Cr => Some(b"\r"),
+      Framing::CrLf => Some(b"\r\n"),
+      Framing::Nul => Some(b"\0"),
+    }
+  }
+}
+
+/// Protocol spoken over a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Protocol {
+  /// Bytes are sent and shown exactly as they are.
+  #[default]
+  Raw,
+  /// Telnet option negotiation is answered and stripped from received data.
+  Telnet,
+  /// Modbus TCP; use the `modbus` command instead of `transmit`.
+  Modbus,
+  /// SCPI instrument control: newline-terminated, with queries sent through the `scpi` command.
+  Scpi,
+  /// MQTT client; `connect` also connects to the broker.
+  Mqtt,
+  /// HTTP/1.1; send requests through the `http` command, reusing the connection for each one.
+  Http,
+  /// Redis RESP; commands typed as text are sent as RESP arrays and replies are decoded.
+  Redis,
+  /// G-code for printers and CNCs: newline-terminated, with programs streamed through `gcode_stream`.
+  Gcode,
+  /// Open Sound Control packets, sent through the `osc` command. SLIP-framed
+  /// over byte streams, one packet per datagram over UDP.
+  Osc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+  Sent,
+  Received,
+  /// Connection status changes and other notes from the plugin itself.
+  Info,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptEntry {
+  pub id: u64,
+  /// Milliseconds since the Unix epoch.
+  pub timestamp: u64,
+  pub direction: Direction,
+  pub data: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptRequest {
+  /// Only return entries with a greater id.
+  pub after: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptResponse {
+  pub entries: Vec<TranscriptEntry>,
+}
+
+/// A Modbus function and the data it needs. Addresses are zero-based.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "function", rename_all = "camelCase")]
+pub enum ModbusOperation {
+  ReadCoils { address: u16, count: u16 },
+  ReadDiscreteInputs { address: u16, count: u16 },
+  ReadHoldingRegisters { address: u16, count: u16 },
+  ReadInputRegisters { address: u16, count: u16 },
+  WriteSingleCoil { address: u16, value: bool },
+  WriteSingleRegister { address: u16, value: u16 },
+  WriteMultipleCoils { address: u16, values: Vec<bool> },
+  WriteMultipleRegisters { address: u16, values: Vec<u16> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModbusRequest {
+  /// Unit (slave) identifier; defaults to 1.
+  pub unit_id: Option<u8>,
+  pub operation: ModbusOperation,
+}
+
+/// Decoded data from a successful Modbus response.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ModbusResult {
+  /// Coils or discrete inputs, starting at the requested address.
+  Bits { values: Vec<bool> },
+  /// Holding or input registers, starting at the requested address.
+  Registers { values: Vec<u16> },
+  /// Echo of what the device wrote.
+  Written { address: u16, count: u16 },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModbusResponse {
+  pub result: Option<ModbusResult>,
+  /// Exception code, if the device rejected the request.
+  pub exception: Option<u8>,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScpiRequest {
+  /// A command such as `*IDN?` or `VOLT 5`; the terminating newline is added automatically.
+  pub command: String,
+  /// Drain `SYST:ERR?` afterwards and report what was queued.
+  pub check_errors: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScpiResponse {
+  /// Text reply to a query.
+  pub reply: Option<String>,
+  /// Data from a binary block reply, such as a waveform or screenshot.
+  pub block: Option<Vec<u8>>,
+  /// Errors the instrument reported through `SYST:ERR?`.
+  pub errors: Vec<String>,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MqttVersion {
+  /// MQTT 3.1.1
+  #[default]
+  V311,
+  /// MQTT 5.0
+  V5,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MqttOptions {
+  pub version: Option<MqttVersion>,
+  /// Generated if not given.
+  pub client_id: Option<String>,
+  pub username: Option<String>,
+  pub password: Option<String>,
+  /// How often the broker expects to hear from us; defaults to 60 seconds.
+  pub keep_alive_secs: Option<u16>,
+  /// Start without any state from an earlier session; defaults to true.
+  pub clean_start: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MqttPublishRequest {
+  pub topic: String,
+  pub payload: String,
+  /// 0, 1 or 2; defaults to 0.
+  pub qos: Option<u8>,
+  pub retain: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MqttPublishResponse {
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MqttSubscribeRequest {
+  /// Topic filter, which may contain `+` and `#` wildcards.
+  pub topic: String,
+  pub qos: Option<u8>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MqttSubscribeResponse {
+  /// QoS the broker granted, which may be lower than requested.
+  pub granted_qos: Option<u8>,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MqttUnsubscribeRequest {
+  pub topic: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpHeader {
+  pub name: String,
+  pub value: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpRequest {
+  /// `GET`, `POST` and so on.
+  pub method: String,
+  /// Request target, such as `/index.html`.
+  pub path: String,
+  /// Sent in order. `Host` and `Content-Length` are added if missing.
+  #[serde(default)]
+  pub headers: Vec<HttpHeader>,
+  pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpResponse {
+  /// Such as `HTTP/1.1`.
+  pub version: Option<String>,
+  pub status: Option<u16>,
+  pub reason: Option<String>,
+  pub headers: Vec<HttpHeader>,
+  /// The body with any chunked encoding undone.
+  pub body: Option<String>,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedisOptions {
+  /// RESP version, 2 or 3. Version 3 is negotiated with `HELLO 3` when connecting. Defaults to 2.
+  pub resp_version: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedisRequest {
+  /// Command lines such as `SET foo "bar baz"`. They are pipelined: all of
+  /// them are sent before waiting for the first reply.
+  pub commands: Vec<String>,
+}
+
+/// A decoded RESP2 or RESP3 reply.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum RedisValue {
+  Simple(String),
+  /// Simple and bulk errors alike.
+  Error(String),
+  Integer(i64),
+  /// Bulk and verbatim strings, lossily converted to UTF-8.
+  Bulk(String),
+  Null,
+  Boolean(bool),
+  Double(f64),
+  BigNumber(String),
+  Array(Vec<RedisValue>),
+  Set(Vec<RedisValue>),
+  Map(Vec<(RedisValue, RedisValue)>),
+  /// Out-of-band data such as pub/sub messages.
+  Push(Vec<RedisValue>),
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedisResponse {
+  /// One reply per command, in order. Error replies are included here
+  /// rather than in `error`.
+  pub replies: Vec<RedisValue>,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcodeStreamRequest {
+  /// G-code to send, one command per line. Comments and blank lines are skipped.
+  pub program: Option<String>,
+  /// Read the program from this file instead.
+  pub path: Option<String>,
+  /// Send line numbers and checksums, so the firmware can ask for lines to
+  /// be resent. Defaults to the plugin's configuration.
+  pub checksums: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GcodeAction {
+  /// Stop once the line in flight has been acknowledged.
+  Pause,
+  Resume,
+  /// Stop sending. Moves the firmware has already buffered still run.
+  Abort,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcodeControlRequest {
+  pub action: GcodeAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GcodeState {
+  /// Nothing has been streamed yet.
+  #[default]
+  Idle,
+  Running,
+  Paused,
+  Finished,
+  Aborted,
+  Failed,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcodeStatus {
+  pub state: GcodeState,
+  /// Lines in the program, not counting comments and blank lines.
+  pub total: usize,
+  /// Lines the firmware has acknowledged.
+  pub acknowledged: usize,
+  /// Lines the firmware asked to have resent.
+  pub resends: usize,
+  pub error: Option<String>,
+}
+
+/// A typed OSC argument.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum OscArgument {
+  Int(i32),
+  Float(f32),
+  String(String),
+  Blob(Vec<u8>),
+  True,
+  False,
+  /// Only decoded, never sent.
+  Int64(i64),
+  /// Only decoded, never sent.
+  Double(f64),
+  /// Only decoded, never sent.
+  Nil,
+  /// Only decoded, never sent.
+  Impulse,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OscMessage {
+  /// Address pattern such as `/mixer/channel/1/fader`.
+  pub address: String,
+  #[serde(default)]
+  pub arguments: Vec<OscArgument>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OscRequest {
+  pub messages: Vec<OscMessage>,
+  /// Wrap a single message in a bundle too. Several messages are always sent as a bundle.
+  pub bundle: Option<bool>,
+  /// Time the bundle's messages this far in the future instead of "immediately".
+  pub delay_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OscResponse {
+  pub error: Option<String>,
+}
+
+pub mod checksum;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod library;
+pub mod template;
+
+/// One step of a sequence.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "step", rename_all = "camelCase")]
+pub enum SequenceStep {
+  /// Sends a message as `transmit` does.
+  Transmit {
+    message: String,
+    /// As in [`TransmitRequest`], for a checksum that isn't text.
+    #[serde(default)]
+    suffix: Option<Vec<u8>>,
+  },
+  Scpi(ScpiRequest),
+  Modbus(ModbusRequest),
+  Publish(MqttPublishRequest),
+  Http(HttpRequest),
+  Osc(OscRequest),
+  Delay { ms: u64 },
+  /// Waits for something to be received whose transcript entry matches the
+  /// regex `pattern`. Everything received since the last step that sent
+  /// something counts, so a reply that beats the wait isn't missed.
+  WaitFor {
+    pattern: String,
+    /// Defaults to the reply timeout; `0` waits indefinitely.
+    #[serde(rename = "timeoutMs")]
+    timeout_ms: Option<u64>,
+  },
+  Repeat { times: u32, steps: Vec<SequenceStep> },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequenceRunRequest {
+  /// Shown in the transcript and the status.
+  pub name: Option<String>,
+  pub steps: Vec<SequenceStep>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SequenceAction {
+  /// Stop before the next step.
+  Pause,
+  Resume,
+  /// Give up on the rest of the sequence, including a delay or wait in progress.
+  Stop,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequenceControlRequest {
+  pub action: SequenceAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SequenceState {
+  /// Nothing has been run yet.
+  #[default]
+  Idle,
+  Running,
+  Paused,
+  Finished,
+  Stopped,
+  Failed,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequenceStatus {
+  pub state: SequenceState,
+  pub name: Option<String>,
+  /// Steps the whole sequence takes, counting each repetition.
+  pub total: usize,
+  pub completed: usize,
+  /// The step being run.
+  pub current: Option<String>,
+  pub error: Option<String>,
+}
+
+/// Sends the same step over and over in the background, for keepalives and polling.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleRequest {
+  /// Shown in the transcript and the list of schedules.
+  pub name: Option<String>,
+  /// What to send; delays, waits and repeats aren't allowed.
+  pub step: SequenceStep,
+  /// Time from the start of one send to the start of the next.
+  pub interval_ms: u64,
+  /// Stop after this many sends.
+  pub count: Option<u64>,
+  /// Stop once this long has passed since the first send.
+  pub duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleStatus {
+  pub id: u64,
+  pub name: Option<String>,
+  /// What's being sent.
+  pub description: String,
+  pub interval_ms: u64,
+  pub count: Option<u64>,
+  pub duration_ms: Option<u64>,
+  /// Sends so far.
+  pub sent: u64,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleStopRequest {
+  /// Which schedule to stop, or all of them if left out.
+  pub id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleListResponse {
+  /// The schedules still running, oldest first.
+  pub schedules: Vec<ScheduleStatus>,
+}
+
+/// What a reply has to look like for a test to pass.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ExpectedReply {
+  /// Anything, as long as it arrives in time.
+  Any,
+  /// The reply as shown in the transcript, exactly.
+  Exact { text: String },
+  /// The reply as shown in the transcript matches the regex `pattern`.
+  Regex { pattern: String },
+  /// The reply is this many bytes long, not counting the framing delimiter.
+  Length { bytes: usize },
+  /// Nothing comes back in time, for commands a device should ignore.
+  Silence,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Expectation {
+  #[serde(flatten)]
+  pub reply: ExpectedReply,
+  /// How long to wait for the reply; defaults to the reply timeout.
+  pub timeout_ms: Option<u64>,
+}
+
+/// One test: steps to run, and what the reply to the last one should be.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestCase {
+  pub name: String,
+  pub steps: Vec<SequenceStep>,
+  /// Without one, the test passes if the steps run without an error.
+  pub expect: Option<Expectation>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestRunRequest {
+  /// Name of the test suite in the report.
+  pub suite: Option<String>,
+  pub cases: Vec<TestCase>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestResult {
+  pub name: String,
+  pub passed: bool,
+  pub duration_ms: u64,
+  /// What was expected, as text.
+  pub expected: Option<String>,
+  /// What was received, if anything.
+  pub actual: Option<String>,
+  /// Where `actual` departs from `expected`, for exact matches.
+  pub diff: Option<String>,
+  /// Why the test failed.
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestReport {
+  pub suite: String,
+  pub results: Vec<TestResult>,
+  pub passed: usize,
+  pub failed: usize,
+  pub duration_ms: u64,
+  /// The same report as JUnit XML.
+  pub junit: String,
+  /// Set if the run couldn't start at all.
+  pub error: Option<String>,
+}
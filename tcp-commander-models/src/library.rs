@@ -0,0 +1,697 @@
+//! What the app saves to `commands.json` and `sequences.json`, shared so
+//! anything else reading those files understands them the same way.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::Checksum;
+use crate::{
+  HttpRequest, ModbusOperation, ModbusRequest, MqttPublishRequest, OscArgument, OscRequest, Protocol, ScpiRequest, SequenceStep,
+};
+
+/// What each of the app's "Append" choices adds to the end of a message.
+pub fn line_ending(option: &str) -> &'static str {
+  match option {
+    "LF" => "\n",
+    "CR" => "\r",
+    "CRLF" => "\r\n",
+    _ => "",
+  }
+}
+
+/// The step that sends `message` followed by `suffix`, keeping the suffix
+/// part of the text unless it isn't text, as a raw checksum can be.
+pub fn transmit_step(message: String, suffix: Vec<u8>) -> SequenceStep {
+  match String::from_utf8(suffix) {
+    Ok(suffix) => SequenceStep::Transmit { message: message + &suffix, suffix: None },
+    Err(suffix) => SequenceStep::Transmit { message, suffix: Some(suffix.into_bytes()) },
+  }
+}
+
+/// How messages are sent when the command doesn't say otherwise, as set in
+/// the app's connection panel.
+#[derive(Debug, Clone, Default)]
+pub struct MessageDefaults {
+  pub protocol: Protocol,
+  /// Only set while the prepend toggle is on.
+  pub prepend: String,
+  /// One of the "Append" choices, such as `CRLF`.
+  pub append: String,
+  pub checksum: Checksum,
+  pub scpi_check_errors: bool,
+}
+
+impl MessageDefaults {
+  /// What goes before and after a message. A preset takes the place of the
+  /// prepend and append, and a terminator that of whichever ending would be used.
+  pub fn affixes(&self, preset: Option<&Preset>, terminator: Option<&str>) -> (String, String) {
+    let (front, ending) = match preset {
+      Some(preset) => preset.affixes(),
+      None => (self.prepend.clone(), line_ending(&self.append).to_string()),
+    };
+    (front, terminator.map_or(ending, |terminator| line_ending(terminator).to_string()))
+  }
+
+  /// The step that sends a message with its affixes. Where any bytes can be
+  /// sent, the checksum goes between the message and its ending, the
+  /// command's own one in place of the default.
+  pub fn step(&self, message: &str, preset: Option<&Preset>, terminator: Option<&str>, checksum: Option<&Checksum>) -> SequenceStep {
+    let (front, ending) = self.affixes(preset, terminator);
+    let message = front + message;
+    match self.protocol {
+      // The plugin adds the newline SCPI needs itself
+      Protocol::Scpi => SequenceStep::Scpi(ScpiRequest { command: message, check_errors: Some(self.scpi_check_errors) }),
+      // RESP has its own framing, so there's nothing to append
+      Protocol::Redis => SequenceStep::Transmit { message, suffix: None },
+      Protocol::Raw | Protocol::Telnet | Protocol::Http => {
+        let mut suffix = checksum.unwrap_or(&self.checksum).suffix(message.as_bytes());
+        suffix.extend_from_slice(ending.as_bytes());
+        transmit_step(message, suffix)
+      }
+      _ => SequenceStep::Transmit { message: message + &ending, suffix: None },
+    }
+  }
+}
+
+/// Turns `\x02`, `\e`, `\r`, `\n`, `\t`, `\0` and `\\` into the characters
+/// they stand for. Messages are sent as text, so `\x` only goes up to `\x7f`.
+pub fn unescape(text: &str) -> Result<String, String> {
+  let mut unescaped = String::with_capacity(text.len());
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      unescaped.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') => unescaped.push('\n'),
+      Some('r') => unescaped.push('\r'),
+      Some('t') => unescaped.push('\t'),
+      Some('0') => unescaped.push('\0'),
+      Some('e') => unescaped.push('\x1b'),
+      Some('\\') => unescaped.push('\\'),
+      Some('x') => {
+        let digits: String = chars.by_ref().take(2).collect();
+        match u8::from_str_radix(&digits, 16) {
+          Ok(byte) if digits.len() == 2 && byte.is_ascii() => unescaped.push(char::from(byte)),
+          Ok(_) if digits.len() == 2 => return Err(format!("\\x{digits} is past \\x7f, which is as far as text goes")),
+          _ => return Err(format!("\\x{digits} needs two hex digits")),
+        }
+      }
+      Some(other) => return Err(format!("\\{other} isn't an escape; write \\\\ for a backslash")),
+      None => return Err("a \\ at the end needs something after it; write \\\\ for a backslash".into()),
+    }
+  }
+  Ok(unescaped)
+}
+
+/// A named prefix and suffix for messages, saved to `presets.json`, for
+/// framing like STX/ETX that the global prepend and append can't express.
+/// Both are written with the escapes [`unescape`] understands.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Preset {
+  pub prefix: String,
+  pub suffix: String,
+}
+
+impl Preset {
+  /// Checks both escapes, so a broken preset can't be saved.
+  pub fn validate(&self) -> Result<(), String> {
+    unescape(&self.prefix).map_err(|e| format!("Prefix: {e}"))?;
+    unescape(&self.suffix).map_err(|e| format!("Suffix: {e}"))?;
+    Ok(())
+  }
+
+  /// The prefix and suffix with their escapes turned into characters.
+  pub fn affixes(&self) -> (String, String) {
+    // Presets are checked when saved, so this only keeps hand-edited files working
+    let unescaped = |text: &str| unescape(text).unwrap_or_else(|_| text.to_string());
+    (unescaped(&self.prefix), unescaped(&self.suffix))
+  }
+}
+
+/// A command saved to `commands.json`. Plain strings, which is all older
+/// versions saved, load as messages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SavedCommand {
+  Message(String),
+  Modbus(ModbusRequest),
+  Publish(MqttPublishRequest),
+  Http(HttpRequest),
+  Osc(OscRequest),
+  /// A message sent with a [`Preset`] instead of the global prepend and append.
+  Wrapped { message: String, preset: String },
+}
+
+impl SavedCommand {
+  /// What to show on the command's card.
+  pub fn describe(&self) -> String {
+    match self {
+      SavedCommand::Message(message) => message.clone(),
+      SavedCommand::Wrapped { message, preset } => format!("{message} ({preset})"),
+      SavedCommand::Modbus(request) => {
+        let unit = request.unit_id.map(|unit| format!(" @ unit {unit}")).unwrap_or_default();
+        let operation = match &request.operation {
+          ModbusOperation::ReadCoils { address, count } => format!("Read coils {address} ×{count}"),
+          ModbusOperation::ReadDiscreteInputs { address, count } => format!("Read discrete inputs {address} ×{count}"),
+          ModbusOperation::ReadHoldingRegisters { address, count } => format!("Read holding registers {address} ×{count}"),
+          ModbusOperation::ReadInputRegisters { address, count } => format!("Read input registers {address} ×{count}"),
+          ModbusOperation::WriteSingleCoil { address, value } => format!("Write coil {address} = {}", u8::from(*value)),
+          ModbusOperation::WriteSingleRegister { address, value } => format!("Write register {address} = {value}"),
+          ModbusOperation::WriteMultipleCoils { address, values } => format!("Write coils {address} = {values:?}"),
+          ModbusOperation::WriteMultipleRegisters { address, values } => format!("Write registers {address} = {values:?}"),
+        };
+        format!("Modbus: {operation}{unit}")
+      }
+      SavedCommand::Publish(publish) => {
+        let retained = if publish.retain.unwrap_or(false) { ", retained" } else { "" };
+        format!("MQTT: {} ← {} (QoS {}{retained})", publish.topic, publish.payload, publish.qos.unwrap_or(0))
+      }
+      SavedCommand::Http(request) => format!("HTTP: {} {}", request.method, request.path),
+      SavedCommand::Osc(request) => {
+        let messages = request.messages.iter()
+          .map(|message| {
+            let arguments = message.arguments.iter().map(describe_osc_argument);
+            std::iter::once(message.address.clone()).chain(arguments).collect::<Vec<_>>().join(" ")
+          })
+          .collect::<Vec<_>>()
+          .join("; ");
+        let delay = request.delay_ms.map(|delay| format!(" (in {delay} ms)")).unwrap_or_default();
+        format!("OSC: {messages}{delay}")
+      }
+    }
+  }
+
+  /// Text for copying and editing: messages as they are, anything structured as its JSON.
+  pub fn to_text(&self) -> String {
+    match self {
+      SavedCommand::Message(message) | SavedCommand::Wrapped { message, .. } => message.clone(),
+      SavedCommand::Modbus(request) => serde_json::to_string(request).unwrap(),
+      SavedCommand::Publish(publish) => serde_json::to_string(publish).unwrap(),
+      SavedCommand::Http(request) => serde_json::to_string(request).unwrap(),
+      SavedCommand::Osc(request) => serde_json::to_string(request).unwrap(),
+    }
+  }
+
+  /// Parses edited text back into the same kind of command.
+  pub fn with_text(&self, text: String) -> Result<Self, String> {
+    match self {
+      SavedCommand::Message(_) => Ok(SavedCommand::Message(text)),
+      SavedCommand::Wrapped { preset, .. } => Ok(SavedCommand::Wrapped { message: text, preset: preset.clone() }),
+      SavedCommand::Modbus(_) => serde_json::from_str(&text)
+        .map(SavedCommand::Modbus)
+        .map_err(|e| format!("Invalid Modbus request: {e}")),
+      SavedCommand::Publish(_) => serde_json::from_str(&text)
+        .map(SavedCommand::Publish)
+        .map_err(|e| format!("Invalid MQTT message: {e}")),
+      SavedCommand::Http(_) => serde_json::from_str(&text)
+        .map(SavedCommand::Http)
+        .map_err(|e| format!("Invalid HTTP request: {e}")),
+      SavedCommand::Osc(_) => serde_json::from_str(&text)
+        .map(SavedCommand::Osc)
+        .map_err(|e| format!("Invalid OSC message: {e}")),
+    }
+  }
+
+  /// Whether this is a plain message, which is all a preset can wrap.
+  pub fn is_message(&self) -> bool {
+    matches!(self, SavedCommand::Message(_) | SavedCommand::Wrapped { .. })
+  }
+
+  /// The name of the preset a message is sent with, if it has one.
+  pub fn preset(&self) -> Option<&str> {
+    match self {
+      SavedCommand::Wrapped { preset, .. } => Some(preset),
+      _ => None,
+    }
+  }
+
+  /// The same message sent with `preset`, or with the global prepend and
+  /// append for `None`. Other commands are left as they are.
+  pub fn with_preset(self, preset: Option<String>) -> Self {
+    match (self, preset) {
+      (SavedCommand::Message(message) | SavedCommand::Wrapped { message, .. }, Some(preset)) => {
+        SavedCommand::Wrapped { message, preset }
+      }
+      (SavedCommand::Message(message) | SavedCommand::Wrapped { message, .. }, None) => SavedCommand::Message(message),
+      (command, _) => command,
+    }
+  }
+}
+
+/// How the text of a saved message turns into the bytes sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Encoding {
+  /// Sent as typed.
+  #[default]
+  Text,
+  /// With the escapes [`unescape`] understands, such as `\x02` or `\r`.
+  Escaped,
+  /// Two hex digits per byte, with spaces allowed between bytes, for
+  /// anything that isn't text.
+  Hex,
+}
+
+impl Encoding {
+  pub const ALL: [Encoding; 3] = [Encoding::Text, Encoding::Escaped, Encoding::Hex];
+
+  pub fn label(self) -> &'static str {
+    match self {
+      Encoding::Text => "Text",
+      Encoding::Escaped => "Text with escapes",
+      Encoding::Hex => "Hex bytes",
+    }
+  }
+
+  pub fn decode(self, text: &str) -> Result<Vec<u8>, String> {
+    match self {
+      Encoding::Text => Ok(text.as_bytes().to_vec()),
+      Encoding::Escaped => unescape(text).map(String::into_bytes),
+      Encoding::Hex => {
+        let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.len() % 2 != 0 {
+          return Err("Hex bytes need two digits each".into());
+        }
+        digits.chunks(2)
+          .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| format!("'{pair}' isn't a hex byte"))
+          })
+          .collect()
+      }
+    }
+  }
+}
+
+/// The server a saved command is always sent to, whatever the connection
+/// fields say.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandTarget {
+  pub address: String,
+  pub port: u16,
+  /// `None` keeps whichever transport is selected.
+  pub transport: Option<String>,
+}
+
+impl CommandTarget {
+  pub fn describe(&self) -> String {
+    match &self.transport {
+      Some(transport) => format!("{}:{} ({transport})", self.address, self.port),
+      None => format!("{}:{}", self.address, self.port),
+    }
+  }
+}
+
+/// A saved command and what's known about it, as kept in `commands.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandRecord {
+  pub command: SavedCommand,
+  #[serde(default)]
+  pub description: String,
+  /// Only for messages.
+  #[serde(default)]
+  pub encoding: Encoding,
+  #[serde(default)]
+  pub target: Option<CommandTarget>,
+  /// One of the "Append" choices, such as `CRLF`, used instead of the global
+  /// append or a preset's suffix. Only for messages.
+  #[serde(default)]
+  pub terminator: Option<String>,
+  /// Used instead of the global checksum.
+  #[serde(default)]
+  pub checksum: Option<Checksum>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  /// Ask before sending it straight from its card.
+  #[serde(default)]
+  pub confirm: bool,
+  /// Milliseconds since the Unix epoch, unknown for commands saved before
+  /// records were.
+  #[serde(default)]
+  pub created_ms: Option<u64>,
+  #[serde(default)]
+  pub modified_ms: Option<u64>,
+}
+
+impl CommandRecord {
+  pub fn new(command: SavedCommand, now_ms: u64) -> Self {
+    CommandRecord {
+      command,
+      description: String::new(),
+      encoding: Encoding::default(),
+      target: None,
+      terminator: None,
+      checksum: None,
+      tags: Vec::new(),
+      confirm: false,
+      created_ms: Some(now_ms),
+      modified_ms: Some(now_ms),
+    }
+  }
+
+  /// The bytes of a message, as its encoding reads them.
+  pub fn message_bytes(&self) -> Option<Result<Vec<u8>, String>> {
+    match &self.command {
+      SavedCommand::Message(message) | SavedCommand::Wrapped { message, .. } => Some(self.encoding.decode(message)),
+      _ => None,
+    }
+  }
+
+  /// Whether `filter` is part of the name, description or a tag, ignoring case.
+  pub fn matches(&self, name: &str, filter: &str) -> bool {
+    let filter = filter.trim().to_lowercase();
+    [name, &self.description].into_iter().chain(self.tags.iter().map(String::as_str))
+      .any(|text| text.to_lowercase().contains(&filter))
+  }
+}
+
+/// An entry of `commands.json`: a record, or the bare command that versions
+/// before records saved. Read entries through this and turn them into
+/// records, so old files keep working.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StoredCommand {
+  Record(CommandRecord),
+  Plain(SavedCommand),
+}
+
+impl From<StoredCommand> for CommandRecord {
+  fn from(stored: StoredCommand) -> Self {
+    match stored {
+      StoredCommand::Record(record) => record,
+      StoredCommand::Plain(command) => CommandRecord { created_ms: None, modified_ms: None, ..CommandRecord::new(command, 0) },
+    }
+  }
+}
+
+/// A step of a sequence saved to `sequences.json`. Saved commands are
+/// referenced by name and looked up when the sequence runs, so editing a
+/// command changes every sequence that uses it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "camelCase")]
+pub enum SequenceItem {
+  Command { name: String },
+  Message { message: String },
+  Delay { ms: u64 },
+  WaitFor {
+    pattern: String,
+    #[serde(rename = "timeoutMs")]
+    timeout_ms: Option<u64>,
+  },
+  Repeat { times: u32, items: Vec<SequenceItem> },
+}
+
+impl SequenceItem {
+  /// Parses the sequence editor's text, one step per line:
+  ///
+  /// ```text
+  /// send Home position
+  /// message G28
+  /// delay 500
+  /// wait 2000 ms ^ok
+  /// repeat 3
+  ///   send Wave
+  /// end
+  /// ```
+  ///
+  /// `wait` uses the reply timeout unless it's given one. Blank lines and
+  /// lines starting with `#` are skipped.
+  pub fn parse(text: &str) -> Result<Vec<SequenceItem>, String> {
+    // Innermost repeat last, with the items collected for it so far
+    let mut open: Vec<(u32, Vec<SequenceItem>)> = vec![(1, Vec::new())];
+    for (number, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let error = |message: &str| format!("Line {}: {message}", number + 1);
+      let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+      let rest = rest.trim();
+      let item = match keyword {
+        "send" if !rest.is_empty() => SequenceItem::Command { name: rest.to_string() },
+        "message" => SequenceItem::Message { message: rest.to_string() },
+        "delay" => SequenceItem::Delay { ms: rest.parse().map_err(|_| error("delay needs a number of ms"))? },
+        "wait" => {
+          let mut words = rest.splitn(3, ' ');
+          let timeout = words.next().and_then(|ms| ms.parse().ok());
+          let (timeout_ms, pattern) = match (timeout, words.next(), words.next()) {
+            (Some(ms), Some("ms"), Some(pattern)) => (Some(ms), pattern.to_string()),
+            _ => (None, rest.to_string()),
+          };
+          if pattern.is_empty() {
+            return Err(error("wait needs a pattern"));
+          }
+          SequenceItem::WaitFor { pattern, timeout_ms }
+        }
+        "repeat" => {
+          let times = rest.parse().map_err(|_| error("repeat needs a number of times"))?;
+          open.push((times, Vec::new()));
+          continue;
+        }
+        "end" if open.len() > 1 => {
+          let (times, items) = open.pop().unwrap();
+          SequenceItem::Repeat { times, items }
+        }
+        "end" => return Err(error("end without a repeat")),
+        _ => return Err(error(&format!("unknown step '{line}'"))),
+      };
+      open.last_mut().unwrap().1.push(item);
+    }
+    if open.len() > 1 {
+      return Err("repeat without an end".into());
+    }
+    Ok(open.pop().unwrap().1)
+  }
+
+  /// The inverse of [`SequenceItem::parse`].
+  pub fn to_text(items: &[SequenceItem]) -> String {
+    fn write(items: &[SequenceItem], indent: usize, lines: &mut Vec<String>) {
+      let pad = "  ".repeat(indent);
+      for item in items {
+        match item {
+          SequenceItem::Command { name } => lines.push(format!("{pad}send {name}")),
+          SequenceItem::Message { message } => lines.push(format!("{pad}message {message}")),
+          SequenceItem::Delay { ms } => lines.push(format!("{pad}delay {ms}")),
+          SequenceItem::WaitFor { pattern, timeout_ms: Some(ms) } => lines.push(format!("{pad}wait {ms} ms {pattern}")),
+          SequenceItem::WaitFor { pattern, timeout_ms: None } => lines.push(format!("{pad}wait {pattern}")),
+          SequenceItem::Repeat { times, items } => {
+            lines.push(format!("{pad}repeat {times}"));
+            write(items, indent + 1, lines);
+            lines.push(format!("{pad}end"));
+          }
+        }
+      }
+    }
+    let mut lines = Vec::new();
+    write(items, 0, &mut lines);
+    lines.join("\n")
+  }
+
+  /// Turns items into steps the plugin can run, with `message` deciding how
+  /// plain messages are sent, or that they can't be, given the preset,
+  /// terminator and checksum to send them with if they have their own.
+  /// Targets and confirmation are left to whoever sends a command on its own.
+  pub fn resolve(
+    items: &[SequenceItem],
+    saved: &BTreeMap<String, CommandRecord>,
+    presets: &BTreeMap<String, Preset>,
+    message: &impl Fn(String, Option<&Preset>, Option<&str>, Option<&Checksum>) -> Result<SequenceStep, String>,
+  ) -> Result<Vec<SequenceStep>, String> {
+    items.iter()
+      .map(|item| Ok(match item {
+        SequenceItem::Command { name } => {
+          let record = saved.get(name).ok_or_else(|| format!("No command with name '{name}' found"))?;
+          match &record.command {
+            SavedCommand::Message(_) | SavedCommand::Wrapped { .. } => {
+              let preset = match record.command.preset() {
+                Some(preset) => Some(presets.get(preset)
+                  .ok_or_else(|| format!("'{name}' uses the preset '{preset}', which doesn't exist"))?),
+                None => None,
+              };
+              let bytes = record.message_bytes().unwrap().map_err(|e| format!("'{name}': {e}"))?;
+              let text = String::from_utf8(bytes)
+                .map_err(|_| format!("'{name}' isn't text, so it can only be sent from its card in the app"))?;
+              message(text, preset, record.terminator.as_deref(), record.checksum.as_ref())
+                .map_err(|e| format!("'{name}': {e}"))?
+            }
+            SavedCommand::Modbus(request) => SequenceStep::Modbus(request.clone()),
+            SavedCommand::Publish(publish) => SequenceStep::Publish(publish.clone()),
+            SavedCommand::Http(request) => SequenceStep::Http(request.clone()),
+            SavedCommand::Osc(request) => SequenceStep::Osc(request.clone()),
+          }
+        }
+        SequenceItem::Message { message: text } => message(text.clone(), None, None, None)?,
+        SequenceItem::Delay { ms } => SequenceStep::Delay { ms: *ms },
+        SequenceItem::WaitFor { pattern, timeout_ms } => {
+          SequenceStep::WaitFor { pattern: pattern.clone(), timeout_ms: *timeout_ms }
+        }
+        SequenceItem::Repeat { times, items } => {
+          SequenceStep::Repeat { times: *times, steps: SequenceItem::resolve(items, saved, presets, message)? }
+        }
+      }))
+      .collect()
+  }
+}
+
+fn describe_osc_argument(argument: &OscArgument) -> String {
+  match argument {
+    OscArgument::Int(value) => value.to_string(),
+    OscArgument::Float(value) => format!("{value:?}"),
+    OscArgument::String(value) => format!("{value:?}"),
+    OscArgument::Blob(value) => format!("<{} bytes>", value.len()),
+    OscArgument::True => "true".into(),
+    OscArgument::False => "false".into(),
+    OscArgument::Int64(value) => format!("{value}L"),
+    OscArgument::Double(value) => format!("{value:?}d"),
+    OscArgument::Nil => "nil".into(),
+    OscArgument::Impulse => "impulse".into(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checksum::{ChecksumAlgorithm, ChecksumFormat};
+
+  #[test]
+  fn escapes_are_unescaped() {
+    assert_eq!(unescape(r"\x02STX\x03").unwrap(), "\x02STX\x03");
+    assert_eq!(unescape(r"\x1b[2J").unwrap(), "\x1b[2J");
+    assert_eq!(unescape(r"\e[0m\r\n\t\0\\").unwrap(), "\x1b[0m\r\n\t\0\\");
+    assert_eq!(unescape("plain").unwrap(), "plain");
+  }
+
+  #[test]
+  fn bad_escapes_are_refused() {
+    assert_eq!(unescape(r"\x80").unwrap_err(), r"\x80 is past \x7f, which is as far as text goes");
+    assert_eq!(unescape(r"\x2").unwrap_err(), r"\x2 needs two hex digits");
+    assert_eq!(unescape(r"\xg1").unwrap_err(), r"\xg1 needs two hex digits");
+    assert_eq!(unescape(r"\q").unwrap_err(), r"\q isn't an escape; write \\ for a backslash");
+    assert!(unescape("end\\").is_err());
+  }
+
+  #[test]
+  fn presets_are_checked_and_unescaped() {
+    let preset = Preset { prefix: r"\x02".into(), suffix: r"\x03\n".into() };
+    assert!(preset.validate().is_ok());
+    assert_eq!(preset.affixes(), ("\x02".to_string(), "\x03\n".to_string()));
+    let broken = Preset { prefix: String::new(), suffix: r"\z".into() };
+    assert!(broken.validate().unwrap_err().starts_with("Suffix: "));
+    // Hand-edited files keep working, just without the escapes
+    assert_eq!(broken.affixes().1, r"\z");
+  }
+
+  #[test]
+  fn messages_are_decoded() {
+    assert_eq!(Encoding::Hex.decode("02 52 03").unwrap(), [0x02, 0x52, 0x03]);
+    assert!(Encoding::Hex.decode("025").is_err());
+    assert!(Encoding::Hex.decode("zz").is_err());
+    assert_eq!(Encoding::Escaped.decode(r"\x02R").unwrap(), [0x02, b'R']);
+    assert_eq!(Encoding::Text.decode(r"\x02").unwrap(), br"\x02");
+  }
+
+  fn migrate(json: &str) -> CommandRecord {
+    serde_json::from_str::<StoredCommand>(json).unwrap().into()
+  }
+
+  #[test]
+  fn plain_commands_become_records() {
+    let record = migrate(r#""G28""#);
+    assert_eq!(record.command, SavedCommand::Message("G28".into()));
+    assert_eq!((record.created_ms, record.modified_ms), (None, None));
+    assert_eq!(record.encoding, Encoding::Text);
+    assert!(record.checksum.is_none() && record.target.is_none() && !record.confirm);
+
+    let record = migrate(r#"{"message": "PING", "preset": "stx"}"#);
+    assert_eq!(record.command, SavedCommand::Wrapped { message: "PING".into(), preset: "stx".into() });
+
+    let record = migrate(r#"{"unitId": 2, "operation": {"function": "readCoils", "address": 0, "count": 8}}"#);
+    assert!(matches!(record.command, SavedCommand::Modbus(ModbusRequest { unit_id: Some(2), .. })));
+  }
+
+  #[test]
+  fn records_load_as_saved() {
+    let mut record = CommandRecord::new(SavedCommand::Message("02 52 03".into()), 1_700_000_000_000);
+    record.encoding = Encoding::Hex;
+    record.checksum = Some(Checksum { skip_start: 1, ..Checksum::default() });
+    record.tags = vec!["rig".into()];
+    assert_eq!(migrate(&serde_json::to_string(&record).unwrap()), record);
+    assert_eq!(record.message_bytes(), Some(Ok(vec![0x02, 0x52, 0x03])));
+    assert!(record.matches("Status", "RIG"));
+    assert!(!record.matches("Status", "other"));
+
+    // Fields added later are optional
+    let record = migrate(r#"{"command": "G28", "description": "Home"}"#);
+    assert_eq!(record.description, "Home");
+    assert_eq!(record.created_ms, None);
+  }
+
+  #[test]
+  fn own_checksums_reach_the_message_step() {
+    let mut record = CommandRecord::new(SavedCommand::Message("a".into()), 0);
+    record.checksum = Some(Checksum::default());
+    let saved = BTreeMap::from([("Own".to_string(), record)]);
+    let items = [SequenceItem::Command { name: "Own".into() }, SequenceItem::Message { message: "b".into() }];
+    let steps = SequenceItem::resolve(&items, &saved, &BTreeMap::new(), &|message, _, _, checksum| {
+      Ok(transmit_step(message, if checksum.is_some() { vec![0xFF] } else { vec![b'\n'] }))
+    });
+    assert_eq!(
+      steps.unwrap(),
+      [
+        SequenceStep::Transmit { message: "a".into(), suffix: Some(vec![0xFF]) },
+        SequenceStep::Transmit { message: "b\n".into(), suffix: None },
+      ]
+    );
+    let refused = SequenceItem::resolve(&items[..1], &saved, &BTreeMap::new(), &|_, _, _, _| Err("no".into()));
+    assert_eq!(refused, Err("'Own': no".into()));
+  }
+
+  fn defaults(protocol: Protocol, checksum: Checksum) -> MessageDefaults {
+    MessageDefaults { protocol, prepend: ">".into(), append: "CRLF".into(), checksum, scpi_check_errors: true }
+  }
+
+  #[test]
+  fn presets_and_terminators_replace_the_defaults() {
+    let defaults = defaults(Protocol::Raw, Checksum::default());
+    let preset = Preset { prefix: r"\x02".into(), suffix: r"\x03".into() };
+    assert_eq!(defaults.affixes(None, None), (">".into(), "\r\n".into()));
+    assert_eq!(defaults.affixes(Some(&preset), None), ("\x02".into(), "\x03".into()));
+    assert_eq!(defaults.affixes(None, Some("LF")), (">".into(), "\n".into()));
+    assert_eq!(defaults.affixes(Some(&preset), Some("None")), ("\x02".into(), "".into()));
+  }
+
+  #[test]
+  fn message_steps_follow_the_protocol() {
+    let sum = Checksum { algorithm: ChecksumAlgorithm::Sum8, format: ChecksumFormat::Hex, ..Checksum::default() };
+    let transmit = |message: &str| SequenceStep::Transmit { message: message.into(), suffix: None };
+    let step = |protocol| defaults(protocol, sum.clone()).step("AB", None, None, None);
+    // "A" + "B" after the prepend, 0x3E + 0x41 + 0x42
+    assert_eq!(step(Protocol::Raw), transmit(">ABC1\r\n"));
+    assert_eq!(step(Protocol::Telnet), transmit(">ABC1\r\n"));
+    assert_eq!(step(Protocol::Http), transmit(">ABC1\r\n"));
+    assert_eq!(step(Protocol::Scpi), SequenceStep::Scpi(ScpiRequest { command: ">AB".into(), check_errors: Some(true) }));
+    assert_eq!(step(Protocol::Redis), transmit(">AB"));
+    assert_eq!(step(Protocol::Gcode), transmit(">AB\r\n"));
+  }
+
+  #[test]
+  fn a_commands_own_checksum_wins() {
+    let raw = Checksum { algorithm: ChecksumAlgorithm::Sum8, ..Checksum::default() };
+    let defaults = defaults(Protocol::Raw, raw);
+    assert_eq!(
+      defaults.step("\u{7f}", None, Some("None"), None),
+      SequenceStep::Transmit { message: ">\u{7f}".into(), suffix: Some(vec![0xBD]) }
+    );
+    let own = Checksum::default();
+    assert_eq!(
+      defaults.step("AB", None, Some("CR"), Some(&own)),
+      SequenceStep::Transmit { message: ">AB\r".into(), suffix: None }
+    );
+  }
+}
@@ -0,0 +1,213 @@
+//! Placeholders in saved messages, such as `MOVE {{distance}}`, filled in
+//! when the message is sent. Where the values come from is up to the caller,
+//! since the frontend can't read the clock or random numbers the way native
+//! code does.
+
+use std::fmt::Write;
+
+/// Something between `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placeholder {
+  /// `{{counter}}`: one more than the last time it was sent.
+  Counter,
+  /// `{{timestamp}}`: milliseconds since the Unix epoch.
+  Timestamp,
+  /// `{{uuid}}`: a random version 4 UUID.
+  Uuid,
+  /// `{{random:0-100}}`: a whole number in the range, both ends included.
+  Random { min: i64, max: i64 },
+  /// Any other name: a saved variable, or asked for at send time.
+  Variable(String),
+}
+
+impl Placeholder {
+  fn parse(inner: &str) -> Result<Self, String> {
+    let inner = inner.trim();
+    let placeholder = match inner {
+      "counter" => Placeholder::Counter,
+      "timestamp" => Placeholder::Timestamp,
+      "uuid" => Placeholder::Uuid,
+      _ => match inner.strip_prefix("random:") {
+        Some(range) => {
+          // Skip the first character so a negative minimum keeps its sign
+          let split = range.char_indices().skip(1).find(|&(_, c)| c == '-').map(|(i, _)| i);
+          let bounds = split.and_then(|i| Some((range[..i].trim().parse().ok()?, range[i + 1..].trim().parse().ok()?)));
+          match bounds {
+            Some((min, max)) if min <= max => Placeholder::Random { min, max },
+            _ => return Err(format!("'{{{{{inner}}}}}' needs a range like random:0-100")),
+          }
+        }
+        None if !inner.is_empty() && inner.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+          Placeholder::Variable(inner.to_string())
+        }
+        None => return Err(format!("'{{{{{inner}}}}}' isn't a valid placeholder")),
+      },
+    };
+    Ok(placeholder)
+  }
+}
+
+/// Splits `text` into literal pieces and placeholders. A `{{` without a
+/// closing `}}` is left as it is.
+fn parse(text: &str) -> Result<Vec<Result<&str, Placeholder>>, String> {
+  let mut parts = Vec::new();
+  let mut rest = text;
+  while let Some(start) = rest.find("{{") {
+    let Some(length) = rest[start + 2..].find("}}") else {
+      break;
+    };
+    parts.push(Ok(&rest[..start]));
+    parts.push(Err(Placeholder::parse(&rest[start + 2..start + 2 + length])?));
+    rest = &rest[start + 2 + length + 2..];
+  }
+  parts.push(Ok(rest));
+  Ok(parts)
+}
+
+/// The placeholders in `text`, in order, each only once.
+pub fn placeholders(text: &str) -> Result<Vec<Placeholder>, String> {
+  let mut found = Vec::new();
+  for placeholder in parse(text)?.into_iter().filter_map(Result::err) {
+    if !found.contains(&placeholder) {
+      found.push(placeholder);
+    }
+  }
+  Ok(found)
+}
+
+/// Fails if `text` has placeholders. Sequences, schedules and tests work out
+/// their messages once but may send them many times, so they'd repeat the
+/// same `{{counter}}` or `{{uuid}}` instead of filling it in each time.
+pub fn no_placeholders(text: &str) -> Result<(), String> {
+  if placeholders(text)?.is_empty() {
+    return Ok(());
+  }
+  Err("placeholders are only filled in when a message is sent on its own, not in sequences, schedules or tests".into())
+}
+
+/// Replaces every placeholder in `text` with what `value` returns for it.
+/// A placeholder used twice is only looked up once, so `{{uuid}}` is the
+/// same everywhere in a message.
+pub fn expand(text: &str, mut value: impl FnMut(&Placeholder) -> Result<String, String>) -> Result<String, String> {
+  let mut values: Vec<(Placeholder, String)> = Vec::new();
+  let mut expanded = String::with_capacity(text.len());
+  for part in parse(text)? {
+    match part {
+      Ok(literal) => expanded.push_str(literal),
+      Err(placeholder) => match values.iter().find(|(known, _)| *known == placeholder) {
+        Some((_, known)) => expanded.push_str(known),
+        None => {
+          let filled = value(&placeholder)?;
+          expanded.push_str(&filled);
+          values.push((placeholder, filled));
+        }
+      },
+    }
+  }
+  Ok(expanded)
+}
+
+/// Picks a number for `{{random:min-max}}` from a uniformly distributed `unit` in `[0, 1)`.
+pub fn random_in(min: i64, max: i64, unit: f64) -> i64 {
+  // The offset can be past `i64::MAX` when the range spans most of `i64`
+  let offset = ((max as f64 - min as f64 + 1.0) * unit) as u64;
+  (i128::from(min) + i128::from(offset)).min(i128::from(max)) as i64
+}
+
+/// Formats 16 random bytes as a version 4 UUID.
+pub fn uuid_v4(mut bytes: [u8; 16]) -> String {
+  bytes[6] = (bytes[6] & 0x0f) | 0x40;
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+  let mut uuid = String::with_capacity(36);
+  for (i, byte) in bytes.iter().enumerate() {
+    if matches!(i, 4 | 6 | 8 | 10) {
+      uuid.push('-');
+    }
+    let _ = write!(uuid, "{byte:02x}");
+  }
+  uuid
+}
+
+/// What will actually be sent: control characters escaped, followed by every byte in hex.
+pub fn preview(message: &[u8]) -> String {
+  let text: String = String::from_utf8_lossy(message)
+    .chars()
+    .map(|c| match c {
+      '\n' => "\\n".to_string(),
+      '\r' => "\\r".to_string(),
+      '\t' => "\\t".to_string(),
+      c if c.is_control() => format!("\\x{:02x}", c as u32),
+      c => c.to_string(),
+    })
+    .collect();
+  let hex = message.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(" ");
+  format!("{text}  ({hex})")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn placeholders_are_parsed() {
+    let text = "{{counter}} {{timestamp}} {{uuid}} {{random:-5--1}} {{ distance }} {{counter}}";
+    assert_eq!(
+      placeholders(text).unwrap(),
+      [
+        Placeholder::Counter,
+        Placeholder::Timestamp,
+        Placeholder::Uuid,
+        Placeholder::Random { min: -5, max: -1 },
+        Placeholder::Variable("distance".into()),
+      ]
+    );
+    assert_eq!(placeholders("{{random:0-100}}").unwrap(), [Placeholder::Random { min: 0, max: 100 }]);
+    assert!(placeholders("no {{ end").unwrap().is_empty());
+  }
+
+  #[test]
+  fn bad_placeholders_are_refused() {
+    assert!(placeholders("{{random:5-1}}").is_err());
+    assert!(placeholders("{{random:a-b}}").is_err());
+    assert!(placeholders("{{two words}}").is_err());
+    assert!(placeholders("{{}}").is_err());
+    assert!(no_placeholders("MOVE {{distance}}").is_err());
+    assert!(no_placeholders("MOVE 10").is_ok());
+  }
+
+  #[test]
+  fn repeated_placeholders_get_one_value() {
+    let mut lookups = 0;
+    let expanded = expand("{{uuid}}/{{distance}}/{{uuid}} {{", |placeholder| {
+      lookups += 1;
+      Ok(match placeholder {
+        Placeholder::Uuid => format!("id{lookups}"),
+        _ => "10".into(),
+      })
+    });
+    assert_eq!(expanded.unwrap(), "id1/10/id1 {{");
+    assert_eq!(lookups, 2);
+    assert_eq!(expand("{{x}}", |_| Err("no value".into())), Err("no value".into()));
+  }
+
+  #[test]
+  fn random_numbers_stay_in_range() {
+    assert_eq!(random_in(-5, -1, 0.0), -5);
+    assert_eq!(random_in(-5, -1, 0.999_999), -1);
+    assert_eq!(random_in(0, 100, 0.5), 50);
+    // The whole range doesn't overflow
+    assert_eq!(random_in(i64::MIN, i64::MAX, 0.0), i64::MIN);
+    assert!(random_in(i64::MIN, i64::MAX, 0.999_999) > i64::MAX / 2);
+  }
+
+  #[test]
+  fn uuids_are_version_4() {
+    assert_eq!(uuid_v4([0; 16]), "00000000-0000-4000-8000-000000000000");
+    assert_eq!(uuid_v4([0xff; 16]), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+  }
+
+  #[test]
+  fn preview_shows_control_characters() {
+    assert_eq!(preview(b"\x02OK\r\n"), "\\x02OK\\r\\n  (02 4f 4b 0d 0a)");
+  }
+}